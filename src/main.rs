@@ -74,7 +74,7 @@ extern crate termios;
 extern crate sesd;
 
 use std::panic::PanicInfo;
-use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use nix::sys::signal;
 
@@ -83,7 +83,7 @@ pub mod presenter;
 pub mod tools;
 pub mod view;
 
-use model::history::History;
+use model::history::{History, HistoryFormat};
 
 extern crate backtrace;
 
@@ -101,7 +101,12 @@ fn panic_hook(info: &PanicInfo) {
         let _ = stderr.write(b"BiTE panicked!!\n");
     }
     eprintln!("BiTE panicked!");
-    let err_msg = match (info.payload().downcast_ref::<&str>(), info.location()) {
+    let payload = info
+        .payload()
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| info.payload().downcast_ref::<String>().map(|s| s.as_str()));
+    let err_msg = match (payload, info.location()) {
         (Some(msg), Some(loc)) => {
             error!(
                 "Panic at {}:{}:{} with '{}'",
@@ -134,6 +139,30 @@ fn panic_hook(info: &PanicInfo) {
     let mut msg = String::new();
     let _ = write!(msg, "{:?}", bt);
     error!("Error:{}\nStack Trace:\n{}", err_msg, msg);
+
+    // Save a crash report, as stderr is not visible when started from a desktop
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let report = tools::crash_report::format_report(
+        time,
+        payload,
+        info.location().map(|loc| tools::crash_report::Location {
+            file: loc.file(),
+            line: loc.line(),
+            column: loc.column(),
+        }),
+        &msg,
+    );
+    let report_path = tools::crash_report::report_path(&tools::home_dir::home_dir(), time);
+    match tools::crash_report::write_report(&report_path, &report) {
+        Ok(()) => eprintln!("Crash report saved to {}", report_path.display()),
+        Err(e) => error!(
+            "Could not save crash report to »{:?}«. Error: {}",
+            report_path, e
+        ),
+    }
     ::std::process::exit(2);
 }
 
@@ -143,13 +172,11 @@ pub fn main() {
     unsafe { signal::signal(signal::Signal::SIGHUP, signal::SigHandler::SigIgn) }.unwrap();
     unsafe { signal::signal(signal::Signal::SIGCONT, signal::SigHandler::SigIgn) }.unwrap();
 
-    // Initialise env_logger first
+    // Initialise the logger first
     let _ = std::env::var("BITE_LOG").and_then(|bite_log| {
-        let _ = flexi_logger::Logger::with_str(bite_log)
-            .format(flexi_logger::with_thread)
-            .log_to_file()
-            .start();
-        info!("Logging is ready");
+        let rotation = tools::logging::Rotation::from_env();
+        let _ = tools::logging::start_file_logger(&bite_log, ".".to_string(), &rotation);
+        info!("Logging is ready, rotating at {:?}", rotation);
         Ok(())
     });
 
@@ -164,6 +191,11 @@ pub fn main() {
     let params = ::tools::commandline::CommandLine::parse();
     debug!("Command line {:?}", params);
 
+    if params.version {
+        print!("{}", ::tools::commandline::version_info());
+        return;
+    }
+
     // Create the session
     let mut session =
         model::session::SharedSession::new(model::screen::Screen::one_line_matrix(b"System"));
@@ -171,16 +203,36 @@ pub fn main() {
     // Start interpreter for processing the init script
     let mut interpreter = model::interpreter::StartupInterpreter::new(session.clone());
 
+    // Run a single command without a window. Like bash -c, this doesn't read the init script.
+    // The output is passed on while the command runs.
+    if let Some(command) = params.command {
+        session.set_pass_through(true);
+        let result = interpreter.run_command(&command);
+        ::std::process::exit(result.exit_status);
+    }
+
     // Run the ini script
-    let home = std::env::var("HOME").unwrap_or(".".to_string());
+    let home = tools::home_dir::home_dir();
     {
-        let mut biterc_name = PathBuf::from(home.clone());
+        let mut biterc_name = home.clone();
         biterc_name.push(BITE_INIFILE);
+        info!("Reading init script from »{:?}«", biterc_name);
         let handle = interpreter.run_init_script(&biterc_name);
         trace!("Init script completed");
         // There is no GUI yet. In order to see the stdout/stderr of the ini script for debugging,
-        // possible contents of the interaction will be printed after the script is done.
-        session.print_interaction(handle);
+        // possible contents of the interaction can be printed after the script is done.
+        let stdout = std::io::stdout();
+        let stderr = std::io::stderr();
+        session.report_init_output(
+            handle,
+            params.debug_init,
+            &mut stdout.lock(),
+            &mut stderr.lock(),
+        );
+        // Like bash, an exit in the init script ends the program
+        if let Some(code) = session.exit_request() {
+            ::std::process::exit(code);
+        }
     }
 
     trace!(
@@ -213,14 +265,152 @@ pub fn main() {
         });
     trace!("BITE_FEAT_COMPOSE: {:?}", feat_compose_variant);
 
-    // Transfer the interpreter to the background thread
-    let interpreter = interpreter.complete_startup();
+    // Extract the word boundaries for word-wise editing
+    let word_boundary = interpreter
+        .runner
+        .shell_stack
+        .find_variable("BITE_WORD_BOUNDARY")
+        .map_or(model::screen::WordBoundary::Whitespace, |v| {
+            if v.as_string() == "punctuation" {
+                model::screen::WordBoundary::Punctuation
+            } else {
+                model::screen::WordBoundary::Whitespace
+            }
+        });
+    trace!("BITE_WORD_BOUNDARY: {:?}", word_boundary);
 
-    // Load the history
-    let history = {
-        let mut bitehist_name = PathBuf::from(home);
-        bitehist_name.push(BITE_HISTFILENAME);
+    // Check if brackets and quotes should be closed automatically
+    let auto_pair = interpreter
+        .runner
+        .shell_stack
+        .find_variable("BITE_AUTO_PAIR")
+        .map_or(false, |v| v.as_string() == "yes");
+    trace!("BITE_AUTO_PAIR: {:?}", auto_pair);
 
+    // Keep the raw output of the commands to debug the rendering
+    let keep_raw_output = interpreter
+        .runner
+        .shell_stack
+        .find_variable("BITE_KEEP_RAW_OUTPUT")
+        .map_or(false, |v| v.as_string() == "yes");
+    trace!("BITE_KEEP_RAW_OUTPUT: {:?}", keep_raw_output);
+    session.set_keep_raw_bytes(keep_raw_output);
+
+    // Width the output of the commands wraps at, 0 for no limit
+    let max_output_width = interpreter
+        .runner
+        .shell_stack
+        .find_variable("BITE_MAX_OUTPUT_WIDTH")
+        .and_then(|v| v.as_string().trim().parse::<usize>().ok())
+        .unwrap_or(model::session::DEFAULT_MAX_OUTPUT_WIDTH);
+    trace!("BITE_MAX_OUTPUT_WIDTH: {:?}", max_output_width);
+    session.set_max_output_width(Some(max_output_width).filter(|w| *w > 0));
+
+    // Show a summary instead of output that looks binary
+    let suppress_binary = interpreter
+        .runner
+        .shell_stack
+        .find_variable("BITE_SUPPRESS_BINARY")
+        .map_or(true, |v| v.as_string() != "no");
+    trace!("BITE_SUPPRESS_BINARY: {:?}", suppress_binary);
+    session.set_suppress_binary(suppress_binary);
+
+    // Decide what Enter does on an incomplete script
+    let incomplete_enter = interpreter
+        .runner
+        .shell_stack
+        .find_variable("BITE_INCOMPLETE_ENTER")
+        .map_or(presenter::IncompleteEnter::RunOnBlankLine, |v| {
+            if v.as_string() == "continue" {
+                presenter::IncompleteEnter::Continue
+            } else if v.as_string() == "run" {
+                presenter::IncompleteEnter::Run
+            } else {
+                presenter::IncompleteEnter::RunOnBlankLine
+            }
+        });
+    trace!("BITE_INCOMPLETE_ENTER: {:?}", incomplete_enter);
+
+    // Decide how the typed word selects the completions
+    let completion_matching = interpreter
+        .runner
+        .shell_stack
+        .find_variable("BITE_COMPLETION_MATCHING")
+        .map_or(presenter::CompletionMatching::Prefix, |v| {
+            if v.as_string() == "ignore-case" {
+                presenter::CompletionMatching::IgnoreCase
+            } else if v.as_string() == "fuzzy" {
+                presenter::CompletionMatching::Fuzzy
+            } else {
+                presenter::CompletionMatching::Prefix
+            }
+        });
+    trace!("BITE_COMPLETION_MATCHING: {:?}", completion_matching);
+
+    // Colors to mark the prompts with
+    let prompt_colors = ::view::colors::prompt_colors(
+        interpreter
+            .runner
+            .shell_stack
+            .find_variable("BITE_PROMPT_COLORS")
+            .map(|v| v.as_string().as_str()),
+    );
+    trace!("BITE_PROMPT_COLORS: {:x?}", prompt_colors);
+
+    // Number of trailing directory components in the prompt, 0 for all
+    let prompt_dir_depth = interpreter
+        .runner
+        .shell_stack
+        .find_variable("BITE_PROMPT_DIR_DEPTH")
+        .and_then(|v| v.as_string().trim().parse::<usize>().ok())
+        .filter(|depth| *depth > 0);
+    trace!("BITE_PROMPT_DIR_DEPTH: {:?}", prompt_dir_depth);
+
+    // Format of the clock in the status, none if empty
+    let clock_format = interpreter
+        .runner
+        .shell_stack
+        .find_variable("BITE_CLOCK_FORMAT")
+        .map(|v| v.as_string().clone())
+        .filter(|format| !format.is_empty());
+    trace!("BITE_CLOCK_FORMAT: {:?}", clock_format);
+
+    // Time to wait for a running command when closing the window
+    let shutdown_timeout = interpreter
+        .runner
+        .shell_stack
+        .find_variable("BITE_SHUTDOWN_TIMEOUT")
+        .and_then(|v| v.as_string().trim().parse::<u64>().ok())
+        .unwrap_or(model::interpreter::DEFAULT_SHUTDOWN_TIMEOUT_MS);
+    trace!("BITE_SHUTDOWN_TIMEOUT: {:?}", shutdown_timeout);
+
+    // Ask before closing the window while commands are running
+    let confirm_exit = interpreter
+        .runner
+        .shell_stack
+        .find_variable("BITE_CONFIRM_EXIT")
+        .map_or(false, |v| v.as_string() == "yes");
+    trace!("BITE_CONFIRM_EXIT: {:?}", confirm_exit);
+
+    // Format to save the history in. Loading detects the format.
+    let history_format = interpreter
+        .runner
+        .shell_stack
+        .find_variable("BITE_HISTORY_FORMAT")
+        .map_or(HistoryFormat::Bite, |v| match v.as_string().as_str() {
+            "plain" => HistoryFormat::Plain,
+            "timestamped" => HistoryFormat::Timestamped,
+            _ => HistoryFormat::Bite,
+        });
+    trace!("BITE_HISTORY_FORMAT: {:?}", history_format);
+
+    // Load the history
+    let bitehist_name = match params.history_file {
+        Some(ref file) => std::path::PathBuf::from(file),
+        None => home.join(BITE_HISTFILENAME),
+    };
+    let mut history = {
+        info!("Loading history from »{:?}«", bitehist_name);
         match History::load(&bitehist_name.to_string_lossy()) {
             Ok(history) => history,
             Err(msg) => {
@@ -233,13 +423,67 @@ pub fn main() {
         }
     };
 
+    history.set_private(params.no_history);
+
+    // Let the history builtin see the entered commands
+    interpreter.set_command_log(history.command_log());
+
+    // Transfer the interpreter to the background thread
+    let mut interpreter = interpreter.complete_startup();
+    interpreter.set_shutdown_timeout(Duration::from_millis(shutdown_timeout));
+
+    // Run the command from the command line as if it had been typed in
+    if let Some(command) = params.execute {
+        match model::interpreter::parse_script(&format!("{}\n", command)) {
+            Ok(instructions) => {
+                let _ = interpreter.run(command, instructions);
+            }
+            Err(msg) => {
+                error!("Can't run »{}«: {}", command, msg);
+                eprintln!("BiTE: Can't run »{}«: {}", command, msg);
+            }
+        }
+    }
+
+    // A zero off time keeps the cursor on
+    let cursor_blink = ::view::CursorBlink {
+        on: Duration::from_millis(params.cursor_blink_on),
+        off: if params.no_cursor_blink {
+            Duration::from_millis(0)
+        } else {
+            Duration::from_millis(params.cursor_blink_off)
+        },
+    };
+
+    let font_chain = params
+        .font_chain
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+
     // Start the gui
     let mut gui = match ::view::Gui::new(
-        session,
+        session.clone(),
         interpreter,
         history,
         fontname,
-        feat_compose_variant,
+        presenter::PresenterConfig {
+            compose_variant: feat_compose_variant,
+            word_boundary,
+            auto_pair,
+            incomplete_enter,
+            completion_matching,
+            prompt_dir_depth,
+            clock_format,
+            backspace_sends: params.backspace_sends,
+            alt_sends_escape: params.alt_sends_escape,
+        },
+        prompt_colors,
+        params.line_padding,
+        cursor_blink,
+        confirm_exit,
     ) {
         Err(err) => {
             error!("Can't init GUI: {}", err);
@@ -249,29 +493,22 @@ pub fn main() {
         Ok(g) => g,
     };
 
-    //   if params.single_program.len() != 0 {
-    //       session.new_interaction(params.single_program[0].clone());
-    //       spawned = Some(execute::spawn_command(&params.single_program));
-    //   }
-
     // Write any panic messages to both log and the term bite was started from. Needs to be called
     // after bash::start.
     std::panic::set_hook(Box::new(&panic_hook));
 
     // Run the gui loop until the program is closed
     gui.main_loop();
-    let (interpreter, history) = gui.finish();
+    let (interpreter, mut history) = gui.finish();
 
     trace!("GUI finished");
-    // Shutdown interpreter and wait for it to end
+    // Shutdown interpreter and wait for it to end, but not forever
     interpreter.shutdown();
     trace!("interpreter shut down");
 
     {
-        let home = std::env::var("HOME").unwrap_or(".".to_string());
-        let mut bitehist_name = PathBuf::from(home);
-        bitehist_name.push(BITE_HISTFILENAME);
-        if let Err(msg) = history.save(&bitehist_name.to_string_lossy()) {
+        info!("Saving history to »{:?}«", bitehist_name);
+        if let Err(msg) = history.save(&bitehist_name.to_string_lossy(), history_format) {
             debug!(
                 "Could not save history file to »{:?}«. Error: {}",
                 bitehist_name, msg
@@ -281,4 +518,7 @@ pub fn main() {
 
     let _ = std::panic::take_hook();
     info!("Exiting bite normally");
+    if let Some(code) = session.exit_request() {
+        ::std::process::exit(code);
+    }
 }
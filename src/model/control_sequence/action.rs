@@ -129,9 +129,21 @@ pub enum Action {
     /// TODO: Implement string decoding
     DecUserDefinedKeys(String),
 
+    /// Request capabilities (XTGETTCAP). Holds hex-encoded names, separated by `;`.
+    RequestTermcap(String),
+
+    /// Query the flags of the kitty keyboard protocol (`CSI ? u`)
+    KeyboardProtocolQuery,
+
+    /// Sixel graphics (`DCS q`). Holds the size of the image in pixels as (width, height).
+    SixelImage(usize, usize),
+
     /// Set text parameter
     SetTextParameter(TextParameter, String),
 
+    /// Semantic prompt mark (OSC 133) sent by shell integrations
+    SemanticPrompt(SemanticMark),
+
     InsertCharacters(ActionParameter),
     InsertLines(ActionParameter),
     InsertColumns(ActionParameter),
@@ -649,6 +661,19 @@ bitflags! {
     }
 }
 
+/// Boundaries between prompt, command and output as marked by shells (OSC 133)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SemanticMark {
+    /// A: The prompt starts
+    PromptStart,
+    /// B: The prompt ends, the command starts
+    PromptEnd,
+    /// C: The command ends, its output starts
+    OutputStart,
+    /// D: The command has finished, optionally with its exit code
+    CommandEnd(Option<i32>),
+}
+
 #[derive(Debug, PartialEq)]
 pub enum TextParameter {
     IconAndTitle,
@@ -656,6 +681,8 @@ pub enum TextParameter {
     Title,
     XProperty,
     NamedColor,
+    /// URL of the working directory as reported by a shell
+    WorkingDirectory,
 }
 
 impl Action {
@@ -19,5 +19,6 @@
 pub mod action;
 pub mod parameter;
 pub mod parser;
+pub mod sixel;
 pub mod types;
 mod vt_parse_table;
@@ -19,16 +19,18 @@
 //! Terminal Control Sequences Parser
 
 use std::char;
+use std::cmp;
 use std::mem;
 
 use super::action::{
     Action, AttributeChangeExtent, CharSet, CharacterAttribute, CharacterProtection, Color,
     CursorStyle, EraseDisplay, EraseLine, FKeys, GraOp, GraReg, LoadLeds, LocatorEvents,
-    LocatorReportEnable, LocatorReportUnit, MediaCopy, PointerMode, ScsType, SetMode,
+    LocatorReportEnable, LocatorReportUnit, MediaCopy, PointerMode, ScsType, SemanticMark, SetMode,
     SetPrivateMode, StringMode, TabClear, Terminal, TextParameter, TitleModes, VideoAttributes,
     WindowOp,
 };
 use super::parameter::Parameters;
+use super::sixel;
 use super::types::{ActionParameter, Case, CaseTable};
 use super::vt_parse_table::*;
 
@@ -369,6 +371,7 @@ mod action {
     action_reset!(DECID, DA1, 0);
     action_reset!(DECSC, SaveCursor);
     action_reset!(DECRC, RestoreCursor);
+    action_reset!(KITTY_KEYBOARD_QUERY, KeyboardProtocolQuery);
     action_reset!(XTERM_TITLE, More);
     action_reset!(ENQ, TerminalEnquire);
     action_reset!(DECRPTUI, TerminalUnitId);
@@ -612,6 +615,12 @@ impl Parser {
         Action::Error
     }
 
+    /// Check if all bytes so far formed complete characters and control sequences
+    pub fn is_ground(&self) -> bool {
+        self.code_byte == 0
+            && self.parsestate as *const CaseTable == &ansi_table as *const CaseTable
+    }
+
     /// Reset to ready state
     pub fn reset(&mut self) {
         self.code_byte = 0;
@@ -620,20 +629,34 @@ impl Parser {
         self.parsestate = &ansi_table;
     }
 
-    fn parse_osc(&mut self, mut s: String) -> Action {
-        if s.len() >= 2 {
-            let n = s.remove(0);
-            let semicolon = s.remove(0);
-            match (n, semicolon) {
-                ('0', ';') => Action::SetTextParameter(TextParameter::IconAndTitle, s),
-                ('1', ';') => Action::SetTextParameter(TextParameter::Icon, s),
-                ('2', ';') => Action::SetTextParameter(TextParameter::Title, s),
-                ('3', ';') => Action::SetTextParameter(TextParameter::XProperty, s),
-                ('4', ';') => Action::SetTextParameter(TextParameter::NamedColor, s),
-                _ => Action::More,
-            }
-        } else {
-            Action::More
+    fn parse_osc(&mut self, s: String) -> Action {
+        let (n, s) = match s.find(';') {
+            Some(i) => (s[..i].to_string(), s[i + 1..].to_string()),
+            None => return Action::More,
+        };
+        match n.as_str() {
+            "0" => Action::SetTextParameter(TextParameter::IconAndTitle, s),
+            "1" => Action::SetTextParameter(TextParameter::Icon, s),
+            "2" => Action::SetTextParameter(TextParameter::Title, s),
+            "3" => Action::SetTextParameter(TextParameter::XProperty, s),
+            "4" => Action::SetTextParameter(TextParameter::NamedColor, s),
+            "7" => Action::SetTextParameter(TextParameter::WorkingDirectory, s),
+            "133" => Self::parse_semantic_prompt(&s),
+            _ => Action::More,
+        }
+    }
+
+    /// Parse the parameters of OSC 133, e.g. `D;1`. Further options are ignored.
+    fn parse_semantic_prompt(s: &str) -> Action {
+        let mut params = s.split(';');
+        match params.next() {
+            Some("A") => Action::SemanticPrompt(SemanticMark::PromptStart),
+            Some("B") => Action::SemanticPrompt(SemanticMark::PromptEnd),
+            Some("C") => Action::SemanticPrompt(SemanticMark::OutputStart),
+            Some("D") => Action::SemanticPrompt(SemanticMark::CommandEnd(
+                params.next().and_then(|code| code.parse().ok()),
+            )),
+            _ => Action::More,
         }
     }
 
@@ -739,8 +762,10 @@ impl Parser {
                 }
             }
             _ => {
-                let attrs: Vec<CharacterAttribute> = self
-                    .parameters()
+                // Omitted parameters are 0 (Normal), even if there are none at all as in ESC [ m
+                let count = cmp::max(1, self.parameter.count());
+                let attrs: Vec<CharacterAttribute> = (0..count)
+                    .map(|i| self.parameter.zero_if_default(i as u8))
                     .filter_map(|attr| match attr {
                         0 => Some(CharacterAttribute::Normal),
                         1 => Some(CharacterAttribute::Bold),
@@ -969,7 +994,13 @@ impl Parser {
         let _ = s.pop();
         let res = match self.string_mode {
             StringMode::Apc => Action::ApplicationProgramCommand(s),
-            StringMode::Dcs => Action::DecUserDefinedKeys(s),
+            StringMode::Dcs => match s.strip_prefix("+q") {
+                Some(query) => Action::RequestTermcap(query.to_string()),
+                None => match sixel::image_size(&s) {
+                    Some((width, height)) => Action::SixelImage(width, height),
+                    None => Action::DecUserDefinedKeys(s),
+                },
+            },
             StringMode::Osc => self.parse_osc(s),
             StringMode::Pm => Action::PrivacyMessage(s),
             StringMode::Sos => Action::StartOfString(s),
@@ -1472,6 +1503,7 @@ static dispatch_case: [CaseDispatch; Case::NUM_CASES as usize] = [
     action::DECRQPSR,
     Parser::action_DECSCPP,
     Parser::action_DECSNLS,
+    action::KITTY_KEYBOARD_QUERY,
 ];
 
 #[cfg(test)]
@@ -1793,6 +1825,15 @@ mod test {
             ApplicationProgramCommand("stuff".to_string()) c'b');
         pt!(b"a\x1bP0;0|17/17;15/15\x1b\\b", c'a' m m m m m m m m m m m m m m m m m m
             DecUserDefinedKeys("0;0|17/17;15/15".to_string()) c'b');
+        pt!(b"a\x1bP+q636F6C6F7273\x1b\\b", c'a' m m m m m m m m m m m m m m m m m
+            RequestTermcap("636F6C6F7273".to_string()) c'b');
+        pt!(b"a\x1b[?ub", c'a' m m m KeyboardProtocolQuery c'b');
+        // Pushing, popping and setting kitty keyboard flags is swallowed
+        pt!(b"a\x1b[>1ub", c'a' m m m m m c'b');
+        pt!(b"a\x1b[<ub", c'a' m m m m c'b');
+        pt!(b"a\x1b[=1;1ub", c'a' m m m m m m m c'b');
+        pt!(b"a\x1bPq\"1;1;20;12~\x1b\\b", c'a' m m m m m m m m m m m m m m m
+            SixelImage(20, 12) c'b');
         pt!(b"a\x1b[12Ab", c'a' m m m m CursorUp(12) c'b');
         pt!(b"a\x1b[12Bb", c'a' m m m m CursorDown(12) c'b');
         pt!(b"a\x1b[12Cb", c'a' m m m m CursorForward(12) c'b');
@@ -2098,6 +2139,11 @@ mod test {
         pt!(b"a\x1b[48;2;12;13;14mx", c'a' m m m m m m m m m m m m m m m
             BackgroundColorRgb(12,13,14) c'x');
 
+        // Missing parameters are Normal
+        pt!(b"a\x1b[mx", c'a' m m CharacterAttributes(vec![CharacterAttribute::Normal]) c'x');
+        pt!(b"a\x1b[;1mx", c'a' m m m m
+            CharacterAttributes(vec![CharacterAttribute::Normal, CharacterAttribute::Bold]) c'x');
+
         pt!(b"a\x1b[0;1;2;3;50;4;5mx", c'a' m m m m m m m m m m m m m m m m
             CharacterAttributes(
                 vec![
@@ -2530,6 +2576,18 @@ mod test {
             SetTextParameter(TextParameter::XProperty,"Iconic".to_string()) c'x');
         pt!(b"a\x1b]4;17;Iconic\x1b\\x", c'a' m m m m m m m m m m m m m m
             SetTextParameter(TextParameter::NamedColor,"17;Iconic".to_string()) c'x');
+        pt!(b"a\x1b]7;file://h/tmp\x07x", c'a' m m m m m m m m m m m m m m m m
+            SetTextParameter(TextParameter::WorkingDirectory,"file://h/tmp".to_string()) c'x');
+        pt!(b"a\x1b]133;A\x07x", c'a' m m m m m m m
+            SemanticPrompt(SemanticMark::PromptStart) c'x');
+        pt!(b"a\x1b]133;B\x1b\\x", c'a' m m m m m m m m
+            SemanticPrompt(SemanticMark::PromptEnd) c'x');
+        pt!(b"a\x1b]133;C\x07x", c'a' m m m m m m m
+            SemanticPrompt(SemanticMark::OutputStart) c'x');
+        pt!(b"a\x1b]133;D;1\x07x", c'a' m m m m m m m m m
+            SemanticPrompt(SemanticMark::CommandEnd(Some(1))) c'x');
+        pt!(b"a\x1b]133;D\x07x", c'a' m m m m m m m
+            SemanticPrompt(SemanticMark::CommandEnd(None)) c'x');
         pt!(b"a\x1b^Stuff\x1b\\x", c'a' m m m m m m m m PrivacyMessage("Stuff".to_string()) c'x');
         pt!(b"a\x08x", c'a' Backspace c'x');
         pt!(b"a\x0ax", c'a' NewLine c'x');
@@ -0,0 +1,128 @@
+/*
+    BiTE - Bash-integrated Terminal Parser
+    Copyright (C) 2018  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Size of Sixel images (`DCS Pa;Pb;Ph q <data> ST`)
+//!
+//! The image itself is not decoded. Only its size is determined so that the screen can reserve
+//! room for it.
+
+/// Height of a band of sixels in pixels
+const SIXEL_HEIGHT: usize = 6;
+
+/// Split a DCS string into the sixel data if it is a Sixel sequence.
+///
+/// The string starts after `ESC P` and does not contain the string terminator.
+fn sixel_data(dcs: &str) -> Option<&str> {
+    let q = dcs.find(|c: char| !c.is_ascii_digit() && c != ';')?;
+    if dcs[q..].starts_with('q') {
+        Some(&dcs[q + 1..])
+    } else {
+        None
+    }
+}
+
+/// Parse a list of numeric parameters separated by semicolons.
+///
+/// Returns the parameters and the rest of the string. Missing parameters are zero.
+fn parameters(s: &str) -> (Vec<usize>, &str) {
+    let end = s
+        .find(|c: char| !c.is_ascii_digit() && c != ';')
+        .unwrap_or(s.len());
+    let params = s[..end]
+        .split(';')
+        .map(|p| p.parse().unwrap_or(0))
+        .collect();
+    (params, &s[end..])
+}
+
+/// Compute the size of a Sixel image in pixels as (width, height).
+///
+/// Returns None if the DCS string is not a Sixel sequence. The size is taken from the raster
+/// attributes (`" Pan;Pad;Ph;Pv`). If they are missing or incomplete, the sixels are counted.
+pub fn image_size(dcs: &str) -> Option<(usize, usize)> {
+    let data = sixel_data(dcs)?;
+
+    if data.starts_with('"') {
+        let (params, _) = parameters(&data[1..]);
+        if let (Some(&width), Some(&height)) = (params.get(2), params.get(3)) {
+            if width != 0 && height != 0 {
+                return Some((width, height));
+            }
+        }
+    }
+
+    let mut width = 0;
+    let mut height = 0;
+    let mut x = 0;
+    let mut band = 0;
+    let mut rest = data;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '?'..='~' => {
+                x += 1;
+                width = std::cmp::max(width, x);
+                height = (band + 1) * SIXEL_HEIGHT;
+            }
+            '!' => {
+                let (params, r) = parameters(rest);
+                let count = params[0];
+                rest = r;
+                if let Some('?'..='~') = rest.chars().next() {
+                    rest = &rest[1..];
+                    x = x.saturating_add(count);
+                    width = std::cmp::max(width, x);
+                    height = (band + 1) * SIXEL_HEIGHT;
+                }
+            }
+            '#' | '"' => rest = parameters(rest).1,
+            '$' => x = 0,
+            '-' => {
+                x = 0;
+                band += 1;
+            }
+            _ => {}
+        }
+    }
+    Some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognize_sixel_sequences() {
+        assert_eq!(image_size("0;1;0q\"1;1;20;12#0~~"), Some((20, 12)));
+        assert_eq!(image_size("q\"1;1;20;12"), Some((20, 12)));
+        assert_eq!(image_size("0;0|17/17;15/15"), None);
+        assert_eq!(image_size("+q636F6C6F7273"), None);
+    }
+
+    #[test]
+    fn count_sixels_without_raster_attributes() {
+        assert_eq!(image_size("q#0;2;0;0;0#0~~~$#1!5~-~~"), Some((5, 12)));
+        assert_eq!(image_size("q~~-"), Some((2, 6)));
+        assert_eq!(
+            image_size("q!18446744073709551615~!9~"),
+            Some((std::usize::MAX, 6))
+        );
+        assert_eq!(image_size("q\"1;1#0!12?"), Some((12, 6)));
+        assert_eq!(image_size("q"), Some((0, 0)));
+    }
+}
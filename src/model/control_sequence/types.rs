@@ -222,6 +222,7 @@ pub enum Case {
     DECRQPSR,
     DECSCPP,
     DECSNLS,
+    KITTY_KEYBOARD_QUERY,
 
     NUM_CASES,
 }
@@ -1861,7 +1861,7 @@ Case::XTERM_RESTORE,
 Case::XTERM_SAVE,
 /*	t		u		v		w	*/
 Case::GROUND_STATE,
-Case::GROUND_STATE,
+Case::KITTY_KEYBOARD_QUERY,
 Case::GROUND_STATE,
 Case::GROUND_STATE,
 /*	x		y		z		{	*/
@@ -18,21 +18,49 @@
 
 //! Keep track of previously entered commands
 //!
-//! TODO: Merge histories on save
+//! Several instances of BiTE can share a history file. Each one adds the commands entered since it
+//! loaded the file when it saves, like bash does with `histappend`.
 
 use itertools::Itertools;
 use std::collections::HashMap;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use model::interpreter::CommandLog;
+use tools::lock_file::LockFile;
 use tools::versioned_file;
 
 /// Map a String key to the number of times it was entered
 type KeyCountMap = qptrie::Trie<String, u32>;
 
+/// Formats of the history file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoryFormat {
+    /// Frequencies of the commands by directory and previous command. Only BiTE can read it.
+    Bite,
+    /// One command per line, oldest first
+    Plain,
+    /// Like plain, but each command is preceded by a line with # and the seconds since the epoch
+    /// when it was entered last. This is what bash writes if HISTTIMEFORMAT is set.
+    Timestamped,
+}
+
 /// Zero-cost abstraction around the trie to add some operations
 #[derive(Debug)]
 struct Predictor(KeyCountMap);
 
+/// Commands in order of entry, shared with the interpreter for the history builtin
+#[derive(Debug)]
+struct OrderedCommands {
+    /// The number denotes the number of the entry
+    commands: Predictor,
+
+    /// The history builtin cleared the commands. The other predictors have to forget them, too.
+    cleared: bool,
+}
+
 /// History of all entered commands, sorted by folder and previous command.
 ///
 /// In order to allow commands to be found regardless where and after which command they were
@@ -67,18 +95,35 @@ pub struct History {
     /// Last command entered
     last_cmd: String,
 
-    /// Commands in order of entry. The number denotes the number of the entry.
-    ordered_cmd: Predictor,
+    /// Commands in order of entry
+    ordered_cmd: Arc<Mutex<OrderedCommands>>,
 
     /// Number of next entry
     next_cmd: u32,
 
+    /// Seconds since the epoch when a command was entered last. Not known for commands loaded
+    /// from a file without timestamps.
+    entered_at: HashMap<String, u64>,
+
+    /// Don't record entered commands, e.g. while typing secrets
+    private: bool,
+
+    /// Directory, command and time of the commands entered since the history was loaded. They
+    /// are added to the file on save.
+    session_cmds: Vec<(String, String, u64)>,
+
     /// Last prediction, most frequent first
     pub prediction: Vec<String>,
 }
 
 const HISTORY_FORMAT_100: &str = "BITE HISTORY 1.0.0";
 
+/// Start of the header of all versions of the BiTE format
+const HISTORY_FORMAT_PREFIX: &str = "BITE HISTORY ";
+
+/// Time to wait for another instance to finish saving the history
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl History {
     /// Create empty history
     pub fn new() -> Self {
@@ -88,29 +133,93 @@ impl History {
             cmd: Predictor::new(),
             last_dir: String::new(),
             last_cmd: String::new(),
-            ordered_cmd: Predictor::new(),
+            ordered_cmd: Arc::new(Mutex::new(OrderedCommands::new(Predictor::new()))),
             next_cmd: 0,
+            entered_at: HashMap::new(),
+            private: false,
+            session_cmds: Vec::new(),
             prediction: Vec::new(),
         }
     }
 
-    /// Load the history from the given file.
+    /// Load the history from the given file. The format is detected from the contents.
+    ///
+    /// A file in another version of the BiTE format is not read as text, but reported as an error.
     pub fn load(file_name: &str) -> Result<History, String> {
-        let file_handle = versioned_file::open(file_name, HISTORY_FORMAT_100)
-            .map_err(|e| e.to_string())?;
-        History::deserialize_from(file_handle)
+        match versioned_file::open(file_name, HISTORY_FORMAT_100) {
+            Ok(file_handle) => History::deserialize_from(file_handle),
+            Err(ref e) if e.kind() == ErrorKind::InvalidInput => {
+                let content = std::fs::read(file_name).map_err(|e| e.to_string())?;
+                if content.starts_with(HISTORY_FORMAT_PREFIX.as_bytes()) {
+                    return Err(format!(
+                        "Unsupported version of the history format in {}",
+                        file_name
+                    ));
+                }
+                // No header, try the text formats
+                Ok(History::from_text(&String::from_utf8_lossy(&content)))
+            }
+            Err(e) => Err(e.to_string()),
+        }
     }
 
-    /// Save the history
-    pub fn save(&self, file_name: &str) -> Result<(), String> {
-        let file_handle = versioned_file::create(file_name, HISTORY_FORMAT_100)
-            .map_err(|e| e.to_string())?;
-        self.serialize_into(file_handle);
+    /// Save the history in the given format.
+    ///
+    /// If the file can be loaded, the commands entered since this history was loaded are added to
+    /// it. Thus, the commands other instances saved in the meantime are kept. A lock file keeps
+    /// them from saving at the same time. A file that exists, but can't be loaded, is not
+    /// overwritten.
+    pub fn save(&mut self, file_name: &str, format: HistoryFormat) -> Result<(), String> {
+        let _lock =
+            LockFile::acquire(Path::new(file_name), LOCK_TIMEOUT).map_err(|e| e.to_string())?;
+        self.forget_if_cleared();
+        let merged = match History::load(file_name) {
+            Ok(mut on_disk) => {
+                for (dir, cmd, time) in self.session_cmds.iter() {
+                    on_disk.enter_at(dir, cmd, *time);
+                }
+                Some(on_disk)
+            }
+            Err(_) if !Path::new(file_name).exists() => None,
+            Err(msg) => return Err(msg),
+        };
+        merged.as_ref().unwrap_or(&*self).write(file_name, format)?;
+        self.session_cmds.clear();
         Ok(())
     }
 
-    /// Enter a command in the history
+    /// Write the history to the file, replacing its contents
+    fn write(&self, file_name: &str, format: HistoryFormat) -> Result<(), String> {
+        match format {
+            HistoryFormat::Bite => {
+                let file_handle = versioned_file::create(file_name, HISTORY_FORMAT_100)
+                    .map_err(|e| e.to_string())?;
+                self.serialize_into(file_handle);
+                Ok(())
+            }
+            HistoryFormat::Plain | HistoryFormat::Timestamped => {
+                std::fs::write(file_name, self.to_text(format)).map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    /// Enter a command in the history.
+    ///
+    /// Nothing is recorded in private mode. Like bash with `HISTCONTROL=ignorespace`, commands
+    /// starting with a space are never recorded.
     pub fn enter(&mut self, dir: &str, cmd: &String) {
+        if self.private || cmd.starts_with(' ') {
+            return;
+        }
+        let time = unix_time();
+        self.session_cmds.push((dir.to_string(), cmd.clone(), time));
+        self.enter_at(dir, cmd, time);
+    }
+
+    /// Enter a command that was entered in the given directory at the given time
+    fn enter_at(&mut self, dir: &str, cmd: &str, time: u64) {
+        self.forget_if_cleared();
+
         // Prepare the last command of a new directory
         if self.last_dir != dir {
             self.last_dir.clear();
@@ -123,34 +232,57 @@ impl History {
         key.push_str("\0");
         key.push_str(&self.last_cmd);
         key.push_str("\0");
-        key.push_str(&cmd);
+        key.push_str(cmd);
         self.dir_prev_cmd.enter(&key);
 
         // Update dir_cmd, reuse key to save allocations
         key.clear();
         key.push_str(&self.last_dir);
         key.push_str("\0");
-        key.push_str(&cmd);
+        key.push_str(cmd);
         self.dir_cmd.enter(&key);
 
-        // Update cmd
-        self.cmd.enter(cmd);
-
         // Remember the last command
         self.last_cmd.clear();
         self.last_cmd.push_str(cmd);
 
+        self.add_command(cmd, Some(time));
+    }
+
+    /// Start or stop recording the entered commands
+    pub fn set_private(&mut self, private: bool) {
+        self.private = private;
+    }
+
+    /// Check if entered commands are not recorded
+    pub fn is_private(&self) -> bool {
+        self.private
+    }
+
+    /// Enter a command without knowing where it was entered, e.g. from a text file.
+    fn add_command(&mut self, cmd: &str, time: Option<u64>) {
+        let cmd = cmd.to_string();
+
+        // Update cmd
+        self.cmd.enter(&cmd);
+
+        if let Some(time) = time {
+            self.entered_at.insert(cmd.clone(), time);
+        }
+
         // Update the bubble-up stack
-        if let Some(counter) = self.ordered_cmd.0.get_mut(cmd) {
+        let mut ordered_cmd = self.ordered_cmd.lock().unwrap();
+        if let Some(counter) = ordered_cmd.commands.0.get_mut(&cmd) {
             *counter = self.next_cmd;
         } else {
-            self.ordered_cmd.0.insert(cmd.to_string(), self.next_cmd);
+            ordered_cmd.commands.0.insert(cmd, self.next_cmd);
         }
         self.next_cmd += 1;
     }
 
     /// Compute a new prediction
     pub fn predict(&mut self, dir: &str, start: &String) {
+        self.forget_if_cleared();
         self.prediction.clear();
         // most specific search first
         if dir == self.last_dir {
@@ -186,10 +318,12 @@ impl History {
 
     /// Compute a new prediction using the bubble-up stack
     pub fn predict_bubble_up(&mut self, start: &String) {
+        self.forget_if_cleared();
         let start_len = start.len();
         self.prediction.clear();
-        for p in self
-            .ordered_cmd
+        let ordered_cmd = self.ordered_cmd.lock().unwrap();
+        for p in ordered_cmd
+            .commands
             .0
             .prefix_iter(start)
             .sorted_by(|a, b| Ord::cmp(a.1, b.1))
@@ -199,6 +333,32 @@ impl History {
         }
     }
 
+    /// If the history builtin cleared the commands, forget everything that was entered before
+    fn forget_if_cleared(&mut self) {
+        let cleared = {
+            let mut ordered_cmd = self.ordered_cmd.lock().unwrap();
+            let cleared = ordered_cmd.cleared;
+            ordered_cmd.cleared = false;
+            cleared
+        };
+        if cleared {
+            self.dir_prev_cmd = Predictor::new();
+            self.dir_cmd = Predictor::new();
+            self.cmd = Predictor::new();
+            self.last_dir.clear();
+            self.last_cmd.clear();
+            self.next_cmd = 0;
+            self.entered_at.clear();
+            self.session_cmds.clear();
+            self.prediction.clear();
+        }
+    }
+
+    /// Give access to the entered commands in order of entry
+    pub fn command_log(&self) -> Arc<dyn CommandLog> {
+        self.ordered_cmd.clone()
+    }
+
     /// Get the latest prediction
     pub fn prediction<'a>(&'a self) -> &'a Vec<String> {
         &self.prediction
@@ -219,7 +379,7 @@ impl History {
         let mut next_cmd = 0;
         for (c, n) in hm.iter() {
             let (pred, key) = if c.starts_with("\0\0\0") {
-                next_cmd = std::cmp::max(next_cmd, *n);
+                next_cmd = std::cmp::max(next_cmd, *n + 1);
                 (&mut ordered_cmd, &c[3..])
             } else if c.starts_with("\0\0") {
                 (&mut cmd, &c[2..])
@@ -236,12 +396,69 @@ impl History {
             cmd,
             last_dir: String::new(),
             last_cmd: String::new(),
-            ordered_cmd,
+            ordered_cmd: Arc::new(Mutex::new(OrderedCommands::new(ordered_cmd))),
             next_cmd,
+            entered_at: HashMap::new(),
+            private: false,
+            session_cmds: Vec::new(),
             prediction: Vec::new(),
         })
     }
 
+    /// Read the commands of the plain or the timestamped format. The latter is detected by the
+    /// timestamp in the first line.
+    ///
+    /// In the timestamped format, all lines up to the next timestamp belong to one command.
+    fn from_text(text: &str) -> History {
+        let mut history = History::new();
+        if text.lines().next().and_then(timestamp).is_some() {
+            let mut entry: Option<(u64, Vec<&str>)> = None;
+            for line in text.lines() {
+                if let Some(time) = timestamp(line) {
+                    if let Some((time, lines)) = entry.take() {
+                        history.add_text_entry(lines.join("\n"), Some(time));
+                    }
+                    entry = Some((time, Vec::new()));
+                } else if let Some((_, ref mut lines)) = entry {
+                    lines.push(line);
+                }
+            }
+            if let Some((time, lines)) = entry {
+                history.add_text_entry(lines.join("\n"), Some(time));
+            }
+        } else {
+            for line in text.lines() {
+                history.add_text_entry(line.to_string(), None);
+            }
+        }
+        history
+    }
+
+    /// Enter a command read from a text file, unless it is empty
+    fn add_text_entry(&mut self, cmd: String, time: Option<u64>) {
+        if !cmd.is_empty() {
+            self.add_command(&cmd, time);
+        }
+    }
+
+    /// Write the commands in order of entry in the plain or the timestamped format.
+    ///
+    /// Commands with an unknown time get the current time. Commands with several lines can only
+    /// be read back as such from the timestamped format.
+    fn to_text(&self, format: HistoryFormat) -> String {
+        let now = unix_time();
+        let mut text = String::new();
+        for cmd in self.ordered_cmd.commands() {
+            if format == HistoryFormat::Timestamped {
+                let time = self.entered_at.get(&cmd).cloned().unwrap_or(now);
+                text.push_str(&format!("#{}\n", time));
+            }
+            text.push_str(&cmd);
+            text.push('\n');
+        }
+        text
+    }
+
     /// As radix_trie does not support serde, serialize a HashMap. Use \u{0} prefixes to
     /// distignuish the entries
     fn serialize_into<W>(&self, writer: W)
@@ -265,7 +482,8 @@ impl History {
             key.push_str(c);
             let _ = hm.insert(key, *n);
         }
-        for (c, n) in self.ordered_cmd.0.prefix_iter(&String::new()) {
+        let ordered_cmd = self.ordered_cmd.lock().unwrap();
+        for (c, n) in ordered_cmd.commands.0.prefix_iter(&String::new()) {
             let mut key = String::new();
             key.push_str("\0\0\0");
             key.push_str(c);
@@ -275,6 +493,22 @@ impl History {
     }
 }
 
+/// Seconds since the epoch
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Time of a timestamp line of the timestamped format
+fn timestamp(line: &str) -> Option<u64> {
+    if line.starts_with('#') && line.len() > 1 && line[1..].bytes().all(|b| b.is_ascii_digit()) {
+        line[1..].parse().ok()
+    } else {
+        None
+    }
+}
+
 impl Predictor {
     fn new() -> Self {
         Self(KeyCountMap::new())
@@ -298,6 +532,34 @@ impl Predictor {
     }
 }
 
+impl OrderedCommands {
+    fn new(commands: Predictor) -> Self {
+        Self {
+            commands,
+            cleared: false,
+        }
+    }
+}
+
+impl CommandLog for Mutex<OrderedCommands> {
+    fn commands(&self) -> Vec<String> {
+        self.lock()
+            .unwrap()
+            .commands
+            .0
+            .prefix_iter(&String::new())
+            .sorted_by(|a, b| Ord::cmp(a.1, b.1))
+            .map(|(s, _)| s.to_string())
+            .collect()
+    }
+
+    fn clear(&self) {
+        let mut ordered_cmd = self.lock().unwrap();
+        ordered_cmd.commands = Predictor::new();
+        ordered_cmd.cleared = true;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -387,6 +649,192 @@ mod tests {
         }
     }
 
+    #[test]
+    fn command_log() {
+        let mut history = History::new();
+        history.enter("/home/user", &"ls".to_string());
+        history.enter("/home/user", &"cd stuff".to_string());
+        history.enter("/home/user/stuff", &"make".to_string());
+        history.enter("/home/user/stuff", &"ls".to_string());
+
+        let log = history.command_log();
+        assert_eq!(log.commands(), vec!["cd stuff", "make", "ls"]);
+
+        log.clear();
+        assert!(log.commands().is_empty());
+        history.enter("/home/user/stuff", &"make".to_string());
+        assert_eq!(log.commands(), vec!["make"]);
+    }
+
+    #[test]
+    fn clear_forgets_predictions() {
+        let mut history = History::new();
+        history.enter("/home/user", &"ls".to_string());
+        history.enter("/home/user", &"make".to_string());
+        history.command_log().clear();
+
+        let start = String::new();
+        history.predict("/home/user", &start);
+        assert!(history.prediction().is_empty());
+        history.predict("/home/other", &start);
+        assert!(history.prediction().is_empty());
+        history.predict_bubble_up(&start);
+        assert!(history.prediction().is_empty());
+
+        history.enter("/home/user", &"ls -l".to_string());
+        history.predict("/home/user", &"ls".to_string());
+        assert_eq!(history.prediction(), &vec![" -l".to_string()]);
+        assert!(history.session_cmds.iter().all(|c| c.1 == "ls -l"));
+    }
+
+    fn text_history() -> History {
+        let mut history = History::new();
+        history.add_command("ls", Some(1600000000));
+        history.add_command("for i in 1 2\ndo echo $i\ndone", Some(1600000010));
+        history.add_command("make", Some(1600000020));
+        history.add_command("ls", Some(1600000030));
+        history
+    }
+
+    #[test]
+    fn plain_round_trip() {
+        let history = text_history();
+        let text = history.to_text(HistoryFormat::Plain);
+        assert_eq!(text, "for i in 1 2\ndo echo $i\ndone\nmake\nls\n");
+
+        let readback = History::from_text(&text);
+        assert_eq!(
+            readback.command_log().commands(),
+            vec!["for i in 1 2", "do echo $i", "done", "make", "ls"]
+        );
+        assert!(readback.entered_at.is_empty());
+        assert_eq!(readback.to_text(HistoryFormat::Plain), text);
+    }
+
+    #[test]
+    fn timestamped_round_trip() {
+        let history = text_history();
+        let text = history.to_text(HistoryFormat::Timestamped);
+        assert_eq!(
+            text,
+            "#1600000010\nfor i in 1 2\ndo echo $i\ndone\n#1600000020\nmake\n#1600000030\nls\n"
+        );
+
+        let readback = History::from_text(&text);
+        assert_eq!(
+            readback.command_log().commands(),
+            history.command_log().commands()
+        );
+        assert_eq!(readback.entered_at, history.entered_at);
+        assert_eq!(readback.to_text(HistoryFormat::Timestamped), text);
+
+        let mut history = History::new();
+        history.enter("/home/user", &"pwd".to_string());
+        let readback = History::from_text(&history.to_text(HistoryFormat::Timestamped));
+        assert_eq!(readback.entered_at, history.entered_at);
+    }
+
+    #[test]
+    fn detect_format_on_load() {
+        let mut history = text_history();
+        let file_name = std::env::temp_dir()
+            .join(format!("bite-history-test-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        for format in &[
+            HistoryFormat::Bite,
+            HistoryFormat::Plain,
+            HistoryFormat::Timestamped,
+        ] {
+            let _ = std::fs::remove_file(&file_name);
+            history.save(&file_name, *format).unwrap();
+            let readback = History::load(&file_name).unwrap();
+            let commands = readback.command_log().commands();
+            if *format == HistoryFormat::Plain {
+                assert_eq!(commands.len(), 5);
+            } else {
+                assert_eq!(commands, history.command_log().commands());
+            }
+            assert_eq!(
+                readback.entered_at.is_empty(),
+                *format != HistoryFormat::Timestamped
+            );
+        }
+
+        // Another version of the BiTE format is neither read as text nor overwritten
+        std::fs::write(&file_name, "BITE HISTORY 2.0.0\0\0\u{1}").unwrap();
+        assert!(History::load(&file_name).is_err());
+        assert!(history.save(&file_name, HistoryFormat::Plain).is_err());
+        assert!(std::fs::read(&file_name)
+            .unwrap()
+            .starts_with(b"BITE HISTORY 2.0.0"));
+        let _ = std::fs::remove_file(&file_name);
+
+        assert!(History::load("/nonexistent/bitehistory").is_err());
+    }
+
+    #[test]
+    fn merge_concurrent_saves() {
+        let file_name = std::env::temp_dir()
+            .join(format!("bite-history-merge-test-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let _ = std::fs::remove_file(&file_name);
+
+        // Two instances that started with the same (missing) file
+        let mut first = History::new();
+        let mut second = History::new();
+        first.enter("/home/user", &"make".to_string());
+        first.enter("/home/user", &"ls".to_string());
+        second.enter("/tmp", &"cargo build".to_string());
+        second.enter("/tmp", &"ls".to_string());
+
+        first.save(&file_name, HistoryFormat::Bite).unwrap();
+        second.save(&file_name, HistoryFormat::Bite).unwrap();
+        // Saving again doesn't add the commands twice
+        second.save(&file_name, HistoryFormat::Bite).unwrap();
+
+        let merged = History::load(&file_name).unwrap();
+        assert_eq!(
+            merged.command_log().commands(),
+            vec!["make", "cargo build", "ls"]
+        );
+        assert_eq!(merged.cmd.0.get(&"ls".to_string()), Some(&2));
+        assert_eq!(
+            merged.dir_cmd.0.get(&"/tmp\0cargo build".to_string()),
+            Some(&1)
+        );
+
+        // The text formats are merged as well
+        second.enter("/tmp", &"cargo test".to_string());
+        second.save(&file_name, HistoryFormat::Timestamped).unwrap();
+        let merged = History::load(&file_name).unwrap();
+        assert_eq!(
+            merged.command_log().commands(),
+            vec!["make", "cargo build", "ls", "cargo test"]
+        );
+        let _ = std::fs::remove_file(&file_name);
+    }
+
+    #[test]
+    fn private_commands_not_recorded() {
+        let mut history = History::new();
+        history.enter("/home/user", &"ls".to_string());
+        history.enter("/home/user", &" export TOKEN=secret".to_string());
+        assert_eq!(history.command_log().commands(), vec!["ls"]);
+
+        history.set_private(true);
+        assert!(history.is_private());
+        history.enter("/home/user", &"mysql -psecret".to_string());
+        history.set_private(false);
+        history.enter("/home/user", &"make".to_string());
+
+        assert_eq!(history.command_log().commands(), vec!["ls", "make"]);
+        history.predict("/home/user", &String::new());
+        assert!(!history.prediction().iter().any(|p| p.contains("secret")));
+        assert!(!history.to_text(HistoryFormat::Plain).contains("secret"));
+    }
+
     #[test]
     fn zero_sep() {
         let mut ccm = KeyCountMap::new();
@@ -18,37 +18,74 @@
 
 //! Change directory builtin
 
-use std::env::VarError;
 use std::io::Write;
 use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
 use std::process::ExitStatus;
 
-use nix::unistd::chdir;
+use nix::unistd::{chdir, getcwd};
 
 use argparse::{ArgumentParser, Store};
 
-fn change_dir(mut dir: String, _stdout: &mut dyn Write, stderr: &mut dyn Write) -> i32 {
+use super::super::variables::ContextStack;
+use super::ShellServices;
+
+/// Change the working directory of the process and update $PWD and $OLDPWD.
+///
+/// An empty dir means $HOME, "-" means $OLDPWD. In the latter case, the new directory is printed.
+/// A subshell only changes its own working directory.
+pub fn change_dir(
+    dir: String,
+    shell_stack: &mut ContextStack,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> i32 {
     // Fix dir
-    if dir.is_empty() {
-        dir = match std::env::var("HOME") {
-            Ok(d) => d,
-            Err(var_err) => {
-                return match var_err {
-                    VarError::NotPresent => {
-                        let _ = write!(stderr, "BiTE: cd can't value of $HOME\n");
-                        3
-                    }
-                    VarError::NotUnicode(_) => {
-                        let _ = write!(stderr, "BiTE: cd: Vlue of $HOME isn't unicode\n");
-                        4
-                    }
-                };
+    let (dir, print_dir) = if dir.is_empty() {
+        match shell_stack.find_variable("HOME") {
+            Some(home) => (home.as_string().clone(), false),
+            None => {
+                let _ = write!(stderr, "BiTE: cd: HOME not set\n");
+                return 3;
             }
-        };
-    }
+        }
+    } else if dir == "-" {
+        match shell_stack.find_variable("OLDPWD") {
+            Some(old) => (old.as_string().clone(), true),
+            None => {
+                let _ = write!(stderr, "BiTE: cd: OLDPWD not set\n");
+                return 1;
+            }
+        }
+    } else {
+        (dir, false)
+    };
     // Change directory
-    match chdir(dir.as_str()) {
-        Ok(()) => 0,
+    let changed = match shell_stack.working_dir().cloned() {
+        Some(old_dir) => subshell_dir(&old_dir, &dir).map(|new_dir| {
+            shell_stack.set_working_dir(new_dir.clone());
+            (Some(old_dir), new_dir)
+        }),
+        None => {
+            let old_dir = getcwd().ok();
+            chdir(dir.as_str()).map_err(|e| e.to_string()).map(|()| {
+                let new_dir = getcwd().unwrap_or_else(|_| PathBuf::from(&dir));
+                (old_dir, new_dir)
+            })
+        }
+    };
+    match changed {
+        Ok((old_dir, new_dir)) => {
+            let new_dir = new_dir.to_string_lossy().into_owned();
+            if let Some(old_dir) = old_dir {
+                export_variable(shell_stack, "OLDPWD", &old_dir.to_string_lossy());
+            }
+            export_variable(shell_stack, "PWD", &new_dir);
+            if print_dir {
+                let _ = write!(stdout, "{}\n", new_dir);
+            }
+            0
+        }
         Err(e) => {
             let _ = write!(stderr, "BiTE: cd can't change to »{}«: {}\n", dir, e);
             5
@@ -56,14 +93,41 @@ fn change_dir(mut dir: String, _stdout: &mut dyn Write, stderr: &mut dyn Write)
     }
 }
 
+/// Directory a subshell in base changes to
+fn subshell_dir(base: &Path, dir: &str) -> Result<PathBuf, String> {
+    let new_dir = std::fs::canonicalize(base.join(dir)).map_err(|e| e.to_string())?;
+    if new_dir.is_dir() {
+        Ok(new_dir)
+    } else {
+        Err(std::io::Error::from_raw_os_error(libc::ENOTDIR).to_string())
+    }
+}
+
+/// Set a variable and export it to the programs started by the shell
+fn export_variable(shell_stack: &mut ContextStack, name: &str, value: &str) {
+    if let Ok(variable) = shell_stack.bind_variable(name, value) {
+        variable.set_exported(true);
+    }
+}
+
 /// Run function for the *change directory* builtin.
 ///
-/// cd [dir]
-pub fn run(words: Vec<String>, stdout: &mut dyn Write, stderr: &mut dyn Write) -> ExitStatus {
+/// cd [dir | -]
+pub fn run(
+    words: Vec<String>,
+    shell_stack: &mut ContextStack,
+    services: &ShellServices,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> ExitStatus {
     trace!("builtin change_dir begins");
     let mut dir = String::new();
 
-    let parse_res = {
+    let parse_res = if words.len() == 2 && words[1] == "-" {
+        // Don't let the argument parser take it for an option
+        dir = words[1].clone();
+        Ok(())
+    } else {
         let mut ap = ArgumentParser::new();
         ap.set_description("Change directory");
         ap.refer(&mut dir)
@@ -73,10 +137,149 @@ pub fn run(words: Vec<String>, stdout: &mut dyn Write, stderr: &mut dyn Write) -
     };
     trace!("builtin change_dir processed command line: dir={}", dir);
     let ret_code = match parse_res {
-        Ok(()) => change_dir(dir, stdout, stderr),
+        Ok(()) => change_dir(dir, shell_stack, stdout, stderr),
         Err(ret_code) => ret_code,
     };
+    if ret_code == 0 && shell_stack.working_dir().is_none() {
+        // Show the new directory in the prompt
+        services.session.clone().mark_cwd_changed();
+    }
 
     trace!("builtin change_dir completed");
     ExitStatusExt::from_raw(ret_code)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::session::test::new_test_session;
+
+    /// Run the builtin with the given arguments, return the exit code, stdout and stderr
+    fn cd(
+        services: &ShellServices,
+        shell_stack: &mut ContextStack,
+        args: &[&str],
+    ) -> (i32, String, String) {
+        let mut words = vec!["cd".to_string()];
+        words.extend(args.iter().map(|a| a.to_string()));
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let es = run(words, shell_stack, services, &mut stdout, &mut stderr);
+        (
+            es.into_raw(),
+            String::from_utf8(stdout).unwrap(),
+            String::from_utf8(stderr).unwrap(),
+        )
+    }
+
+    /// Value of a variable, None if it isn't set
+    fn value(shell_stack: &ContextStack, name: &str) -> Option<String> {
+        shell_stack
+            .find_variable(name)
+            .map(|v| v.as_string().clone())
+    }
+
+    // The tests stay in the current directory to not disturb the other tests, but spell it
+    // differently.
+
+    #[test]
+    fn cd_marks_prompt_for_refresh() {
+        let services = ShellServices::new(new_test_session(b"test"));
+        let mut session = services.session.clone();
+        let mut shell_stack = ContextStack::new();
+        assert!(!session.check_cwd_changed());
+
+        assert_eq!(cd(&services, &mut shell_stack, &["."]).0, 0);
+        assert!(session.check_cwd_changed());
+        assert!(!session.check_cwd_changed());
+
+        assert_eq!(
+            cd(&services, &mut shell_stack, &["/nonexistent/directory"]).0,
+            5
+        );
+        assert!(!session.check_cwd_changed());
+    }
+
+    #[test]
+    fn cd_to_home() {
+        let services = ShellServices::new(new_test_session(b"test"));
+        let cwd = getcwd().unwrap().to_string_lossy().into_owned();
+        let mut shell_stack = ContextStack::new();
+
+        let (es, _, stderr) = cd(&services, &mut shell_stack, &[]);
+        assert_eq!(es, 3);
+        assert_eq!(stderr, "BiTE: cd: HOME not set\n");
+
+        let _ = shell_stack.bind_variable("HOME", &format!("{}/.", cwd));
+        assert_eq!(
+            cd(&services, &mut shell_stack, &[]),
+            (0, String::new(), String::new())
+        );
+        assert_eq!(value(&shell_stack, "PWD"), Some(cwd.clone()));
+        assert_eq!(value(&shell_stack, "OLDPWD"), Some(cwd.clone()));
+        assert!(shell_stack.find_variable("PWD").unwrap().is_exported());
+    }
+
+    #[test]
+    fn cd_back_and_forth() {
+        let services = ShellServices::new(new_test_session(b"test"));
+        let cwd = getcwd().unwrap().to_string_lossy().into_owned();
+        let mut shell_stack = ContextStack::new();
+
+        let (es, _, stderr) = cd(&services, &mut shell_stack, &["-"]);
+        assert_eq!(es, 1);
+        assert_eq!(stderr, "BiTE: cd: OLDPWD not set\n");
+
+        let _ = shell_stack.bind_variable("OLDPWD", &format!("{}/.", cwd));
+        for _ in 0..2 {
+            assert_eq!(
+                cd(&services, &mut shell_stack, &["-"]),
+                (0, format!("{}\n", cwd), String::new())
+            );
+            assert_eq!(value(&shell_stack, "PWD"), Some(cwd.clone()));
+            assert_eq!(value(&shell_stack, "OLDPWD"), Some(cwd.clone()));
+        }
+    }
+
+    #[test]
+    fn cd_in_subshell() {
+        let services = ShellServices::new(new_test_session(b"test"));
+        let mut session = services.session.clone();
+        let cwd = getcwd().unwrap();
+        let mut shell_stack = ContextStack::new();
+        shell_stack.push_subshell();
+
+        assert_eq!(cd(&services, &mut shell_stack, &["/"]).0, 0);
+        assert_eq!(shell_stack.working_dir(), Some(&PathBuf::from("/")));
+        assert_eq!(value(&shell_stack, "PWD"), Some("/".to_string()));
+        assert_eq!(
+            value(&shell_stack, "OLDPWD"),
+            Some(cwd.to_string_lossy().into_owned())
+        );
+        // Neither the process nor the prompt change
+        assert_eq!(getcwd().unwrap(), cwd);
+        assert!(!session.check_cwd_changed());
+
+        let (es, _, stderr) = cd(&services, &mut shell_stack, &["/nonexistent/directory"]);
+        assert_eq!(es, 5);
+        assert!(stderr.starts_with("BiTE: cd can't change to »/nonexistent/directory«"));
+        assert_eq!(shell_stack.working_dir(), Some(&PathBuf::from("/")));
+
+        shell_stack.pop_subshell();
+        assert_eq!(shell_stack.working_dir(), None);
+        assert_eq!(value(&shell_stack, "PWD"), None);
+    }
+
+    #[test]
+    fn cd_to_missing_directory() {
+        let services = ShellServices::new(new_test_session(b"test"));
+        let mut shell_stack = ContextStack::new();
+
+        let (es, stdout, stderr) = cd(&services, &mut shell_stack, &["/nonexistent/directory"]);
+        assert_eq!(es, 5);
+        assert_eq!(stdout, "");
+        assert!(stderr.starts_with("BiTE: cd can't change to »/nonexistent/directory«"));
+        assert_eq!(value(&shell_stack, "PWD"), None);
+        assert_eq!(value(&shell_stack, "OLDPWD"), None);
+    }
+}
@@ -0,0 +1,59 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2018  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Clear screen builtin
+//!
+//! Nothing is deleted. The session view is scrolled so that the current prompt is at the top.
+
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use argparse::ArgumentParser;
+
+use super::super::variables::ContextStack;
+use super::ShellServices;
+
+/// Run function for the *clear* builtin.
+///
+/// clear
+pub fn run(
+    words: Vec<String>,
+    _shell_stack: &mut ContextStack,
+    services: &ShellServices,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> ExitStatus {
+    trace!("builtin clear begins");
+
+    let parse_res = {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Clear the screen");
+        ap.parse(words, stdout, stderr)
+    };
+    let ret_code = match parse_res {
+        Ok(()) => {
+            services.session.clone().clear_view();
+            0
+        }
+        Err(ret_code) => ret_code,
+    };
+
+    trace!("builtin clear completed");
+    ExitStatusExt::from_raw(ret_code)
+}
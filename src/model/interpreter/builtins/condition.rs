@@ -0,0 +1,322 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2018  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Builtins *test* and *[* to evaluate conditional expressions
+
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
+
+use nix::unistd::{access, AccessFlags};
+
+use super::super::variables::ContextStack;
+use super::ShellServices;
+
+/// Run function for the *test* and *[* builtins.
+///
+/// test expression
+/// [ expression ]
+///
+/// The exit code is 0 if the expression is true, 1 if it is false and 2 if it is malformed.
+pub fn run(
+    words: Vec<String>,
+    _shell_stack: &mut ContextStack,
+    _services: &ShellServices,
+    _stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> ExitStatus {
+    trace!("builtin test begins");
+
+    let ret_code = match evaluate(&words) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(msg) => {
+            let _ = writeln!(stderr, "{}: {}", words[0], msg);
+            2
+        }
+    };
+
+    trace!("builtin test completed");
+    ExitStatusExt::from_raw(ret_code)
+}
+
+/// Evaluate the words of the command line, including the name of the builtin.
+fn evaluate(words: &[String]) -> Result<bool, String> {
+    let mut args: Vec<&str> = words.iter().skip(1).map(String::as_str).collect();
+    if words[0] == "[" {
+        if args.last() != Some(&"]") {
+            return Err("missing `]'".to_string());
+        }
+        args.pop();
+    }
+    if args.is_empty() {
+        return Ok(false);
+    }
+
+    let mut expression = Expression {
+        args: &args,
+        pos: 0,
+    };
+    let value = expression.or()?;
+    match expression.peek(0) {
+        None => Ok(value),
+        Some(arg) => Err(format!("{}: unexpected argument", arg)),
+    }
+}
+
+/// Recursive descent parser that evaluates the expression while parsing it.
+///
+/// Precedence from low to high: `-o`, `-a`, `!`, primaries.
+struct Expression<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> Expression<'a> {
+    /// Argument at an offset from the current position
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.pos + offset).cloned()
+    }
+
+    /// Consume the current argument
+    fn next(&mut self) -> Option<&'a str> {
+        let arg = self.peek(0);
+        if arg.is_some() {
+            self.pos += 1;
+        }
+        arg
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut value = self.and()?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            let rhs = self.and()?;
+            value = value || rhs;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut value = self.not()?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            let rhs = self.not()?;
+            value = value && rhs;
+        }
+        Ok(value)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        // A lone ! is a non-empty string
+        if self.peek(0) == Some("!") && self.peek(1).is_some() {
+            self.pos += 1;
+            Ok(!self.not()?)
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        let arg = self.next().ok_or_else(|| "argument expected".to_string())?;
+
+        // Binary operators take precedence, e.g. in [ -n = -n ]
+        if let (Some(op), Some(rhs)) = (self.peek(0), self.peek(1)) {
+            if is_binary(op) {
+                self.pos += 2;
+                return binary(arg, op, rhs);
+            }
+        }
+
+        if arg == "(" {
+            let value = self.or()?;
+            return match self.next() {
+                Some(")") => Ok(value),
+                _ => Err("missing `)'".to_string()),
+            };
+        }
+
+        if is_unary(arg) {
+            if let Some(operand) = self.next() {
+                return Ok(unary(arg, operand));
+            }
+        }
+
+        Ok(!arg.is_empty())
+    }
+}
+
+fn is_unary(op: &str) -> bool {
+    match op {
+        "-z" | "-n" | "-e" | "-f" | "-d" | "-r" | "-w" | "-x" => true,
+        _ => false,
+    }
+}
+
+fn is_binary(op: &str) -> bool {
+    match op {
+        "=" | "==" | "!=" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => true,
+        _ => false,
+    }
+}
+
+/// Evaluate a string or file test
+fn unary(op: &str, operand: &str) -> bool {
+    let path = Path::new(operand);
+    match op {
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        "-e" => path.exists(),
+        "-f" => path.is_file(),
+        "-d" => path.is_dir(),
+        "-r" => access(path, AccessFlags::R_OK).is_ok(),
+        "-w" => access(path, AccessFlags::W_OK).is_ok(),
+        "-x" => access(path, AccessFlags::X_OK).is_ok(),
+        _ => false,
+    }
+}
+
+/// Evaluate a string comparison or an integer comparison
+fn binary(lhs: &str, op: &str, rhs: &str) -> Result<bool, String> {
+    match op {
+        "=" | "==" => Ok(lhs == rhs),
+        "!=" => Ok(lhs != rhs),
+        _ => {
+            let lhs = integer(lhs)?;
+            let rhs = integer(rhs)?;
+            Ok(match op {
+                "-eq" => lhs == rhs,
+                "-ne" => lhs != rhs,
+                "-lt" => lhs < rhs,
+                "-le" => lhs <= rhs,
+                "-gt" => lhs > rhs,
+                _ => lhs >= rhs,
+            })
+        }
+    }
+}
+
+fn integer(arg: &str) -> Result<i64, String> {
+    arg.trim()
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::session::test::new_test_session;
+
+    /// Run the builtin with the given words, return the exit code and stderr
+    fn test(words: &[&str]) -> (i32, String) {
+        let services = ShellServices::new(new_test_session(b"test"));
+        let mut shell_stack = ContextStack::new();
+        let words = words.iter().map(|w| w.to_string()).collect();
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let es = run(words, &mut shell_stack, &services, &mut stdout, &mut stderr);
+        (es.into_raw(), String::from_utf8(stderr).unwrap())
+    }
+
+    /// Exit code of the bracket form
+    fn bracket(args: &[&str]) -> i32 {
+        let mut words = vec!["["];
+        words.extend_from_slice(args);
+        words.push("]");
+        test(&words).0
+    }
+
+    #[test]
+    fn file_tests() {
+        // Tests run in the crate directory
+        assert_eq!(bracket(&["-e", "Cargo.toml"]), 0);
+        assert_eq!(bracket(&["-e", "no-such-file"]), 1);
+        assert_eq!(bracket(&["-f", "Cargo.toml"]), 0);
+        assert_eq!(bracket(&["-f", "src"]), 1);
+        assert_eq!(bracket(&["-d", "src"]), 0);
+        assert_eq!(bracket(&["-d", "Cargo.toml"]), 1);
+        assert_eq!(bracket(&["-r", "Cargo.toml"]), 0);
+        assert_eq!(bracket(&["-r", "no-such-file"]), 1);
+        assert_eq!(bracket(&["-w", "no-such-file"]), 1);
+        assert_eq!(bracket(&["-x", "/bin/sh"]), 0);
+        assert_eq!(bracket(&["-x", "no-such-file"]), 1);
+    }
+
+    #[test]
+    fn string_tests() {
+        assert_eq!(bracket(&["-z", ""]), 0);
+        assert_eq!(bracket(&["-z", "a"]), 1);
+        assert_eq!(bracket(&["-n", "a"]), 0);
+        assert_eq!(bracket(&["-n", ""]), 1);
+        assert_eq!(bracket(&["a", "=", "a"]), 0);
+        assert_eq!(bracket(&["a", "=", "b"]), 1);
+        assert_eq!(bracket(&["a", "!=", "b"]), 0);
+        assert_eq!(bracket(&["a", "==", "b"]), 1);
+        assert_eq!(bracket(&["abc"]), 0);
+        assert_eq!(bracket(&[""]), 1);
+        assert_eq!(bracket(&[]), 1);
+        // Operators as operands
+        assert_eq!(bracket(&["-n"]), 0);
+        assert_eq!(bracket(&["-n", "=", "-n"]), 0);
+    }
+
+    #[test]
+    fn numeric_tests() {
+        assert_eq!(bracket(&["3", "-eq", "3"]), 0);
+        assert_eq!(bracket(&["3", "-ne", "3"]), 1);
+        assert_eq!(bracket(&["2", "-lt", "10"]), 0);
+        assert_eq!(bracket(&["10", "-le", "2"]), 1);
+        assert_eq!(bracket(&["-1", "-gt", "-2"]), 0);
+        assert_eq!(bracket(&["3", "-ge", "4"]), 1);
+        assert_eq!(
+            test(&["test", "a", "-eq", "1"]),
+            (2, "test: a: integer expression expected\n".to_string())
+        );
+    }
+
+    #[test]
+    fn negation_and_grouping() {
+        assert_eq!(bracket(&["!", "-e", "no-such-file"]), 0);
+        assert_eq!(bracket(&["!", "a", "=", "a"]), 1);
+        assert_eq!(bracket(&["!", "!", "abc"]), 0);
+        assert_eq!(bracket(&["!"]), 0);
+        assert_eq!(bracket(&["a", "-a", ""]), 1);
+        assert_eq!(bracket(&["a", "-o", ""]), 0);
+        assert_eq!(bracket(&["!", "(", "a", "-o", "", ")"]), 1);
+        assert_eq!(bracket(&["", "-o", "a", "-a", "b"]), 0);
+    }
+
+    #[test]
+    fn test_form_and_errors() {
+        assert_eq!(test(&["test", "1", "-lt", "2"]).0, 0);
+        assert_eq!(test(&["test", "-d", "src"]).0, 0);
+        assert_eq!(
+            test(&["[", "-d", "src"]),
+            (2, "[: missing `]'\n".to_string())
+        );
+        assert_eq!(
+            test(&["[", "(", "a", "]"]),
+            (2, "[: missing `)'\n".to_string())
+        );
+        assert_eq!(
+            test(&["test", "a", "b"]),
+            (2, "test: b: unexpected argument\n".to_string())
+        );
+    }
+}
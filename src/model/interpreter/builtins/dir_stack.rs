@@ -0,0 +1,448 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2018  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Directory stack builtins: pushd, popd and dirs
+//!
+//! Like bash, the saved directories are stored in DIRSTACK. As there are no arrays, the entries
+//! are separated by newlines. The current directory is not stored, but shown as the first entry
+//! of the stack.
+
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use argparse::{ArgumentParser, StoreTrue};
+use nix::unistd::getcwd;
+
+use super::super::variables::ContextStack;
+use super::change_dir::change_dir;
+use super::ShellServices;
+
+/// Variable that holds the saved directories
+const DIRSTACK: &str = "DIRSTACK";
+
+/// Saved directories, most recent first
+fn saved_dirs(shell_stack: &ContextStack) -> Vec<String> {
+    shell_stack
+        .variable_as_str(DIRSTACK)
+        .map(|dirs| dirs.lines().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+/// Replace the saved directories
+fn set_saved_dirs(shell_stack: &mut ContextStack, dirs: &[String]) {
+    if let Err(e) = shell_stack.bind_variable(DIRSTACK, &dirs.join("\n")) {
+        warn!("directory stack could not set {}: {:?}", DIRSTACK, e);
+    }
+}
+
+/// The current directory followed by the saved ones, as printed by dirs
+fn dir_list(shell_stack: &ContextStack) -> Vec<String> {
+    let cwd = getcwd().map_or(String::from("."), |d| d.to_string_lossy().into_owned());
+    std::iter::once(cwd)
+        .chain(saved_dirs(shell_stack).into_iter())
+        .collect()
+}
+
+/// Replace the home directory at the start of a path by ~
+fn abbreviate(shell_stack: &ContextStack, dir: &str) -> String {
+    if let Some(home) = shell_stack.find_variable("HOME") {
+        let home = home.as_str();
+        if !home.is_empty() && dir.starts_with(home) {
+            let rest = &dir[home.len()..];
+            if rest.is_empty() || rest.starts_with('/') {
+                return format!("~{}", rest);
+            }
+        }
+    }
+    dir.to_string()
+}
+
+/// Print the directory stack in one line
+fn print_dirs(shell_stack: &ContextStack, long: bool, stdout: &mut dyn Write) {
+    let dirs: Vec<String> = dir_list(shell_stack)
+        .iter()
+        .map(|d| {
+            if long {
+                d.clone()
+            } else {
+                abbreviate(shell_stack, d)
+            }
+        })
+        .collect();
+    let _ = writeln!(stdout, "{}", dirs.join(" "));
+}
+
+/// Check if the argument is a stack index, i.e. +N or -N
+fn is_index(arg: &str) -> bool {
+    (arg.starts_with('+') || arg.starts_with('-')) && arg.len() > 1
+}
+
+/// Position in the directory list for +N or -N.
+///
+/// +N counts from the left of the list as printed by dirs, -N from the right, both starting at
+/// zero. None if the argument is invalid or out of range.
+fn stack_index(arg: &str, len: usize) -> Option<usize> {
+    if !is_index(arg) {
+        return None;
+    }
+    let n = arg[1..].parse::<usize>().ok()?;
+    if n >= len {
+        None
+    } else if arg.starts_with('+') {
+        Some(n)
+    } else {
+        Some(len - 1 - n)
+    }
+}
+
+/// Change into the first directory of the list and save the others.
+fn enter_list(
+    shell_stack: &mut ContextStack,
+    list: &[String],
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> i32 {
+    let ret_code = change_dir(list[0].clone(), shell_stack, stdout, stderr);
+    if ret_code == 0 {
+        set_saved_dirs(shell_stack, &list[1..]);
+    }
+    ret_code
+}
+
+/// Run function for the *pushd* builtin.
+///
+/// pushd [dir | +N | -N]
+///
+/// Without arguments, the top two directories are exchanged. +N and -N rotate the stack so that
+/// the given entry comes to the top.
+pub fn pushd(
+    words: Vec<String>,
+    shell_stack: &mut ContextStack,
+    services: &ShellServices,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> ExitStatus {
+    trace!("builtin pushd begins");
+    // Not using the argument parser, as it takes -N for an option
+    let mut list = dir_list(shell_stack);
+    let ret_code = match words.len() {
+        1 if list.len() < 2 => {
+            let _ = writeln!(stderr, "BiTE: pushd: no other directory");
+            1
+        }
+        1 => {
+            list.swap(0, 1);
+            enter_list(shell_stack, &list, stdout, stderr)
+        }
+        2 if is_index(&words[1]) => match stack_index(&words[1], list.len()) {
+            Some(n) => {
+                list.rotate_left(n);
+                enter_list(shell_stack, &list, stdout, stderr)
+            }
+            None => {
+                let _ = writeln!(
+                    stderr,
+                    "BiTE: pushd: {}: directory stack index out of range",
+                    words[1]
+                );
+                1
+            }
+        },
+        2 => {
+            let ret_code = change_dir(words[1].clone(), shell_stack, stdout, stderr);
+            if ret_code == 0 {
+                set_saved_dirs(shell_stack, &list);
+            }
+            ret_code
+        }
+        _ => {
+            let _ = writeln!(stderr, "BiTE: pushd: too many arguments");
+            1
+        }
+    };
+    if ret_code == 0 {
+        services.session.clone().mark_cwd_changed();
+        print_dirs(shell_stack, false, stdout);
+    }
+
+    trace!("builtin pushd completed");
+    ExitStatusExt::from_raw(ret_code)
+}
+
+/// Run function for the *popd* builtin.
+///
+/// popd [+N | -N]
+///
+/// Without arguments, the top directory is removed and the next one entered. +N and -N remove the
+/// given entry.
+pub fn popd(
+    words: Vec<String>,
+    shell_stack: &mut ContextStack,
+    services: &ShellServices,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> ExitStatus {
+    trace!("builtin popd begins");
+    // Not using the argument parser, as it takes -N for an option
+    let mut list = dir_list(shell_stack);
+    let index = match words.len() {
+        1 => Some(0),
+        2 => stack_index(&words[1], list.len()),
+        _ => None,
+    };
+    let ret_code = match index {
+        _ if list.len() < 2 => {
+            let _ = writeln!(stderr, "BiTE: popd: directory stack empty");
+            1
+        }
+        Some(0) => {
+            list.remove(0);
+            enter_list(shell_stack, &list, stdout, stderr)
+        }
+        Some(n) => {
+            list.remove(n);
+            set_saved_dirs(shell_stack, &list[1..]);
+            0
+        }
+        None if words.len() == 2 && is_index(&words[1]) => {
+            let _ = writeln!(
+                stderr,
+                "BiTE: popd: {}: directory stack index out of range",
+                words[1]
+            );
+            1
+        }
+        None => {
+            let _ = writeln!(stderr, "BiTE: popd: invalid arguments");
+            1
+        }
+    };
+    if ret_code == 0 {
+        services.session.clone().mark_cwd_changed();
+        print_dirs(shell_stack, false, stdout);
+    }
+
+    trace!("builtin popd completed");
+    ExitStatusExt::from_raw(ret_code)
+}
+
+/// Run function for the *dirs* builtin.
+///
+/// dirs [-c] [-l]
+pub fn dirs(
+    words: Vec<String>,
+    shell_stack: &mut ContextStack,
+    _services: &ShellServices,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> ExitStatus {
+    trace!("builtin dirs begins");
+    let mut clear = false;
+    let mut long = false;
+
+    let parse_res = {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Display the directory stack");
+        ap.refer(&mut clear)
+            .add_option(&["-c"], StoreTrue, "Clear the directory stack");
+        ap.refer(&mut long)
+            .add_option(&["-l"], StoreTrue, "Don't abbreviate the home directory");
+
+        ap.parse(words, stdout, stderr)
+    };
+    let ret_code = match parse_res {
+        Err(ret_code) => ret_code,
+        Ok(()) => {
+            if clear {
+                set_saved_dirs(shell_stack, &[]);
+            } else {
+                print_dirs(shell_stack, long, stdout);
+            }
+            0
+        }
+    };
+
+    trace!("builtin dirs completed");
+    ExitStatusExt::from_raw(ret_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::session::test::new_test_session;
+
+    /// Run a builtin with the given arguments, return the exit code, stdout and stderr
+    fn builtin(
+        run: super::super::BuiltinRunner,
+        shell_stack: &mut ContextStack,
+        words: &[&str],
+    ) -> (i32, String, String) {
+        let services = ShellServices::new(new_test_session(b"test"));
+        let words = words.iter().map(|w| w.to_string()).collect();
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let es = run(words, shell_stack, &services, &mut stdout, &mut stderr);
+        (
+            es.into_raw(),
+            String::from_utf8(stdout).unwrap(),
+            String::from_utf8(stderr).unwrap(),
+        )
+    }
+
+    fn ok(stdout: String) -> (i32, String, String) {
+        (0, stdout, String::new())
+    }
+
+    // The tests stay in the current directory to not disturb the other tests. Different
+    // spellings of it tell the entries apart.
+
+    #[test]
+    fn push_and_pop() {
+        let cwd = getcwd().unwrap().to_string_lossy().into_owned();
+        let a = format!("{}/.", cwd);
+        let b = format!("{}/./.", cwd);
+        let mut shell_stack = ContextStack::new();
+
+        assert_eq!(
+            builtin(dirs, &mut shell_stack, &["dirs"]),
+            ok(format!("{}\n", cwd))
+        );
+        assert_eq!(
+            builtin(popd, &mut shell_stack, &["popd"]),
+            (
+                1,
+                String::new(),
+                "BiTE: popd: directory stack empty\n".to_string()
+            )
+        );
+
+        assert_eq!(
+            builtin(pushd, &mut shell_stack, &["pushd", &a]),
+            ok(format!("{} {}\n", cwd, cwd))
+        );
+        assert_eq!(saved_dirs(&shell_stack), vec![cwd.clone()]);
+
+        set_saved_dirs(&mut shell_stack, &[a.clone(), b.clone()]);
+        assert_eq!(
+            builtin(popd, &mut shell_stack, &["popd"]),
+            ok(format!("{} {}\n", cwd, b))
+        );
+        assert_eq!(saved_dirs(&shell_stack), vec![b.clone()]);
+
+        // Remove an entry without changing the directory
+        set_saved_dirs(&mut shell_stack, &[a.clone(), b.clone()]);
+        assert_eq!(
+            builtin(popd, &mut shell_stack, &["popd", "+1"]),
+            ok(format!("{} {}\n", cwd, b))
+        );
+        set_saved_dirs(&mut shell_stack, &[a.clone(), b.clone()]);
+        assert_eq!(
+            builtin(popd, &mut shell_stack, &["popd", "-0"]),
+            ok(format!("{} {}\n", cwd, a))
+        );
+        assert_eq!(
+            builtin(popd, &mut shell_stack, &["popd", "+2"]),
+            (
+                1,
+                String::new(),
+                "BiTE: popd: +2: directory stack index out of range\n".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn push_rotates() {
+        let cwd = getcwd().unwrap().to_string_lossy().into_owned();
+        let a = format!("{}/.", cwd);
+        let b = format!("{}/./.", cwd);
+        let mut shell_stack = ContextStack::new();
+
+        assert_eq!(
+            builtin(pushd, &mut shell_stack, &["pushd"]),
+            (
+                1,
+                String::new(),
+                "BiTE: pushd: no other directory\n".to_string()
+            )
+        );
+
+        // Exchange the top two
+        set_saved_dirs(&mut shell_stack, &[a.clone(), b.clone()]);
+        assert_eq!(
+            builtin(pushd, &mut shell_stack, &["pushd"]),
+            ok(format!("{} {} {}\n", cwd, cwd, b))
+        );
+
+        // Bring the given entry to the top
+        set_saved_dirs(&mut shell_stack, &[a.clone(), b.clone()]);
+        assert_eq!(
+            builtin(pushd, &mut shell_stack, &["pushd", "+1"]),
+            ok(format!("{} {} {}\n", cwd, b, cwd))
+        );
+        set_saved_dirs(&mut shell_stack, &[a.clone(), b.clone()]);
+        assert_eq!(
+            builtin(pushd, &mut shell_stack, &["pushd", "-0"]),
+            ok(format!("{} {} {}\n", cwd, cwd, a))
+        );
+
+        assert_eq!(
+            builtin(pushd, &mut shell_stack, &["pushd", "+3"]),
+            (
+                1,
+                String::new(),
+                "BiTE: pushd: +3: directory stack index out of range\n".to_string()
+            )
+        );
+        assert_eq!(
+            builtin(
+                pushd,
+                &mut shell_stack,
+                &["pushd", "/nonexistent/directory"]
+            )
+            .0,
+            5
+        );
+        assert_eq!(saved_dirs(&shell_stack), vec![cwd.clone(), a.clone()]);
+    }
+
+    #[test]
+    fn print_stack() {
+        let cwd = getcwd().unwrap().to_string_lossy().into_owned();
+        let mut shell_stack = ContextStack::new();
+        let _ = shell_stack.bind_variable("HOME", &cwd);
+        set_saved_dirs(
+            &mut shell_stack,
+            &[format!("{}/.", cwd), format!("{}x", cwd), "/".to_string()],
+        );
+
+        assert_eq!(
+            builtin(dirs, &mut shell_stack, &["dirs"]),
+            ok(format!("~ ~/. {}x /\n", cwd))
+        );
+        assert_eq!(
+            builtin(dirs, &mut shell_stack, &["dirs", "-l"]),
+            ok(format!("{} {}/. {}x /\n", cwd, cwd, cwd))
+        );
+        assert_eq!(
+            builtin(dirs, &mut shell_stack, &["dirs", "-c"]),
+            ok(String::new())
+        );
+        assert_eq!(saved_dirs(&shell_stack), Vec::<String>::new());
+    }
+}
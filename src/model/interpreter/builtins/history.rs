@@ -0,0 +1,154 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2018  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! History builtin
+
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use argparse::{ArgumentParser, StoreOption, StoreTrue};
+
+use super::super::variables::ContextStack;
+use super::ShellServices;
+
+/// List of entered commands that the history builtin works on.
+///
+/// The history is kept by the presenter, which shares it with the interpreter.
+pub trait CommandLog: Send + Sync {
+    /// All commands, oldest first
+    fn commands(&self) -> Vec<String>;
+
+    /// Forget all commands
+    fn clear(&self);
+}
+
+/// Run function for the *history* builtin.
+///
+/// history [-c] [n]
+pub fn run(
+    words: Vec<String>,
+    _shell_stack: &mut ContextStack,
+    services: &ShellServices,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> ExitStatus {
+    trace!("builtin history begins");
+    let mut clear = false;
+    let mut count: Option<usize> = None;
+
+    let parse_res = {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Display or clear the command history");
+        ap.refer(&mut clear)
+            .add_option(&["-c"], StoreTrue, "Clear the history");
+        ap.refer(&mut count)
+            .add_argument("n", StoreOption, "Only show the last n commands");
+
+        ap.parse(words, stdout, stderr)
+    };
+    let ret_code = match (parse_res, &services.history) {
+        (Err(ret_code), _) => ret_code,
+        (Ok(()), None) => {
+            let _ = writeln!(stderr, "BiTE: history: no history available");
+            1
+        }
+        (Ok(()), Some(history)) => {
+            if clear {
+                history.clear();
+            } else {
+                let commands = history.commands();
+                let skip = count.map_or(0, |n| commands.len().saturating_sub(n));
+                for (index, command) in commands.iter().enumerate().skip(skip) {
+                    let _ = writeln!(stdout, "{:5}  {}", index + 1, command);
+                }
+            }
+            0
+        }
+    };
+
+    trace!("builtin history completed");
+    ExitStatusExt::from_raw(ret_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::session::test::new_test_session;
+    use std::sync::{Arc, Mutex};
+
+    impl CommandLog for Mutex<Vec<String>> {
+        fn commands(&self) -> Vec<String> {
+            self.lock().unwrap().clone()
+        }
+
+        fn clear(&self) {
+            self.lock().unwrap().clear();
+        }
+    }
+
+    /// Run the builtin on the given log, return the exit code and stdout
+    fn history(log: &Arc<Mutex<Vec<String>>>, args: &[&str]) -> (i32, String) {
+        let mut services = ShellServices::new(new_test_session(b"test"));
+        services.history = Some(log.clone());
+        let mut words = vec!["history".to_string()];
+        words.extend(args.iter().map(|a| a.to_string()));
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let es = run(
+            words,
+            &mut ContextStack::new(),
+            &services,
+            &mut stdout,
+            &mut stderr,
+        );
+        (es.into_raw(), String::from_utf8(stdout).unwrap())
+    }
+
+    #[test]
+    fn list_and_clear() {
+        let log = Arc::new(Mutex::new(vec![
+            "ls".to_string(),
+            "cd /tmp".to_string(),
+            "echo done".to_string(),
+        ]));
+
+        assert_eq!(
+            history(&log, &[]),
+            (
+                0,
+                "    1  ls\n    2  cd /tmp\n    3  echo done\n".to_string()
+            )
+        );
+        assert_eq!(
+            history(&log, &["2"]),
+            (0, "    2  cd /tmp\n    3  echo done\n".to_string())
+        );
+        assert_eq!(
+            history(&log, &["5"]),
+            (
+                0,
+                "    1  ls\n    2  cd /tmp\n    3  echo done\n".to_string()
+            )
+        );
+
+        assert_eq!(history(&log, &["-c"]), (0, String::new()));
+        assert!(log.lock().unwrap().is_empty());
+        assert_eq!(history(&log, &[]), (0, String::new()));
+    }
+}
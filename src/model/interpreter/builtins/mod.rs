@@ -19,17 +19,68 @@
 //! Builtin commands
 
 pub mod change_dir;
+pub mod clear;
+pub mod condition;
+pub mod dir_stack;
+pub mod history;
+pub mod shopt;
+pub mod status;
+pub mod transcript;
 
 use std::io::Write;
 use std::process::ExitStatus;
+use std::sync::Arc;
 
-pub type BuiltinRunner =
-    fn(words: Vec<String>, stdout: &mut dyn Write, stderr: &mut dyn Write) -> ExitStatus;
+use self::history::CommandLog;
+use super::super::session::SharedSession;
+use super::variables::ContextStack;
+
+/// Parts of the application that builtins can access besides the shell state.
+#[derive(Clone)]
+pub struct ShellServices {
+    /// Session the shell writes to
+    pub session: SharedSession,
+
+    /// History of the entered commands, if the application keeps one
+    pub history: Option<Arc<dyn CommandLog>>,
+}
+
+impl ShellServices {
+    pub fn new(session: SharedSession) -> Self {
+        Self {
+            session,
+            history: None,
+        }
+    }
+}
+
+/// Function to run a builtin.
+///
+/// Builtins can change the state of the shell they run in.
+pub type BuiltinRunner = fn(
+    words: Vec<String>,
+    shell_stack: &mut ContextStack,
+    services: &ShellServices,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> ExitStatus;
 
 pub fn runner(cmd: &str) -> Option<BuiltinRunner> {
-    if cmd == "cd" {
-        Some(change_dir::run)
-    } else {
-        None
+    match cmd {
+        ":" => Some(status::success),
+        "[" => Some(condition::run),
+        "cd" => Some(change_dir::run),
+        "clear" => Some(clear::run),
+        "dirs" => Some(dir_stack::dirs),
+        "exit" => Some(status::exit),
+        "false" => Some(status::failure),
+        "history" => Some(history::run),
+        "popd" => Some(dir_stack::popd),
+        "pushd" => Some(dir_stack::pushd),
+        "shopt" => Some(shopt::run),
+        "test" => Some(condition::run),
+        "transcript" => Some(transcript::run),
+        "true" => Some(status::success),
+        _ => None,
     }
 }
@@ -0,0 +1,120 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2018  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Shell options builtin
+//!
+//! Like bash, the enabled options are stored as a colon-separated list in BASHOPTS.
+
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use argparse::{ArgumentParser, List, StoreTrue};
+
+use super::super::variables::{ContextStack, BASHOPTS};
+use super::ShellServices;
+
+/// Names of all options known to shopt
+pub const OPTIONS: [&str; 3] = ["dotglob", "failglob", "nullglob"];
+
+/// Enable or disable a number of options
+fn set_options(shell_stack: &mut ContextStack, names: &[String], on: bool) {
+    let enabled: Vec<&str> = OPTIONS
+        .iter()
+        .cloned()
+        .filter(|o| {
+            if names.iter().any(|n| n == o) {
+                on
+            } else {
+                shell_stack.is_option_set(o)
+            }
+        })
+        .collect();
+    if let Err(e) = shell_stack.bind_variable(BASHOPTS, &enabled.join(":")) {
+        warn!("shopt could not set {}: {:?}", BASHOPTS, e);
+    }
+}
+
+/// Run function for the *shopt* builtin.
+///
+/// shopt [-q] [-s|-u] [optname ...]
+pub fn run(
+    words: Vec<String>,
+    shell_stack: &mut ContextStack,
+    _services: &ShellServices,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> ExitStatus {
+    trace!("builtin shopt begins");
+    let mut set = false;
+    let mut unset = false;
+    let mut quiet = false;
+    let mut names: Vec<String> = Vec::new();
+
+    let parse_res = {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Set and unset shell options");
+        ap.refer(&mut set)
+            .add_option(&["-s"], StoreTrue, "Enable each option");
+        ap.refer(&mut unset)
+            .add_option(&["-u"], StoreTrue, "Disable each option");
+        ap.refer(&mut quiet)
+            .add_option(&["-q"], StoreTrue, "Suppress output");
+        ap.refer(&mut names)
+            .add_argument("optname", List, "Options to change or show");
+
+        ap.parse(words, stdout, stderr)
+    };
+    let ret_code = match parse_res {
+        Err(ret_code) => ret_code,
+        Ok(()) if set && unset => {
+            let _ = writeln!(stderr, "BiTE: shopt: cannot set and unset options at once");
+            1
+        }
+        Ok(()) => {
+            if let Some(name) = names.iter().find(|n| !OPTIONS.contains(&n.as_str())) {
+                let _ = writeln!(stderr, "BiTE: shopt: {}: invalid shell option name", name);
+                1
+            } else if set || unset {
+                set_options(shell_stack, &names, set);
+                0
+            } else {
+                // Show the options, all of them if none are given
+                if names.is_empty() {
+                    names = OPTIONS.iter().map(|o| o.to_string()).collect();
+                }
+                let mut all_set = true;
+                for name in names.iter() {
+                    let on = shell_stack.is_option_set(name);
+                    all_set &= on;
+                    if !quiet {
+                        let _ = writeln!(stdout, "{}\t{}", name, if on { "on" } else { "off" });
+                    }
+                }
+                if all_set {
+                    0
+                } else {
+                    1
+                }
+            }
+        }
+    };
+
+    trace!("builtin shopt completed");
+    ExitStatusExt::from_raw(ret_code)
+}
@@ -0,0 +1,85 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2018  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Builtins that only set the exit status: *true*, *false*, *:* and *exit*
+
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use super::super::variables::ContextStack;
+use super::ShellServices;
+
+/// Run function for the *true* and *:* builtins.
+///
+/// true [arguments]
+pub fn success(
+    _words: Vec<String>,
+    _shell_stack: &mut ContextStack,
+    _services: &ShellServices,
+    _stdout: &mut dyn Write,
+    _stderr: &mut dyn Write,
+) -> ExitStatus {
+    trace!("builtin true");
+    ExitStatusExt::from_raw(0)
+}
+
+/// Run function for the *false* builtin.
+///
+/// false [arguments]
+pub fn failure(
+    _words: Vec<String>,
+    _shell_stack: &mut ContextStack,
+    _services: &ShellServices,
+    _stdout: &mut dyn Write,
+    _stderr: &mut dyn Write,
+) -> ExitStatus {
+    trace!("builtin false");
+    ExitStatusExt::from_raw(1)
+}
+
+/// Run function for the *exit* builtin.
+///
+/// exit [n]
+///
+/// Without *n*, the shell exits with the status of the previous command. Like bash, only the
+/// lower eight bits of *n* are kept.
+pub fn exit(
+    words: Vec<String>,
+    shell_stack: &mut ContextStack,
+    _services: &ShellServices,
+    _stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> ExitStatus {
+    trace!("builtin exit begins");
+
+    let code = match words.get(1) {
+        None => None,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(n) => Some((n & 0xff) as i32),
+            Err(_) => {
+                let _ = writeln!(stderr, "BiTE: exit: {}: numeric argument required", arg);
+                Some(2)
+            }
+        },
+    };
+    shell_stack.request_exit(code);
+
+    trace!("builtin exit completed");
+    ExitStatusExt::from_raw(code.unwrap_or(0))
+}
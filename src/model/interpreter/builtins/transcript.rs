@@ -0,0 +1,143 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Transcript builtin
+//!
+//! The session is written by the GUI as only it knows how the lines are shown. The builtin creates
+//! the file, so that it can report if that fails, and asks the GUI to fill it.
+
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+use argparse::{ArgumentParser, Store, StoreTrue};
+
+use super::super::super::session::TranscriptFormat;
+use super::super::variables::ContextStack;
+use super::ShellServices;
+
+/// Run function for the *transcript* builtin.
+///
+/// transcript [--html] file
+pub fn run(
+    words: Vec<String>,
+    _shell_stack: &mut ContextStack,
+    services: &ShellServices,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> ExitStatus {
+    trace!("builtin transcript begins");
+    let mut html = false;
+    let mut file = String::new();
+
+    let parse_res = {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Write the session to a file");
+        ap.refer(&mut html).add_option(
+            &["--html"],
+            StoreTrue,
+            "Write an HTML page with colors instead of plain text",
+        );
+        ap.refer(&mut file)
+            .add_argument("file", Store, "File to write the session to")
+            .required();
+
+        ap.parse(words, stdout, stderr)
+    };
+    let ret_code = match parse_res {
+        Ok(()) => {
+            // The GUI writes the file later, when the directory might have changed.
+            let path = std::env::current_dir()
+                .map(|dir| dir.join(&file))
+                .unwrap_or_else(|_| PathBuf::from(&file));
+            let format = if html {
+                TranscriptFormat::Html
+            } else {
+                TranscriptFormat::Text
+            };
+            match std::fs::File::create(&path) {
+                Ok(_) => {
+                    services.session.clone().request_transcript(path, format);
+                    0
+                }
+                Err(e) => {
+                    let _ = write!(stderr, "BiTE: transcript can't write »{}«: {}\n", file, e);
+                    1
+                }
+            }
+        }
+        Err(ret_code) => ret_code,
+    };
+
+    trace!("builtin transcript completed");
+    ExitStatusExt::from_raw(ret_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::session::test::new_test_session;
+
+    #[test]
+    fn request_transcript() {
+        let mut session = new_test_session(b"test");
+        let services = ShellServices::new(session.clone());
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let path =
+            std::env::temp_dir().join(format!("bite-transcript-{}.html", std::process::id()));
+        let words = vec!["transcript", "--html", path.to_str().unwrap()]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let es = run(
+            words,
+            &mut ContextStack::new(),
+            &services,
+            &mut stdout,
+            &mut stderr,
+        );
+        assert_eq!(es.into_raw(), 0);
+        assert!(path.exists());
+        assert_eq!(
+            session.take_transcript_requests(),
+            vec![(path.clone(), TranscriptFormat::Html)]
+        );
+        assert!(session.take_transcript_requests().is_empty());
+        std::fs::remove_file(&path).unwrap();
+
+        // A file that can't be created fails right away
+        let words = vec!["transcript", "/nonexistent/x.html"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let es = run(
+            words,
+            &mut ContextStack::new(),
+            &services,
+            &mut stdout,
+            &mut stderr,
+        );
+        assert_eq!(es.into_raw(), 1);
+        assert!(String::from_utf8(stderr)
+            .unwrap()
+            .starts_with("BiTE: transcript can't write »/nonexistent/x.html«"));
+        assert!(session.take_transcript_requests().is_empty());
+    }
+}
@@ -22,13 +22,16 @@ use super::super::session::{
     InteractionHandle, OutputVisibility, RunningStatus, SharedSession, DEFAULT_TUI_HEIGHT,
     DEFAULT_TUI_WIDTH,
 };
+use super::builtins::ShellServices;
 use super::data_stack::Stack;
+use super::expansion::{self, ExpSpan, Operator};
 use super::jobs;
 use super::parser::{
     AbstractSyntaxTree, BackgroundMode, Command, LogicalOperator, Pipeline, PipelineCommand,
-    PipelineOperator,
+    PipelineOperator, Span,
 };
 use super::variables::ContextStack;
+use glob::Pattern;
 
 use std::sync::Arc;
 use std::thread::spawn;
@@ -60,6 +63,10 @@ pub type Instructions = Vec<Instruction>;
 ///
 /// ## Logical Processing
 ///
+/// The check is placed after a pipeline and skips only the following pipeline. If that is skipped,
+/// the exit status remains unchanged and the next check works on the same value. This makes the
+/// operators left-associative with equal precedence, as in bash.
+///
 /// Source:
 ///     ab cd && de ef
 /// Byte Code:
@@ -70,6 +77,46 @@ pub type Instructions = Vec<Instruction>;
 /// Byte Code:
 ///      Begin Lit("ab") Word Lit("cd") Word Exec Wait Success Not JumpIfNot(6) Lit("de") Word Lit("ef") Word Exec Wait
 ///
+/// ## Sequences
+///
+/// Source:
+///     ab ; cd
+/// Byte Code:
+///      Begin Lit("ab") Word Exec Wait Begin Lit("cd") Word Exec Wait
+///
+/// ## Tilde Expansion
+///
+/// Source:
+///     ab ~cd/ef
+/// Byte Code:
+///      Begin Lit("ab") Word Tilde("cd") Lit("/ef") Word Exec Wait
+///
+/// ## Quoting
+///
+/// Source:
+///     ab "c d"e\$
+/// Byte Code:
+///      Begin Lit("ab") Word Quoted("c d") Lit("e") Quoted("$") Word Exec Wait
+///
+/// ## Variables
+///
+/// Source:
+///     ab $cd "$ef"
+/// Byte Code:
+///      Begin Lit("ab") Word Variable("cd") Word QuotedVariable("ef") Word Exec Wait
+///
+/// ## Compound Commands
+///
+/// Source:
+///     ( ab ; cd )
+/// Byte Code:
+///      Subshell(11) Begin Lit("ab") Word Exec Wait Begin Lit("cd") Word Exec Wait
+///
+/// Source:
+///     { ab ; cd ; }
+/// Byte Code:
+///      Begin Lit("ab") Word Exec Wait Begin Lit("cd") Word Exec Wait
+///
 /// ## Backgrounding
 ///
 /// Source:
@@ -85,6 +132,44 @@ pub enum Instruction {
     /// Put a literal string on the stack of the last word in the launchpad
     Lit(String),
 
+    /// Put a quoted string on the stack of the last word in the launchpad
+    ///
+    /// The string will not be split or used for pattern matching.
+    Quoted(String),
+
+    /// Put the value of a variable on the stack of the last word in the launchpad
+    ///
+    /// The value is split into words. Parameter is the name of the variable.
+    Variable(String),
+
+    /// Put the value of a variable on the stack of the last word in the launchpad
+    ///
+    /// The value is used as is. Parameter is the name of the variable.
+    QuotedVariable(String),
+
+    /// Put the result of a parameter expansion with an operator on the stack of the last word in
+    /// the launchpad
+    ///
+    /// The result is split into words. Parameters are the name of the variable, the operator
+    /// and the unexpanded word.
+    Operation(String, Operator, String),
+
+    /// Put the result of a parameter expansion with an operator on the stack of the last word in
+    /// the launchpad
+    ///
+    /// The result is used as is. Parameters are the same as for `Operation`.
+    QuotedOperation(String, Operator, String),
+
+    /// Put the length of the value of a variable on the stack of the last word in the launchpad
+    ///
+    /// Parameter is the name of the variable.
+    Length(String),
+
+    /// Expand a tilde prefix and put the result on the stack of the last word in the launchpad
+    ///
+    /// Parameter is the text between the tilde and the first slash.
+    Tilde(String),
+
     /// Combine all stacks and store as words in the launchpad
     Word,
 
@@ -109,15 +194,43 @@ pub enum Instruction {
     /// move the instruction pointer according to the parameter.
     JumpIfNot(i32),
 
+    /// Move the instruction pointer according to the parameter.
+    Jump(i32),
+
+    /// Set the exit status, e.g. of an if command without a matching branch
+    SetStatus(i32),
+
+    /// Put the exit status on the stack, e.g. to restore it after the condition of a while loop
+    PushStatus,
+
+    /// Set the exit status to the value on the stack
+    PopStatus,
+
+    /// Start collecting words that are not a command, e.g. the words of a for loop
+    BeginWords,
+
+    /// Put the words from the launchpad on the stack for `NextWord`
+    ///
+    /// Words that can't be expanded set the exit status to 1 and leave the list empty.
+    LoopWords,
+
+    /// If words from `LoopWords` are left on the stack, assign the next one to a variable and
+    /// continue with the next instruction. Otherwise, move the instruction pointer.
+    ///
+    /// Parameters are the name of the variable and the jump distance.
+    NextWord(String, i32),
+
     /// Create a thread and a subshell, execute instructions.
     ///
     /// Parameter is number of instructions to execute in background.
     BackgroundJob(usize),
 
-    // Not yet implemented below this line
     /// Create a subshell, execute instructions, then drop subshell.
-    Subshell(Instructions),
+    ///
+    /// Parameter is number of instructions to execute in the subshell.
+    Subshell(usize),
 
+    // Not yet implemented below this line
     /// Placeholder for redirection
     Redirect,
 
@@ -147,16 +260,57 @@ pub struct Runner {
 
     /// Stack frames of the running shell script.
     pub shell_stack: ContextStack,
+
+    /// Exit status of the last pipeline that has been run
+    pub last_exit_status: i32,
+
+    /// Signal that killed the last pipeline, None if it exited on its own
+    last_signal: Option<i32>,
+
+    /// Application parts available to builtins
+    pub services: ShellServices,
+}
+
+/// Part of an argument on the launchpad
+#[derive(Debug, PartialEq)]
+struct Piece {
+    text: String,
+
+    /// The text wasn't quoted, i.e. pattern characters are active
+    unquoted: bool,
+}
+
+impl<'a> PartialEq<&'a str> for Piece {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.text == *other
+    }
 }
 
 /// The array of stacks to construct command line arguments
 #[derive(Debug)]
 struct Launchpad {
     /// One stack (inner Vec) for each future argument (outer Vec)
-    args: Vec<Vec<String>>,
+    args: Vec<Vec<Piece>>,
 
     /// Index of first argument that hasn't been finalized
     marker: usize,
+
+    /// The program name has to be taken from the next complete word.
+    ///
+    /// This happens if the first word vanished during expansion.
+    program_pending: bool,
+
+    /// Options for pathname expansion
+    glob_options: expansion::GlobOptions,
+
+    /// Pattern that didn't match any file while failglob was set
+    no_match: Option<String>,
+
+    /// Problems during pathname expansion, e.g. directories that could not be searched
+    glob_errors: Vec<String>,
+
+    /// First expansion that failed, e.g. `${name:?}` of an unset variable
+    expansion_error: Option<String>,
 }
 
 impl Launchpad {
@@ -164,6 +318,11 @@ impl Launchpad {
         Self {
             args: Vec::new(),
             marker: 0,
+            program_pending: false,
+            glob_options: expansion::GlobOptions::default(),
+            no_match: None,
+            glob_errors: Vec::new(),
+            expansion_error: None,
         }
     }
 
@@ -176,48 +335,153 @@ impl Launchpad {
     }
 
     /// Return the incomplete arguments
-    fn incomplete_args(&mut self) -> &mut [Vec<String>] {
+    fn incomplete_args(&mut self) -> &mut [Vec<Piece>] {
         &mut self.args[self.marker..]
     }
 
-    /// Add a literal string to all incomplete words
-    fn lit(&mut self, l: &str) {
+    /// Add a string to all incomplete words
+    fn push(&mut self, text: &str, unquoted: bool) {
         self.prepare_arg();
         for arg in self.incomplete_args() {
-            arg.push(l.to_string());
+            arg.push(Piece {
+                text: text.to_string(),
+                unquoted,
+            });
+        }
+    }
+
+    /// Add a literal string to all incomplete words. It will be used as a pattern.
+    fn lit(&mut self, l: &str) {
+        self.push(l, true)
+    }
+
+    /// Add a quoted string to all incomplete words
+    fn quoted(&mut self, q: &str) {
+        self.push(q, false)
+    }
+
+    /// Add the value of an unquoted expansion to all incomplete words.
+    ///
+    /// The value is split into words at the characters in `ifs`. Sequences of whitespace count as
+    /// one separator. Any other separator ends a word, even an empty one.
+    fn split(&mut self, value: &str, ifs: &str) {
+        let mut field = String::new();
+        let mut pending_break = false;
+        for c in value.chars() {
+            if ifs.contains(c) {
+                if " \t\n".contains(c) {
+                    pending_break = true;
+                } else {
+                    self.lit(&field);
+                    field.clear();
+                    self.finalize_words();
+                    pending_break = false;
+                }
+            } else {
+                if pending_break {
+                    if !field.is_empty() {
+                        self.lit(&field);
+                        field.clear();
+                    }
+                    self.finalize_words();
+                    pending_break = false;
+                }
+                field.push(c);
+            }
+        }
+        if !field.is_empty() {
+            self.lit(&field);
+        }
+        if pending_break {
+            self.finalize_words();
         }
     }
 
-    /// Complete ann incomplete words
+    /// Complete all incomplete words
+    ///
+    /// Words without any parts vanish, e.g. if they consisted only of an empty variable.
+    ///
+    /// Words with unquoted pattern characters are replaced by the matching file names. If nothing
+    /// matches, the word is kept unless nullglob or failglob are set.
     fn finalize_words(&mut self) {
         if self.marker < self.args.len() {
-            for arg in self.incomplete_args() {
-                if arg.len() != 1 {
-                    let mut res = String::new();
-                    for s in &*arg {
-                        res.push_str(&s);
+            let incomplete = self.args.split_off(self.marker);
+            for arg in incomplete {
+                if arg.is_empty() {
+                    continue;
+                }
+                let text: String = arg.iter().map(|p| p.text.as_str()).collect();
+                if arg
+                    .iter()
+                    .any(|p| p.unquoted && expansion::is_pattern(&p.text))
+                {
+                    let pattern: String = arg
+                        .iter()
+                        .map(|p| {
+                            if p.unquoted {
+                                p.text.clone()
+                            } else {
+                                Pattern::escape(&p.text)
+                            }
+                        })
+                        .collect();
+                    match expansion::glob(&pattern, &self.glob_options, &mut self.glob_errors) {
+                        Some(names) => {
+                            for text in names {
+                                self.args.push(vec![Piece {
+                                    text,
+                                    unquoted: false,
+                                }]);
+                            }
+                            continue;
+                        }
+                        None if self.glob_options.failglob => {
+                            if self.no_match.is_none() {
+                                self.no_match = Some(text);
+                            }
+                            continue;
+                        }
+                        None if self.glob_options.nullglob => continue,
+                        None => {}
                     }
-                    *arg = vec![res];
                 }
+                self.args.push(vec![Piece {
+                    text,
+                    unquoted: false,
+                }]);
             }
         }
         self.marker = self.args.len();
     }
 
+    /// Remember the first failed expansion. The command won't be run.
+    fn fail(&mut self, msg: String) {
+        if self.expansion_error.is_none() {
+            self.expansion_error = Some(msg);
+        }
+    }
+
     fn clear(&mut self) {
         self.args = Vec::new();
         self.marker = 0;
+        self.program_pending = false;
+        self.no_match = None;
+        self.glob_errors.clear();
+        self.expansion_error = None;
     }
 }
 
 impl Runner {
     pub fn new(session: SharedSession, shell_stack: ContextStack) -> Self {
         Self {
+            services: ShellServices::new(session.clone()),
             session,
             launchpad: Launchpad::new(),
             current_pipeline: None,
             data_stack: Stack::new(),
             shell_stack,
+            last_exit_status: 0,
+            last_signal: None,
         }
     }
 
@@ -249,22 +513,168 @@ impl Runner {
     /// This function will block until all intstructions are done
     pub fn run(&mut self, instructions: Arc<Instructions>, interaction: InteractionHandle) {
         let end = instructions.len();
-        let last_exit_status = self.run_sub_set(instructions, interaction, 0, end);
+        self.run_sub_set(instructions, interaction, 0, end);
+        if self.shell_stack.take_exit_request().is_some() {
+            self.session.request_exit(self.last_exit_status);
+        }
         self.session
-            .set_running_status(interaction, RunningStatus::Exited(last_exit_status));
+            .set_running_status(interaction, self.running_status());
+    }
+
+    /// Final status of the script, depending on how the last pipeline finished
+    fn running_status(&self) -> RunningStatus {
+        match self.last_signal {
+            Some(sig) => RunningStatus::Signaled(sig),
+            None => RunningStatus::Exited(self.last_exit_status),
+        }
+    }
+
+    /// Get the value of a variable or a special parameter.
+    ///
+    /// Unknown variables are empty.
+    fn parameter(&self, name: &str) -> String {
+        match name {
+            "?" => self.last_exit_status.to_string(),
+            _ => self
+                .shell_stack
+                .variable_as_str(name)
+                .unwrap_or("")
+                .to_string(),
+        }
+    }
+
+    /// Get the separators for word splitting
+    fn ifs(&self) -> String {
+        self.shell_stack
+            .find_variable("IFS")
+            .map(|v| v.as_str())
+            .unwrap_or(" \t\n")
+            .to_string()
+    }
+
+    /// Get the options for pathname expansion as set by the *shopt* builtin
+    fn glob_options(&self) -> expansion::GlobOptions {
+        expansion::GlobOptions {
+            nullglob: self.shell_stack.is_option_set("nullglob"),
+            failglob: self.shell_stack.is_option_set("failglob"),
+            dotglob: self.shell_stack.is_option_set("dotglob"),
+        }
+    }
+
+    /// Get the length of the value of a variable or special parameter in characters
+    fn length(&self, name: &str) -> String {
+        self.parameter(name).chars().count().to_string()
+    }
+
+    /// Evaluate a parameter expansion with an operator, e.g. `${name:-word}`.
+    ///
+    /// Returns the error message if the expansion failed, e.g. for `${name:?word}` of an unset
+    /// variable.
+    fn operation(&mut self, name: &str, operator: Operator, word: &str) -> Result<String, String> {
+        let value = self.parameter(name);
+        match operator {
+            Operator::UseDefault if value.is_empty() => self.expand_word(word, false),
+            Operator::AssignDefault if value.is_empty() => {
+                let value = self.expand_word(word, false)?;
+                self.shell_stack
+                    .bind_variable(name, &value)
+                    .map_err(|e| e.readable("\n"))?;
+                Ok(value)
+            }
+            Operator::UseAlternative if value.is_empty() => Ok(String::new()),
+            Operator::UseAlternative => self.expand_word(word, false),
+            Operator::ErrorIfUnset if value.is_empty() => {
+                let msg = if word.is_empty() {
+                    "parameter null or not set".to_string()
+                } else {
+                    self.expand_word(word, false)?
+                };
+                Err(format!("{}: {}\n", name, msg))
+            }
+            Operator::RemoveShortestPrefix
+            | Operator::RemoveLongestPrefix
+            | Operator::RemoveShortestSuffix
+            | Operator::RemoveLongestSuffix => {
+                let pattern = self.expand_word(word, true)?;
+                Ok(expansion::remove_matching(&value, &pattern, operator))
+            }
+            _ => Ok(value),
+        }
+    }
+
+    /// Expand the word of a parameter expansion with an operator to a single string.
+    ///
+    /// Quotes are removed and the result isn't split. If `as_pattern` is true, the quoted parts
+    /// are escaped, so that they only match themselves.
+    fn expand_word(&mut self, word: &str, as_pattern: bool) -> Result<String, String> {
+        let parts = match expansion::expansion(Span::new(word)) {
+            Ok((_, parts)) => parts,
+            Err(e) => {
+                error!("Can't expand word »{}« due to {:?}", word, e);
+                return Err(format!("Can't expand word »{}«\n", word));
+            }
+        };
+        let quote = |text: &str| {
+            if as_pattern {
+                Pattern::escape(text)
+            } else {
+                text.to_string()
+            }
+        };
+        let mut text = String::new();
+        for p in parts {
+            match p {
+                ExpSpan::Verbatim(s) => text.push_str(s.fragment()),
+                ExpSpan::Quoted(s) => text.push_str(&quote(s.fragment())),
+                ExpSpan::Tilde(name) => text.push_str(&quote(&expansion::tilde(
+                    name.fragment(),
+                    &self.shell_stack,
+                ))),
+                ExpSpan::Variable(name) => text.push_str(&self.parameter(name.fragment())),
+                ExpSpan::QuotedVariable(name) => {
+                    text.push_str(&quote(&self.parameter(name.fragment())))
+                }
+                ExpSpan::Operation(name, operator, word) => {
+                    text.push_str(&self.operation(name.fragment(), operator, word.fragment())?)
+                }
+                ExpSpan::QuotedOperation(name, operator, word) => {
+                    let value = self.operation(name.fragment(), operator, word.fragment())?;
+                    text.push_str(&quote(&value))
+                }
+                ExpSpan::Length(name) => text.push_str(&self.length(name.fragment())),
+            }
+        }
+        Ok(text)
+    }
+
+    /// Take the program name from the launchpad if it is still missing
+    fn set_program(&mut self) {
+        if self.launchpad.program_pending && !self.launchpad.args.is_empty() {
+            if let Some(ref mut pb) = self.current_pipeline {
+                let mut name_stack = self.launchpad.args.remove(0);
+                let name = name_stack.remove(0).text;
+                pb.set_program(name);
+            } else {
+                error!("No pipeline builder in SetProgram");
+            }
+            self.launchpad.program_pending = false;
+        }
     }
 
+    /// Run a range of the instructions.
+    ///
+    /// The exit status of the last pipeline is stored in `last_exit_status`.
     fn run_sub_set(
         &mut self,
         instructions: Arc<Instructions>,
         interaction: InteractionHandle,
         start: usize,
         end: usize,
-    ) -> i32 {
+    ) {
         trace!("Running subset [{},{}] of {:?}", start, end, instructions);
         let mut ip = start;
-        let mut last_exit_status = 0;
-        while (start <= ip) && (ip < end) {
+        // Stop at the end of the range or when the exit builtin ran
+        while (start <= ip) && (ip < end) && self.shell_stack.exit_request().is_none() {
             let i = &instructions[ip];
             trace!("Instruction {} in {:?}: {:?}", ip, instructions, i);
             match i {
@@ -275,6 +685,7 @@ impl Runner {
                             self.current_pipeline
                         );
                     }
+                    self.launchpad.glob_options = self.glob_options();
                     self.check_error(
                         interaction,
                         jobs::PipelineBuilder::new(
@@ -287,33 +698,87 @@ impl Runner {
                 }
 
                 Instruction::Lit(s) => self.launchpad.lit(s),
-                Instruction::Word => self.launchpad.finalize_words(),
+                Instruction::Quoted(s) => self.launchpad.quoted(s),
+                Instruction::Tilde(name) => {
+                    let dir = expansion::tilde(name, &self.shell_stack);
+                    self.launchpad.quoted(&dir);
+                }
+                Instruction::Variable(name) => {
+                    let ifs = self.ifs();
+                    let value = self.parameter(name);
+                    self.launchpad.split(&value, &ifs);
+                }
+                Instruction::QuotedVariable(name) => {
+                    let value = self.parameter(name);
+                    self.launchpad.quoted(&value);
+                }
+                Instruction::Operation(name, operator, word) => {
+                    match self.operation(name, *operator, word) {
+                        Ok(value) => {
+                            let ifs = self.ifs();
+                            self.launchpad.split(&value, &ifs);
+                        }
+                        Err(msg) => self.launchpad.fail(msg),
+                    }
+                }
+                Instruction::QuotedOperation(name, operator, word) => {
+                    match self.operation(name, *operator, word) {
+                        Ok(value) => self.launchpad.quoted(&value),
+                        Err(msg) => self.launchpad.fail(msg),
+                    }
+                }
+                Instruction::Length(name) => {
+                    let length = self.length(name);
+                    self.launchpad.quoted(&length);
+                }
+                Instruction::Word => {
+                    self.launchpad.finalize_words();
+                    self.set_program();
+                }
                 Instruction::SetProgram => {
                     // finalize the words to have single strings
                     self.launchpad.finalize_words();
-                    if self.launchpad.args.len() != 1 {
-                        warn!(
-                            "Launchpad isn't 1 word long for program name: »{:?}«",
-                            self.launchpad
-                        );
-                    }
-
-                    if let Some(ref mut pb) = self.current_pipeline {
-                        let mut name_stack = self.launchpad.args.remove(0);
-                        let name = name_stack.remove(0);
-                        pb.set_program(name);
-                    } else {
-                        error!("No pipeline builder in SetProgram");
-                    }
+                    self.launchpad.program_pending = true;
+                    self.set_program();
                 }
                 Instruction::Exec(is_last) => {
+                    // Like in bash, only the last program of the pipeline decides on its exit
+                    // status.
+                    //
+                    // Did the program fail to start?
+                    let mut start_failed = false;
+                    // Did the expansion of its arguments fail?
+                    let mut expansion_failed = false;
                     // finalize the words to have single strings
                     self.launchpad.finalize_words();
                     trace!("Launchpad: {:?}", self.launchpad);
-                    if let Some(ref mut pb) = self.current_pipeline {
-                        let args = self.launchpad.args.drain(0..).map(|mut w| w.remove(0));
+                    for msg in std::mem::replace(&mut self.launchpad.glob_errors, Vec::new()) {
+                        self.report_error(interaction, &format!("{}\n", msg));
+                    }
+                    if let Some(msg) = self.launchpad.expansion_error.take() {
+                        self.report_error(interaction, &msg);
+                        expansion_failed = true;
+                    } else if let Some(pattern) = self.launchpad.no_match.take() {
+                        // The program isn't run, same as bash with failglob
+                        self.report_error(interaction, &format!("no match: {}\n", pattern));
+                        expansion_failed = true;
+                    } else if self.launchpad.program_pending {
+                        // All words vanished, there is nothing to run
+                        trace!("No program to run");
+                    } else if let Some(ref mut pb) = self.current_pipeline {
+                        let args = self.launchpad.args.drain(0..).map(|mut w| w.remove(0).text);
                         // Start the pipeline
-                        let res = pb.start(*is_last, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT, args);
+                        let res = pb.start(
+                            *is_last,
+                            DEFAULT_TUI_WIDTH,
+                            DEFAULT_TUI_HEIGHT,
+                            args,
+                            &self.shell_stack,
+                            &self.services,
+                        );
+                        if res.is_err() {
+                            start_failed = true;
+                        }
                         self.check_error(interaction, res, |_, _| {});
                     } else {
                         error!("No pipeline builder in Exec");
@@ -325,7 +790,29 @@ impl Runner {
                             // Set the current job in the session
                             self.session.set_job(interaction, Some(pb.create_job()));
                             // If that worked, wait for the command to complete
-                            last_exit_status = pb.wait(self.session.clone(), interaction);
+                            let (exit_status, shell_stack) =
+                                pb.wait(self.session.clone(), interaction);
+                            // A builtin that ran in this shell might have changed it
+                            if let Some(shell_stack) = shell_stack {
+                                self.shell_stack = shell_stack;
+                            }
+                            let previous_exit_status = self.last_exit_status;
+                            self.last_signal = None;
+                            self.last_exit_status = if expansion_failed {
+                                1
+                            } else if start_failed {
+                                // Same as bash for a command that could not be found
+                                127
+                            } else {
+                                if let RunningStatus::Signaled(sig) = exit_status {
+                                    self.last_signal = Some(sig);
+                                }
+                                exit_status.exit_code().unwrap_or(1)
+                            };
+                            if let Some(None) = self.shell_stack.exit_request() {
+                                // exit without a code keeps the status of the previous command
+                                self.last_exit_status = previous_exit_status;
+                            }
                             self.session.set_job(interaction, None);
                         } else {
                             error!("No pipeline builder in Exec of last command");
@@ -336,10 +823,10 @@ impl Runner {
                 }
 
                 Instruction::Success => {
-                    let success = last_exit_status == 0;
+                    let success = self.last_exit_status == 0;
                     trace!(
                         "check last_exit_status {:?} -> success {:?}",
-                        last_exit_status,
+                        self.last_exit_status,
                         success
                     );
                     self.data_stack.push_bool(success);
@@ -353,30 +840,80 @@ impl Runner {
                 Instruction::JumpIfNot(delta) => {
                     let b = self.data_stack.pop_bool(false);
                     if !b {
-                        let mut out_of_range = false;
-                        if *delta < 0 {
-                            // Backwards jump
-                            let delta = (-delta) as usize;
-                            if ip >= delta {
-                                ip = ip - delta - 1;
-                            } else {
-                                out_of_range = true;
-                            }
-                        } else {
-                            let delta = *delta as usize;
-                            if ip + 1 + delta <= end {
-                                ip = ip + delta - 1;
-                            } else {
-                                out_of_range = true;
-                            }
-                        }
-                        if out_of_range {
-                            ip = end;
-                            error!(
-                                "Instruction Pointer ({}) out of range: [{},{}]",
-                                ip, start, end
-                            );
-                        }
+                        ip = jump_target(ip, *delta, start, end);
+                        continue;
+                    }
+                }
+
+                Instruction::Jump(delta) => {
+                    ip = jump_target(ip, *delta, start, end);
+                    continue;
+                }
+
+                Instruction::SetStatus(status) => {
+                    self.last_exit_status = *status;
+                    self.last_signal = None;
+                }
+
+                Instruction::PushStatus => {
+                    self.data_stack.push_int(self.last_exit_status);
+                }
+
+                Instruction::PopStatus => {
+                    self.last_exit_status = self.data_stack.pop_int(0);
+                    self.last_signal = None;
+                }
+
+                Instruction::BeginWords => {
+                    self.launchpad.clear();
+                    self.launchpad.glob_options = self.glob_options();
+                }
+
+                Instruction::LoopWords => {
+                    self.launchpad.finalize_words();
+                    for msg in std::mem::replace(&mut self.launchpad.glob_errors, Vec::new()) {
+                        self.report_error(interaction, &format!("{}\n", msg));
+                    }
+                    let expansion_error = self.launchpad.expansion_error.take();
+                    let (words, status) = if let Some(msg) = expansion_error {
+                        self.report_error(interaction, &msg);
+                        (Vec::new(), 1)
+                    } else if let Some(pattern) = self.launchpad.no_match.take() {
+                        self.report_error(interaction, &format!("no match: {}\n", pattern));
+                        (Vec::new(), 1)
+                    } else {
+                        let words: Vec<String> = self
+                            .launchpad
+                            .args
+                            .drain(0..)
+                            .map(|mut w| w.remove(0).text)
+                            .collect();
+                        (words, 0)
+                    };
+                    self.last_exit_status = status;
+                    self.last_signal = None;
+                    let count = words.len() as i32;
+                    for word in words.into_iter().rev() {
+                        self.data_stack.push_str(word);
+                    }
+                    self.data_stack.push_int(count);
+                    self.launchpad.clear();
+                }
+
+                Instruction::NextWord(name, delta) => {
+                    let remaining = self.data_stack.pop_int(0);
+                    if remaining <= 0 {
+                        ip = jump_target(ip, *delta, start, end);
+                        continue;
+                    }
+                    let word = self.data_stack.pop_str("");
+                    self.data_stack.push_int(remaining - 1);
+                    if let Err(err) = self.shell_stack.bind_variable(name, &word) {
+                        error!(
+                            "Can't set variable »{}« to »{}« due to {:?}",
+                            name, word, err
+                        );
+                        self.report_error(interaction, &err.readable(""));
                     }
                 }
 
@@ -391,22 +928,26 @@ impl Runner {
                     // TODO: Compress the stack to one level
                     let clone_stack = self.shell_stack.clone();
                     let mut clone_self = Runner::new(self.session.clone(), clone_stack);
+                    clone_self.services = self.services.clone();
                     let clone_instructions = instructions.clone();
                     let clone_start = ip + 1;
                     let clone_end = ip + len;
                     spawn(move || {
-                        let last_exit_status = clone_self.run_sub_set(
+                        clone_self.run_sub_set(
                             clone_instructions,
                             new_handle,
                             clone_start,
                             clone_end,
                         );
-                        clone_self.session.set_running_status(
-                            new_handle,
-                            RunningStatus::Exited(last_exit_status),
-                        );
+                        clone_self
+                            .session
+                            .set_running_status(new_handle, clone_self.running_status());
                     });
 
+                    // Starting a job in background is always successful
+                    self.last_exit_status = 0;
+                    self.last_signal = None;
+
                     // Skip over background instructions
                     ip += len - 1;
                     if ip > end {
@@ -417,10 +958,31 @@ impl Runner {
                     }
                 }
 
+                Instruction::Subshell(len) => {
+                    // Variables and the current directory must not leak to the parent shell
+                    self.shell_stack.push_subshell();
+                    self.run_sub_set(instructions.clone(), interaction, ip + 1, ip + len);
+                    // exit only leaves the subshell
+                    self.shell_stack.take_exit_request();
+                    self.shell_stack.pop_subshell();
+
+                    // Skip over subshell instructions
+                    ip += len - 1;
+                    if ip > end {
+                        error!(
+                            "Instruction Pointer ({}) out of range: [{},{}]",
+                            ip, start, end
+                        );
+                    }
+                }
+
                 Instruction::Assign => {
-                    if self.launchpad.args.len() == 2 {
-                        let var = self.launchpad.args.remove(0).remove(0);
-                        let val = self.launchpad.args.remove(0).remove(0);
+                    if let Some(msg) = self.launchpad.expansion_error.take() {
+                        self.report_error(interaction, &msg);
+                        self.last_exit_status = 1;
+                    } else if self.launchpad.args.len() == 2 {
+                        let var = self.launchpad.args.remove(0).remove(0).text;
+                        let val = self.launchpad.args.remove(0).remove(0).text;
                         match self.shell_stack.bind_variable(&var, &val) {
                             Ok(_) => {
                                 // Nothing to do
@@ -454,10 +1016,72 @@ impl Runner {
             end,
             instructions
         );
-        last_exit_status
     }
 }
 
+/// Instruction pointer after a relative jump.
+///
+/// Jumps out of the range `[start, end]` end the range.
+fn jump_target(ip: usize, delta: i32, start: usize, end: usize) -> usize {
+    let target = ip as isize + delta as isize;
+    if target < start as isize || target > end as isize {
+        error!(
+            "Jump from {} by {} out of range: [{},{}]",
+            ip, delta, start, end
+        );
+        end
+    } else {
+        target as usize
+    }
+}
+
+/// Compile the expansions of a single word
+///
+/// If `quoted` is true, the word is neither split nor used for pattern matching, e.g. for the
+/// value of an assignment.
+fn compile_word<'a>(
+    instructions: &mut Instructions,
+    word: &Span<'a>,
+    quoted: bool,
+) -> Result<(), String> {
+    let parts = match expansion::expansion(*word) {
+        Ok((_, parts)) => parts,
+        Err(e) => {
+            error!("Can't expand word »{}« due to {:?}", word, e);
+            return Err(format!("BiTE: Can't expand word »{}«\n", word));
+        }
+    };
+    if parts.is_empty() {
+        // Keep empty words, e.g. the value of an assignment
+        instructions.push(Instruction::Quoted(String::new()));
+    }
+    for p in parts {
+        match p {
+            ExpSpan::Verbatim(s) if !quoted => instructions.push(Instruction::Lit(s.to_string())),
+            ExpSpan::Verbatim(s) | ExpSpan::Quoted(s) => {
+                instructions.push(Instruction::Quoted(s.to_string()))
+            }
+            ExpSpan::Tilde(name) => instructions.push(Instruction::Tilde(name.to_string())),
+            ExpSpan::Variable(name) if !quoted => {
+                instructions.push(Instruction::Variable(name.to_string()))
+            }
+            ExpSpan::Variable(name) | ExpSpan::QuotedVariable(name) => {
+                instructions.push(Instruction::QuotedVariable(name.to_string()))
+            }
+            ExpSpan::Operation(name, operator, word) if !quoted => instructions.push(
+                Instruction::Operation(name.to_string(), operator, word.to_string()),
+            ),
+            ExpSpan::Operation(name, operator, word)
+            | ExpSpan::QuotedOperation(name, operator, word) => instructions.push(
+                Instruction::QuotedOperation(name.to_string(), operator, word.to_string()),
+            ),
+            ExpSpan::Length(name) => instructions.push(Instruction::Length(name.to_string())),
+        }
+    }
+    instructions.push(Instruction::Word);
+    Ok(())
+}
+
 fn compile_command<'a>(
     instructions: &mut Instructions,
     pipeline_command: &PipelineCommand<'a>,
@@ -467,14 +1091,21 @@ fn compile_command<'a>(
         Command::Program(args) => {
             let mut is_first = true;
             for a in args {
-                instructions.push(Instruction::Lit(a.to_string()));
-                instructions.push(Instruction::Word);
+                compile_word(instructions, a, false)?;
                 if is_first {
                     instructions.push(Instruction::SetProgram);
                     is_first = false;
                 }
             }
         }
+        Command::Subshell(_)
+        | Command::Group(_)
+        | Command::If(_, _)
+        | Command::For(_, _, _)
+        | Command::While(_, _) => {
+            // All commands of a pipeline run at the same time, which needs a separate shell
+            return Err("BiTE: Compound commands can't be part of a pipeline\n".to_string());
+        }
     }
     match pipeline_command.operator {
         PipelineOperator::StderrAndStdout => {
@@ -490,16 +1121,63 @@ fn compile_command<'a>(
     Ok(())
 }
 
+/// Compile a pipeline and the check of its logical operator.
+///
+/// If the previous pipeline left a conditional jump, it is patched to skip this pipeline. The
+/// check of this pipeline is stored in `pending_jump` to be patched by the next one.
 fn compile_pipeline<'a>(
     instructions: &mut Instructions,
-    jump_stack: &mut Vec<usize>,
+    pending_jump: &mut Option<usize>,
     pipeline: &Pipeline<'a>,
 ) -> Result<(), String> {
-    instructions.push(Instruction::Begin);
-    let num_commands = pipeline.commands.len();
-    for (ind, cmd) in pipeline.commands.iter().enumerate() {
-        let is_last = (ind + 1) == num_commands;
-        compile_command(instructions, cmd, is_last)?;
+    match pipeline.commands.as_slice() {
+        // A single compound command runs in this shell and doesn't need a pipeline
+        [PipelineCommand {
+            command: Command::Subshell(list),
+            ..
+        }] => {
+            let jump_source = instructions.len();
+            instructions.push(Instruction::Subshell(0));
+            compile_list(instructions, list)?;
+            let jump_tgt = instructions.len();
+            patch_jump(instructions, jump_source, jump_tgt)?;
+        }
+        [PipelineCommand {
+            command: Command::Group(list),
+            ..
+        }] => {
+            compile_list(instructions, list)?;
+        }
+        [PipelineCommand {
+            command: Command::If(branches, otherwise),
+            ..
+        }] => {
+            compile_if(instructions, branches, otherwise)?;
+        }
+        [PipelineCommand {
+            command: Command::For(name, words, body),
+            ..
+        }] => {
+            compile_for(instructions, name, words, body)?;
+        }
+        [PipelineCommand {
+            command: Command::While(condition, body),
+            ..
+        }] => {
+            compile_while(instructions, condition, body)?;
+        }
+        commands => {
+            instructions.push(Instruction::Begin);
+            let num_commands = commands.len();
+            for (ind, cmd) in commands.iter().enumerate() {
+                let is_last = (ind + 1) == num_commands;
+                compile_command(instructions, cmd, is_last)?;
+            }
+        }
+    }
+    if let Some(jump_source) = pending_jump.take() {
+        let jump_tgt = instructions.len();
+        patch_jump(instructions, jump_source, jump_tgt)?;
     }
     match pipeline.operator {
         LogicalOperator::Nothing => {
@@ -510,59 +1188,173 @@ fn compile_pipeline<'a>(
             if pipeline.operator == LogicalOperator::Or {
                 instructions.push(Instruction::Not);
             }
-            jump_stack.push(instructions.len());
+            *pending_jump = Some(instructions.len());
             instructions.push(Instruction::JumpIfNot(0));
         }
     }
     Ok(())
 }
 
-pub fn compile<'a>(
+/// Compile an if command.
+///
+/// Each condition that fails jumps to the next one. Each body jumps to the end. Without a
+/// matching branch, the exit status is 0.
+fn compile_if<'a>(
     instructions: &mut Instructions,
-    ast: AbstractSyntaxTree<'a>,
+    branches: &Vec<(Vec<AbstractSyntaxTree<'a>>, Vec<AbstractSyntaxTree<'a>>)>,
+    otherwise: &Option<Vec<AbstractSyntaxTree<'a>>>,
 ) -> Result<(), String> {
-    match ast {
-        AbstractSyntaxTree::Comment(_) | AbstractSyntaxTree::Nothing => {
-            // Do nothing
+    let mut end_jumps = Vec::new();
+    for (condition, body) in branches {
+        compile_list(instructions, condition)?;
+        instructions.push(Instruction::Success);
+        let next_branch = instructions.len();
+        instructions.push(Instruction::JumpIfNot(0));
+        compile_list(instructions, body)?;
+        end_jumps.push(instructions.len());
+        instructions.push(Instruction::Jump(0));
+        let jump_tgt = instructions.len();
+        patch_jump(instructions, next_branch, jump_tgt)?;
+    }
+    match otherwise {
+        Some(list) => compile_list(instructions, list)?,
+        None => instructions.push(Instruction::SetStatus(0)),
+    }
+    let jump_tgt = instructions.len();
+    for jump_source in end_jumps {
+        patch_jump(instructions, jump_source, jump_tgt)?;
+    }
+    Ok(())
+}
+
+/// Compile a for loop.
+///
+/// The expanded words are kept on the data stack while the body runs.
+fn compile_for<'a>(
+    instructions: &mut Instructions,
+    name: &Span<'a>,
+    words: &Vec<Span<'a>>,
+    body: &Vec<AbstractSyntaxTree<'a>>,
+) -> Result<(), String> {
+    instructions.push(Instruction::BeginWords);
+    for w in words {
+        compile_word(instructions, w, false)?;
+    }
+    instructions.push(Instruction::LoopWords);
+    let loop_start = instructions.len();
+    instructions.push(Instruction::NextWord(name.to_string(), 0));
+    compile_list(instructions, body)?;
+    let jump_source = instructions.len();
+    instructions.push(Instruction::Jump(loop_start as i32 - jump_source as i32));
+    let jump_tgt = instructions.len();
+    patch_jump(instructions, loop_start, jump_tgt)
+}
+
+/// Compile a while loop.
+///
+/// Like in bash, the exit status is the one of the last command of the body, or 0 if the body
+/// didn't run. It is kept on the data stack while the condition runs.
+fn compile_while<'a>(
+    instructions: &mut Instructions,
+    condition: &Vec<AbstractSyntaxTree<'a>>,
+    body: &Vec<AbstractSyntaxTree<'a>>,
+) -> Result<(), String> {
+    instructions.push(Instruction::SetStatus(0));
+    let loop_start = instructions.len();
+    instructions.push(Instruction::PushStatus);
+    compile_list(instructions, condition)?;
+    instructions.push(Instruction::Success);
+    let loop_exit = instructions.len();
+    instructions.push(Instruction::JumpIfNot(0));
+    // Drop the saved status. The condition succeeded.
+    instructions.push(Instruction::PopStatus);
+    instructions.push(Instruction::SetStatus(0));
+    compile_list(instructions, body)?;
+    let jump_source = instructions.len();
+    instructions.push(Instruction::Jump(loop_start as i32 - jump_source as i32));
+    let jump_tgt = instructions.len();
+    instructions.push(Instruction::PopStatus);
+    patch_jump(instructions, loop_exit, jump_tgt)
+}
+
+/// Set the target of a forward jump
+fn patch_jump(
+    instructions: &mut Instructions,
+    jump_source: usize,
+    jump_tgt: usize,
+) -> Result<(), String> {
+    match instructions[jump_source] {
+        Instruction::JumpIfNot(_) => {
+            instructions[jump_source] = Instruction::JumpIfNot((jump_tgt - jump_source) as i32);
         }
-        AbstractSyntaxTree::Logical(terms, background_mode) => {
-            // Compile the terms one by one
+        Instruction::Jump(_) => {
+            instructions[jump_source] = Instruction::Jump((jump_tgt - jump_source) as i32);
+        }
+        Instruction::NextWord(ref name, _) => {
+            let name = name.clone();
+            instructions[jump_source] =
+                Instruction::NextWord(name, (jump_tgt - jump_source) as i32);
+        }
+        Instruction::BackgroundJob(_) => {
+            instructions[jump_source] = Instruction::BackgroundJob(jump_tgt - jump_source);
+        }
+        Instruction::Subshell(_) => {
+            instructions[jump_source] = Instruction::Subshell(jump_tgt - jump_source);
+        }
+        _ => {
+            error!(
+                "Found unhandled jump source »{:?}« at {}",
+                instructions[jump_source], jump_source
+            );
+            return Err("BiTE: Internal error\n".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Compile a list of commands, e.g. the body of a subshell
+fn compile_list<'a>(
+    instructions: &mut Instructions,
+    list: &Vec<AbstractSyntaxTree<'a>>,
+) -> Result<(), String> {
+    for ast in list.iter() {
+        compile(instructions, ast)?;
+    }
+    Ok(())
+}
+
+pub fn compile<'a>(
+    instructions: &mut Instructions,
+    ast: &AbstractSyntaxTree<'a>,
+) -> Result<(), String> {
+    match ast {
+        AbstractSyntaxTree::Comment(_) | AbstractSyntaxTree::Nothing => {
+            // Do nothing
+        }
+        AbstractSyntaxTree::Logical(terms, background_mode) => {
+            // Compile the terms one by one
             // Remember where forward jumps were, so their targets can be fixed
             let mut jump_stack: Vec<usize> = Vec::new();
-            if background_mode == BackgroundMode::Background {
+            if *background_mode == BackgroundMode::Background {
                 jump_stack.push(instructions.len());
                 instructions.push(Instruction::BackgroundJob(0));
             }
+            let mut pending_jump = None;
             for p in terms.iter() {
-                compile_pipeline(instructions, &mut jump_stack, p)?;
+                compile_pipeline(instructions, &mut pending_jump, p)?;
             }
+            // A dangling operator skips to the end
+            jump_stack.extend(pending_jump);
             let jump_tgt = instructions.len();
             for jump_source in jump_stack {
-                match instructions[jump_source] {
-                    Instruction::JumpIfNot(_) => {
-                        instructions[jump_source] =
-                            Instruction::JumpIfNot((jump_tgt - jump_source) as i32);
-                    }
-                    Instruction::BackgroundJob(_) => {
-                        instructions[jump_source] =
-                            Instruction::BackgroundJob(jump_tgt - jump_source);
-                    }
-                    _ => {
-                        error!(
-                            "Found unhandled jump stack entry »{:?}« when compiling »{:?}«",
-                            instructions[jump_source], terms
-                        );
-                        return Err("BiTE: Internal error\n".to_string());
-                    }
-                }
+                patch_jump(instructions, jump_source, jump_tgt)?;
             }
         }
         AbstractSyntaxTree::Assignments(asgn) => {
             for (var, val) in asgn {
                 instructions.push(Instruction::Lit(var.to_string()));
                 instructions.push(Instruction::Word);
-                instructions.push(Instruction::Lit(val.to_string()));
-                instructions.push(Instruction::Word);
+                compile_word(instructions, val, true)?;
                 instructions.push(Instruction::Assign);
             }
         }
@@ -574,7 +1366,10 @@ pub fn compile<'a>(
 #[cfg(test)]
 mod tests {
     use super::super::parser;
+    use super::super::{parse_script, ScriptError};
     use super::*;
+    use model::screen::Screen;
+    use model::session::test::new_test_session;
 
     fn compile_full_script(script: &str) -> Instructions {
         let mut instructions = Vec::new();
@@ -583,12 +1378,12 @@ mod tests {
 
         let mut input = script_span;
 
-        while input.fragment.len() != 0 {
+        while input.fragment().len() != 0 {
             let ast = parser::script(input);
             assert_eq!(ast.is_ok(), true);
 
             if let Ok((rest, ast)) = ast {
-                let compile_result = super::compile(&mut instructions, ast);
+                let compile_result = super::compile(&mut instructions, &ast);
                 assert_eq!(compile_result.is_ok(), true);
                 input = rest;
             }
@@ -644,6 +1439,698 @@ mod tests {
         );
     }
 
+    #[test]
+    fn compile_logical_chain() {
+        let instructions = compile_full_script("ab && cd || ef\n");
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::Begin,
+                Instruction::Lit("ab".to_string()),
+                Instruction::Word,
+                Instruction::SetProgram,
+                Instruction::Exec(true),
+                Instruction::Success,
+                Instruction::JumpIfNot(6),
+                Instruction::Begin,
+                Instruction::Lit("cd".to_string()),
+                Instruction::Word,
+                Instruction::SetProgram,
+                Instruction::Exec(true),
+                Instruction::Success,
+                Instruction::Not,
+                Instruction::JumpIfNot(6),
+                Instruction::Begin,
+                Instruction::Lit("ef".to_string()),
+                Instruction::Word,
+                Instruction::SetProgram,
+                Instruction::Exec(true),
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_sequence() {
+        let instructions = compile_full_script("ab ; cd\n");
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::Begin,
+                Instruction::Lit("ab".to_string()),
+                Instruction::Word,
+                Instruction::SetProgram,
+                Instruction::Exec(true),
+                Instruction::Begin,
+                Instruction::Lit("cd".to_string()),
+                Instruction::Word,
+                Instruction::SetProgram,
+                Instruction::Exec(true),
+            ]
+        );
+    }
+
+    /// Compile and run a script, return the runner for inspection
+    fn run_full_script_runner(script: &str) -> Runner {
+        let instructions = compile_full_script(script);
+        let mut session = new_test_session(b"test");
+        let handle = session.add_interaction(Screen::one_line_matrix(script.as_bytes()));
+        let mut runner = Runner::new(session, ContextStack::new());
+        runner.run(Arc::new(instructions), handle);
+        runner
+    }
+
+    /// Create a runner that doesn't show its output
+    fn test_runner() -> Runner {
+        Runner::new(new_test_session(b"test"), ContextStack::new())
+    }
+
+    /// Compile a single command and compute its words without running it
+    fn expand_words(runner: &mut Runner, script: &str) -> Vec<String> {
+        let instructions: Instructions = compile_full_script(script)
+            .into_iter()
+            .filter(|i| match i {
+                Instruction::Begin | Instruction::SetProgram | Instruction::Exec(_) => false,
+                _ => true,
+            })
+            .collect();
+        let end = instructions.len();
+        runner.launchpad.glob_options = runner.glob_options();
+        runner.run_sub_set(Arc::new(instructions), InteractionHandle::INVALID, 0, end);
+        let words: Vec<String> = runner
+            .launchpad
+            .args
+            .drain(..)
+            .map(|mut w| w.remove(0).text)
+            .collect();
+        runner.launchpad.clear();
+        words
+    }
+
+    /// Compile and run a script, return the last exit status
+    fn run_full_script(script: &str) -> i32 {
+        run_full_script_runner(script).last_exit_status
+    }
+
+    #[test]
+    fn run_logical_and() {
+        assert_eq!(run_full_script("true && true\n"), 0);
+        assert_eq!(run_full_script("true && false\n"), 1);
+        assert_eq!(run_full_script("false && true\n"), 1);
+    }
+
+    #[test]
+    fn run_logical_or() {
+        assert_eq!(run_full_script("false || true\n"), 0);
+        assert_eq!(run_full_script("false || false\n"), 1);
+        assert_eq!(run_full_script("true || false\n"), 0);
+    }
+
+    #[test]
+    fn run_logical_chain() {
+        assert_eq!(run_full_script("false && false || true\n"), 0);
+        assert_eq!(run_full_script("true || true && false\n"), 1);
+        assert_eq!(run_full_script("false || false && true\n"), 1);
+    }
+
+    #[test]
+    fn run_pipeline_status() {
+        assert_eq!(run_full_script("no_such_program_for_bite | true\n"), 0);
+        assert_eq!(run_full_script("true | no_such_program_for_bite\n"), 127);
+        assert_eq!(run_full_script("false | true\n"), 0);
+        assert_eq!(run_full_script("true | false\n"), 1);
+    }
+
+    #[test]
+    fn run_sequence() {
+        assert_eq!(run_full_script("false ; true\n"), 0);
+        assert_eq!(run_full_script("true ; false\n"), 1);
+        assert_eq!(run_full_script("false ; true && false\n"), 1);
+    }
+
+    #[test]
+    fn compile_tilde() {
+        let instructions = compile_full_script("ab ~cd/ef\nx=~\n");
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::Begin,
+                Instruction::Lit("ab".to_string()),
+                Instruction::Word,
+                Instruction::SetProgram,
+                Instruction::Tilde("cd".to_string()),
+                Instruction::Lit("/ef".to_string()),
+                Instruction::Word,
+                Instruction::Exec(true),
+                Instruction::Lit("x".to_string()),
+                Instruction::Word,
+                Instruction::Tilde("".to_string()),
+                Instruction::Word,
+                Instruction::Assign,
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_quoted() {
+        let instructions = compile_full_script("ab \"*\" * \\*\n");
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::Begin,
+                Instruction::Lit("ab".to_string()),
+                Instruction::Word,
+                Instruction::SetProgram,
+                Instruction::Quoted("*".to_string()),
+                Instruction::Word,
+                Instruction::Lit("*".to_string()),
+                Instruction::Word,
+                Instruction::Quoted("*".to_string()),
+                Instruction::Word,
+                Instruction::Exec(true),
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_quoted_words() {
+        let mut runner = test_runner();
+        assert_eq!(
+            expand_words(&mut runner, "echo \"a b\" 'c  d' e\\ f\n"),
+            vec!["echo", "a b", "c  d", "e f"]
+        );
+        assert_eq!(
+            expand_words(&mut runner, "echo 'a$b' \\$c \"\\$d\" \"\"\n"),
+            vec!["echo", "a$b", "$c", "$d", ""]
+        );
+        assert_eq!(
+            expand_words(&mut runner, "echo a\"b c\"'d'\n"),
+            vec!["echo", "ab cd"]
+        );
+    }
+
+    #[test]
+    fn expand_split_words() {
+        let mut runner = test_runner();
+        assert_eq!(
+            expand_words(&mut runner, "x=\"a b\"; echo $x\n"),
+            vec!["echo", "a", "b"]
+        );
+        assert_eq!(
+            expand_words(&mut runner, "echo \"$x\"\n"),
+            vec!["echo", "a b"]
+        );
+        assert_eq!(
+            expand_words(&mut runner, "echo a${x}c\n"),
+            vec!["echo", "aa", "bc"]
+        );
+
+        // Leading and trailing whitespace separates words
+        assert_eq!(
+            expand_words(&mut runner, "z=\" a  b \"; echo x${z}y\n"),
+            vec!["echo", "x", "a", "b", "y"]
+        );
+
+        // Unquoted empty variables vanish
+        assert_eq!(
+            expand_words(&mut runner, "echo $nothing \"$nothing\"\n"),
+            vec!["echo", ""]
+        );
+
+        // Assignments aren't split
+        assert_eq!(
+            expand_words(&mut runner, "w=$x; echo \"$w\"\n"),
+            vec!["echo", "a b"]
+        );
+
+        // Other separators
+        assert_eq!(
+            expand_words(&mut runner, "IFS=:; y=a::b; echo $y\n"),
+            vec!["echo", "a", "", "b"]
+        );
+        assert_eq!(
+            expand_words(&mut runner, "IFS=; echo $x\n"),
+            vec!["echo", "a b"]
+        );
+    }
+
+    #[test]
+    fn expand_quoted_variables() {
+        let mut runner = test_runner();
+        assert!(runner
+            .shell_stack
+            .bind_variable("HOME", "/home/a user")
+            .is_ok());
+        assert!(runner.shell_stack.bind_variable("p", "*").is_ok());
+
+        // Double quotes expand the variable but keep the value in one word
+        assert_eq!(
+            expand_words(&mut runner, "echo \"$HOME/bin\" \"${HOME}bin\"\n"),
+            vec!["echo", "/home/a user/bin", "/home/a userbin"]
+        );
+
+        // Single quotes keep the reference literally, also inside double quotes
+        assert_eq!(
+            expand_words(&mut runner, "echo '$HOME/bin' \"'$p'\" '\"$p\"'\n"),
+            vec!["echo", "$HOME/bin", "'*'", "\"$p\""]
+        );
+
+        // Mixed quoting in one word
+        assert_eq!(
+            expand_words(&mut runner, "echo \"$p\"'$p'$p\n"),
+            vec!["echo", "*$p*"]
+        );
+    }
+
+    #[test]
+    fn expand_conditionals() {
+        let mut runner = test_runner();
+        assert!(runner
+            .shell_stack
+            .bind_variable("HOME", "/home/test")
+            .is_ok());
+        assert!(runner.shell_stack.bind_variable("set", "a b").is_ok());
+        assert!(runner.shell_stack.bind_variable("empty", "").is_ok());
+
+        // Use default, empty counts as unset
+        assert_eq!(
+            expand_words(
+                &mut runner,
+                "echo ${set:-x} ${unset:-x y} \"${empty:-$set}\"\n"
+            ),
+            vec!["echo", "a", "b", "x", "y", "a b"]
+        );
+        assert_eq!(
+            expand_words(&mut runner, "echo ${unset:-${empty:-'}'}}\n"),
+            vec!["echo", "}"]
+        );
+
+        // Use alternative
+        assert_eq!(
+            expand_words(&mut runner, "echo ${set:+x} \"${unset:+x}\" ${empty:+x}\n"),
+            vec!["echo", "x", ""]
+        );
+
+        // Assign default
+        assert_eq!(
+            expand_words(&mut runner, "echo ${new:=~/b} $new ${set:=x}\n"),
+            vec!["echo", "/home/test/b", "/home/test/b", "a", "b"]
+        );
+        assert_eq!(
+            runner.shell_stack.variable_as_str("new").ok(),
+            Some("/home/test/b")
+        );
+        assert_eq!(runner.shell_stack.variable_as_str("set").ok(), Some("a b"));
+
+        // Error if unset
+        assert_eq!(
+            expand_words(&mut runner, "echo ${set:?x} \"${new:?}\"\n"),
+            vec!["echo", "a", "b", "/home/test/b"]
+        );
+    }
+
+    #[test]
+    fn expand_removals() {
+        let mut runner = test_runner();
+        assert!(runner
+            .shell_stack
+            .bind_variable("file", "/tmp/archive.tar.gz")
+            .is_ok());
+        assert!(runner.shell_stack.bind_variable("star", "*").is_ok());
+
+        assert_eq!(
+            expand_words(&mut runner, "echo ${#file} ${#unset} \"${#star}\"\n"),
+            vec!["echo", "19", "0", "1"]
+        );
+        assert_eq!(
+            expand_words(&mut runner, "echo ${file#*/} ${file##*/}\n"),
+            vec!["echo", "tmp/archive.tar.gz", "archive.tar.gz"]
+        );
+        assert_eq!(
+            expand_words(&mut runner, "echo ${file%.*} \"${file%%.*}\"\n"),
+            vec!["echo", "/tmp/archive.tar", "/tmp/archive"]
+        );
+
+        // Quoted pattern characters only match themselves
+        assert_eq!(
+            expand_words(
+                &mut runner,
+                "echo ${file##'*/'} ${file##\"$star\"/} ${file##$star/}\n"
+            ),
+            vec![
+                "echo",
+                "/tmp/archive.tar.gz",
+                "/tmp/archive.tar.gz",
+                "archive.tar.gz"
+            ]
+        );
+    }
+
+    #[test]
+    fn run_conditional_errors() {
+        assert_eq!(
+            run_full_script_errors("true ${unset:?}\n"),
+            (1, "BiTE: unset: parameter null or not set\n".to_string())
+        );
+        assert_eq!(
+            run_full_script_errors("x=y; true \"${unset:?need $x}\"\n"),
+            (1, "BiTE: unset: need y\n".to_string())
+        );
+        assert_eq!(
+            run_full_script_errors("x=y; true ${x:?no}\n"),
+            (0, String::new())
+        );
+
+        // The assignment doesn't happen
+        let runner = run_full_script_runner("a=b; a=${unset:?}\n");
+        assert_eq!(runner.last_exit_status, 1);
+        assert_eq!(runner.shell_stack.variable_as_str("a").ok(), Some("b"));
+    }
+
+    #[test]
+    fn run_vanished_program() {
+        assert_eq!(run_full_script("$nothing false\n"), 1);
+        assert_eq!(run_full_script("false || $nothing\n"), 0);
+    }
+
+    #[test]
+    fn compile_subshell() {
+        let instructions = compile_full_script("(a=b; cd)\n");
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::Subshell(11),
+                Instruction::Lit("a".to_string()),
+                Instruction::Word,
+                Instruction::Quoted("b".to_string()),
+                Instruction::Word,
+                Instruction::Assign,
+                Instruction::Begin,
+                Instruction::Lit("cd".to_string()),
+                Instruction::Word,
+                Instruction::SetProgram,
+                Instruction::Exec(true),
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_exit_status() {
+        let mut runner = test_runner();
+        assert_eq!(expand_words(&mut runner, "echo $?\n"), vec!["echo", "0"]);
+
+        let mut runner = run_full_script_runner("false\n");
+        assert_eq!(
+            expand_words(&mut runner, "echo $? \"${?}\"\n"),
+            vec!["echo", "1", "1"]
+        );
+
+        let mut runner = run_full_script_runner("no_such_program_for_bite\n");
+        assert_eq!(expand_words(&mut runner, "echo $?\n"), vec!["echo", "127"]);
+
+        // The status is that of the last pipeline
+        let mut runner = run_full_script_runner("false || true\n");
+        assert_eq!(expand_words(&mut runner, "echo $?\n"), vec!["echo", "0"]);
+    }
+
+    /// Create an empty directory for tests that need files
+    fn test_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("bite-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn run_shopt() {
+        let runner = run_full_script_runner("shopt -s nullglob dotglob\nshopt -u dotglob\n");
+        assert_eq!(runner.last_exit_status, 0);
+        assert_eq!(
+            runner.shell_stack.variable_as_str("BASHOPTS").ok(),
+            Some("nullglob")
+        );
+
+        assert_eq!(run_full_script("shopt -s nullglob\nshopt -q nullglob\n"), 0);
+        assert_eq!(run_full_script("shopt -q nullglob\n"), 1);
+        assert_eq!(run_full_script("shopt -s no_such_option\n"), 1);
+
+        // Changes in a pipeline are lost
+        let runner = run_full_script_runner("shopt -s nullglob | true\n");
+        assert!(runner.shell_stack.find_variable("BASHOPTS").is_none());
+    }
+
+    #[test]
+    fn expand_glob() {
+        let dir = test_dir("expand_glob");
+        for name in &["a.txt", "b.txt", ".hidden.txt", "c.rs"] {
+            std::fs::File::create(dir.join(name)).unwrap();
+        }
+        let dir_name = dir.to_string_lossy().into_owned();
+        let file = |name: &str| format!("{}/{}", dir_name, name);
+
+        // By default, dot files are skipped and patterns without matches are kept
+        let mut runner = test_runner();
+        assert_eq!(
+            expand_words(&mut runner, &format!("ls {}/*.txt\n", dir_name)),
+            vec!["ls".to_string(), file("a.txt"), file("b.txt")]
+        );
+        assert_eq!(
+            expand_words(&mut runner, &format!("ls {}/*.none\n", dir_name)),
+            vec!["ls".to_string(), file("*.none")]
+        );
+
+        // Quoted pattern characters match literally
+        assert_eq!(
+            expand_words(&mut runner, &format!("ls {}/'*'.txt\n", dir_name)),
+            vec!["ls".to_string(), file("*.txt")]
+        );
+        assert_eq!(
+            expand_words(&mut runner, &format!("ls \"{}\"/?.rs\n", dir_name)),
+            vec!["ls".to_string(), file("c.rs")]
+        );
+
+        // Unquoted variables are expanded, quoted ones aren't
+        let script = format!("x={}/*.rs; ls $x \"$x\"\n", dir_name);
+        assert_eq!(
+            expand_words(&mut runner, &script),
+            vec!["ls".to_string(), file("c.rs"), file("*.rs")]
+        );
+
+        let mut runner = run_full_script_runner("shopt -s dotglob\n");
+        assert_eq!(
+            expand_words(&mut runner, &format!("ls {}/*.txt\n", dir_name)),
+            vec![
+                "ls".to_string(),
+                file(".hidden.txt"),
+                file("a.txt"),
+                file("b.txt")
+            ]
+        );
+
+        let mut runner = run_full_script_runner("shopt -s nullglob\n");
+        assert_eq!(
+            expand_words(&mut runner, &format!("ls {}/*.none\n", dir_name)),
+            vec!["ls"]
+        );
+
+        // With failglob, the command isn't run
+        let runner =
+            run_full_script_runner(&format!("shopt -s failglob\ntrue {}/*.none\n", dir_name));
+        assert_eq!(runner.last_exit_status, 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Compile and run a script, return the last exit status and the error output
+    fn run_full_script_errors(script: &str) -> (i32, String) {
+        let instructions = compile_full_script(script);
+        let mut session = new_test_session(b"test");
+        let handle = session.add_interaction(Screen::one_line_matrix(script.as_bytes()));
+        let mut runner = Runner::new(session.clone(), ContextStack::new());
+        runner.run(Arc::new(instructions), handle);
+        while session.has_feeding_threads(handle) {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        (runner.last_exit_status, session.response_texts(handle).1)
+    }
+
+    #[test]
+    fn report_glob_errors() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("report_glob_errors");
+        let locked = dir.join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        std::fs::File::create(locked.join("a.txt")).unwrap();
+        let dir_name = dir.to_string_lossy().into_owned();
+
+        let script = format!("shopt -s failglob\ntrue {}/*.none\n", dir_name);
+        assert_eq!(
+            run_full_script_errors(&script),
+            (1, format!("BiTE: no match: {}/*.none\n", dir_name))
+        );
+
+        // Root can search any directory
+        if !nix::unistd::getuid().is_root() {
+            std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+            let (es, errors) = run_full_script_errors(&format!("true {}/*/*.txt\n", dir_name));
+            std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+            assert_eq!(es, 0);
+            assert!(errors.starts_with(&format!("BiTE: {}/locked: ", dir_name)));
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn run_subshell_scope() {
+        let runner = run_full_script_runner("(a=1)\n");
+        assert!(runner.shell_stack.find_variable("a").is_none());
+
+        let runner = run_full_script_runner("{ a=1; }\n");
+        assert_eq!(runner.shell_stack.variable_as_str("a").ok(), Some("1"));
+
+        let runner = run_full_script_runner("a=1\n(a=2)\n");
+        assert_eq!(runner.shell_stack.variable_as_str("a").ok(), Some("1"));
+
+        let runner = run_full_script_runner("a=1\n{ a=2; }\n");
+        assert_eq!(runner.shell_stack.variable_as_str("a").ok(), Some("2"));
+    }
+
+    #[test]
+    fn run_subshell_working_dir() {
+        let cwd = nix::unistd::getcwd().unwrap();
+        let script = "(cd /; pwd)\n";
+        let instructions = compile_full_script(script);
+        let mut session = new_test_session(b"test");
+        let handle = session.add_interaction(Screen::one_line_matrix(script.as_bytes()));
+        let mut runner = Runner::new(session.clone(), ContextStack::new());
+        runner.run(Arc::new(instructions), handle);
+        while session.has_feeding_threads(handle) {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        // Programs in the subshell run in its directory, the process stays where it is
+        assert_eq!(session.response_texts(handle).0, "/\n");
+        assert_eq!(nix::unistd::getcwd().unwrap(), cwd);
+        assert_eq!(runner.shell_stack.working_dir(), None);
+    }
+
+    #[test]
+    fn reject_compound_commands_in_pipelines() {
+        for script in &["(true) | cat\n", "true | { cat; }\n"] {
+            assert_eq!(
+                parse_script(&script.to_string()),
+                Err(ScriptError::Error(
+                    "BiTE: Compound commands can't be part of a pipeline\n".to_string()
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn run_subshell_exit_status() {
+        assert_eq!(run_full_script("(true; false)\n"), 1);
+        assert_eq!(run_full_script("(false; true)\n"), 0);
+        assert_eq!(run_full_script("{ true; false; }\n"), 1);
+        assert_eq!(run_full_script("(false) || true\n"), 0);
+        assert_eq!(run_full_script("{ false; } && true\n"), 1);
+    }
+
+    #[test]
+    fn run_if() {
+        let runner = run_full_script_runner("if true; then a=1; else a=2; fi\n");
+        assert_eq!(runner.shell_stack.variable_as_str("a").ok(), Some("1"));
+
+        let runner = run_full_script_runner("if false; then a=1; else a=2; fi\n");
+        assert_eq!(runner.shell_stack.variable_as_str("a").ok(), Some("2"));
+
+        let runner =
+            run_full_script_runner("if false; then a=1; elif true; then a=3; else a=2; fi\n");
+        assert_eq!(runner.shell_stack.variable_as_str("a").ok(), Some("3"));
+
+        // Without a matching branch, the status is 0, otherwise the one of the branch
+        assert_eq!(run_full_script("if false; then true; fi\n"), 0);
+        assert_eq!(run_full_script("if true; then false; fi\n"), 1);
+        assert_eq!(run_full_script("if true; then true; fi && false\n"), 1);
+    }
+
+    #[test]
+    fn run_for() {
+        let runner = run_full_script_runner("l=x\nfor i in a b c; do l=$l$i; done\n");
+        assert_eq!(runner.shell_stack.variable_as_str("l").ok(), Some("xabc"));
+        assert_eq!(runner.shell_stack.variable_as_str("i").ok(), Some("c"));
+
+        // Words are split after expansion
+        let runner = run_full_script_runner("w='a b'\nn=\nfor i in $w \"$w\"; do n=$n.; done\n");
+        assert_eq!(runner.shell_stack.variable_as_str("n").ok(), Some("..."));
+
+        assert_eq!(run_full_script("for i in; do false; done\n"), 0);
+        assert_eq!(run_full_script("for i in a; do false; done\n"), 1);
+        assert_eq!(run_full_script("for i in a b; do true; done\n"), 0);
+    }
+
+    #[test]
+    fn run_while() {
+        let runner = run_full_script_runner("a=\nwhile [ -z \"$a\" ]; do a=x$a; done\n");
+        assert_eq!(runner.shell_stack.variable_as_str("a").ok(), Some("x"));
+        assert_eq!(runner.data_stack.depth(), 0);
+
+        // The status is the one of the last body, 0 if it never ran
+        assert_eq!(run_full_script("while false; do true; done\n"), 0);
+        assert_eq!(run_full_script("(false; while false; do true; done)\n"), 0);
+        assert_eq!(
+            run_full_script("a=\nwhile [ -z \"$a\" ]; do a=x; false; done\n"),
+            1
+        );
+        assert_eq!(
+            run_full_script("a=\nwhile [ -z \"$a\" ]; do false; a=x; done\n"),
+            0
+        );
+    }
+
+    #[test]
+    fn run_nested_loops() {
+        let runner = run_full_script_runner(
+            "l=\nn=\nwhile [ -z \"$n\" ]; do n=x\n\
+             for i in a b c; do if [ $i = b ]; then l=$l-; else l=$l$i; fi; done\n\
+             done\n",
+        );
+        assert_eq!(runner.shell_stack.variable_as_str("l").ok(), Some("a-c"));
+        assert_eq!(runner.data_stack.depth(), 0);
+        assert_eq!(runner.last_exit_status, 0);
+    }
+
+    #[test]
+    fn run_status_builtins() {
+        assert_eq!(run_full_script("true\n"), 0);
+        assert_eq!(run_full_script("false\n"), 1);
+        assert_eq!(run_full_script(":\n"), 0);
+        assert_eq!(run_full_script(": && false || true\n"), 0);
+        assert_eq!(run_full_script("true && false\n"), 1);
+    }
+
+    #[test]
+    fn run_exit_builtin() {
+        let runner = run_full_script_runner("exit 3\na=1\n");
+        assert_eq!(runner.last_exit_status, 3);
+        assert_eq!(runner.shell_stack.variable_as_str("a").ok(), None);
+        assert_eq!(runner.session.exit_request(), Some(3));
+
+        // Without a code, the status of the previous command is kept
+        let runner = run_full_script_runner("false\nexit\n");
+        assert_eq!(runner.last_exit_status, 1);
+        assert_eq!(runner.session.exit_request(), Some(1));
+
+        // A subshell exits only itself
+        let runner = run_full_script_runner("(exit 4; a=1)\nb=2\n");
+        assert_eq!(runner.last_exit_status, 0);
+        assert_eq!(runner.shell_stack.variable_as_str("b").ok(), Some("2"));
+        assert_eq!(runner.session.exit_request(), None);
+        assert_eq!(run_full_script("(exit 4)\n"), 4);
+
+        assert_eq!(run_full_script("exit 258\n"), 2);
+        assert_eq!(run_full_script("exit x\n"), 2);
+    }
+
     #[test]
     fn compile_logical_background() {
         let instructions = compile_full_script("ab cd | ef gh ij || stuff & xy z\n");
@@ -31,6 +31,12 @@ impl Stack {
         Self(Vec::new())
     }
 
+    /// Number of values on the stack
+    #[cfg(test)]
+    pub fn depth(&self) -> usize {
+        self.0.len()
+    }
+
     pub fn push_bool(&mut self, b: bool) {
         self.0.push(Value::Bool(b));
     }
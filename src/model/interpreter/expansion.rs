@@ -0,0 +1,706 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2018  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Word expansion.
+//!
+//! The script parser only determines the boundaries of the words. This module splits a word into
+//! the parts that need to be expanded differently and performs the expansions that depend on the
+//! state of the shell.
+
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take};
+use nom::character::complete::{alpha1, alphanumeric1, anychar, none_of, one_of};
+use nom::combinator::{eof, map, opt, peek, recognize, value};
+use nom::multi::{many0, many1};
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
+use nom::IResult;
+
+use glob::{glob_with, MatchOptions, Pattern};
+use nix::unistd::{getcwd, getuid, User};
+
+use super::parser::Span;
+use super::variables::ContextStack;
+
+/// Part of a word that needs to be expanded differently
+#[derive(Debug, PartialEq)]
+pub enum ExpSpan<'a> {
+    /// Text to be used as is
+    Verbatim(Span<'a>),
+
+    /// Quoted or escaped text. It must not be expanded any further.
+    Quoted(Span<'a>),
+
+    /// Tilde prefix. Contains the text between the tilde and the first slash.
+    Tilde(Span<'a>),
+
+    /// Variable outside of quotes. Its value will be split into words.
+    Variable(Span<'a>),
+
+    /// Variable inside double quotes. Its value is used as is.
+    QuotedVariable(Span<'a>),
+
+    /// Parameter expansion with an operator, e.g. `${name:-word}`. Contains the name, the
+    /// operator and the unexpanded word. The result will be split into words.
+    Operation(Span<'a>, Operator, Span<'a>),
+
+    /// Parameter expansion with an operator inside double quotes. The result is used as is.
+    QuotedOperation(Span<'a>, Operator, Span<'a>),
+
+    /// Length of the value of a variable, i.e. `${#name}`.
+    Length(Span<'a>),
+}
+
+/// Operator of a parameter expansion.
+///
+/// For the conditional operators, a variable counts as unset if it is empty.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operator {
+    /// `${name:-word}`: Use the word if the variable is unset
+    UseDefault,
+
+    /// `${name:=word}`: Assign the word to the variable if it is unset
+    AssignDefault,
+
+    /// `${name:+word}`: Use the word if the variable is set, nothing otherwise
+    UseAlternative,
+
+    /// `${name:?word}`: Report the word as an error if the variable is unset
+    ErrorIfUnset,
+
+    /// `${name#word}`: Remove the shortest prefix that matches the pattern
+    RemoveShortestPrefix,
+
+    /// `${name##word}`: Remove the longest prefix that matches the pattern
+    RemoveLongestPrefix,
+
+    /// `${name%word}`: Remove the shortest suffix that matches the pattern
+    RemoveShortestSuffix,
+
+    /// `${name%%word}`: Remove the longest suffix that matches the pattern
+    RemoveLongestSuffix,
+}
+
+/// Split a word into its expansions.
+///
+/// Expects the complete word as input. The quotes and escaping backslashes are removed.
+pub fn expansion(input: Span) -> IResult<Span, Vec<ExpSpan>> {
+    map(
+        tuple((
+            opt(tilde_prefix),
+            many0(alt((
+                map(single_quoted, |q| vec![q]),
+                double_quoted,
+                map(escaped, |e| vec![e]),
+                map(operation, |(n, c, w)| vec![ExpSpan::Operation(n, c, w)]),
+                map(length, |p| vec![ExpSpan::Length(p)]),
+                map(parameter, |p| vec![ExpSpan::Variable(p)]),
+                map(verbatim, |v| vec![v]),
+            ))),
+        )),
+        |(t, parts)| t.into_iter().chain(parts.into_iter().flatten()).collect(),
+    )(input)
+}
+
+/// Parse a tilde prefix at the start of a word.
+///
+/// If any letter of the prefix is quoted, it isn't a tilde prefix.
+fn tilde_prefix(input: Span) -> IResult<Span, ExpSpan> {
+    map(
+        terminated(
+            preceded(tag("~"), recognize(many0(none_of("/'\"\\")))),
+            peek(alt((tag("/"), eof))),
+        ),
+        ExpSpan::Tilde,
+    )(input)
+}
+
+/// Parse unquoted text without any expansions.
+///
+/// A dollar sign that doesn't start a parameter is taken literally.
+fn verbatim(input: Span) -> IResult<Span, ExpSpan> {
+    map(
+        alt((recognize(many1(none_of("'\"\\$"))), tag("$"))),
+        ExpSpan::Verbatim,
+    )(input)
+}
+
+/// Parse a parameter, i.e. $name or ${name}. Return the name.
+fn parameter(input: Span) -> IResult<Span, Span> {
+    let name = |i| alt((identifier, special_parameter))(i);
+    preceded(tag("$"), alt((delimited(tag("{"), name, tag("}")), name)))(input)
+}
+
+/// Parse a parameter expansion with an operator, e.g. ${name:-word} or ${name#word}. Return the
+/// name, the operator and the word.
+fn operation(input: Span) -> IResult<Span, (Span, Operator, Span)> {
+    let operator = alt((
+        value(Operator::UseDefault, tag(":-")),
+        value(Operator::AssignDefault, tag(":=")),
+        value(Operator::UseAlternative, tag(":+")),
+        value(Operator::ErrorIfUnset, tag(":?")),
+        value(Operator::RemoveLongestPrefix, tag("##")),
+        value(Operator::RemoveShortestPrefix, tag("#")),
+        value(Operator::RemoveLongestSuffix, tag("%%")),
+        value(Operator::RemoveShortestSuffix, tag("%")),
+    ));
+    delimited(
+        tag("${"),
+        tuple((alt((identifier, special_parameter)), operator, default_word)),
+        tag("}"),
+    )(input)
+}
+
+/// Parse the length of a parameter, i.e. ${#name}. Return the name.
+fn length(input: Span) -> IResult<Span, Span> {
+    delimited(tag("${#"), alt((identifier, special_parameter)), tag("}"))(input)
+}
+
+/// Parse the word of a parameter expansion with an operator up to the closing brace.
+///
+/// Quotes and nested parameters are kept, they will be expanded when the word is used.
+fn default_word(input: Span) -> IResult<Span, Span> {
+    recognize(many0(alt((
+        recognize(operation),
+        recognize(length),
+        recognize(parameter),
+        recognize(single_quoted),
+        recognize(double_quoted),
+        recognize(escaped),
+        recognize(none_of("}'\"\\")),
+    ))))(input)
+}
+
+/// Parse the name of a special parameter, e.g. ? for the exit status
+fn special_parameter(input: Span) -> IResult<Span, Span> {
+    recognize(one_of("?"))(input)
+}
+
+/// Parse the name of a variable
+fn identifier(input: Span) -> IResult<Span, Span> {
+    recognize(pair(
+        alt((alpha1, tag("_"))),
+        many0(alt((alphanumeric1, tag("_")))),
+    ))(input)
+}
+
+/// Parse a backslash and the letter it escapes
+fn escaped(input: Span) -> IResult<Span, ExpSpan> {
+    map(preceded(tag("\\"), recognize(anychar)), ExpSpan::Quoted)(input)
+}
+
+/// Parse a string in single quotes
+fn single_quoted(input: Span) -> IResult<Span, ExpSpan> {
+    map(
+        delimited(tag("'"), recognize(many0(none_of("'"))), tag("'")),
+        ExpSpan::Quoted,
+    )(input)
+}
+
+/// Parse a string in double quotes.
+///
+/// Backslashes only escape the letters that would be special inside the quotes. A backslash
+/// before a newline continues the line and is removed together with the newline. An empty string
+/// yields an empty part, so that the word is kept.
+fn double_quoted(input: Span) -> IResult<Span, Vec<ExpSpan>> {
+    let continuation = preceded(tag("\\\n"), take(0usize));
+    let escaped = preceded(tag("\\"), recognize(one_of("$`\"\\")));
+    let text = recognize(many1(none_of("\"\\$")));
+    let backslash = tag("\\");
+    let dollar = tag("$");
+    map(
+        delimited(
+            tag("\""),
+            tuple((
+                many0(alt((
+                    map(operation, |(n, c, w)| ExpSpan::QuotedOperation(n, c, w)),
+                    map(length, ExpSpan::Length),
+                    map(parameter, ExpSpan::QuotedVariable),
+                    map(
+                        alt((continuation, escaped, text, backslash, dollar)),
+                        ExpSpan::Quoted,
+                    ),
+                ))),
+                take(0usize),
+            )),
+            tag("\""),
+        ),
+        |(mut parts, empty)| {
+            if parts.is_empty() {
+                parts.push(ExpSpan::Quoted(empty));
+            }
+            parts
+        },
+    )(input)
+}
+
+/// Expand a tilde prefix.
+///
+/// * `~` is the value of HOME or the home directory of the current user.
+/// * `~+` is the value of PWD or the current directory.
+/// * `~-` is the value of OLDPWD.
+/// * `~name` is the home directory of user *name*.
+///
+/// If the prefix can't be expanded, it is returned unchanged, including the tilde.
+pub fn tilde(name: &str, shell_stack: &ContextStack) -> String {
+    let expanded = match name {
+        "" => shell_stack
+            .find_variable("HOME")
+            .map(|v| v.as_string().clone())
+            .or_else(|| {
+                User::from_uid(getuid())
+                    .ok()
+                    .and_then(|u| u)
+                    .map(|u| u.dir.to_string_lossy().into_owned())
+            }),
+        "+" => shell_stack
+            .find_variable("PWD")
+            .map(|v| v.as_string().clone())
+            .or_else(|| getcwd().ok().map(|d| d.to_string_lossy().into_owned())),
+        "-" => shell_stack
+            .find_variable("OLDPWD")
+            .map(|v| v.as_string().clone()),
+        _ => User::from_name(name)
+            .ok()
+            .and_then(|u| u)
+            .map(|u| u.dir.to_string_lossy().into_owned()),
+    };
+    expanded.unwrap_or_else(|| format!("~{}", name))
+}
+
+/// Options for pathname expansion
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GlobOptions {
+    /// Patterns that don't match anything are removed
+    pub nullglob: bool,
+
+    /// Patterns that don't match anything are an error
+    pub failglob: bool,
+
+    /// Patterns match file names starting with a dot
+    pub dotglob: bool,
+}
+
+/// Check if the text contains any pattern characters
+pub fn is_pattern(text: &str) -> bool {
+    text.contains(&['*', '?', '['][..])
+}
+
+/// Expand a pattern to the matching file names in alphabetical order.
+///
+/// Returns None if the pattern is invalid or nothing matches. Directories that could not be
+/// searched, e.g. for lack of permissions, are reported in `errors`.
+pub fn glob(pattern: &str, options: &GlobOptions, errors: &mut Vec<String>) -> Option<Vec<String>> {
+    let match_options = MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: !options.dotglob,
+    };
+    let mut names = Vec::new();
+    for entry in glob_with(pattern, match_options).ok()? {
+        match entry {
+            Ok(path) => names.push(path.to_string_lossy().into_owned()),
+            Err(e) => errors.push(format!("{}: {}", e.path().to_string_lossy(), e.error())),
+        }
+    }
+    if names.is_empty() {
+        None
+    } else {
+        Some(names)
+    }
+}
+
+/// Remove a prefix or a suffix that matches a pattern.
+///
+/// The operator selects which end of the value is matched and if the shortest or the longest match
+/// is removed. Other operators and invalid patterns leave the value unchanged.
+pub fn remove_matching(value: &str, pattern: &str, operator: Operator) -> String {
+    let pattern = match Pattern::new(pattern) {
+        Ok(pattern) => pattern,
+        Err(_) => return value.to_string(),
+    };
+    let match_options = MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: false,
+        require_literal_leading_dot: false,
+    };
+    let matches = |text: &str| pattern.matches_with(text, match_options);
+
+    // All positions between letters, including the ends
+    let mut bounds: Vec<usize> = value.char_indices().map(|(i, _)| i).collect();
+    bounds.push(value.len());
+
+    let found = match operator {
+        Operator::RemoveShortestPrefix => bounds.iter().find(|i| matches(&value[..**i])),
+        Operator::RemoveLongestPrefix => bounds.iter().rev().find(|i| matches(&value[..**i])),
+        Operator::RemoveShortestSuffix => bounds.iter().rev().find(|i| matches(&value[**i..])),
+        Operator::RemoveLongestSuffix => bounds.iter().find(|i| matches(&value[**i..])),
+        _ => None,
+    };
+    match (operator, found) {
+        (Operator::RemoveShortestPrefix, Some(i)) | (Operator::RemoveLongestPrefix, Some(i)) => {
+            value[*i..].to_string()
+        }
+        (Operator::RemoveShortestSuffix, Some(i)) | (Operator::RemoveLongestSuffix, Some(i)) => {
+            value[..*i].to_string()
+        }
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(offset: usize, line: u32, fragment: &str) -> Span {
+        unsafe { Span::new_from_raw_offset(offset, line, fragment, ()) }
+    }
+
+    #[test]
+    fn parse_tilde() {
+        assert_eq!(
+            expansion(Span::new("ab")),
+            Ok((span(2, 1, ""), vec![ExpSpan::Verbatim(span(0, 1, "ab"))]))
+        );
+        assert_eq!(
+            expansion(Span::new("~")),
+            Ok((span(1, 1, ""), vec![ExpSpan::Tilde(span(1, 1, ""))]))
+        );
+        assert_eq!(
+            expansion(Span::new("~ab/cd")),
+            Ok((
+                span(6, 1, ""),
+                vec![
+                    ExpSpan::Tilde(span(1, 1, "ab")),
+                    ExpSpan::Verbatim(span(3, 1, "/cd"))
+                ]
+            ))
+        );
+        assert_eq!(
+            expansion(Span::new("a~b")),
+            Ok((span(3, 1, ""), vec![ExpSpan::Verbatim(span(0, 1, "a~b"))]))
+        );
+    }
+
+    #[test]
+    fn parse_quotes() {
+        assert_eq!(
+            expansion(Span::new("'a$b c'")),
+            Ok((span(7, 1, ""), vec![ExpSpan::Quoted(span(1, 1, "a$b c"))]))
+        );
+        assert_eq!(
+            expansion(Span::new("\"a b\"")),
+            Ok((span(5, 1, ""), vec![ExpSpan::Quoted(span(1, 1, "a b"))]))
+        );
+        assert_eq!(
+            expansion(Span::new("a\\$b")),
+            Ok((
+                span(4, 1, ""),
+                vec![
+                    ExpSpan::Verbatim(span(0, 1, "a")),
+                    ExpSpan::Quoted(span(2, 1, "$")),
+                    ExpSpan::Verbatim(span(3, 1, "b")),
+                ]
+            ))
+        );
+
+        // Empty quotes keep the word
+        assert_eq!(
+            expansion(Span::new("\"\"")),
+            Ok((span(2, 1, ""), vec![ExpSpan::Quoted(span(1, 1, ""))]))
+        );
+        assert_eq!(
+            expansion(Span::new("''")),
+            Ok((span(2, 1, ""), vec![ExpSpan::Quoted(span(1, 1, ""))]))
+        );
+
+        // Inside double quotes, the backslash only escapes some letters
+        assert_eq!(
+            expansion(Span::new("\"a\\\"b\\c\"")),
+            Ok((
+                span(8, 1, ""),
+                vec![
+                    ExpSpan::Quoted(span(1, 1, "a")),
+                    ExpSpan::Quoted(span(3, 1, "\"")),
+                    ExpSpan::Quoted(span(4, 1, "b")),
+                    ExpSpan::Quoted(span(5, 1, "\\")),
+                    ExpSpan::Quoted(span(6, 1, "c")),
+                ]
+            ))
+        );
+
+        // Inside double quotes, a backslash before a newline continues the line
+        assert_eq!(
+            expansion(Span::new("\"a\\\nb\"")),
+            Ok((
+                span(6, 2, ""),
+                vec![
+                    ExpSpan::Quoted(span(1, 1, "a")),
+                    ExpSpan::Quoted(span(4, 2, "")),
+                    ExpSpan::Quoted(span(4, 2, "b")),
+                ]
+            ))
+        );
+
+        // A quoted tilde prefix isn't expanded
+        assert_eq!(
+            expansion(Span::new("~'ab'")),
+            Ok((
+                span(5, 1, ""),
+                vec![
+                    ExpSpan::Verbatim(span(0, 1, "~")),
+                    ExpSpan::Quoted(span(2, 1, "ab")),
+                ]
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_variables() {
+        assert_eq!(
+            expansion(Span::new("a$bc${d}e")),
+            Ok((
+                span(9, 1, ""),
+                vec![
+                    ExpSpan::Verbatim(span(0, 1, "a")),
+                    ExpSpan::Variable(span(2, 1, "bc")),
+                    ExpSpan::Variable(span(6, 1, "d")),
+                    ExpSpan::Verbatim(span(8, 1, "e")),
+                ]
+            ))
+        );
+        assert_eq!(
+            expansion(Span::new("\"a$b$\"")),
+            Ok((
+                span(6, 1, ""),
+                vec![
+                    ExpSpan::Quoted(span(1, 1, "a")),
+                    ExpSpan::QuotedVariable(span(3, 1, "b")),
+                    ExpSpan::Quoted(span(4, 1, "$")),
+                ]
+            ))
+        );
+
+        assert_eq!(
+            expansion(Span::new("$?${?}")),
+            Ok((
+                span(6, 1, ""),
+                vec![
+                    ExpSpan::Variable(span(1, 1, "?")),
+                    ExpSpan::Variable(span(4, 1, "?")),
+                ]
+            ))
+        );
+
+        // Single quotes inside double quotes don't stop the expansion and vice versa
+        assert_eq!(
+            expansion(Span::new("\"'$a'\"'\"$b\"'")),
+            Ok((
+                span(12, 1, ""),
+                vec![
+                    ExpSpan::Quoted(span(1, 1, "'")),
+                    ExpSpan::QuotedVariable(span(3, 1, "a")),
+                    ExpSpan::Quoted(span(4, 1, "'")),
+                    ExpSpan::Quoted(span(7, 1, "\"$b\"")),
+                ]
+            ))
+        );
+
+        // A lone dollar sign is kept
+        assert_eq!(
+            expansion(Span::new("$1$")),
+            Ok((
+                span(3, 1, ""),
+                vec![
+                    ExpSpan::Verbatim(span(0, 1, "$")),
+                    ExpSpan::Verbatim(span(1, 1, "1")),
+                    ExpSpan::Verbatim(span(2, 1, "$")),
+                ]
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_conditionals() {
+        assert_eq!(
+            expansion(Span::new("a${b:-c d}${e:=}")),
+            Ok((
+                span(16, 1, ""),
+                vec![
+                    ExpSpan::Verbatim(span(0, 1, "a")),
+                    ExpSpan::Operation(span(3, 1, "b"), Operator::UseDefault, span(6, 1, "c d")),
+                    ExpSpan::Operation(span(12, 1, "e"), Operator::AssignDefault, span(15, 1, "")),
+                ]
+            ))
+        );
+        assert_eq!(
+            expansion(Span::new("\"${?:+x}${a:?$b}\"")),
+            Ok((
+                span(17, 1, ""),
+                vec![
+                    ExpSpan::QuotedOperation(
+                        span(3, 1, "?"),
+                        Operator::UseAlternative,
+                        span(6, 1, "x")
+                    ),
+                    ExpSpan::QuotedOperation(
+                        span(10, 1, "a"),
+                        Operator::ErrorIfUnset,
+                        span(13, 1, "$b")
+                    ),
+                ]
+            ))
+        );
+
+        // Nested parameters and quoted braces belong to the word
+        assert_eq!(
+            expansion(Span::new("${a:-${b:-\\}}'}'}")),
+            Ok((
+                span(17, 1, ""),
+                vec![ExpSpan::Operation(
+                    span(2, 1, "a"),
+                    Operator::UseDefault,
+                    span(5, 1, "${b:-\\}}'}'")
+                )]
+            ))
+        );
+
+        // Without an operator, the braces are taken literally
+        assert_eq!(
+            expansion(Span::new("${a-b}")),
+            Ok((
+                span(6, 1, ""),
+                vec![
+                    ExpSpan::Verbatim(span(0, 1, "$")),
+                    ExpSpan::Verbatim(span(1, 1, "{a-b}")),
+                ]
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_removals() {
+        assert_eq!(
+            expansion(Span::new("${#a}${b#*.}${c##$d}")),
+            Ok((
+                span(20, 1, ""),
+                vec![
+                    ExpSpan::Length(span(3, 1, "a")),
+                    ExpSpan::Operation(
+                        span(7, 1, "b"),
+                        Operator::RemoveShortestPrefix,
+                        span(9, 1, "*.")
+                    ),
+                    ExpSpan::Operation(
+                        span(14, 1, "c"),
+                        Operator::RemoveLongestPrefix,
+                        span(17, 1, "$d")
+                    ),
+                ]
+            ))
+        );
+        assert_eq!(
+            expansion(Span::new("\"${a%x}${#?}${b%%}\"")),
+            Ok((
+                span(19, 1, ""),
+                vec![
+                    ExpSpan::QuotedOperation(
+                        span(3, 1, "a"),
+                        Operator::RemoveShortestSuffix,
+                        span(5, 1, "x")
+                    ),
+                    ExpSpan::Length(span(10, 1, "?")),
+                    ExpSpan::QuotedOperation(
+                        span(14, 1, "b"),
+                        Operator::RemoveLongestSuffix,
+                        span(17, 1, "")
+                    ),
+                ]
+            ))
+        );
+    }
+
+    #[test]
+    fn remove_prefix_and_suffix() {
+        let path = "/usr/lib/libfoo.so.1";
+        assert_eq!(
+            remove_matching(path, "*/", Operator::RemoveShortestPrefix),
+            "usr/lib/libfoo.so.1"
+        );
+        assert_eq!(
+            remove_matching(path, "*/", Operator::RemoveLongestPrefix),
+            "libfoo.so.1"
+        );
+        assert_eq!(
+            remove_matching(path, ".*", Operator::RemoveShortestSuffix),
+            "/usr/lib/libfoo.so"
+        );
+        assert_eq!(
+            remove_matching(path, ".*", Operator::RemoveLongestSuffix),
+            "/usr/lib/libfoo"
+        );
+
+        // Patterns match the whole prefix or suffix
+        assert_eq!(
+            remove_matching(path, "usr", Operator::RemoveShortestPrefix),
+            path
+        );
+        assert_eq!(
+            remove_matching(path, "[0-9]", Operator::RemoveLongestSuffix),
+            "/usr/lib/libfoo.so."
+        );
+        assert_eq!(
+            remove_matching("äöü", "?", Operator::RemoveShortestPrefix),
+            "öü"
+        );
+
+        // An empty pattern removes nothing
+        assert_eq!(
+            remove_matching(path, "", Operator::RemoveLongestSuffix),
+            path
+        );
+    }
+
+    #[test]
+    fn expand_tilde() {
+        let mut shell_stack = ContextStack::new();
+        assert!(shell_stack.bind_variable("HOME", "/home/test").is_ok());
+        assert!(shell_stack.bind_variable("PWD", "/current").is_ok());
+        assert!(shell_stack.bind_variable("OLDPWD", "/previous").is_ok());
+
+        assert_eq!(tilde("", &shell_stack), "/home/test");
+        assert_eq!(tilde("+", &shell_stack), "/current");
+        assert_eq!(tilde("-", &shell_stack), "/previous");
+
+        // The current user is known to exist
+        let user = User::from_uid(getuid()).unwrap().unwrap();
+        assert_eq!(
+            tilde(&user.name, &shell_stack),
+            user.dir.to_string_lossy().into_owned()
+        );
+
+        // Unknown users stay literal
+        assert_eq!(
+            tilde("no_such_user_for_bite", &shell_stack),
+            "~no_such_user_for_bite"
+        );
+
+        // Without OLDPWD, ~- stays literal
+        let shell_stack = ContextStack::new();
+        assert_eq!(tilde("-", &shell_stack), "~-");
+    }
+}
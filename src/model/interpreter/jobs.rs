@@ -31,16 +31,17 @@ use std::ffi::OsStr;
 use std::fs::File;
 use std::mem;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
-use std::os::unix::process::CommandExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::Path;
 use std::process::{Child, Command, ExitStatus, Stdio};
 use std::thread::{spawn, JoinHandle};
 use termios::os::target::*;
 use termios::*;
 
-use super::super::session::{InteractionHandle, OutputVisibility, SharedSession};
+use super::super::session::{InteractionHandle, OutputVisibility, RunningStatus, SharedSession};
 use super::builtins;
-use super::builtins::BuiltinRunner;
+use super::builtins::{BuiltinRunner, ShellServices};
+use super::variables::ContextStack;
 
 /// Public info about the job.
 ///
@@ -109,7 +110,8 @@ enum ProgramOrBuiltin {
 #[derive(Debug)]
 enum ChildOrThread {
     Child(Child),
-    Thread(JoinHandle<ExitStatus>),
+    /// Builtin, returns the state of the shell it ran in
+    Thread(JoinHandle<(ExitStatus, ContextStack)>),
 }
 
 /// Compute the matching control character of a letter
@@ -349,12 +351,16 @@ impl PipelineBuilder {
     ///
     /// If it's the last program in the pipeline, connect to the command_side of the stdout/stderr
     /// pts, otherwise create them as pipes.
+    ///
+    /// Builtins run on a copy of `shell_stack` and can access `services`.
     pub fn start<I, S>(
         &mut self,
         is_last: bool,
         window_width: usize,
         window_height: usize,
         args: I,
+        shell_stack: &ContextStack,
+        services: &ShellServices,
     ) -> Result<(), String>
     where
         I: IntoIterator<Item = S> + std::fmt::Debug,
@@ -377,6 +383,9 @@ impl PipelineBuilder {
                 cmd.args(args)
                     .env("TERM", "xterm")
                     .stdin(unsafe { Stdio::from_raw_fd(self.prev_stdout) });
+                if let Some(dir) = shell_stack.working_dir() {
+                    cmd.current_dir(dir);
+                }
 
                 // If stderr isn't redirected, it will go out to the pts directly.
                 {
@@ -461,13 +470,18 @@ impl PipelineBuilder {
                 // Insert a fake argv[0]. Replace with name of builtin if that is important.
                 args.insert(0, "builtin".to_string());
                 let b = b.clone();
+                let mut shell_stack = shell_stack.clone();
+                let services = services.clone();
                 trace!("about to spawn thread");
                 let t = spawn(move || {
-                    b(
+                    let es = b(
                         args,
+                        &mut shell_stack,
+                        &services,
                         &mut unsafe { File::from_raw_fd(stdout_command_side) },
                         &mut unsafe { File::from_raw_fd(stderr_pair.command_side) },
-                    )
+                    );
+                    (es, shell_stack)
                 });
                 trace!("thread spawned");
 
@@ -502,8 +516,16 @@ impl PipelineBuilder {
         }
     }
 
-    // Run the programs in the pipeline to completion, return the exit status of the last program
-    pub fn wait(self, session: SharedSession, interaction_handle: InteractionHandle) -> i32 {
+    /// Run the programs in the pipeline to completion, return the exit status of the last program.
+    ///
+    /// If the pipeline consists of a single builtin, it runs in the current shell. In that case,
+    /// the changed shell state is returned as well. Otherwise, the changes are lost as if the
+    /// builtins ran in subshells.
+    pub fn wait(
+        self,
+        session: SharedSession,
+        interaction_handle: InteractionHandle,
+    ) -> (RunningStatus, Option<ContextStack>) {
         let mut reader_threads = Vec::new();
 
         // Start a reader thread for each stderr
@@ -529,7 +551,9 @@ impl PipelineBuilder {
             reader_threads.push(jh);
         }
 
-        let mut exit_status = 0;
+        let mut exit_status = RunningStatus::Exited(0);
+        let mut shell_stack = None;
+        let single_command = self.children.len() == 1;
         // Waiting for the reader threads to complete  doesn't require locking the mutex around
         // the children, but it also does not catch all cases (e.g. gvim going into background)
         // because the file handle might be passed down to the forked process and still be open.
@@ -538,30 +562,65 @@ impl PipelineBuilder {
         // (incorrectly) block until gvim exits.
         //
         // Instead, get the process ids and call waitpid.
-        for pid in self.child_pids() {
-            // Wait for each child, report on the exit code of each failing program. Keep the exit code
-            // of the last failing program.
-            trace!("Waiting for pid {:?}", pid);
-            match waitpid(Some(Pid::from_raw(pid as i32)), None) {
-                Err(e) => {
-                    debug!("Error waiting for pid: »{:?}«", e);
-                }
-                Ok(WaitStatus::Exited(_, es)) => {
-                    exit_status = es;
+        //
+        // Builtins run in threads, so join them to get their exit code.
+        for cot in self.children.into_iter() {
+            // Wait for each child, keep the exit code of the last program in the pipeline.
+            match cot {
+                ChildOrThread::Child(c) => {
+                    let pid = c.id();
+                    trace!("Waiting for pid {:?}", pid);
+                    match waitpid(Some(Pid::from_raw(pid as i32)), None) {
+                        Err(e) => {
+                            debug!("Error waiting for pid: »{:?}«", e);
+                        }
+                        Ok(WaitStatus::Exited(_, es)) => {
+                            exit_status = RunningStatus::Exited(es);
+                        }
+                        Ok(WaitStatus::Signaled(_, sig, _)) => {
+                            exit_status = RunningStatus::Signaled(sig as i32);
+                        }
+                        ret => {
+                            debug!("waitpid returned with unexpected reason: {:?}", ret);
+                        }
+                    }
                 }
-                ret => {
-                    debug!("waitpid returned with unexpected reason: {:?}", ret);
+                ChildOrThread::Thread(t) => {
+                    trace!("Waiting for builtin thread");
+                    match t.join() {
+                        Ok((es, stack)) => {
+                            // Builtins store their exit code as the raw value
+                            exit_status = RunningStatus::Exited(es.into_raw());
+                            if single_command {
+                                shell_stack = Some(stack);
+                            }
+                        }
+                        Err(e) => {
+                            debug!("Builtin thread panicked: »{:?}«", e);
+                            exit_status = RunningStatus::Exited(1);
+                        }
+                    }
                 }
             }
         }
         // Close bite's side of stdin
         let _ = close(self.stdin_bite_side);
 
-        exit_status
+        (exit_status, shell_stack)
     }
 }
 
 impl Job {
+    /// Job without programs that sends its stdin to the given file descriptor
+    #[cfg(test)]
+    pub fn with_stdin(stdin_bite_side: RawFd) -> Self {
+        Job {
+            stdin_bite_side,
+            other_bite_side: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
     /// Terminate all the programs
     pub fn terminate(&self) {
         for pid in self.children.iter() {
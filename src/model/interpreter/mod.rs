@@ -23,9 +23,10 @@
 use super::session::{InteractionHandle, OutputVisibility, RunningStatus, SharedSession};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 use super::screen::Screen;
 use tools::logging::unwrap_log;
@@ -33,13 +34,19 @@ use tools::logging::unwrap_log;
 mod builtins;
 mod byte_code;
 mod data_stack;
+mod expansion;
 pub mod grammar;
 pub mod jobs;
 mod parser;
 mod variables;
 
+pub use self::builtins::history::CommandLog;
+
 use self::variables::ContextStack;
 
+/// Milliseconds shutdown waits for a running command by default
+pub const DEFAULT_SHUTDOWN_TIMEOUT_MS: u64 = 2000;
+
 pub struct StartupInterpreter {
     /// Session to print output to.
     session: SharedSession,
@@ -55,6 +62,12 @@ pub struct InteractiveInterpreter {
     /// Interpreter thread
     thread: JoinHandle<()>,
 
+    /// Receives a message when the interpreter thread ends
+    stopped: Receiver<()>,
+
+    /// How long shutdown waits for the interpreter thread to end
+    shutdown_timeout: Duration,
+
     /// Channel to send instructions to be run in a given interaction
     ///
     /// If the data holds None, the thread is to be stopped.
@@ -72,6 +85,7 @@ pub struct InteractiveInterpreter {
 /// Processing function that gets input from the mutex
 fn interpreter_loop(
     mut runner: byte_code::Runner,
+    session: SharedSession,
     is_running: Arc<AtomicBool>,
     is_busy: Arc<Mutex<Option<InteractionHandle>>>,
     input: Receiver<Option<(byte_code::Instructions, InteractionHandle)>>,
@@ -82,16 +96,45 @@ fn interpreter_loop(
         if let Ok(Some((instructions, interaction_handle))) = input.recv() {
             trace!("Got instructions: »{:?}«", instructions);
             *is_busy.lock().unwrap() = Some(interaction_handle);
+            session.wake_up();
             runner.run(Arc::new(instructions), interaction_handle);
             *is_busy.lock().unwrap() = None;
+            session.wake_up();
         } else {
             return;
         }
     }
 }
 
-/// Parse a (partial) script and either return the byte code array or an error message
-pub fn parse_script(script: &String) -> Result<byte_code::Instructions, String> {
+/// Output and exit status of a command run outside of the GUI
+#[derive(Debug, PartialEq)]
+pub struct CommandResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_status: i32,
+}
+
+/// Reasons why a script can't be run
+#[derive(Debug, PartialEq)]
+pub enum ScriptError {
+    /// The script ended before the last command was complete. More lines are needed.
+    Incomplete,
+
+    /// The script can't be parsed or compiled. The message explains why.
+    Error(String),
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ScriptError::Incomplete => write!(f, "Syntax error: unexpected end of script\n"),
+            ScriptError::Error(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// Parse a (partial) script and either return the byte code array or an error
+pub fn parse_script(script: &String) -> Result<byte_code::Instructions, ScriptError> {
     let mut instructions: byte_code::Instructions = Vec::new();
 
     let mut input = parser::Span::new(script);
@@ -99,26 +142,20 @@ pub fn parse_script(script: &String) -> Result<byte_code::Instructions, String>
         match parser::script(input) {
             Ok((rest, ast)) => {
                 // Compile AST into instructions
-                byte_code::compile(&mut instructions, ast)?;
+                byte_code::compile(&mut instructions, &ast).map_err(ScriptError::Error)?;
                 input = rest;
             }
-            Err(nom::Err::Incomplete(needed)) => {
-                // TODO: Create error message
-                let mut msg = String::new();
-                msg.push_str(&format!("Incomplete: {:?}", needed));
-                return Err(msg);
-            }
-            Err(nom::Err::Error(code)) => {
-                // TODO: Create error message
-                let mut msg = String::new();
-                msg.push_str(&format!("Error: {:?}", code));
-                return Err(msg);
+            Err(nom::Err::Incomplete(_)) => {
+                // The parser only uses complete parsers, so this shouldn't happen.
+                return Err(ScriptError::Incomplete);
             }
-            Err(nom::Err::Failure(code)) => {
-                // TODO: Create error message
-                let mut msg = String::new();
-                msg.push_str(&format!("Failure: {:?}", code));
-                return Err(msg);
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                // If the parser fails at the end, more input could complete the script.
+                return Err(if e.input.fragment().trim().is_empty() {
+                    ScriptError::Incomplete
+                } else {
+                    ScriptError::Error(format_error_message(script, e.input))
+                });
             }
         }
     }
@@ -126,6 +163,22 @@ pub fn parse_script(script: &String) -> Result<byte_code::Instructions, String>
     Ok(instructions)
 }
 
+/// Create a readable error message that marks the position of the error in the script
+fn format_error_message(script: &str, position: parser::Span) -> String {
+    let line_number = position.location_line();
+    let line = script
+        .lines()
+        .nth(line_number as usize - 1)
+        .unwrap_or_default();
+    format!(
+        "Syntax error in line {}:\n{}\n{:>column$}---- error here\n",
+        line_number,
+        line,
+        "^",
+        column = position.get_utf8_column()
+    )
+}
+
 impl StartupInterpreter {
     /// Create a new interpreter.
     pub fn new(session: SharedSession) -> Self {
@@ -135,6 +188,11 @@ impl StartupInterpreter {
         Self { session, runner }
     }
 
+    /// Give the history builtin access to the entered commands
+    pub fn set_command_log(&mut self, log: Arc<dyn CommandLog>) {
+        self.runner.services.history = Some(log);
+    }
+
     /// Run a script in a given interaction
     fn run_script(&mut self, script_name: &PathBuf, interaction_handle: InteractionHandle) {
         match std::fs::File::open(script_name) {
@@ -210,6 +268,39 @@ impl StartupInterpreter {
         interaction
     }
 
+    /// Run a single command in a new interaction and wait until all its output has arrived.
+    pub fn run_command(&mut self, command: &str) -> CommandResult {
+        trace!("Want to run command »{}«", command);
+        let interaction = self
+            .session
+            .add_interaction(Screen::one_line_matrix(command.as_bytes()));
+        let mut script = command.to_string();
+        if !script.ends_with('\n') {
+            script.push('\n');
+        }
+        match parse_script(&script) {
+            Ok(instructions) => self.runner.run(Arc::new(instructions), interaction),
+            Err(msg) => {
+                self.session.add_bytes(
+                    OutputVisibility::Error,
+                    interaction,
+                    format!("BiTE: {}", msg).as_bytes(),
+                );
+                self.session
+                    .set_running_status(interaction, RunningStatus::Exited(2));
+            }
+        }
+        while self.session.has_feeding_threads(interaction) {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let (stdout, stderr) = self.session.response_texts(interaction);
+        CommandResult {
+            stdout,
+            stderr,
+            exit_status: self.session.exit_status(interaction).unwrap_or(1),
+        }
+    }
+
     /// Start the interpreter threat and transfer the interpreter state to it.
     ///
     /// Return the interface to this thread
@@ -217,19 +308,26 @@ impl StartupInterpreter {
         let is_running = Arc::new(AtomicBool::new(true));
         let is_busy = Arc::new(Mutex::new(None));
         let (sender, receiver) = channel();
+        let (stopped_sender, stopped) = channel();
         let thread = {
             let is_running = is_running.clone();
             let is_busy = is_busy.clone();
             let runner = self.runner;
+            let session = self.session.clone();
             std::thread::Builder::new()
                 .name("interpreter".to_string())
-                .spawn(move || interpreter_loop(runner, is_running, is_busy, receiver))
+                .spawn(move || {
+                    interpreter_loop(runner, session, is_running, is_busy, receiver);
+                    let _ = stopped_sender.send(());
+                })
                 .unwrap()
         };
 
         InteractiveInterpreter {
             session: self.session,
             thread,
+            stopped,
+            shutdown_timeout: Duration::from_millis(DEFAULT_SHUTDOWN_TIMEOUT_MS),
             sender,
             is_running,
             is_busy,
@@ -257,13 +355,34 @@ impl InteractiveInterpreter {
         interaction
     }
 
+    /// Set how long shutdown waits for a running command
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = timeout;
+    }
+
     /// Shut down the interpreter.
     ///
-    /// This function will block until the interpreter has completed the last command.
-    pub fn shutdown(self) {
+    /// A running command is terminated first. This function will block until the interpreter has
+    /// completed the last command, but not longer than the shutdown timeout. If the command
+    /// doesn't end in time, e.g. because it ignores the signal, the interpreter thread is left
+    /// behind so that the application can exit anyway.
+    pub fn shutdown(mut self) {
         self.is_running.store(false, Ordering::Release);
         let _ = self.sender.send(None);
-        let _ = self.thread.join();
+        if let Some(handle) = self.is_busy() {
+            self.session.terminate(handle);
+        }
+        match self.stopped.recv_timeout(self.shutdown_timeout) {
+            Err(RecvTimeoutError::Timeout) => {
+                warn!(
+                    "Interpreter did not stop within {:?}, leaving it behind",
+                    self.shutdown_timeout
+                );
+            }
+            _ => {
+                let _ = self.thread.join();
+            }
+        }
     }
 
     /// Get the current working directory
@@ -281,3 +400,101 @@ impl InteractiveInterpreter {
         handle
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::session::test::new_test_session;
+    use nix::sys::signal::{killpg, Signal};
+    use nix::unistd::Pid;
+
+    #[test]
+    fn run_command() {
+        let mut interpreter = StartupInterpreter::new(new_test_session(b"test"));
+        assert_eq!(
+            interpreter.run_command("echo hi"),
+            CommandResult {
+                stdout: "hi\n".to_string(),
+                stderr: String::new(),
+                exit_status: 0,
+            }
+        );
+        assert_eq!(interpreter.run_command("false").exit_status, 1);
+        assert_eq!(interpreter.run_command("exit 3").exit_status, 3);
+        assert_eq!(interpreter.run_command("a | | b").exit_status, 2);
+    }
+
+    #[test]
+    fn shutdown_leaves_wedged_command_behind() {
+        let session = new_test_session(b"test");
+        let mut interpreter = StartupInterpreter::new(session.clone()).complete_startup();
+        interpreter.set_shutdown_timeout(Duration::from_millis(100));
+
+        // The command ignores the termination signal. It reports its PID to be killed later.
+        let script = "sh -c 'echo $$; trap \"\" TERM; sleep 1'\n".to_string();
+        let handle = interpreter.run(script.clone(), parse_script(&script).unwrap());
+        let pid = loop {
+            let (output, _) = session.response_texts(handle);
+            if let Ok(pid) = output.trim().parse::<i32>() {
+                break pid;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+
+        let start = std::time::Instant::now();
+        interpreter.shutdown();
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // Don't leave the command running after the test
+        let _ = killpg(Pid::from_raw(pid), Signal::SIGKILL);
+    }
+
+    #[test]
+    fn parse_error_message() {
+        assert_eq!(
+            parse_script(&"ab | | cd\n".to_string()).err(),
+            Some(ScriptError::Error(
+                "Syntax error in line 1:\nab | | cd\n     ^---- error here\n".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_script(&"ab\ncd && ; ef\n".to_string()).err(),
+            Some(ScriptError::Error(
+                "Syntax error in line 2:\ncd && ; ef\n      ^---- error here\n".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_script(&"ab )\n".to_string()).err(),
+            Some(ScriptError::Error(
+                "Syntax error in line 1:\nab )\n   ^---- error here\n".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_incomplete() {
+        // Each script is only complete with its last line
+        let scripts = [
+            vec!["( ab\n", "cd\n", ")\n"],
+            vec!["{ ab\n", "cd; }\n"],
+            vec!["ab |\n", "cd\n"],
+            vec!["ab &&\n", "\n", "cd\n"],
+            vec!["echo 'ab\n", "cd'\n"],
+            vec!["echo \"ab\n", "cd\"\n"],
+            vec!["if true; then\n", "ab\n", "fi\n"],
+            vec!["for x in ab\n", "do cd\n", "done\n"],
+        ];
+        for lines in scripts.iter() {
+            let mut script = String::new();
+            for (i, line) in lines.iter().enumerate() {
+                script.push_str(line);
+                let res = parse_script(&script);
+                if i + 1 < lines.len() {
+                    assert_eq!(res.err(), Some(ScriptError::Incomplete), "{:?}", script);
+                } else {
+                    assert!(res.is_ok(), "{:?}", script);
+                }
+            }
+        }
+    }
+}
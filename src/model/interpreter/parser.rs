@@ -19,11 +19,13 @@
 //! Bash script parser.
 
 use nom::branch::alt;
-use nom::bytes::complete::tag;
-use nom::character::complete::{line_ending, none_of, not_line_ending, space0, space1};
-use nom::combinator::{map, recognize};
-use nom::multi::{many1, separated_list1};
-use nom::sequence::{preceded, terminated, tuple};
+use nom::bytes::complete::{tag, take};
+use nom::character::complete::{
+    anychar, line_ending, multispace0, multispace1, none_of, not_line_ending, space0, space1,
+};
+use nom::combinator::{cut, map, not, opt, peek, recognize};
+use nom::multi::{many0, many1, many_till, separated_list1};
+use nom::sequence::{delimited, preceded, terminated, tuple};
 use nom::IResult;
 
 use nom_locate::LocatedSpan;
@@ -90,6 +92,26 @@ pub struct PipelineCommand<'a> {
 pub enum Command<'a> {
     /// Call a program
     Program(Vec<Span<'a>>),
+    /// Run a list of commands in a subshell, i.e. ( list )
+    Subshell(Vec<AbstractSyntaxTree<'a>>),
+    /// Run a list of commands in the current shell, i.e. { list; }
+    Group(Vec<AbstractSyntaxTree<'a>>),
+    /// Run the body of the first branch whose condition succeeds, i.e.
+    /// if list; then list; [elif list; then list;] ... [else list;] fi
+    ///
+    /// (branches as (condition, body), else branch)
+    If(
+        Vec<(Vec<AbstractSyntaxTree<'a>>, Vec<AbstractSyntaxTree<'a>>)>,
+        Option<Vec<AbstractSyntaxTree<'a>>>,
+    ),
+    /// Run a list once for each word, i.e. for name [in words]; do list; done
+    ///
+    /// (variable name, words, body)
+    For(Span<'a>, Vec<Span<'a>>, Vec<AbstractSyntaxTree<'a>>),
+    /// Run a list as long as the condition succeeds, i.e. while list; do list; done
+    ///
+    /// (condition, body)
+    While(Vec<AbstractSyntaxTree<'a>>, Vec<AbstractSyntaxTree<'a>>),
 }
 
 /// Parse a (partial) bash script.
@@ -170,6 +192,11 @@ where
     }
 }
 
+/// Version of nom's separated_nonempty_list that can fix the last parsed output by the value of
+/// the separator.
+///
+/// In contrast to nom, an element must follow each separator. If it doesn't, parsing fails at the
+/// position where the element was expected.
 pub fn separated_nonempty_list_fix<I, O, O2, E, F, G, Fix>(
     sep: G,
     f: F,
@@ -215,7 +242,7 @@ where
                     }
 
                     match f(i1.clone()) {
-                        Err(nom::Err::Error(_)) => return Ok((i, res)),
+                        Err(nom::Err::Error(e)) => return Err(nom::Err::Failure(e)),
                         Err(e) => return Err(e),
                         Ok((i2, o)) => {
                             if i2 == i {
@@ -261,6 +288,8 @@ fn logical(input: Span) -> IResult<Span, AbstractSyntaxTree> {
         map(tag("&"), |_| BackgroundMode::Background),
         map(tag(";"), |_| BackgroundMode::Foreground),
         map(line_ending, |_| BackgroundMode::Foreground),
+        // The last command of a subshell doesn't need a separator. Don't consume the parenthesis.
+        map(peek(tag(")")), |_| BackgroundMode::Foreground),
     ));
     map(
         tuple((
@@ -273,14 +302,15 @@ fn logical(input: Span) -> IResult<Span, AbstractSyntaxTree> {
     )(input)
 }
 
-/// Parse a logical operator
+/// Parse a logical operator. The next pipeline can start on another line.
 fn logical_operator(input: Span) -> IResult<Span, LogicalOperator> {
-    preceded(
+    delimited(
         space0,
         alt((
             map(tag("&&"), |_| LogicalOperator::And),
             map(tag("||"), |_| LogicalOperator::Or),
         )),
+        multispace0,
     )(input)
 }
 
@@ -298,48 +328,266 @@ fn pipeline(input: Span) -> IResult<Span, Pipeline> {
     preceded(space0, pipe)(input)
 }
 
-/// Pipeline operator, i.e. | or |&
-///
-/// TODO: Newlines are allowed after the operators.
+/// Pipeline operator, i.e. | or |&. The next command can start on another line.
 fn pipeline_operator(input: Span) -> IResult<Span, PipelineOperator> {
     let operators = alt((
         map(tag("|&"), |_| PipelineOperator::StderrAndStdout),
-        map(tag("|"), |_| PipelineOperator::StdoutOnly),
+        // Don't take the first half of a logical or
+        map(terminated(tag("|"), not(tag("|"))), |_| {
+            PipelineOperator::StdoutOnly
+        }),
     ));
-    preceded(space0, operators)(input)
+    delimited(space0, operators, multispace0)(input)
 }
 
 /// Down to basic commands
 fn command(input: Span) -> IResult<Span, PipelineCommand> {
-    map(simple_command, |c| PipelineCommand {
-        command: c,
-        operator: PipelineOperator::Nothing,
-    })(input)
+    map(
+        alt((
+            subshell,
+            group,
+            if_command,
+            for_command,
+            while_command,
+            simple_command,
+        )),
+        |c| PipelineCommand {
+            command: c,
+            operator: PipelineOperator::Nothing,
+        },
+    )(input)
 }
 
-fn simple_command(input: Span) -> IResult<Span, Command> {
+/// Parse a list of commands until the closing token. The closing token is not consumed.
+fn compound_list<'a>(
+    closing: &'static str,
+) -> impl Fn(Span<'a>) -> IResult<Span<'a>, Vec<AbstractSyntaxTree<'a>>> {
+    move |input| {
+        map(
+            many_till(script, peek(preceded(multispace0, tag(closing)))),
+            |(list, _)| list,
+        )(input)
+    }
+}
+
+/// Parse a subshell, i.e. ( list )
+fn subshell(input: Span) -> IResult<Span, Command> {
+    map(
+        preceded(
+            tuple((space0, tag("("))),
+            cut(terminated(
+                compound_list(")"),
+                tuple((multispace0, tag(")"))),
+            )),
+        ),
+        |list| Command::Subshell(list),
+    )(input)
+}
+
+/// Parse a group command, i.e. { list; }
+///
+/// As in bash, the braces are reserved words and need to be separated from the list.
+fn group(input: Span) -> IResult<Span, Command> {
+    map(
+        preceded(
+            tuple((space0, tag("{"), multispace1)),
+            cut(terminated(
+                compound_list("}"),
+                tuple((multispace0, tag("}"))),
+            )),
+        ),
+        |list| Command::Group(list),
+    )(input)
+}
+
+/// Parse a reserved word. It must not be the start of a longer word.
+fn reserved<'a>(keyword: &'static str) -> impl Fn(Span<'a>) -> IResult<Span<'a>, Span<'a>> {
+    move |input| terminated(tag(keyword), not(word_letter))(input)
+}
+
+/// Parse a list of commands until one of the given reserved words. The reserved word is not
+/// consumed.
+fn reserved_list<'a>(
+    closing: &'static [&'static str],
+) -> impl Fn(Span<'a>) -> IResult<Span<'a>, Vec<AbstractSyntaxTree<'a>>> {
+    move |input| {
+        let any_closing = |input: Span<'a>| -> IResult<Span<'a>, Span<'a>> {
+            for keyword in closing.iter() {
+                if let Ok(res) = reserved(*keyword)(input) {
+                    return Ok(res);
+                }
+            }
+            Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::Tag,
+            )))
+        };
+        map(
+            many_till(script, peek(preceded(multispace0, any_closing))),
+            |(list, _)| list,
+        )(input)
+    }
+}
+
+/// Parse a condition and its body, i.e. list; then list;
+fn if_branch(input: Span) -> IResult<Span, (Vec<AbstractSyntaxTree>, Vec<AbstractSyntaxTree>)> {
+    map(
+        tuple((
+            reserved_list(&["then"]),
+            multispace0,
+            reserved("then"),
+            reserved_list(&["elif", "else", "fi"]),
+            multispace0,
+        )),
+        |(condition, _, _, body, _)| (condition, body),
+    )(input)
+}
+
+/// Parse an if command, i.e. if list; then list; [elif list; then list;] ... [else list;] fi
+fn if_command(input: Span) -> IResult<Span, Command> {
     map(
-        preceded(space0, separated_list1(space1, word)),
-        |words| Command::Program(words),
+        preceded(
+            tuple((space0, reserved("if"))),
+            cut(tuple((
+                if_branch,
+                many0(preceded(reserved("elif"), cut(if_branch))),
+                opt(preceded(reserved("else"), reserved_list(&["fi"]))),
+                multispace0,
+                reserved("fi"),
+            ))),
+        ),
+        |(first, mut branches, otherwise, _, _)| {
+            branches.insert(0, first);
+            Command::If(branches, otherwise)
+        },
     )(input)
 }
 
+/// Parse a for command, i.e. for name [in words]; do list; done
+///
+/// Without `in`, the list of words is empty as there are no positional parameters.
+fn for_command(input: Span) -> IResult<Span, Command> {
+    map(
+        preceded(
+            tuple((space0, reserved("for"), space1)),
+            cut(tuple((
+                word,
+                opt(preceded(
+                    tuple((space1, reserved("in"))),
+                    many0(preceded(space1, word)),
+                )),
+                space0,
+                alt((tag(";"), line_ending)),
+                multispace0,
+                reserved("do"),
+                reserved_list(&["done"]),
+                multispace0,
+                reserved("done"),
+            ))),
+        ),
+        |(name, words, _, _, _, _, body, _, _)| Command::For(name, words.unwrap_or_default(), body),
+    )(input)
+}
+
+/// Parse a while command, i.e. while list; do list; done
+fn while_command(input: Span) -> IResult<Span, Command> {
+    map(
+        preceded(
+            tuple((space0, reserved("while"))),
+            cut(tuple((
+                reserved_list(&["do"]),
+                multispace0,
+                reserved("do"),
+                reserved_list(&["done"]),
+                multispace0,
+                reserved("done"),
+            ))),
+        ),
+        |(condition, _, _, body, _, _)| Command::While(condition, body),
+    )(input)
+}
+
+fn simple_command(input: Span) -> IResult<Span, Command> {
+    map(preceded(space0, separated_list1(space1, word)), |words| {
+        Command::Program(words)
+    })(input)
+}
+
+/// Parse a word including quoted parts. The quotes are removed during expansion.
 fn word(input: Span) -> IResult<Span, Span> {
-    recognize(many1(word_letter))(input)
+    recognize(many1(alt((
+        single_quoted,
+        double_quoted,
+        escaped_letter,
+        braced_parameter,
+        recognize(word_letter),
+    ))))(input)
 }
 
 fn word_letter(input: Span) -> IResult<Span, char> {
-    none_of(" \n\t\"\'|&;()<>=")(input)
+    none_of(" \n\t\"\'\\|&;()<>=")(input)
+}
+
+/// Parse a backslash and the letter it escapes
+fn escaped_letter(input: Span) -> IResult<Span, Span> {
+    recognize(tuple((tag("\\"), anychar)))(input)
+}
+
+/// Parse a string in single quotes. The string can span several lines.
+fn single_quoted(input: Span) -> IResult<Span, Span> {
+    recognize(preceded(
+        tag("'"),
+        cut(tuple((many0(none_of("'")), tag("'")))),
+    ))(input)
+}
+
+/// Parse a string in double quotes. Backslashes escape the next letter. The string can span
+/// several lines.
+fn double_quoted(input: Span) -> IResult<Span, Span> {
+    recognize(preceded(
+        tag("\""),
+        cut(tuple((
+            many0(alt((escaped_letter, recognize(none_of("\"\\"))))),
+            tag("\""),
+        ))),
+    ))(input)
 }
 
+/// Parse a parameter in braces. The braces can contain separators, e.g. in `${name:-a b}`.
+fn braced_parameter(input: Span) -> IResult<Span, Span> {
+    recognize(preceded(
+        tag("${"),
+        cut(tuple((
+            many0(alt((
+                single_quoted,
+                double_quoted,
+                escaped_letter,
+                braced_parameter,
+                recognize(none_of("}'\"\\")),
+            ))),
+            tag("}"),
+        ))),
+    ))(input)
+}
+
+/// Parse an assignment. The value can be empty.
 fn single_assignment(input: Span) -> IResult<Span, (Span, Span)> {
-    map(tuple((word, tag("="), word)), |(var, _, val)| (var, val))(input)
+    map(
+        tuple((word, tag("="), alt((word, take(0usize))))),
+        |(var, _, val)| (var, val),
+    )(input)
 }
 
+/// Parse a number of assignments. Consume a following separator, if any.
 fn assignments(input: Span) -> IResult<Span, AbstractSyntaxTree> {
-    map(separated_list1(space1, single_assignment), |asgn| {
-        AbstractSyntaxTree::Assignments(asgn)
-    })(input)
+    map(
+        delimited(
+            space0,
+            separated_list1(space1, single_assignment),
+            opt(tuple((space0, alt((tag(";"), line_ending))))),
+        ),
+        |asgn| AbstractSyntaxTree::Assignments(asgn),
+    )(input)
 }
 
 //   /* Reserved words.  Members of the first group are only recognized
@@ -1198,12 +1446,7 @@ mod tests {
     use super::*;
 
     fn span(offset: usize, line: u32, fragment: &str) -> Span {
-        Span {
-            offset,
-            line,
-            fragment,
-            extra: (),
-        }
+        unsafe { Span::new_from_raw_offset(offset, line, fragment, ()) }
     }
 
     //   #[test]
@@ -1291,6 +1534,49 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_quoted_words() {
+        assert_eq!(
+            simple_command(Span::new("ab \"c d\" 'e f' g\\ h i\"j\"'k'\n")),
+            Ok((
+                span(27, 1, "\n"),
+                Command::Program(vec![
+                    span(0, 1, "ab"),
+                    span(3, 1, "\"c d\""),
+                    span(9, 1, "'e f'"),
+                    span(15, 1, "g\\ h"),
+                    span(20, 1, "i\"j\"'k'"),
+                ])
+            ))
+        );
+
+        // Escaped quotes and separators inside double quotes
+        assert_eq!(
+            simple_command(Span::new("\"a\\\"|;\" b\n")),
+            Ok((
+                span(9, 1, "\n"),
+                Command::Program(vec![span(0, 1, "\"a\\\"|;\""), span(8, 1, "b"),])
+            ))
+        );
+
+        // Unterminated quotes are not a word
+        assert!(word(Span::new("\"ab")).is_err());
+
+        // Braces keep parameters with separators together
+        assert_eq!(
+            simple_command(Span::new("a ${b:-c; d} e${f:=\"}\"}\n")),
+            Ok((
+                span(23, 1, "\n"),
+                Command::Program(vec![
+                    span(0, 1, "a"),
+                    span(2, 1, "${b:-c; d}"),
+                    span(13, 1, "e${f:=\"}\"}"),
+                ])
+            ))
+        );
+        assert!(word(Span::new("${a:-b")).is_err());
+    }
+
     #[test]
     fn parse_pipeline() {
         // A simple command with trailing spaces
@@ -1555,12 +1841,135 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_compound() {
+        assert_eq!(
+            script(Span::new("(ab; cd=ef)\n")),
+            Ok((
+                span(12, 2, ""),
+                AbstractSyntaxTree::Logical(
+                    vec![Pipeline {
+                        commands: vec![PipelineCommand {
+                            command: Command::Subshell(vec![
+                                AbstractSyntaxTree::Logical(
+                                    vec![Pipeline {
+                                        commands: vec![PipelineCommand {
+                                            command: Command::Program(vec![span(1, 1, "ab")]),
+                                            operator: PipelineOperator::Nothing
+                                        }],
+                                        operator: LogicalOperator::Nothing
+                                    }],
+                                    BackgroundMode::Foreground
+                                ),
+                                AbstractSyntaxTree::Assignments(vec![(
+                                    span(5, 1, "cd"),
+                                    span(8, 1, "ef")
+                                )]),
+                            ]),
+                            operator: PipelineOperator::Nothing
+                        }],
+                        operator: LogicalOperator::Nothing
+                    }],
+                    BackgroundMode::Foreground
+                )
+            ))
+        );
+
+        assert_eq!(
+            script(Span::new("{ ab; }\n")),
+            Ok((
+                span(8, 2, ""),
+                AbstractSyntaxTree::Logical(
+                    vec![Pipeline {
+                        commands: vec![PipelineCommand {
+                            command: Command::Group(vec![AbstractSyntaxTree::Logical(
+                                vec![Pipeline {
+                                    commands: vec![PipelineCommand {
+                                        command: Command::Program(vec![span(2, 1, "ab")]),
+                                        operator: PipelineOperator::Nothing
+                                    }],
+                                    operator: LogicalOperator::Nothing
+                                }],
+                                BackgroundMode::Foreground
+                            )]),
+                            operator: PipelineOperator::Nothing
+                        }],
+                        operator: LogicalOperator::Nothing
+                    }],
+                    BackgroundMode::Foreground
+                )
+            ))
+        );
+    }
+
+    /// Parse a script that consists of a single command and return that command
+    fn parse_single_command(text: &str) -> Command {
+        match script(Span::new(text)) {
+            Ok((rest, AbstractSyntaxTree::Logical(mut pipelines, _))) => {
+                assert_eq!(*rest.fragment(), "");
+                pipelines.remove(0).commands.remove(0).command
+            }
+            res => panic!("Unexpected parse result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn parse_control_structures() {
+        match parse_single_command("if a; then b; elif c; then d; else e; fi\n") {
+            Command::If(branches, Some(otherwise)) => {
+                assert_eq!(branches.len(), 2);
+                assert_eq!(branches[1].0.len(), 1);
+                assert_eq!(branches[1].1.len(), 1);
+                assert_eq!(otherwise.len(), 1);
+            }
+            c => panic!("Not an if command: {:?}", c),
+        }
+        match parse_single_command("if a\nthen\nb\nfi\n") {
+            Command::If(branches, None) => assert_eq!(branches.len(), 1),
+            c => panic!("Not an if command: {:?}", c),
+        }
+        match parse_single_command("for x in a \"b c\"; do e; done\n") {
+            Command::For(name, words, body) => {
+                assert_eq!(*name.fragment(), "x");
+                let words: Vec<&str> = words.iter().map(|w| *w.fragment()).collect();
+                assert_eq!(words, vec!["a", "\"b c\""]);
+                assert_eq!(body.len(), 1);
+            }
+            c => panic!("Not a for command: {:?}", c),
+        }
+        match parse_single_command("for x; do e; done\n") {
+            Command::For(_, words, _) => assert!(words.is_empty()),
+            c => panic!("Not a for command: {:?}", c),
+        }
+        match parse_single_command("while a; do b; done\n") {
+            Command::While(condition, body) => {
+                assert_eq!(condition.len(), 1);
+                assert_eq!(body.len(), 1);
+            }
+            c => panic!("Not a while command: {:?}", c),
+        }
+
+        // Reserved words are only recognized as the first word of a command
+        match parse_single_command("echo if fi\n") {
+            Command::Program(words) => assert_eq!(words.len(), 3),
+            c => panic!("Not a program: {:?}", c),
+        }
+        match parse_single_command("iffy\n") {
+            Command::Program(words) => assert_eq!(words.len(), 1),
+            c => panic!("Not a program: {:?}", c),
+        }
+    }
+
     #[test]
     fn parse_assignments() {
         assert_eq!(
             single_assignment(Span::new("ab=cd")),
             Ok((span(5, 1, ""), (span(0, 1, "ab"), span(3, 1, "cd")),))
         );
+        assert_eq!(
+            single_assignment(Span::new("ab= cd")),
+            Ok((span(3, 1, " cd"), (span(0, 1, "ab"), span(3, 1, ""))))
+        );
         assert_eq!(
             assignments(Span::new("ab=cd ef=gh")),
             Ok((
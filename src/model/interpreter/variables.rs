@@ -24,6 +24,10 @@ use std::collections::HashMap;
 use model::error::{Error, Result};
 
 use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Variable that holds the enabled shell options as a colon-separated list, like in bash
+pub const BASHOPTS: &str = "BASHOPTS";
 
 /// Stack of contexts / frames, i.e. dictionaries of variables.
 ///
@@ -31,6 +35,20 @@ use std::ffi::OsString;
 #[derive(Clone, Debug)]
 pub struct ContextStack {
     frames: Vec<Context>,
+
+    /// Frames and working directories of the parent shells while a subshell is running.
+    parent_frames: Vec<(Vec<Context>, Option<PathBuf>)>,
+
+    /// Working directory of a subshell.
+    ///
+    /// The working directory of the process is shared by all threads, so subshells keep their own
+    /// instead of changing it. None uses the directory of the process.
+    working_dir: Option<PathBuf>,
+
+    /// Request of the *exit* builtin to leave the shell.
+    ///
+    /// The inner value is the requested exit code. None keeps the status of the previous command.
+    exit_request: Option<Option<i32>>,
 }
 
 /// A stack frame, named context as in bash.
@@ -82,6 +100,9 @@ impl ContextStack {
     pub fn new() -> Self {
         Self {
             frames: vec![Context::new(ContextType::Global, "")],
+            parent_frames: Vec::new(),
+            working_dir: None,
+            exit_request: None,
         }
     }
 
@@ -120,6 +141,13 @@ impl ContextStack {
         }
     }
 
+    /// Check if the shell option *name* is enabled, see the *shopt* builtin
+    pub fn is_option_set(&self, name: &str) -> bool {
+        self.variable_as_str(BASHOPTS)
+            .map(|opts| opts.split(':').any(|o| o == name))
+            .unwrap_or(false)
+    }
+
     pub fn variable_as_str<'a>(&'a self, name: &str) -> Result<&'a str> {
         match self.find_variable(name) {
             Some(v) => Ok(v.as_str()),
@@ -222,6 +250,53 @@ impl ContextStack {
         }
     }
 
+    /// Enter a subshell.
+    ///
+    /// The subshell works on a copy of all frames and starts in the working directory of its
+    /// parent. Changes are discarded by `pop_subshell`.
+    pub fn push_subshell(&mut self) {
+        self.parent_frames
+            .push((self.frames.clone(), self.working_dir.clone()));
+        if self.working_dir.is_none() {
+            self.working_dir = std::env::current_dir().ok();
+        }
+    }
+
+    /// Leave a subshell and restore the frames of the parent shell.
+    pub fn pop_subshell(&mut self) {
+        if let Some((frames, working_dir)) = self.parent_frames.pop() {
+            self.frames = frames;
+            self.working_dir = working_dir;
+        } else {
+            error!("Tried to leave a subshell that wasn't entered");
+        }
+    }
+
+    /// Working directory of the subshell, None outside of subshells
+    pub fn working_dir(&self) -> Option<&PathBuf> {
+        self.working_dir.as_ref()
+    }
+
+    /// Change the working directory of the subshell
+    pub fn set_working_dir(&mut self, dir: PathBuf) {
+        self.working_dir = Some(dir);
+    }
+
+    /// Ask the shell to stop running commands and exit with the given code.
+    pub fn request_exit(&mut self, code: Option<i32>) {
+        self.exit_request = Some(code);
+    }
+
+    /// Pending request to exit the shell
+    pub fn exit_request(&self) -> Option<Option<i32>> {
+        self.exit_request
+    }
+
+    /// Get and clear the pending request to exit the shell
+    pub fn take_exit_request(&mut self) -> Option<Option<i32>> {
+        self.exit_request.take()
+    }
+
     pub fn drop_temp_context(&mut self) {
         loop {
             let drop = if let Some(true) = self.frames.last().map(|t| t.is_temp()) {
@@ -22,12 +22,15 @@
 
 use std::cmp;
 use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
 
 use super::control_sequence::action::{
-    Action, CharSet, CharacterAttribute, Color, EraseDisplay, EraseLine, ScrollRegion, ScsType,
+    Action, CharSet, CharacterAttribute, CharacterProtection, Color, EraseDisplay, EraseLine,
+    ScrollRegion, ScsType, SemanticMark, SetPrivateMode, TextParameter,
 };
 use super::control_sequence::parser::Parser;
 use super::control_sequence::types::Rectangle;
+use tools::file_url::path_from_file_url;
 
 mod charset;
 mod test;
@@ -72,6 +75,93 @@ impl Colors {
     }
 }
 
+/// Maximum number of combining characters that are kept per cell.
+///
+/// Further combining characters are dropped.
+pub const MAX_COMBINING: usize = 2;
+
+/// Number of bytes needed to encode a cell with all its combining characters as UTF-8.
+pub const CELL_UTF8_LEN: usize = 4 * (1 + MAX_COMBINING);
+
+/// Nominal size of a cell in pixels as (width, height), used to reserve room for images.
+pub const IMAGE_CELL_SIZE: (usize, usize) = (10, 20);
+
+/// Largest room reserved for an image in cells as (columns, rows). Larger images are clipped.
+pub const MAX_IMAGE_CELLS: (usize, usize) = (1000, 1000);
+
+/// Character that fills the room reserved for an image
+pub const IMAGE_PLACEHOLDER: char = '\u{2592}';
+
+/// Check if the character is a zero-width combining mark that belongs to the previous character.
+///
+/// Only the combining diacritical mark blocks are considered.
+pub fn is_combining(c: char) -> bool {
+    match c as u32 {
+        0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x20D0..=0x20FF | 0xFE20..=0xFE2F => {
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Number of columns the character takes on screen.
+///
+/// East Asian wide and fullwidth characters, e.g. CJK ideographs, and most emoji take two columns.
+/// Everything else takes one.
+pub fn char_columns(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x231A..=0x231B
+        | 0x2329..=0x232A
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xA960..=0xA97F
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE10..=0xFE19
+        | 0xFE30..=0xFE6F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x2FFFD
+        | 0x30000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// Number of columns the text takes on screen. Combining characters take none.
+pub fn str_columns(s: &str) -> usize {
+    s.chars()
+        .map(|c| if is_combining(c) { 0 } else { char_columns(c) })
+        .sum()
+}
+
+/// Text of a line of cells.
+///
+/// Empty cells that were skipped by a tabulator are turned back into a tab character. If the
+/// start of the skipped cells has been overwritten, the remaining ones are kept as spaces.
+pub fn text_with_tabs(line: &[Cell]) -> String {
+    let mut text = String::new();
+    let mut in_tab = false;
+    for c in line {
+        if c.drawn() {
+            in_tab = false;
+            text.extend(c.chars());
+        } else if c.attributes.contains(Attributes::TABULATOR) {
+            in_tab = true;
+            text.push('\t');
+        } else if !(in_tab && c.attributes.contains(Attributes::TAB_FILL)) {
+            in_tab = false;
+            text.push(c.code_point);
+        }
+    }
+    text
+}
+
 /// A cell is a character and its colors and attributes.
 ///
 /// TODO: Pack data more tightly
@@ -80,6 +170,9 @@ pub struct Cell {
     /// The unicode character to show
     code_point: char,
 
+    /// Zero-width combining characters drawn on top of code_point. Unused slots are '\0'.
+    combining: [char; MAX_COMBINING],
+
     /// Attributes as a bit field
     attributes: Attributes,
 
@@ -91,6 +184,7 @@ impl Cell {
     pub fn new(colors: Colors) -> Self {
         Self {
             code_point: ' ',
+            combining: ['\0'; MAX_COMBINING],
             attributes: Attributes::empty(),
             colors,
         }
@@ -99,6 +193,7 @@ impl Cell {
     pub fn with_attr(colors: Colors, attributes: Attributes) -> Self {
         Self {
             code_point: ' ',
+            combining: ['\0'; MAX_COMBINING],
             attributes,
             colors,
         }
@@ -127,17 +222,64 @@ impl Cell {
         }
     }
 
+    /// Encode the character and its combining characters as UTF-8.
+    ///
+    /// The buffer must be at least CELL_UTF8_LEN bytes long.
     pub fn encode_utf8<'a>(&self, buf: &'a mut [u8]) -> &'a mut str {
-        self.code_point.encode_utf8(buf)
+        let mut len = 0;
+        for c in self.chars() {
+            len += c.encode_utf8(&mut buf[len..]).len();
+        }
+        std::str::from_utf8_mut(&mut buf[..len]).unwrap()
     }
 
+    /// The base character of the cell, without combining characters.
     pub fn code_point(&self) -> char {
         self.code_point
     }
 
+    /// The base character of the cell, followed by its combining characters.
+    ///
+    /// The right half of a wide character has no characters of its own.
+    pub fn chars(&self) -> impl Iterator<Item = char> {
+        let combining = self.combining;
+        let tail = self.is_wide_tail();
+        std::iter::once(self.code_point)
+            .filter(move |_| !tail)
+            .chain(
+                (0..MAX_COMBINING)
+                    .map(move |i| combining[i])
+                    .take_while(|c| *c != '\0'),
+            )
+    }
+
+    /// Attach a combining character to the cell.
+    ///
+    /// Returns false if there is no room left and the character was dropped.
+    pub fn add_combining(&mut self, c: char) -> bool {
+        match self.combining.iter_mut().find(|slot| **slot == '\0') {
+            Some(slot) => {
+                *slot = c;
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn drawn(&self) -> bool {
         self.attributes.contains(Attributes::CHARDRAWN)
     }
+
+    /// Check if the cell is the right half of a wide character.
+    ///
+    /// The left half holds the character and covers both cells when drawn.
+    pub fn is_wide_tail(&self) -> bool {
+        self.attributes.contains(Attributes::WIDE_TAIL)
+    }
+
+    pub fn attributes(&self) -> Attributes {
+        self.attributes
+    }
 }
 
 impl PartialEq for Cell {
@@ -145,6 +287,9 @@ impl PartialEq for Cell {
         if self.code_point != other.code_point {
             return false;
         }
+        if self.combining != other.combining {
+            return false;
+        }
         if self.attributes != other.attributes {
             return false;
         }
@@ -183,6 +328,13 @@ bitflags! {
         const ATR_DBL_UNDER = 0b0100000000000;
         const INVISIBLE     = 0b1000000000000;
 
+        /// first empty cell skipped by a tabulator. Used to restore the tab when extracting text.
+        const TABULATOR     = 0b010000000000000;
+        /// further empty cells skipped by a tabulator
+        const TAB_FILL      = 0b100000000000000;
+        /// right half of a wide character, covered by the cell to its left
+        const WIDE_TAIL     = 0b1000000000000000;
+
         const SGR_MASK2     = Self::ATR_FAINT.bits | Self::ATR_ITALIC.bits |
                               Self::ATR_STRIKEOUT.bits | Self::ATR_DBL_UNDER.bits;
 
@@ -199,12 +351,115 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// DEC private modes the screen keeps track of
+    pub struct PrivateModes: u8 {
+        const APPLICATION_CURSOR_KEYS = 0b000001;
+        const ORIGIN_MODE             = 0b000010;
+        const AUTO_WRAP               = 0b000100;
+        const REVERSE_WRAP_AROUND     = 0b001000;
+        const SHOW_CURSOR             = 0b010000;
+        const SEND_FOCUS_EVENTS       = 0b100000;
+    }
+}
+
+impl PrivateModes {
+    /// Flag for a private mode or None if the mode is not tracked
+    fn from_mode(mode: &SetPrivateMode) -> Option<Self> {
+        match mode {
+            SetPrivateMode::ApplicationCursorKeys => Some(Self::APPLICATION_CURSOR_KEYS),
+            SetPrivateMode::OriginMode => Some(Self::ORIGIN_MODE),
+            SetPrivateMode::AutoWrapMode => Some(Self::AUTO_WRAP),
+            SetPrivateMode::ReverseWrapAroundMode => Some(Self::REVERSE_WRAP_AROUND),
+            SetPrivateMode::ShowCursor => Some(Self::SHOW_CURSOR),
+            SetPrivateMode::SendFocusEvents => Some(Self::SEND_FOCUS_EVENTS),
+            _ => None,
+        }
+    }
+}
+
+/// Characters that separate words when editing word-wise
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum WordBoundary {
+    /// Only white space separates words
+    Whitespace,
+
+    /// Everything except letters, digits and underscores separates words, e.g. `/`, `-` or `.`
+    Punctuation,
+}
+
+impl WordBoundary {
+    /// Check if the character separates words
+    pub fn is_boundary(self, c: char) -> bool {
+        match self {
+            WordBoundary::Whitespace => c.is_ascii_whitespace(),
+            WordBoundary::Punctuation => !(c.is_alphanumeric() || c == '_'),
+        }
+    }
+
+    /// Count the characters up to the end of the first word, including the boundaries before it.
+    ///
+    /// Pass the characters in reverse order to search backwards.
+    pub fn word_length<I>(self, chars: I) -> usize
+    where
+        I: Iterator<Item = char>,
+    {
+        let mut length = 0;
+        let mut in_word = false;
+        for c in chars {
+            if !self.is_boundary(c) {
+                in_word = true;
+            } else if in_word {
+                break;
+            }
+            length += 1;
+        }
+        length
+    }
+}
+
+/// Change of letter case when editing word-wise
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum CaseChange {
+    /// All letters upper case
+    Upper,
+
+    /// All letters lower case
+    Lower,
+
+    /// First letter of each word upper case, the rest lower case
+    Capitalize,
+}
+
+impl CaseChange {
+    /// Change the case of the text. Boundary characters are kept as they are.
+    pub fn apply(self, boundary: WordBoundary, text: &str) -> String {
+        let mut in_word = false;
+        text.chars()
+            .map(|c| {
+                let upper = match self {
+                    CaseChange::Upper => true,
+                    CaseChange::Lower => false,
+                    CaseChange::Capitalize => !in_word,
+                };
+                in_word = !boundary.is_boundary(c);
+                if upper {
+                    c.to_uppercase().next().unwrap_or(c)
+                } else {
+                    c.to_lowercase().next().unwrap_or(c)
+                }
+            })
+            .collect()
+    }
+}
+
 /// A matrix is a rectangular area of cells.
 ///
 /// A matrix is meant to be stored, but not modified.
 ///
 /// Be aware that a matrix can have width of 0, but a non-zero height. This is caused by adding
-/// newlines to an empty screen.
+/// newlines to an empty screen. Such a matrix has no cells. Its rows are empty, i.e. the row
+/// accessors return empty slices and there are still `rows()` lines.
 #[derive(Clone)]
 pub struct Matrix {
     /// The cells of the screen, stored in a row-major ordering.
@@ -246,7 +501,6 @@ impl Matrix {
         x + y * self.width
     }
 
-    #[allow(dead_code)]
     fn cell_at(&self, x: isize, y: isize) -> Cell {
         self.cells[self.cell_index(x, y) as usize]
     }
@@ -257,27 +511,40 @@ impl Matrix {
         &mut self.cells[index]
     }
 
+    /// Change the size to exactly the given one, keeping the upper left content.
+    ///
+    /// New cells are filled with the given cell.
+    fn resize(&mut self, new_w: isize, new_h: isize, fill: Cell) {
+        let mut new_cells = Vec::new();
+        new_cells.resize((new_w * new_h) as usize, fill);
+
+        let keep_w = cmp::min(self.width, new_w) as usize;
+        for y in 0..cmp::min(self.height, new_h) {
+            let new_start = (new_w * y) as usize;
+            let old_start = (self.width * y) as usize;
+            new_cells[new_start..(new_start + keep_w)]
+                .copy_from_slice(&self.cells[old_start..(old_start + keep_w)]);
+        }
+        self.cells = new_cells;
+        self.width = new_w;
+        self.height = new_h;
+    }
+
     pub fn compacted_row_slice(&self, row: isize) -> &[Cell] {
         if self.width == 0 {
             // Return an empty slice
             &self.cells[0..0]
         } else {
-            let row_start = self.cell_index(0, row);
-            let mut row_end = self.cell_index(self.width - 1, row);
-            while row_end >= row_start {
-                if self.cells[row_end as usize].drawn() {
-                    break;
-                }
-                row_end -= 1;
-            }
-
-            // If we have seen an empty row, row_end < row_start. If this happens in the first row, we
-            // would underflow when casting to usize for slicing, thus we update now and then cast.
-            row_end += 1;
-            let row_start = row_start as usize;
-            let row_end = row_end as usize;
-
-            &self.cells[row_start..row_end]
+            // Find the last drawn cell in the row. A blank row yields an empty slice, regardless of
+            // its position in the matrix.
+            let row_start = self.cell_index(0, row) as usize;
+            let row_cells = &self.cells[row_start..(row_start + self.width as usize)];
+            let row_len = row_cells
+                .iter()
+                .rposition(|c| c.drawn())
+                .map_or(0, |last| last + 1);
+
+            &row_cells[..row_len]
         }
     }
 
@@ -411,6 +678,9 @@ pub struct Screen {
     /// Shall the screen keep it size?
     fixed_size: bool,
 
+    /// Width a screen that grows is limited to. Characters beyond it wrap to the next line.
+    max_width: Option<isize>,
+
     /// Last printed character
     last_char: char,
 
@@ -419,6 +689,11 @@ pub struct Screen {
     /// The values will be checked every time as non-fixed_size screens might change them.
     scroll_region: ScrollRegion,
 
+    /// Left and right margin (DECSLRM), same representation as the scroll region.
+    ///
+    /// Only horizontal scrolling (SL/SR) is confined to the margins so far.
+    margins: ScrollRegion,
+
     /// Character set for G0 - G3
     gsets: [CharSet; ScsType::NUM as usize],
 
@@ -427,6 +702,24 @@ pub struct Screen {
 
     /// Character set for characters >= 128
     curgr: ScsType,
+
+    /// Working directory reported by the program (OSC 7) and not yet taken
+    working_directory: Option<PathBuf>,
+
+    /// Semantic prompt marks (OSC 133) received and not yet taken, with the row of the cursor
+    semantic_marks: Vec<(SemanticMark, isize)>,
+
+    /// Capability queries (XTGETTCAP) received and not yet answered
+    termcap_queries: Vec<String>,
+
+    /// Replies to queries of the program that have not been sent yet
+    replies: Vec<u8>,
+
+    /// Current state of the tracked private modes
+    private_modes: PrivateModes,
+
+    /// Private mode values saved with XTERM_SAVE, most recent last
+    saved_private_modes: Vec<(PrivateModes, bool)>,
 }
 
 const INITIAL_COLORS: Colors = Colors {
@@ -451,8 +744,10 @@ impl Screen {
             colors: INITIAL_COLORS,
             parser: Parser::new(),
             fixed_size: false,
+            max_width: None,
             last_char: ' ',
             scroll_region: None,
+            margins: None,
             gsets: [
                 CharSet::UsAscii,
                 CharSet::UsAscii,
@@ -461,6 +756,12 @@ impl Screen {
             ],
             curgl: ScsType::G0,
             curgr: ScsType::G2,
+            working_directory: None,
+            semantic_marks: Vec::new(),
+            termcap_queries: Vec::new(),
+            replies: Vec::new(),
+            private_modes: PrivateModes::AUTO_WRAP | PrivateModes::SHOW_CURSOR,
+            saved_private_modes: Vec::new(),
         }
     }
 
@@ -474,8 +775,10 @@ impl Screen {
             colors: INITIAL_COLORS,
             parser: Parser::new(),
             fixed_size: false,
+            max_width: None,
             last_char: ' ',
             scroll_region: None,
+            margins: None,
             gsets: [
                 CharSet::UsAscii,
                 CharSet::UsAscii,
@@ -484,6 +787,12 @@ impl Screen {
             ],
             curgl: ScsType::G0,
             curgr: ScsType::G2,
+            working_directory: None,
+            semantic_marks: Vec::new(),
+            termcap_queries: Vec::new(),
+            replies: Vec::new(),
+            private_modes: PrivateModes::AUTO_WRAP | PrivateModes::SHOW_CURSOR,
+            saved_private_modes: Vec::new(),
         }
     }
 
@@ -513,10 +822,114 @@ impl Screen {
         self.fixed_size = false;
     }
 
-    /// Mark screen as fixed-size
+    /// Wrap characters to the next line instead of growing the screen beyond the given width.
+    ///
+    /// None or zero lets the screen grow without limit. Screens of fixed size ignore this.
+    pub fn set_max_width(&mut self, max_width: Option<usize>) {
+        self.max_width = max_width.filter(|w| *w > 0).map(|w| w as isize);
+    }
+
+    /// Take the working directory the program reported since the last call
+    pub fn take_working_directory(&mut self) -> Option<PathBuf> {
+        self.working_directory.take()
+    }
+
+    /// Take the semantic prompt marks received since the last call, with the row they were
+    /// received on
+    pub fn take_semantic_marks(&mut self) -> Vec<(SemanticMark, isize)> {
+        std::mem::take(&mut self.semantic_marks)
+    }
+
+    /// Take the capability queries received since the last call
+    pub fn take_termcap_queries(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.termcap_queries)
+    }
+
+    /// Take the replies to be sent to the program since the last call
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replies)
+    }
+
+    /// Turn a tracked private mode on or off. Other modes are ignored.
+    ///
+    /// Changing the origin mode moves the cursor to the new origin.
+    fn set_private_mode(&mut self, mode: &SetPrivateMode, on: bool) {
+        if let Some(flag) = PrivateModes::from_mode(mode) {
+            self.private_modes.set(flag, on);
+            if flag == PrivateModes::ORIGIN_MODE {
+                let y = self.absolute_row(0);
+                self.move_cursor_to(0, y);
+            }
+        } else {
+            warn!("Private mode {:?} not implemented", mode);
+        }
+    }
+
+    /// Row of an absolute cursor position. In origin mode, it counts from the top of the scroll
+    /// region and stays inside of it.
+    fn absolute_row(&self, row: isize) -> isize {
+        match self.scroll_region {
+            Some((top, bottom)) if self.private_modes.contains(PrivateModes::ORIGIN_MODE) => {
+                cmp::min(top as isize + row, bottom as isize)
+            }
+            _ => row,
+        }
+    }
+
+    /// Bytes a cursor key sends, given the final byte of its sequence.
+    ///
+    /// In application cursor key mode (DECCKM), the keys send SS3 sequences, otherwise CSI ones.
+    pub fn cursor_key(&self, final_byte: u8) -> [u8; 3] {
+        if self
+            .private_modes
+            .contains(PrivateModes::APPLICATION_CURSOR_KEYS)
+        {
+            [0x1b, b'O', final_byte]
+        } else {
+            [0x1b, b'[', final_byte]
+        }
+    }
+
+    /// Should the cursor be shown (DECTCEM)?
+    pub fn cursor_visible(&self) -> bool {
+        self.private_modes.contains(PrivateModes::SHOW_CURSOR)
+    }
+
+    /// Current state of the tracked private modes
+    pub fn private_modes(&self) -> PrivateModes {
+        self.private_modes
+    }
+
+    /// Take over the private modes, e.g. those set by a program before it switched screens
+    pub fn set_private_modes(&mut self, modes: PrivateModes) {
+        self.private_modes = modes;
+    }
+
+    /// Bytes to send to the program when the window gained or lost the focus.
+    ///
+    /// None if the program didn't ask for focus events (mode 1004).
+    pub fn focus_report(&self, gained: bool) -> Option<&'static [u8]> {
+        if !self.private_modes.contains(PrivateModes::SEND_FOCUS_EVENTS) {
+            None
+        } else if gained {
+            Some(b"\x1b[I")
+        } else {
+            Some(b"\x1b[O")
+        }
+    }
+
+    /// Mark screen as fixed-size and give it exactly the given size
+    ///
+    /// Content that does not fit anymore is cut off and the cursors are moved onto the screen.
     pub fn fixed_size(&mut self, nx: usize, ny: usize) {
         self.fixed_size = true;
-        self.make_room_for((nx - 1) as isize, (ny - 1) as isize);
+        let nx = cmp::max(1, nx) as isize;
+        let ny = cmp::max(1, ny) as isize;
+        self.matrix.resize(nx, ny, Cell::new(self.colors));
+        for cursor in [&mut self.cursor, &mut self.saved_cursor].iter_mut() {
+            cursor.x = cmp::max(0, cmp::min(nx - 1, cursor.x));
+            cursor.y = cmp::max(0, cmp::min(ny - 1, cursor.y));
+        }
         trace!("fixed_size: {}x{}", nx, ny);
     }
 
@@ -551,6 +964,7 @@ impl Screen {
         }
     }
 
+    /// Iterate over the rows, each without the undrawn cells at its end
     pub fn line_iter(&self) -> impl Iterator<Item = &[Cell]> {
         self.matrix.line_iter()
     }
@@ -596,13 +1010,26 @@ impl Screen {
         let mut text = String::new();
         for l in self.line_iter() {
             for c in l {
-                text.push(c.code_point);
+                text.extend(c.chars());
             }
             text.push('\n');
         }
         text
     }
 
+    /// Return the whole text on screen as a string with new lines.
+    ///
+    /// Tabs are restored as described for `text_with_tabs`.
+    #[cfg(test)]
+    pub fn extract_text_with_tabs(&self) -> String {
+        let mut text = String::new();
+        for l in self.line_iter() {
+            text.push_str(&text_with_tabs(l));
+            text.push('\n');
+        }
+        text
+    }
+
     /// Return the whole text on screen as a string with new lines.
     ///
     /// The last line does not have a new line.
@@ -615,20 +1042,22 @@ impl Screen {
             }
             place_nl = true;
             for c in l {
-                text.push(c.code_point);
+                text.extend(c.chars());
             }
         }
         text
     }
 
+    /// Collect the characters of the cells in the given index range.
+    ///
+    /// Indices past the allocated cells are ignored, e.g. for a matrix of width 0.
     fn collect_text(&self, start_index: usize, end_index: usize) -> String {
-        let mut text = String::new();
-        let mut current_index = start_index;
-        while current_index < end_index {
-            text.push(self.matrix.cells[current_index].code_point);
-            current_index += 1;
-        }
-        text
+        let end_index = cmp::min(end_index, self.matrix.cells.len());
+        let start_index = cmp::min(start_index, end_index);
+        self.matrix.cells[start_index..end_index]
+            .iter()
+            .flat_map(|c| c.chars())
+            .collect()
     }
 
     pub fn text_before_cursor(&mut self) -> String {
@@ -639,7 +1068,25 @@ impl Screen {
         )
     }
 
-    fn index_word_before_cursor(&mut self) -> usize {
+    /// Text from the cursor to the end of the line
+    pub fn text_after_cursor(&mut self) -> String {
+        self.make_room();
+        let cursor_index = self.cursor_index();
+        self.collect_text(cursor_index, self.index_line_end().max(cursor_index))
+    }
+
+    /// Text between the given column and the cursor, both in the current line.
+    ///
+    /// Columns past the end of the line are clamped to it.
+    pub fn text_to_column(&mut self, x: isize) -> String {
+        self.make_room();
+        let line_start = self.matrix.cell_index(0, self.cursor.y) as usize;
+        let index = cmp::min(line_start + cmp::max(0, x) as usize, self.index_line_end());
+        let cursor_index = self.cursor_index() as usize;
+        self.collect_text(cmp::min(index, cursor_index), cmp::max(index, cursor_index))
+    }
+
+    fn index_word_before_cursor(&mut self, boundary: WordBoundary) -> usize {
         self.make_room();
         // End at the beginning of the line
         let start_index = self.matrix.cell_index(0, self.cursor.y) as usize;
@@ -649,11 +1096,8 @@ impl Screen {
         // If we can move one character backwards
         while current_index > start_index {
             current_index -= 1;
-            if self.matrix.cells[current_index]
-                .code_point
-                .is_ascii_whitespace()
-            {
-                // White space found, go to the character after that, then leave
+            if boundary.is_boundary(self.matrix.cells[current_index].code_point) {
+                // Boundary found, go to the character after that, then leave
                 current_index += 1;
                 break;
             }
@@ -661,23 +1105,163 @@ impl Screen {
         current_index
     }
 
-    /// Scan backwards from cursor to the first whitespace
-    pub fn word_before_cursor(&mut self) -> String {
-        let current_index = self.index_word_before_cursor();
+    /// Find the start of the word left of the cursor.
+    ///
+    /// Boundary characters directly left of the cursor are skipped first.
+    fn index_previous_word_start(&mut self, boundary: WordBoundary) -> usize {
+        self.make_room();
+        // End at the beginning of the line
+        let start_index = self.matrix.cell_index(0, self.cursor.y) as usize;
+        let cursor_index = self.cursor_index() as usize;
+        let length = boundary.word_length(
+            self.matrix.cells[start_index..cursor_index]
+                .iter()
+                .rev()
+                .map(|c| c.code_point),
+        );
+        cursor_index - length
+    }
+
+    /// Find the end of the word right of the cursor.
+    ///
+    /// Boundary characters directly right of the cursor are skipped first.
+    fn index_next_word_end(&mut self, boundary: WordBoundary) -> usize {
+        self.make_room();
+        let cursor_index = self.cursor_index() as usize;
+        // End at the last character of the line
+        let end_index = self.index_line_end().max(cursor_index);
+        let length = boundary.word_length(
+            self.matrix.cells[cursor_index..end_index]
+                .iter()
+                .map(|c| c.code_point),
+        );
+        cursor_index + length
+    }
+
+    /// Find the end of the text in the current line
+    fn index_line_end(&self) -> usize {
+        self.matrix.cell_index(0, self.cursor.y) as usize
+            + self.matrix.compacted_row_slice(self.cursor.y).len()
+    }
+
+    /// Scan backwards from cursor to the first word boundary
+    pub fn word_before_cursor(&mut self, boundary: WordBoundary) -> String {
+        let current_index = self.index_word_before_cursor(boundary);
         let cursor_index = self.cursor_index() as usize;
         self.collect_text(current_index, cursor_index)
     }
 
-    pub fn delete_word_before_cursor(&mut self) {
-        let current_index = self.index_word_before_cursor();
-        let cell = self.clone_cell(' ');
+    /// Delete the text between the index and the cursor, return the deleted text.
+    ///
+    /// The index must be in the current line. The rest of the line is moved to the left.
+    fn delete_to_index(&mut self, index: usize) -> String {
         let cursor_index = self.cursor_index() as usize;
-        for index in current_index..cursor_index {
-            self.matrix.cells[index] = cell;
+        let (start_index, end_index) = if index < cursor_index {
+            (index, cursor_index)
+        } else {
+            (cursor_index, index)
+        };
+        let text = self.collect_text(start_index, end_index);
+        self.cursor.x -= (cursor_index - start_index) as isize;
+        for _ in start_index..end_index {
+            self.delete_cell();
+        }
+        text
+    }
+
+    /// Delete the word left of the cursor, return the deleted text.
+    pub fn delete_word_before_cursor(&mut self, boundary: WordBoundary) -> String {
+        let index = self.index_previous_word_start(boundary);
+        self.delete_to_index(index)
+    }
+
+    /// Delete the word right of the cursor, return the deleted text.
+    pub fn delete_word_after_cursor(&mut self, boundary: WordBoundary) -> String {
+        let index = self.index_next_word_end(boundary);
+        self.delete_to_index(index)
+    }
+
+    /// Delete from the start of the line to the cursor, return the deleted text.
+    pub fn delete_to_line_start(&mut self) -> String {
+        self.make_room();
+        let index = self.matrix.cell_index(0, self.cursor.y) as usize;
+        self.delete_to_index(index)
+    }
+
+    /// Delete from the cursor to the end of the line, return the deleted text.
+    pub fn delete_to_line_end(&mut self) -> String {
+        self.make_room();
+        let index = self.index_line_end().max(self.cursor_index() as usize);
+        self.delete_to_index(index)
+    }
+
+    /// Swap the character left of the cursor with the one under it and advance the cursor.
+    ///
+    /// At the end of the line, the last two characters are swapped. Nothing happens at the start
+    /// of the line.
+    pub fn transpose_characters(&mut self) {
+        self.make_room();
+        let start_index = self.matrix.cell_index(0, self.cursor.y) as usize;
+        let end_index = self.index_line_end();
+        let cursor_index = self.cursor_index();
+        if cursor_index == start_index || end_index < start_index + 2 {
+            return;
+        }
+        let right = if cursor_index < end_index {
+            self.cursor.x += 1;
+            cursor_index
+        } else {
+            end_index - 1
+        };
+        let left = right - 1;
+        // Wide characters can't be swapped cell by cell
+        if self.matrix.cells[left].is_wide_tail() || self.matrix.cells[right].is_wide_tail() {
+            if cursor_index < end_index {
+                self.cursor.x -= 1;
+            }
+            return;
         }
+        let left_cell = self.matrix.cells[left];
+        self.matrix.cells[left].code_point = self.matrix.cells[right].code_point;
+        self.matrix.cells[left].combining = self.matrix.cells[right].combining;
+        self.matrix.cells[right].code_point = left_cell.code_point;
+        self.matrix.cells[right].combining = left_cell.combining;
+    }
+
+    /// Change the case of the text from the cursor to the end of the word and move the cursor
+    /// behind the word.
+    pub fn change_case_of_word(&mut self, boundary: WordBoundary, change: CaseChange) {
+        let end_index = self.index_next_word_end(boundary);
+        let cursor_index = self.cursor_index();
+        // Only the base characters change case, combining characters stay with their cell.
+        let base: String = self.matrix.cells[cursor_index..end_index]
+            .iter()
+            .map(|c| c.code_point)
+            .collect();
+        let text = change.apply(boundary, &base);
+        for (cell, c) in self.matrix.cells[cursor_index..end_index]
+            .iter_mut()
+            .zip(text.chars())
+        {
+            cell.code_point = c;
+        }
+        self.cursor.x += (end_index - cursor_index) as isize;
+    }
+
+    /// Move the cursor to the start of the word left of it
+    pub fn move_word_left(&mut self, boundary: WordBoundary) {
+        let current_index = self.index_previous_word_start(boundary);
+        let cursor_index = self.cursor_index() as usize;
         self.cursor.x -= (cursor_index - current_index) as isize;
     }
 
+    /// Move the cursor to the end of the word right of it
+    pub fn move_word_right(&mut self, boundary: WordBoundary) {
+        let current_index = self.index_next_word_end(boundary);
+        let cursor_index = self.cursor_index() as usize;
+        self.cursor.x += (current_index - cursor_index) as isize;
+    }
+
     pub fn replace(&mut self, s: &str, stay_there: bool) {
         let x = self.cursor.x;
         self.reset();
@@ -689,25 +1273,157 @@ impl Screen {
 
     /// Place a character at the current position and advance the cursor
     pub fn place_char(&mut self, c: char) {
+        if is_combining(c) && self.add_combining(c) {
+            return;
+        }
+        let columns = char_columns(c) as isize;
+        if let Some(max_width) = self.max_width {
+            if !self.fixed_size && self.cursor.x > 0 && self.cursor.x + columns > max_width {
+                self.new_line();
+            }
+        }
+        // A wide character doesn't fit into the last column
+        if self.fixed_size && columns > 1 && self.cursor.x + columns > self.width() {
+            if self.private_modes.contains(PrivateModes::AUTO_WRAP) {
+                self.new_line();
+            } else {
+                self.cursor.x = cmp::max(0, self.width() - columns);
+            }
+        }
         self.make_room();
+        self.break_wide_chars(columns);
         let idx = self.cursor_index();
-        self.matrix.cells[idx] = Cell {
+        let cell = Cell {
             code_point: c,
+            combining: ['\0'; MAX_COMBINING],
             attributes: self.attributes | Attributes::CHARDRAWN,
             colors: self.colors,
         };
+        self.matrix.cells[idx] = cell;
         self.cursor.x += 1;
-        if self.fixed_size {
-            if self.cursor.x == self.width() {
+        if columns > 1 && !(self.fixed_size && self.cursor.x == self.width()) {
+            self.make_room();
+            let idx = self.cursor_index();
+            self.matrix.cells[idx] = Cell {
+                attributes: cell.attributes | Attributes::WIDE_TAIL,
+                ..cell
+            };
+            self.cursor.x += 1;
+        }
+        if self.fixed_size && self.cursor.x == self.width() {
+            // Without auto wrap, further characters overwrite the last column
+            if self.private_modes.contains(PrivateModes::AUTO_WRAP) {
                 self.new_line();
+            } else {
+                self.cursor.x -= 1;
+            }
+        }
+    }
+
+    /// Reserve room for an image of the given size in pixels.
+    ///
+    /// The image is replaced by a block of placeholder characters, starting at the cursor. The
+    /// cursor ends up in the row below the block, in the column where the block starts.
+    ///
+    /// The size comes from the program and is clipped to the screen and MAX_IMAGE_CELLS.
+    fn place_image_placeholder(&mut self, width: usize, height: usize) {
+        let (cell_width, cell_height) = IMAGE_CELL_SIZE;
+        let columns = cmp::min(
+            width.saturating_add(cell_width - 1) / cell_width,
+            MAX_IMAGE_CELLS.0,
+        ) as isize;
+        let rows = cmp::min(
+            height.saturating_add(cell_height - 1) / cell_height,
+            MAX_IMAGE_CELLS.1,
+        ) as isize;
+        if columns == 0 || rows == 0 {
+            return;
+        }
+        self.make_room();
+        let x = self.cursor.x;
+        let columns = if self.fixed_size {
+            cmp::min(columns, self.width() - x)
+        } else if let Some(max_width) = self.max_width {
+            cmp::min(columns, cmp::max(max_width - x, 1))
+        } else {
+            columns
+        };
+        for _ in 0..rows {
+            for _ in 0..columns {
+                self.make_room();
+                let idx = self.cursor_index();
+                let mut cell = self.clone_cell(IMAGE_PLACEHOLDER);
+                cell.attributes.insert(Attributes::CHARDRAWN);
+                self.matrix.cells[idx] = cell;
+                self.cursor.x += 1;
+            }
+            self.new_line();
+            self.cursor.x = x;
+        }
+    }
+
+    /// Blank the halves of wide characters that are cut apart by placing a character at the cursor.
+    ///
+    /// The character covers the given number of columns. The cursor must be inside the matrix.
+    fn break_wide_chars(&mut self, columns: isize) {
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        if x > 0 && self.matrix.cells[self.cursor_index()].is_wide_tail() {
+            self.blank_cell(x - 1, y);
+        }
+        if x + columns < self.width() {
+            let idx = self.matrix.cell_index(x + columns, y) as usize;
+            if self.matrix.cells[idx].is_wide_tail() {
+                self.blank_cell(x + columns, y);
+            }
+        }
+    }
+
+    /// Replace the character of the cell by a space, keeping its attributes
+    fn blank_cell(&mut self, x: isize, y: isize) {
+        let idx = self.matrix.cell_index(x, y) as usize;
+        let cell = &mut self.matrix.cells[idx];
+        cell.code_point = ' ';
+        cell.combining = ['\0'; MAX_COMBINING];
+        cell.attributes.remove(Attributes::WIDE_TAIL);
+    }
+
+    /// Check if the cursor is on the right half of a wide character
+    fn cursor_on_wide_tail(&self) -> bool {
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        0 <= x
+            && x < self.width()
+            && 0 <= y
+            && y < self.height()
+            && self.matrix.cells[self.cursor_index()].is_wide_tail()
+    }
+
+    /// Attach a combining character to the drawn cell left of the cursor.
+    ///
+    /// Returns false if there is no such cell. The cursor does not move. Combining characters
+    /// beyond MAX_COMBINING are dropped.
+    fn add_combining(&mut self, c: char) -> bool {
+        let mut x = self.cursor.x - 1;
+        let y = self.cursor.y;
+        if 0 <= x && x < self.width() && 0 <= y && y < self.height() {
+            // The left half of a wide character carries the combining characters
+            if x > 0 && self.matrix.cells[self.matrix.cell_index(x, y) as usize].is_wide_tail() {
+                x -= 1;
+            }
+            let idx = self.matrix.cell_index(x, y) as usize;
+            let cell = &mut self.matrix.cells[idx];
+            if cell.drawn() {
+                cell.add_combining(c);
+                return true;
             }
         }
+        false
     }
 
     /// Return a new cell with current colors and attributes
     fn clone_cell(&self, c: char) -> Cell {
         Cell {
             code_point: c,
+            combining: ['\0'; MAX_COMBINING],
             colors: self.colors,
             attributes: self.attributes,
         }
@@ -737,11 +1453,25 @@ impl Screen {
         }
     }
 
+    /// Find left and right column of the margins.
+    ///
+    /// Both columns are inside the screen. Invalid margins are treated as no margins.
+    fn determine_margins(&self) -> (isize, isize) {
+        match self.margins {
+            Some((left, right))
+                if (left as isize) < (right as isize) && (right as isize) < self.width() =>
+            {
+                (left as isize, right as isize)
+            }
+            _ => (0, self.width() - 1),
+        }
+    }
+
     /// Scroll the character matrix up by n rows and fill the last rows with fresh cells.
     /// Everything below and including at_row will scroll up.
     fn scroll_up(&mut self, at_row: isize, scroll_rows: isize) {
         let (start_row, end_row, _) = self.determine_scroll_region();
-        let scroll_rows = cmp::min(scroll_rows, end_row + 1 - start_row);
+        let scroll_rows = cmp::min(scroll_rows, end_row + 1 - at_row);
         debug_assert!(start_row <= at_row && at_row <= end_row);
         if scroll_rows >= 1 {
             // Scroll up
@@ -763,13 +1493,20 @@ impl Screen {
     }
 
     /// Scroll the line left by n columns and fill the last columns with fresh cells.
-    /// Everything right of column at_col is scrolled left
+    /// Everything right of column at_col up to and including right_col is scrolled left
     ///
     /// We assume the parameters are valid.
-    fn scroll_left_line(&mut self, at_row: isize, at_col: isize, scroll_cols: isize) {
+    fn scroll_left_line(
+        &mut self,
+        at_row: isize,
+        at_col: isize,
+        right_col: isize,
+        scroll_cols: isize,
+    ) {
+        let scroll_cols = cmp::min(scroll_cols, right_col + 1 - at_col);
         let row_index = self.matrix.cell_index(0, at_row);
         let mut dst_index = row_index + at_col;
-        let mut n_to_move = self.width() - scroll_cols - at_col;
+        let mut n_to_move = right_col + 1 - scroll_cols - at_col;
         while n_to_move > 0 {
             self.matrix.cells[dst_index as usize] =
                 self.matrix.cells[(dst_index + scroll_cols) as usize];
@@ -786,14 +1523,14 @@ impl Screen {
     }
 
     /// Scroll the character matrix left by n columns and fill the last columns with fresh cells.
-    /// Everything right of column at_col is scrolled left
-    fn scroll_left(&mut self, at_col: isize, scroll_cols: isize) {
-        let scroll_cols = cmp::min(scroll_cols, self.width());
+    /// Everything right of column at_col up to and including right_col is scrolled left
+    fn scroll_left(&mut self, at_col: isize, right_col: isize, scroll_cols: isize) {
         let at_col = cmp::max(at_col, 0);
-        if scroll_cols >= 1 && at_col < self.width() {
+        let right_col = cmp::min(right_col, self.width() - 1);
+        if scroll_cols >= 1 && at_col <= right_col {
             let (start_row, end_row, _) = self.determine_scroll_region();
             for row in start_row..(end_row + 1) {
-                self.scroll_left_line(row, at_col, scroll_cols);
+                self.scroll_left_line(row, at_col, right_col, scroll_cols);
             }
         }
     }
@@ -802,7 +1539,7 @@ impl Screen {
     /// Every below of and including at_row is scrolled down.
     fn scroll_down(&mut self, at_row: isize, scroll_rows: isize) {
         let (start_row, end_row, _) = self.determine_scroll_region();
-        let scroll_rows = cmp::min(scroll_rows, end_row + 1 - start_row);
+        let scroll_rows = cmp::min(scroll_rows, end_row + 1 - at_row);
         debug_assert!(start_row <= at_row && at_row <= end_row);
         if scroll_rows >= 1 {
             // Scroll down
@@ -826,13 +1563,20 @@ impl Screen {
     }
 
     /// Scroll one line of the character matrix right by n columns and fill the gap with fresh
-    /// cells.
+    /// cells. Columns right of right_col are not changed.
     ///
     /// We assume the parameters are valid.
-    fn scroll_right_line(&mut self, at_row: isize, at_col: isize, scroll_cols: isize) {
+    fn scroll_right_line(
+        &mut self,
+        at_row: isize,
+        at_col: isize,
+        right_col: isize,
+        scroll_cols: isize,
+    ) {
+        let scroll_cols = cmp::min(scroll_cols, right_col + 1 - at_col);
         let row_index = self.matrix.cell_index(0, at_row);
-        let mut n_to_move = self.width() - at_col - scroll_cols;
-        let mut dst_index = row_index + self.width();
+        let mut n_to_move = right_col + 1 - at_col - scroll_cols;
+        let mut dst_index = row_index + right_col + 1;
         while n_to_move > 0 {
             dst_index -= 1;
             self.matrix.cells[dst_index as usize] =
@@ -847,14 +1591,14 @@ impl Screen {
     }
 
     /// Scroll the character matrix right by n columns and fill the gap with fresh cells.
-    /// All columns, including at_col are moved to the right
-    fn scroll_right(&mut self, at_col: isize, scroll_cols: isize) {
-        let scroll_cols = cmp::min(scroll_cols, self.width());
+    /// All columns from at_col up to and including right_col are moved to the right
+    fn scroll_right(&mut self, at_col: isize, right_col: isize, scroll_cols: isize) {
         let at_col = cmp::max(at_col, 0);
-        if scroll_cols >= 1 && at_col < self.width() {
+        let right_col = cmp::min(right_col, self.width() - 1);
+        if scroll_cols >= 1 && at_col <= right_col {
             let (start_row, end_row, _) = self.determine_scroll_region();
             for row in start_row..(end_row + 1) {
-                self.scroll_right_line(row, at_col, scroll_cols);
+                self.scroll_right_line(row, at_col, right_col, scroll_cols);
             }
         }
     }
@@ -867,7 +1611,9 @@ impl Screen {
 
     pub fn insert_str(&mut self, s: &str) {
         for c in s.chars() {
-            self.insert_character();
+            for _ in 0..char_columns(c) {
+                self.insert_character();
+            }
             self.place_char(c);
         }
     }
@@ -946,6 +1692,28 @@ impl Screen {
         }
     }
 
+    /// Mark the empty cells from the cursor to the given column as skipped by a tabulator.
+    ///
+    /// If any of the cells has been drawn, nothing is marked.
+    fn mark_tabulator(&mut self, to_x: isize) {
+        self.make_room();
+        if !self.fixed_size {
+            self.make_room_for(to_x - 1, self.cursor.y);
+        }
+        let start_index = self.cursor_index();
+        let end_index = start_index + (to_x - self.cursor.x) as usize;
+        let cells = &mut self.matrix.cells[start_index..end_index];
+        if cells.iter().all(|c| !c.drawn()) {
+            for c in cells.iter_mut() {
+                c.attributes.insert(Attributes::TAB_FILL);
+            }
+            if let Some(c) = cells.first_mut() {
+                c.attributes.remove(Attributes::TAB_FILL);
+                c.attributes.insert(Attributes::TABULATOR);
+            }
+        }
+    }
+
     /// Compute the index of the cursor position into the cell array
     fn cursor_index(&self) -> usize {
         self.matrix.cell_index(self.cursor.x, self.cursor.y) as usize
@@ -971,16 +1739,32 @@ impl Screen {
         self.cursor.y = self.height();
     }
 
-    /// Move one cell to the right
+    /// Move n characters to the right. A wide character counts as one.
     pub fn move_right(&mut self, n: isize) {
-        let c = self.cursor;
-        self.move_cursor_to(c.x + n, c.y);
+        for _ in 0..n {
+            self.move_cursor_by(1);
+            if self.cursor_on_wide_tail() {
+                self.move_cursor_by(1);
+            }
+        }
     }
 
-    /// Move one cell to the left
+    /// Move n characters to the left. A wide character counts as one.
     pub fn move_left(&mut self, n: isize) {
+        for _ in 0..n {
+            self.move_cursor_by(-1);
+            if self.cursor_on_wide_tail() {
+                self.move_cursor_by(-1);
+            }
+        }
+    }
+
+    /// Move the cursor n cells to the right, or to the left if n is negative.
+    ///
+    /// Programs count the columns themselves, so wide characters are not skipped.
+    fn move_cursor_by(&mut self, n: isize) {
         let c = self.cursor;
-        self.move_cursor_to(c.x - n, c.y);
+        self.move_cursor_to(c.x + n, c.y);
     }
 
     /// Move n lines down. Stop at the border for fixed-sized screens.
@@ -1072,8 +1856,16 @@ impl Screen {
 
     /// Delete the character under the cursor.
     ///
-    /// Move the rest of the line to the left.
+    /// Move the rest of the line to the left. A wide character is deleted as a whole.
     pub fn delete_character(&mut self) {
+        self.delete_cell();
+        if self.cursor_on_wide_tail() {
+            self.delete_cell();
+        }
+    }
+
+    /// Delete the cell under the cursor and move the rest of the line to the left.
+    fn delete_cell(&mut self) {
         self.make_room();
 
         let mut current = self.matrix.cell_index(self.cursor.x, self.cursor.y) as usize;
@@ -1195,13 +1987,17 @@ impl Screen {
     /// Fill a rectangle with a the same cells.
     ///
     /// rect is assumed to be valid
-    fn fill_rect(&mut self, rect: Rectangle, cell: Cell) {
+    fn fill_rect(&mut self, rect: Rectangle, cell: Cell, keep_protected: bool) {
         for y in rect.start.y..(rect.end.y + 1) {
             let from_index = self.matrix.cell_index(rect.start.x, y);
             let to_index = self.matrix.cell_index(rect.end.x + 1, y);
             for index in from_index..to_index {
+                let target = &mut self.matrix.cells[index as usize];
+                if keep_protected && target.attributes.contains(Attributes::PROTECTED) {
+                    continue;
+                }
                 // TODO: Preserve color
-                self.matrix.cells[index as usize] = cell;
+                *target = cell;
             }
         }
     }
@@ -1263,7 +2059,10 @@ impl Screen {
             Action::CharacterAttributes(attrs) => {
                 for attr in attrs {
                     match attr {
-                        CharacterAttribute::Normal => self.attributes = Attributes::empty(),
+                        CharacterAttribute::Normal => {
+                            // DECSCA protection is not a graphic rendition and survives SGR 0
+                            self.attributes &= Attributes::PROTECTED
+                        }
                         CharacterAttribute::Bold => self.attributes.insert(Attributes::BOLD),
                         CharacterAttribute::Faint => self.attributes.insert(Attributes::ATR_FAINT),
                         CharacterAttribute::Italicized => {
@@ -1291,7 +2090,9 @@ impl Screen {
                             self.attributes.remove(Attributes::ATR_ITALIC)
                         }
                         CharacterAttribute::NotUnderlined => {
-                            self.attributes.remove(Attributes::UNDERLINE)
+                            // Turns off single and double underline
+                            self.attributes.remove(Attributes::UNDERLINE);
+                            self.attributes.remove(Attributes::ATR_DBL_UNDER);
                         }
                         CharacterAttribute::Steady => self.attributes.remove(Attributes::BLINK),
                         CharacterAttribute::Positive => self.attributes.remove(Attributes::INVERSE),
@@ -1324,7 +2125,7 @@ impl Screen {
                 Event::Ignore
             }
             Action::VerticalPositionAbsolute(n) => {
-                self.cursor.y = n as isize;
+                self.cursor.y = self.absolute_row(n as isize);
                 Event::Ignore
             }
             Action::SaveCursor => {
@@ -1346,7 +2147,26 @@ impl Screen {
                 Event::Ignore
             }
             Action::CursorAbsolutePosition(r, c) => {
-                self.move_cursor_to(c as isize, r as isize);
+                let y = self.absolute_row(r as isize);
+                self.move_cursor_to(c as isize, y);
+                Event::Ignore
+            }
+            Action::SemanticPrompt(mark) => {
+                self.semantic_marks.push((mark, self.cursor.y));
+                Event::Ignore
+            }
+            Action::RequestTermcap(query) => {
+                self.termcap_queries.push(query);
+                Event::Ignore
+            }
+            Action::KeyboardProtocolQuery => {
+                // The kitty keyboard protocol is not supported. Report no flags, so the program
+                // falls back to legacy key encoding.
+                self.replies.extend_from_slice(b"\x1b[?0u");
+                Event::Ignore
+            }
+            Action::SixelImage(width, height) => {
+                self.place_image_placeholder(width, height);
                 Event::Ignore
             }
             Action::CursorUp(n) => {
@@ -1358,14 +2178,20 @@ impl Screen {
                 Event::Ignore
             }
             Action::CursorForward(n) => {
-                self.move_right(n as isize);
+                self.move_cursor_by(n as isize);
                 Event::Ignore
             }
             Action::CursorBackward(n) => {
-                self.move_left(n as isize);
+                self.move_cursor_by(-(n as isize));
                 Event::Ignore
             }
             Action::Bell => Event::Bell,
+            Action::SetTextParameter(TextParameter::WorkingDirectory, url) => {
+                if let Some(path) = path_from_file_url(&url) {
+                    self.working_directory = Some(path);
+                }
+                Event::Ignore
+            }
             Action::VerticalPositionRelative(n) => {
                 let x = self.cursor.x;
                 let y = self.cursor.y;
@@ -1420,7 +2246,8 @@ impl Screen {
                 self.make_room();
                 let (start_row, end_row, _) = self.determine_scroll_region();
                 if start_row <= self.cursor.y && self.cursor.y <= end_row {
-                    self.scroll_left(0, n as isize);
+                    let (left, right) = self.determine_margins();
+                    self.scroll_left(left, right, n as isize);
                 }
                 Event::Ignore
             }
@@ -1428,49 +2255,65 @@ impl Screen {
                 self.make_room();
                 let (start_row, end_row, _) = self.determine_scroll_region();
                 if start_row <= self.cursor.y && self.cursor.y <= end_row {
-                self.scroll_right(0, n as isize);
+                    let (left, right) = self.determine_margins();
+                    self.scroll_right(left, right, n as isize);
                 }
                 Event::Ignore
             }
             Action::Backspace => {
-                self.move_left(1);
+                // Reverse wrap around needs auto wrap, too, like in xterm
+                let wrap_modes = PrivateModes::REVERSE_WRAP_AROUND | PrivateModes::AUTO_WRAP;
+                if self.fixed_size
+                    && self.cursor.x == 0
+                    && self.cursor.y > 0
+                    && self.private_modes.contains(wrap_modes)
+                {
+                    let (x, y) = (self.width() - 1, self.cursor.y - 1);
+                    self.move_cursor_to(x, y);
+                } else {
+                    self.move_cursor_by(-1);
+                }
                 Event::Ignore
             }
             Action::DecBackIndex => {
                 if self.fixed_size {
                     if self.cursor.x == 0 {
-                        self.scroll_right(0,1);
+                        let w = self.width();
+                        self.scroll_right(0, w - 1, 1);
                     } else {
-                        self.move_left(1);
+                        self.move_cursor_by(-1);
                     }
                 } else {
-                    self.move_left(1);
+                    self.move_cursor_by(-1);
                 }
                 Event::Ignore
             }
             Action::DecForwardIndex => {
                 if self.fixed_size {
                     if self.cursor.x + 1 == self.width() {
-                        self.scroll_left(0, 1);
+                        let w = self.width();
+                        self.scroll_left(0, w - 1, 1);
                     } else {
-                        self.move_right(1);
+                        self.move_cursor_by(1);
                     }
                 } else {
-                    self.move_right(1);
+                    self.move_cursor_by(1);
                 }
                 Event::Ignore
             }
             Action::FillArea(c, rect) => {
-                if (c >= 0x20 && c < 0x80) || (c >= 0xa1 && c < 0xff) {
-                    self.make_room();
-                    let rect = rect.clipped(&self.matrix.rectangle());
-                    let mut cell = Cell::new(self.colors);
-                    cell.code_point = unsafe { std::char::from_u32_unchecked(c as u32) };
-                    cell.attributes = self.attributes;
-                    cell.attributes.insert(Attributes::CHARDRAWN);
-                    self.fill_rect(rect,cell);
-                } else {
-                    warn!("FillArea called for non-ascii character {}", c as u32);
+                // DECFRA fills protected cells, too
+                match std::char::from_u32(c as u32) {
+                    Some(code_point) if !code_point.is_control() => {
+                        self.make_room();
+                        let rect = rect.clipped(&self.matrix.rectangle());
+                        let mut cell = Cell::new(self.colors);
+                        cell.code_point = code_point;
+                        cell.attributes = self.attributes;
+                        cell.attributes.insert(Attributes::CHARDRAWN);
+                        self.fill_rect(rect,cell,false);
+                    }
+                    _ => warn!("FillArea called for invalid character {}", c as u32),
                 }
                 Event::Ignore
             }
@@ -1497,21 +2340,34 @@ impl Screen {
                 }
                 Event::Ignore
             }
+            Action::SetMargins(left, right) => {
+                self.margins = Some((left, right));
+                Event::Ignore
+            }
             Action::InsertColumns(n) => {
                 let c =self.cursor;
-               self.scroll_right( c.x, n as isize);
+               let w = self.width();
+               self.scroll_right( c.x, w - 1, n as isize);
                Event::Ignore
             }
             Action::DeleteColumns(n) => {
                 let c =self.cursor;
-               self.scroll_left( c.x, n as isize);
+               let w = self.width();
+               self.scroll_left( c.x, w - 1, n as isize);
                Event::Ignore
             }
-            Action::EraseArea(rect, _) => {
-                // TODO: handle protection
+            Action::EraseArea(rect, selective) => {
+                // Selective erase keeps the cells protected by DECSCA
                 let rect = rect.clipped(&self.matrix.rectangle());
                 let c = self.clone_cell(' ');
-                self.fill_rect(rect,c);
+                self.fill_rect(rect,c,selective);
+                Event::Ignore
+            }
+            Action::CharacterProtection(protection) => {
+                match protection {
+                    CharacterProtection::NoErase => self.attributes.insert(Attributes::PROTECTED),
+                    CharacterProtection::CanErase => self.attributes.remove(Attributes::PROTECTED),
+                }
                 Event::Ignore
             }
             Action::RepeatCharacter(n) => {
@@ -1579,19 +2435,24 @@ impl Screen {
             Action::InsertCharacters(n) => {
                 self.make_room();
                 let c=self.cursor;
-                self.scroll_right_line( c.y, c.x, n as isize);
+                let w = self.width();
+                self.scroll_right_line( c.y, c.x, w - 1, n as isize);
                 Event::Ignore
             }
             Action::DeleteCharacters(n) => {
                 self.make_room();
                 let c=self.cursor;
-                self.scroll_left_line( c.y, c.x, n as isize);
+                let w = self.width();
+                self.scroll_left_line( c.y, c.x, w - 1, n as isize);
                 Event::Ignore
             }
             Action::InsertLines(n) => {
                 self.make_room();
                 let c=self.cursor;
-                self.scroll_down(c.y, n as isize);
+                let (start_row, end_row, limited) = self.determine_scroll_region();
+                if start_row <= c.y && c.y<= end_row || !limited {
+                    self.scroll_down(c.y, n as isize);
+                }
                 Event::Ignore
             }
             Action::DeleteLines(n) => {
@@ -1610,9 +2471,11 @@ impl Screen {
                 let cx=((self.cursor.x + 8) / 8 ) * 8;
                 if self.fixed_size {
                     if cx < self.width() {
+                        self.mark_tabulator(cx);
                         self.cursor.x = cx;
                     }
                 } else {
+                    self.mark_tabulator(cx);
                     self.cursor.x = cx;
                 }
                 Event::Ignore
@@ -1637,13 +2500,39 @@ impl Screen {
                 Event::Ignore
             }
 
+            Action::SavePrivateMode(mode) => {
+                if let Some(flag) = PrivateModes::from_mode(&mode) {
+                    let value = self.private_modes.contains(flag);
+                    self.saved_private_modes.push((flag, value));
+                }
+                Event::Ignore
+            }
+            Action::RestorePrivateMode(mode) => {
+                if let Some(flag) = PrivateModes::from_mode(&mode) {
+                    let saved = self.saved_private_modes.iter().rposition(|(f, _)| *f == flag);
+                    if let Some(index) = saved {
+                        let (_, value) = self.saved_private_modes.remove(index);
+                        self.private_modes.set(flag, value);
+                    }
+                }
+                Event::Ignore
+            }
+
+            // Enter TUI mode. Only the modes in PrivateModes have an effect.
+            Action::SetPrivateMode(mode) => {
+                self.set_private_mode(&mode, true);
+                Event::StartTui
+            }
+            Action::ResetPrivateMode(mode) => {
+                self.set_private_mode(&mode, false);
+                Event::StartTui
+            }
+
             // Silently ignore these sequences until functionality is required.
             // Enter TUI mode.
             Action::DecApplicationKeypad(_) |
             Action::SetMode(_) |
             Action::ResetMode(_) |
-            Action::SetPrivateMode(_) |
-            Action::ResetPrivateMode(_) |
             Action::WindowOp(_) => {
                 warn!("StartTui Actions not fully implemented");
                     Event::StartTui
@@ -1664,7 +2553,6 @@ impl Screen {
             Action::TabClear(_) |
             Action::CursorForwardTab(_) |
             Action::CursorBackwardTab(_) |
-            Action::SetMargins(_, _) |
             Action::StartGuardedArea |
             Action::EndGuardedArea |
             Action::EnableFilterArea(_) |
@@ -1712,7 +2600,6 @@ impl Screen {
             Action::SetTextParameter(_, _) |
             Action::SetModFKeys(_, _) |
             Action::DisableModFKeys(_) |
-            Action::CharacterProtection(_) |
             Action::ConformanceLevel(_, _) |
             Action::Show8BitControl(_) |
             Action::AnsiConformanceLevel(_) |
@@ -1720,8 +2607,6 @@ impl Screen {
             Action::DecDoubleHeight(_) |
             Action::DecDoubleWidth(_) |
             Action::RequestPrivateMode(_) |
-            Action::RestorePrivateMode(_) |
-            Action::SavePrivateMode(_) |
             Action::RequestAnsiMode(_) |
             // Category: Mouse handling, Prio 8
             Action::SelectLocatorEvents(_, _) |
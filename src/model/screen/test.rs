@@ -22,6 +22,56 @@
 
 use super::*;
 
+impl Colors {
+    /// Foreground color index, regardless of whether the color has been set
+    fn foreground(&self) -> u8 {
+        self.foreground
+    }
+
+    /// Background color index, regardless of whether the color has been set
+    fn background(&self) -> u8 {
+        self.background
+    }
+}
+
+impl Cell {
+    /// Raw colors of the cell
+    fn colors(&self) -> Colors {
+        self.colors
+    }
+}
+
+impl Matrix {
+    /// Get the cell at the given position, if it is inside the matrix
+    fn cell(&self, x: isize, y: isize) -> Option<Cell> {
+        if 0 <= x && x < self.width && 0 <= y && y < self.height {
+            Some(self.cell_at(x, y))
+        } else {
+            None
+        }
+    }
+}
+
+impl Screen {
+    /// Create a screen of the given size that behaves like a terminal window
+    fn new_fixed(width: usize, height: usize) -> Self {
+        let mut screen = Self::new();
+        screen.fixed_size(width, height);
+        screen
+    }
+
+    /// Feed the bytes to a fixed-size screen and return what it shows.
+    ///
+    /// Events like a bell or a window title change are ignored.
+    fn render_bytes(width: usize, height: usize, bytes: &[u8]) -> Matrix {
+        let mut screen = Screen::new_fixed(width, height);
+        for b in bytes {
+            let _ = screen.add_byte(*b);
+        }
+        screen.freeze()
+    }
+}
+
 fn check_compacted_row(s: &Screen, row: isize, gt: &str) {
     let cr = s.matrix.compacted_row(row);
     let gti = gt.chars();
@@ -35,6 +85,65 @@ fn check_compacted_row(s: &Screen, row: isize, gt: &str) {
     );
 }
 
+/// Look of a cell besides its characters. Empty for a drawn cell without attributes or colors.
+fn cell_style(cell: &Cell) -> String {
+    let mut parts = Vec::new();
+    if !cell.drawn() {
+        parts.push("undrawn".to_string());
+    }
+    let attributes =
+        cell.attributes() & !(Attributes::CHARDRAWN | Attributes::FG_COLOR | Attributes::BG_COLOR);
+    if !attributes.is_empty() {
+        parts.push(format!("{:?}", attributes));
+    }
+    if let Some(fg) = cell.foreground_color() {
+        parts.push(format!("fg={}", fg));
+    }
+    if let Some(bg) = cell.background_color() {
+        parts.push(format!("bg={}", bg));
+    }
+    parts.join(" ")
+}
+
+/// Describe a matrix row by row.
+///
+/// Each row shows its characters, followed by the ranges of columns that share a style.
+fn describe_matrix(matrix: &Matrix) -> Vec<String> {
+    let mut lines = vec![format!("{}x{}", matrix.columns(), matrix.rows())];
+    for y in 0..matrix.rows() {
+        let cells: Vec<Cell> = (0..matrix.columns())
+            .filter_map(|x| matrix.cell(x, y))
+            .collect();
+        let text: String = cells.iter().flat_map(|c| c.chars()).collect();
+        let mut line = format!("{:3} |{}|", y, text);
+        let mut x = 0;
+        while x < cells.len() {
+            let style = cell_style(&cells[x]);
+            let run = cells[x..]
+                .iter()
+                .take_while(|c| cell_style(c) == style)
+                .count();
+            if !style.is_empty() {
+                line.push_str(&format!(" {}..{}:{}", x, x + run, style));
+            }
+            x += run;
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Compare a rendered matrix with the expected one.
+///
+/// On mismatch, the diff shows both matrices row by row, including the attributes and colors of
+/// the cells.
+fn assert_matrix_eq(actual: &Matrix, expected: &Matrix) {
+    if actual != expected {
+        assert_eq!(describe_matrix(actual), describe_matrix(expected));
+        panic!("Matrices differ, but their descriptions are the same");
+    }
+}
+
 /// Test object as thin wrapper around screen.
 struct Test(Screen);
 
@@ -48,8 +157,7 @@ impl Test {
 
     /// Create a test object with a fixed-sized screen, then add bytes.
     fn s(w: isize, h: isize, bytes: &[u8]) -> Test {
-        let mut s = Screen::new();
-        s.fixed_size(w as usize, h as usize);
+        let mut s = Screen::new_fixed(w as usize, h as usize);
         s.add_bytes(bytes).unwrap();
         Test(s)
     }
@@ -86,6 +194,12 @@ impl Test {
         self
     }
 
+    /// Check that the screen shows the expected matrix
+    fn m(self, gt: &Matrix) -> Test {
+        assert_matrix_eq(&self.0.matrix, gt);
+        self
+    }
+
     /// Check if the cursor positions
     fn cp(self, gt_x: isize, gt_y: isize) -> Test {
         assert_eq!(self.0.cursor.x, gt_x);
@@ -371,7 +485,8 @@ fn bell() {
 
 #[test]
 fn simple_text() {
-    Test::e(b"he\rwo").cr(0, "wo").height(1);
+    // Overwritten characters look like they were written in the first place
+    Test::e(b"he\rwo").m(&Screen::one_line_matrix(b"wo"));
     Test::e(b"he\nwo\n")
         .cr(0, "he")
         .cr(1, "wo")
@@ -914,7 +1029,343 @@ fn word_before_cursor() {
     // Fill the screen with some text, then move cursor behind *som*
     Test::e(b"Hello World\nIt is some text\x1b[6D")
         .cp(9, 1)
-        .check_mut("som".to_string(), |s| s.word_before_cursor());
+        .check_mut("som".to_string(), |s| {
+            s.word_before_cursor(WordBoundary::Whitespace)
+        });
+}
+
+#[test]
+fn word_before_cursor_boundaries() {
+    Test::e(b"ls /usr/local-dir/file.txt")
+        .check_mut("/usr/local-dir/file.txt".to_string(), |s| {
+            s.word_before_cursor(WordBoundary::Whitespace)
+        })
+        .check_mut("txt".to_string(), |s| {
+            s.word_before_cursor(WordBoundary::Punctuation)
+        });
+}
+
+#[test]
+fn delete_word_before_cursor() {
+    // Whitespace: The whole path is a word
+    Test::e(b"ls /usr/local-dir/file.txt")
+        .check_mut("/usr/local-dir/file.txt".to_string(), |s| {
+            s.delete_word_before_cursor(WordBoundary::Whitespace)
+        })
+        .cr(0, "ls ")
+        .cp(3, 0)
+        .check_mut("ls ".to_string(), |s| {
+            s.delete_word_before_cursor(WordBoundary::Whitespace)
+        })
+        .cr(0, "")
+        .cp(0, 0);
+
+    // Punctuation: Stop at /, - and .
+    Test::e(b"ls /usr/local-dir/file.txt")
+        .check_mut("txt".to_string(), |s| {
+            s.delete_word_before_cursor(WordBoundary::Punctuation)
+        })
+        .check_mut("file.".to_string(), |s| {
+            s.delete_word_before_cursor(WordBoundary::Punctuation)
+        })
+        .check_mut("dir/".to_string(), |s| {
+            s.delete_word_before_cursor(WordBoundary::Punctuation)
+        })
+        .check_mut("local-".to_string(), |s| {
+            s.delete_word_before_cursor(WordBoundary::Punctuation)
+        })
+        .cr(0, "ls /usr/")
+        .cp(8, 0);
+
+    // The rest of the line is kept
+    Test::e(b"ls /usr/local-dir/file.txt\x1b[4D")
+        .check_mut("file".to_string(), |s| {
+            s.delete_word_before_cursor(WordBoundary::Punctuation)
+        })
+        .cr(0, "ls /usr/local-dir/.txt")
+        .cp(18, 0);
+}
+
+#[test]
+fn delete_word_after_cursor() {
+    // Whitespace: The whole path is a word
+    Test::e(b"ls /usr/local-dir/file.txt\x1b[4G")
+        .check_mut("/usr/local-dir/file.txt".to_string(), |s| {
+            s.delete_word_after_cursor(WordBoundary::Whitespace)
+        })
+        .cr(0, "ls ")
+        .cp(3, 0);
+
+    // Punctuation: Stop at /, - and .
+    Test::e(b"ls /usr/local-dir/file.txt\x1b[4G")
+        .check_mut("/usr".to_string(), |s| {
+            s.delete_word_after_cursor(WordBoundary::Punctuation)
+        })
+        .check_mut("/local".to_string(), |s| {
+            s.delete_word_after_cursor(WordBoundary::Punctuation)
+        })
+        .check_mut("-dir".to_string(), |s| {
+            s.delete_word_after_cursor(WordBoundary::Punctuation)
+        })
+        .cr(0, "ls /file.txt")
+        .check_mut("/file".to_string(), |s| {
+            s.delete_word_after_cursor(WordBoundary::Punctuation)
+        })
+        .check_mut(".txt".to_string(), |s| {
+            s.delete_word_after_cursor(WordBoundary::Punctuation)
+        })
+        .cr(0, "ls ")
+        .cp(3, 0);
+}
+
+#[test]
+fn delete_to_line_start_and_end() {
+    Test::e(b"first line\nls -l /tmp\x1b[5D")
+        .check_mut("ls -l".to_string(), |s| s.delete_to_line_start())
+        .cr(0, "first line")
+        .cr(1, " /tmp")
+        .cp(0, 1)
+        .check_mut(" /tmp".to_string(), |s| s.delete_to_line_end())
+        .cr(1, "")
+        .cp(0, 1);
+}
+
+#[test]
+fn move_word() {
+    let mut s = Screen::new();
+    s.add_bytes(b"ls /usr/local-dir/file.txt").unwrap();
+
+    // (word boundary, move to the right, expected cursor column)
+    let moves = [
+        (WordBoundary::Punctuation, false, 23),
+        (WordBoundary::Punctuation, false, 18),
+        (WordBoundary::Punctuation, false, 14),
+        (WordBoundary::Punctuation, false, 8),
+        (WordBoundary::Punctuation, false, 4),
+        (WordBoundary::Punctuation, false, 0),
+        (WordBoundary::Punctuation, false, 0),
+        (WordBoundary::Whitespace, true, 2),
+        (WordBoundary::Whitespace, true, 26),
+        (WordBoundary::Whitespace, true, 26),
+        (WordBoundary::Whitespace, false, 3),
+        (WordBoundary::Punctuation, true, 7),
+        (WordBoundary::Punctuation, true, 13),
+        (WordBoundary::Punctuation, true, 17),
+        (WordBoundary::Punctuation, true, 22),
+        (WordBoundary::Punctuation, true, 26),
+    ];
+    for (boundary, right, column) in moves.iter() {
+        if *right {
+            s.move_word_right(*boundary);
+        } else {
+            s.move_word_left(*boundary);
+        }
+        assert_eq!(s.cursor_x(), *column);
+    }
+}
+
+#[test]
+fn transpose_characters() {
+    // At the end of the line, the last two characters are swapped
+    Test::e(b"first line\nls -al")
+        .check_mut((), |s| s.transpose_characters())
+        .cr(0, "first line")
+        .cr(1, "ls -la")
+        .cp(6, 1)
+        // In the middle of the line, the character under the cursor moves left
+        .check_mut((), |s| s.move_left(4))
+        .check_mut((), |s| s.transpose_characters())
+        .cr(1, "l s-la")
+        .cp(3, 1)
+        // Nothing happens at the start of the line
+        .check_mut((), |s| s.move_left_edge())
+        .check_mut((), |s| s.transpose_characters())
+        .cr(1, "l s-la")
+        .cp(0, 1);
+}
+
+#[test]
+fn change_case_of_word() {
+    Test::e(b"echo hello world\x1b[12D")
+        .check_mut((), |s| {
+            s.change_case_of_word(WordBoundary::Whitespace, CaseChange::Upper)
+        })
+        .cr(0, "echo HELLO world")
+        .cp(10, 0)
+        .check_mut((), |s| {
+            s.change_case_of_word(WordBoundary::Whitespace, CaseChange::Capitalize)
+        })
+        .cr(0, "echo HELLO World")
+        .cp(16, 0)
+        .check_mut((), |s| s.move_left(11))
+        .check_mut((), |s| {
+            s.change_case_of_word(WordBoundary::Whitespace, CaseChange::Lower)
+        })
+        .cr(0, "echo hello World")
+        .cp(10, 0);
+}
+
+#[test]
+fn extract_text_with_tabs() {
+    let mut s = Screen::new();
+    s.add_bytes(b"a\tb\nabc\tde\tf\n12345678\tx\n\tyz\rw")
+        .unwrap();
+    assert_eq!(
+        s.extract_text_with_tabs(),
+        "a\tb\nabc\tde\tf\n12345678\tx\nw       yz\n"
+    );
+    // Without restoring the tabs, the alignment is kept by spaces
+    assert_eq!(
+        s.extract_text(),
+        "a       b\nabc     de      f\n12345678        x\nw       yz\n"
+    );
+}
+
+/// Expected content of a cell: (x, y, character, foreground color, bold)
+type CellTruth = (isize, isize, char, Option<u8>, bool);
+
+#[test]
+fn render_bytes() {
+    let cases: [(&[u8], &[CellTruth]); 4] = [
+        // Colored text
+        (
+            b"a\x1b[31mb\x1b[1mc\x1b[0md",
+            &[
+                (0, 0, 'a', None, false),
+                (1, 0, 'b', Some(1), false),
+                (2, 0, 'c', Some(9), true),
+                (3, 0, 'd', None, false),
+            ],
+        ),
+        // Cursor movement
+        (
+            b"\x1b[2;3Hx\x1b[Ay\x1b[3Cz",
+            &[
+                (2, 1, 'x', None, false),
+                (3, 0, 'y', None, false),
+                (7, 0, 'z', None, false),
+            ],
+        ),
+        // Erase line to the right
+        (
+            b"abcdef\x1b[3D\x1b[K",
+            &[
+                (2, 0, 'c', None, false),
+                (3, 0, ' ', None, false),
+                (5, 0, ' ', None, false),
+            ],
+        ),
+        // Erase display
+        (
+            b"abc\ndef\x1b[2J",
+            &[(0, 0, ' ', None, false), (1, 1, ' ', None, false)],
+        ),
+    ];
+    for (bytes, cells) in cases.iter() {
+        let matrix = Screen::render_bytes(10, 3, bytes);
+        assert_eq!(matrix.columns(), 10);
+        assert_eq!(matrix.rows(), 3);
+        for (x, y, c, fg, bold) in cells.iter() {
+            let cell = matrix.cell(*x, *y).unwrap();
+            assert_eq!(cell.code_point(), *c, "{:?} at ({},{})", bytes, x, y);
+            assert_eq!(cell.foreground_color(), *fg, "{:?} at ({},{})", bytes, x, y);
+            assert_eq!(
+                cell.attributes().contains(Attributes::BOLD),
+                *bold,
+                "{:?} at ({},{})",
+                bytes,
+                x,
+                y
+            );
+        }
+    }
+    assert!(Screen::render_bytes(10, 3, b"").cell(10, 0).is_none());
+}
+
+#[test]
+fn matrix_cell_accessors() {
+    let matrix = Screen::render_bytes(4, 2, b"\x1b[4;32;44mab\x1b[0m\ncd");
+
+    let cell = matrix.cell(1, 0).unwrap();
+    assert_eq!(cell.code_point(), 'b');
+    assert!(cell.drawn());
+    assert!(cell.attributes().contains(Attributes::UNDERLINE));
+    assert_eq!(cell.colors().foreground(), 2);
+    assert_eq!(cell.colors().background(), 4);
+    assert_eq!(cell.background_color(), Some(4));
+
+    let cell = matrix.cell(0, 1).unwrap();
+    assert_eq!(cell.code_point(), 'c');
+    assert!(!cell.attributes().contains(Attributes::UNDERLINE));
+    assert_eq!(cell.foreground_color(), None);
+
+    // Cells that were never written are blank
+    let cell = matrix.cell(3, 1).unwrap();
+    assert!(!cell.drawn());
+    assert_eq!(cell.attributes(), Attributes::empty());
+
+    assert!(matrix.cell(-1, 0).is_none());
+    assert!(matrix.cell(4, 0).is_none());
+    assert!(matrix.cell(0, 2).is_none());
+}
+
+#[test]
+fn sgr_resets_individual_attributes() {
+    let underlines = Attributes::UNDERLINE | Attributes::ATR_DBL_UNDER;
+    let matrix = Screen::render_bytes(8, 1, b"\x1b[21ma\x1b[24mb\x1b[4;21mc\x1b[24md");
+    let underline = |x| matrix.cell(x, 0).unwrap().attributes() & underlines;
+    assert_eq!(underline(0), Attributes::ATR_DBL_UNDER);
+    assert_eq!(underline(1), Attributes::empty());
+    assert_eq!(underline(2), underlines);
+    assert_eq!(underline(3), Attributes::empty());
+
+    let others = Attributes::BOLD
+        | Attributes::ATR_FAINT
+        | Attributes::ATR_ITALIC
+        | Attributes::BLINK
+        | Attributes::INVERSE
+        | Attributes::INVISIBLE;
+    let matrix = Screen::render_bytes(8, 1, b"\x1b[1;2;3;5;7;8ma\x1b[22;23;25;27;28mb");
+    assert_eq!(matrix.cell(0, 0).unwrap().attributes() & others, others);
+    assert_eq!(
+        matrix.cell(1, 0).unwrap().attributes() & others,
+        Attributes::empty()
+    );
+}
+
+#[test]
+fn sgr_without_parameters_resets() {
+    let matrix = Screen::render_bytes(4, 1, b"\x1b[1;31;44ma\x1b[mb");
+    let cell = matrix.cell(0, 0).unwrap();
+    assert!(cell.attributes().contains(Attributes::BOLD));
+    assert_eq!(cell.foreground_color(), Some(1));
+
+    let cell = matrix.cell(1, 0).unwrap();
+    assert!(!cell.attributes().contains(Attributes::BOLD));
+    assert_eq!(cell.foreground_color(), None);
+    assert_eq!(cell.background_color(), None);
+}
+
+#[test]
+fn describe_matrix_for_diffs() {
+    let matrix = Screen::render_bytes(4, 2, b"a\x1b[1;31mb\x1b[0;44mc");
+    assert_eq!(
+        describe_matrix(&matrix),
+        vec![
+            "4x2".to_string(),
+            "  0 |abc | 1..2:BOLD fg=9 2..3:bg=4 3..4:undrawn".to_string(),
+            "  1 |    | 0..4:undrawn".to_string(),
+        ]
+    );
+}
+
+#[test]
+#[should_panic]
+fn assert_matrix_eq_fails_on_different_colors() {
+    assert_matrix_eq(
+        &Screen::render_bytes(2, 1, b"\x1b[31ma"),
+        &Screen::render_bytes(2, 1, b"\x1b[32ma"),
+    );
 }
 
 #[test]
@@ -922,4 +1373,419 @@ fn erase_character_at_eol() {
     Test::e(b"Hello\x1b[10X").cp(5, 0).width(16).height(1);
 }
 
+#[test]
+fn save_restore_private_modes() {
+    let mut s = Screen::new();
+    let add = |s: &mut Screen, bytes: &[u8]| {
+        for b in bytes {
+            let _ = s.add_byte(*b);
+        }
+    };
+    let initial = PrivateModes::AUTO_WRAP | PrivateModes::SHOW_CURSOR;
+    assert_eq!(s.private_modes(), initial);
+
+    // Save autowrap and origin mode, change them
+    add(&mut s, b"\x1b[?7s\x1b[?6s");
+    add(&mut s, b"\x1b[?7l\x1b[?6h\x1b[?1h");
+    assert_eq!(
+        s.private_modes(),
+        PrivateModes::ORIGIN_MODE
+            | PrivateModes::SHOW_CURSOR
+            | PrivateModes::APPLICATION_CURSOR_KEYS
+    );
+
+    // Save the changed autowrap again and change it once more
+    add(&mut s, b"\x1b[?7s\x1b[?7h");
+
+    // Restoring pops the saved values one by one
+    add(&mut s, b"\x1b[?7r");
+    assert!(!s.private_modes().contains(PrivateModes::AUTO_WRAP));
+    add(&mut s, b"\x1b[?7r\x1b[?6r");
+    assert_eq!(
+        s.private_modes(),
+        initial | PrivateModes::APPLICATION_CURSOR_KEYS
+    );
+
+    // Nothing left to restore
+    add(&mut s, b"\x1b[?7l\x1b[?7r");
+    assert!(!s.private_modes().contains(PrivateModes::AUTO_WRAP));
+}
+
+#[test]
+fn private_modes_take_effect() {
+    // Private modes start TUI mode, so add_bytes would stop at them
+    let run = |w: isize, h: isize, bytes: &[u8]| {
+        let mut s = Screen::new_fixed(w as usize, h as usize);
+        for b in bytes {
+            let _ = s.add_byte(*b);
+        }
+        Test(s)
+    };
+
+    // Without auto wrap, the last column is overwritten
+    run(3, 2, b"abcd").cr(0, "abc").cp(1, 1);
+    run(3, 2, b"\x1b[?7labcd").cr(0, "abd").cr(1, "").cp(2, 0);
+
+    // Origin mode counts rows from the top of the scroll region and stays inside of it
+    run(5, 5, b"\x1b[2;4r\x1b[?6h").cp(0, 1);
+    run(5, 5, b"\x1b[2;4r\x1b[?6h\x1b[2;3H").cp(2, 2);
+    run(5, 5, b"\x1b[2;4r\x1b[?6h\x1b[5d").cp(0, 3);
+    run(5, 5, b"\x1b[2;4r\x1b[?6h\x1b[?6l\x1b[2;3H").cp(2, 1);
+
+    // Reverse wrap around lets backspace continue in the previous row
+    run(3, 2, b"\nx\r\x08").cp(0, 1);
+    run(3, 2, b"\x1b[?45h\nx\r\x08").cp(2, 0);
+
+    // Cursor keys and visibility
+    let Test(s) = run(3, 2, b"");
+    assert_eq!(&s.cursor_key(b'A'), b"\x1b[A");
+    assert!(s.cursor_visible());
+    let Test(s) = run(3, 2, b"\x1b[?1h\x1b[?25l");
+    assert_eq!(&s.cursor_key(b'A'), b"\x1bOA");
+    assert!(!s.cursor_visible());
+}
+
 // TODO: Test for protected
+
+#[test]
+fn newlines_on_empty_screen() {
+    for n in 1..5 {
+        let mut s = Screen::new();
+        for _ in 0..n {
+            assert_eq!(s.add_byte(b'\n'), Event::NewLine);
+        }
+        assert_eq!(s.width(), 0);
+        assert_eq!(s.height(), n);
+        assert_eq!(s.extract_text(), "\n".repeat(n as usize));
+        assert_eq!(s.extract_text_with_tabs(), "\n".repeat(n as usize));
+        assert_eq!(
+            s.extract_text_without_last_nl(),
+            "\n".repeat(n as usize - 1)
+        );
+        assert_eq!(s.line_iter().count(), n as usize);
+        assert!(s.line_iter().all(|l| l.is_empty()));
+        assert!(s.line_iter_full().all(|l| l.is_empty()));
+
+        let m = s.freeze();
+        assert_eq!(m.rows(), n);
+        assert_eq!(m.columns(), 0);
+        assert_eq!(m.first_row_cell_vec(), Vec::new());
+        assert!(m.compacted_row(n - 1).is_empty());
+    }
+
+    // Text around the cursor is empty, the cursor line is added
+    let mut s = Screen::new();
+    assert_eq!(s.add_bytes(b"\n\n"), Ok(()));
+    assert_eq!(s.text_before_cursor(), "");
+    assert_eq!(s.text_after_cursor(), "");
+    assert_eq!(s.extract_text(), "\n\n\n");
+
+    // Text after the empty lines ends up in the right row
+    let mut s = Screen::new();
+    assert_eq!(s.add_bytes(b"\n\nab"), Ok(()));
+    assert_eq!(s.height(), 3);
+    assert_eq!(s.extract_text(), "\n\nab\n");
+}
+
+#[test]
+fn compacted_row_slice_blank_rows() {
+    // Blank rows at the top, in the middle and at the bottom
+    let matrix = Screen::render_bytes(4, 5, b"\nab\n\nabcd\n");
+    let lens: Vec<usize> = (0..5)
+        .map(|r| matrix.compacted_row_slice(r).len())
+        .collect();
+    assert_eq!(lens, vec![0, 2, 0, 4, 0]);
+
+    // Drawn cell only in the last column
+    let matrix = Screen::render_bytes(4, 3, b"\n\x1b[4Gx\n");
+    assert_eq!(matrix.compacted_row_slice(0).len(), 0);
+    assert_eq!(matrix.compacted_row_slice(1).len(), 4);
+    assert_eq!(matrix.compacted_row_slice(1)[3].code_point(), 'x');
+    assert_eq!(matrix.compacted_row_slice(2).len(), 0);
+
+    // Width-1 matrix
+    let matrix = Screen::render_bytes(1, 3, b"\nx");
+    let lens: Vec<usize> = (0..3)
+        .map(|r| matrix.compacted_row_slice(r).len())
+        .collect();
+    assert_eq!(lens, vec![0, 1, 0]);
+
+    // Completely blank matrix
+    let matrix = Screen::render_bytes(3, 3, b"");
+    assert!(matrix.line_iter().all(|l| l.is_empty()));
+}
+
+#[test]
+fn combining_characters() {
+    // U+0301 COMBINING ACUTE ACCENT stays with the e and does not move the cursor
+    Test::e("xe\u{301}y".as_bytes())
+        .cp(3, 0)
+        .width(3)
+        .cr(0, "xey")
+        .check("xe\u{301}y\n".to_string(), |s| s.extract_text());
+
+    let mut s = Screen::new();
+    assert_eq!(s.add_bytes("e\u{301}".as_bytes()), Ok(()));
+    let cell = s.freeze().cell(0, 0).unwrap();
+    assert_eq!(cell.code_point(), 'e');
+    assert_eq!(cell.chars().collect::<String>(), "e\u{301}");
+    let mut buf = [0; CELL_UTF8_LEN];
+    assert_eq!(cell.encode_utf8(&mut buf[..]), "e\u{301}");
+
+    // Surplus combining characters are dropped
+    Test::e("a\u{301}\u{302}\u{303}".as_bytes())
+        .cp(1, 0)
+        .check("a\u{301}\u{302}\n".to_string(), |s| s.extract_text());
+
+    // Without a base character, the combining character gets its own cell
+    Test::e("\u{301}".as_bytes())
+        .cp(1, 0)
+        .check("\u{301}\n".to_string(), |s| s.extract_text());
+
+    // Deleting the base character takes the combining character with it
+    let mut s = Screen::new();
+    assert_eq!(s.add_bytes("ae\u{301}".as_bytes()), Ok(()));
+    s.move_left(1);
+    s.delete_character();
+    assert_eq!(s.extract_text(), "a\n");
+}
+
+#[test]
+fn wide_characters() {
+    assert_eq!(char_columns('a'), 1);
+    assert_eq!(char_columns('中'), 2);
+    assert_eq!(char_columns('😀'), 2);
+    assert_eq!(str_columns("a中e\u{301}"), 4);
+
+    // The right half of a wide character gets its own cell without text
+    Test::e("a中b".as_bytes())
+        .cp(4, 0)
+        .width(4)
+        .cr(0, "a中中b")
+        .check("a中b\n".to_string(), |s| s.extract_text());
+    let matrix = Screen::one_line_matrix("a中b".as_bytes());
+    assert!(!matrix.cell(1, 0).unwrap().is_wide_tail());
+    assert!(matrix.cell(2, 0).unwrap().is_wide_tail());
+
+    // Combining characters go to the left half
+    Test::e("中\u{301}".as_bytes())
+        .cp(2, 0)
+        .check("中\u{301}\n".to_string(), |s| s.extract_text());
+
+    // Overwriting one half of a wide character blanks the other one
+    Test::e("中\x08x".as_bytes())
+        .cp(2, 0)
+        .check(" x\n".to_string(), |s| s.extract_text());
+    Test::e("中\x08\x08x".as_bytes())
+        .cp(1, 0)
+        .check("x \n".to_string(), |s| s.extract_text());
+
+    // A wide character that doesn't fit into the last column wraps
+    Test::s(3, 2, "ab中".as_bytes())
+        .cr(0, "ab")
+        .cr(1, "中中")
+        .cp(2, 1);
+}
+
+#[test]
+fn wide_character_wraps_at_max_width() {
+    let mut s = Screen::new();
+    s.set_max_width(Some(3));
+    s.place_str("ab中c中");
+    // The wide character would reach past the limit, so it starts the next line
+    assert_eq!(s.extract_text(), "ab\n中c\n中\n");
+    assert_eq!(s.width(), 3);
+}
+
+#[test]
+fn edit_wide_characters() {
+    let mut s = Screen::new();
+    s.place_str("a中b");
+
+    // Cursor movement skips the right half
+    s.move_left(1);
+    assert_eq!(s.cursor_x(), 3);
+    s.move_left(1);
+    assert_eq!(s.cursor_x(), 1);
+    s.move_right(1);
+    assert_eq!(s.cursor_x(), 3);
+    s.move_left(1);
+
+    // Deleting removes both halves
+    s.delete_character();
+    assert_eq!(s.extract_text(), "ab\n");
+
+    // Inserting makes room for both halves
+    s.insert_str("中");
+    assert_eq!(s.cursor_x(), 3);
+    assert_eq!(s.extract_text(), "a中b\n");
+
+    s.move_right(1);
+    s.delete_left();
+    s.delete_left();
+    assert_eq!(s.cursor_x(), 1);
+    assert_eq!(s.extract_text(), "a\n");
+}
+
+#[test]
+fn insert_delete_lines_in_scroll_region() {
+    let lines: &[u8] = b"00\n01\n02\n03\n04\n05\n06\n07\n08\n09\n\x1b[2;8r";
+    let run = |cmd: &[u8]| Test::s(80, 25, &[lines, cmd].concat());
+
+    // Cursor below and above the region: nothing happens
+    for cmd in [
+        &b"\x1b[10d\x1b[2L"[..],
+        b"\x1b[10d\x1b[2M",
+        b"\x1b[1d\x1b[2L",
+        b"\x1b[1d\x1b[2M",
+    ]
+    .iter()
+    {
+        let t = run(cmd);
+        for row in 0..10 {
+            check_compacted_row(&t.0, row, &format!("{:02}", row));
+        }
+    }
+
+    // Inserting near the bottom of the region only moves lines up to the bottom
+    run(b"\x1b[7d\x1b[5L")
+        .cr(5, "05")
+        .cr(6, "")
+        .cr(7, "")
+        .cr(8, "08")
+        .cr(9, "09");
+
+    // Deleting near the bottom of the region keeps the lines above the cursor
+    run(b"\x1b[7d\x1b[5M")
+        .cr(3, "03")
+        .cr(5, "05")
+        .cr(6, "")
+        .cr(7, "")
+        .cr(8, "08");
+}
+
+#[test]
+fn scroll_left_right_within_margins() {
+    // Without margins, the whole line is scrolled
+    Test::s(12, 3, b"0123456789\x1b[2 @").cr(0, "23456789");
+    Test::s(12, 3, b"0123456789\x1b[2 A").cr(0, "  0123456789");
+
+    // With margins, the columns outside are untouched
+    Test::s(12, 3, b"0123456789\x1b[3;8s\x1b[2 @").cr(0, "014567  89");
+    Test::s(12, 3, b"0123456789\x1b[3;8s\x1b[2 A").cr(0, "01  234589");
+
+    // The count is clamped to the width of the margins
+    Test::s(12, 3, b"0123456789\x1b[3;8s\x1b[20 @").cr(0, "01      89");
+    Test::s(12, 3, b"0123456789\x1b[3;8s\x1b[20 A").cr(0, "01      89");
+}
+
+#[test]
+fn fill_area_characters() {
+    // Any printable character can be used, e.g. U+0100
+    Test::s(80, 25, b"Hello\x1b[256;2;1;3;4$x")
+        .cr(0, "Hello")
+        .cr(1, "\u{100}\u{100}\u{100}\u{100}")
+        .cr(2, "\u{100}\u{100}\u{100}\u{100}")
+        .cr(3, "");
+
+    // Surrogates and control characters are rejected
+    Test::s(80, 25, b"Hello\x1b[55296;1;1;3;4$x")
+        .cr(0, "Hello")
+        .cr(1, "");
+    Test::s(80, 25, b"Hello\x1b[10;1;1;3;4$x")
+        .cr(0, "Hello")
+        .cr(1, "");
+}
+
+#[test]
+fn erase_area_protection() {
+    let bytes: &[u8] = b"01\x1b[1\"q23\x1b[2\"q45";
+
+    // Selective erase keeps protected cells
+    Test::s(80, 25, &[bytes, b"\x1b[1;1;1;6${"].concat()).cr(0, "  23");
+
+    // SGR 0 doesn't end the protection
+    Test::s(
+        80,
+        25,
+        b"01\x1b[1\"q\x1b[1m2\x1b[0m3\x1b[2\"q45\x1b[1;1;1;6${",
+    )
+    .cr(0, "  23");
+
+    // Erase and fill don't care about protection
+    Test::s(80, 25, &[bytes, b"\x1b[1;1;1;6$z"].concat()).cr(0, "");
+    Test::s(80, 25, &[bytes, b"\x1b[42;1;1;1;6$x"].concat()).cr(0, "******");
+}
+
+#[test]
+fn new_fixed_screen() {
+    let mut s = Screen::new_fixed(80, 24);
+    assert_eq!((s.width(), s.height()), (80, 24));
+    assert_eq!((s.cursor_x(), s.cursor_y()), (0, 0));
+    assert_eq!(s.line_iter().count(), 24);
+
+    // The size doesn't change when moving beyond the edges
+    s.add_bytes(b"\x1b[30;90H").unwrap();
+    assert_eq!((s.cursor_x(), s.cursor_y()), (79, 23));
+    s.add_bytes(b"\x1b[30;1HX").unwrap();
+    let matrix = s.freeze();
+    assert_eq!((matrix.columns(), matrix.rows()), (80, 24));
+    assert_eq!(matrix.cell(0, 23).map(|c| c.code_point()), Some('X'));
+}
+
+#[test]
+fn sixel_image_placeholder() {
+    // A 25x45 pixel image covers 3 columns and 3 rows of the nominal cell size
+    let image = b"\x1bP0;1;0q\"1;1;25;45#0;2;100;0;0#0~~~~-~~~~-~~~~\x1b\\";
+    Test::e(&[b"ab", &image[..], b"cd"].concat())
+        .cr(0, "ab\u{2592}\u{2592}\u{2592}")
+        .cr(1, "  \u{2592}\u{2592}\u{2592}")
+        .cr(2, "  \u{2592}\u{2592}\u{2592}")
+        .cr(3, "  cd")
+        .cp(4, 3)
+        .height(4);
+
+    // The placeholder is clipped at the right edge of a fixed screen
+    Test::s(4, 5, &[b"ab", &image[..], b"c"].concat())
+        .cr(0, "ab\u{2592}\u{2592}")
+        .cr(2, "  \u{2592}\u{2592}")
+        .cr(3, "  c");
+
+    // The size from the program is clipped
+    let image = b"\x1bPq\"1;1;18446744073709551615;18446744073709551615\x1b\\";
+    Test::s(4, 5, &[b"ab", &image[..], b"c"].concat())
+        .cr(0, "  \u{2592}\u{2592}")
+        .cr(3, "  \u{2592}\u{2592}")
+        .cr(4, "  c");
+    let image = b"\x1bPq\"1;1;25;18446744073709551615\x1b\\";
+    Test::e(&[&image[..], b"c"].concat())
+        .cr(999, "\u{2592}\u{2592}\u{2592}")
+        .cr(1000, "c")
+        .height(1001);
+}
+
+#[test]
+fn kitty_keyboard_query_reply() {
+    let mut s = Screen::new();
+    s.add_bytes(b"a\x1b[?ub").unwrap();
+    assert_eq!(s.take_replies(), b"\x1b[?0u".to_vec());
+    assert!(s.take_replies().is_empty());
+
+    // Push and pop are swallowed without a reply and don't start TUI mode
+    assert!(s.add_bytes(b"\x1b[>1u\x1b[<u").is_ok());
+    assert!(s.take_replies().is_empty());
+    check_compacted_row(&s, 0, "ab");
+}
+
+#[test]
+fn focus_reporting_mode() {
+    let mut s = Screen::new();
+    assert_eq!(s.focus_report(true), None);
+
+    s.add_bytes(b"\x1b[?1004h").unwrap_or(());
+    assert_eq!(s.focus_report(true), Some(&b"\x1b[I"[..]));
+    assert_eq!(s.focus_report(false), Some(&b"\x1b[O"[..]));
+
+    s.add_bytes(b"\x1b[?1004l").unwrap_or(());
+    assert_eq!(s.focus_report(true), None);
+}
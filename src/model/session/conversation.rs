@@ -32,11 +32,16 @@ pub struct Conversation {
     pub prompt: Matrix,
     /// Hash value of the prompt for displaying a color
     pub prompt_hash: u64,
+    /// Index of the color of the prompt seam
+    pub prompt_color: usize,
 }
 
 impl Conversation {
     /// Creates a new conversation without any interactions.
-    pub fn new(prompt: Matrix) -> Conversation {
+    ///
+    /// The color is picked from the prompt, but differs from the color of the previous
+    /// conversation.
+    pub fn new(prompt: Matrix, previous_color: Option<usize>, num_colors: usize) -> Conversation {
         let mut h = DefaultHasher::new();
         prompt.hash(&mut h);
         let prompt_hash = h.finish();
@@ -45,6 +50,18 @@ impl Conversation {
             prompt,
             interactions: vec![],
             prompt_hash,
+            prompt_color: Self::pick_color(prompt_hash, previous_color, num_colors),
+        }
+    }
+
+    /// Choose a color for the prompt hash that is not the previous one, if possible.
+    pub fn pick_color(prompt_hash: u64, previous_color: Option<usize>, num_colors: usize) -> usize {
+        let num_colors = std::cmp::max(1, num_colors);
+        let color = (prompt_hash % (num_colors as u64)) as usize;
+        if previous_color == Some(color) {
+            (color + 1) % num_colors
+        } else {
+            color
         }
     }
 }
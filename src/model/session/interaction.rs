@@ -18,9 +18,15 @@
 
 //! Organizes the output of a sequence of programs
 
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
 use super::response::Response;
+use model::control_sequence::action::SemanticMark;
 use model::interpreter::jobs::Job;
-use model::screen::{Matrix, Screen};
+use model::screen::{Cell, Matrix, Screen};
 
 /// Which output is visible.
 ///
@@ -38,6 +44,8 @@ pub enum RunningStatus {
     Running,
     Unknown,
     Exited(i32),
+    /// Terminated by the given signal
+    Signaled(i32),
 }
 
 /// A command and its output.
@@ -63,15 +71,57 @@ pub struct Interaction {
     pub threads: usize,
     /// Job currently writing output to this interaction
     pub job: Option<Job>,
+    /// When output was received the last time
+    pub last_output: Option<Instant>,
+    /// False if output was received that has not been displayed yet
+    pub seen: bool,
+    /// Working directory last reported by the program via OSC 7
+    pub working_directory: Option<PathBuf>,
+    /// Exit code of the last command as reported by a semantic prompt mark (OSC 133;D)
+    pub reported_exit_code: Option<i32>,
+    /// Capability queries (XTGETTCAP) of the program that have not been answered yet
+    pub termcap_queries: Vec<String>,
+    /// Replies to queries of the program that have not been sent yet
+    pub replies: Vec<u8>,
 }
 
 impl RunningStatus {
     pub fn is_running(&self) -> bool {
         match self {
-            Self::Exited(_) => false,
+            Self::Exited(_) | Self::Signaled(_) => false,
             _ => true,
         }
     }
+
+    /// Exit status as the shell reports it in `$?`, None while running.
+    ///
+    /// Like bash, a program that was killed by a signal has the status 128 plus the signal
+    /// number.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Self::Exited(es) => Some(*es),
+            Self::Signaled(sig) => Some(128 + *sig),
+            _ => None,
+        }
+    }
+
+    /// Human readable description of how the program finished, None while running.
+    pub fn description(&self) -> Option<String> {
+        match self {
+            Self::Exited(es) => Some(format!("exited with code {}", es)),
+            Self::Signaled(sig) => Some(format!("terminated by signal {}", sig)),
+            _ => None,
+        }
+    }
+}
+
+impl From<ExitStatus> for RunningStatus {
+    fn from(status: ExitStatus) -> Self {
+        match status.signal() {
+            Some(sig) => Self::Signaled(sig),
+            None => Self::Exited(status.code().unwrap_or(1)),
+        }
+    }
 }
 
 impl Interaction {
@@ -90,9 +140,22 @@ impl Interaction {
             tui_screen,
             threads: 0,
             job: None,
+            last_output: None,
+            seen: true,
+            working_directory: None,
+            reported_exit_code: None,
+            termcap_queries: Vec::new(),
+            replies: Vec::new(),
         }
     }
 
+    /// Check if output has been received less than idle_time before now
+    pub fn received_output_within(&self, now: Instant, idle_time: Duration) -> bool {
+        self.last_output.map_or(false, |last| {
+            now.saturating_duration_since(last) < idle_time
+        })
+    }
+
     /// Get the visible response, if any.
     pub fn visible_response(&self) -> Option<&Response> {
         match self.visible {
@@ -107,6 +170,17 @@ impl Interaction {
         !self.errors.lines.is_empty()
     }
 
+    /// Cells to append to the last row of the command if the program was killed by a signal
+    pub fn signal_suffix(&self) -> Option<Vec<Cell>> {
+        match self.running_status {
+            RunningStatus::Signaled(_) => self
+                .running_status
+                .description()
+                .map(|d| Screen::one_line_cell_vec(format!(" \x1b[2m[{}]", d).as_bytes())),
+            _ => None,
+        }
+    }
+
     /// Make the error lines visible
     pub fn show_errors(&mut self) {
         self.visible = OutputVisibility::Error;
@@ -116,6 +190,7 @@ impl Interaction {
     pub fn show_potential_errors(&mut self) {
         let failure = match self.running_status {
             RunningStatus::Exited(es) => es != 0,
+            RunningStatus::Signaled(_) => true,
             _ => false,
         };
         if !failure {
@@ -125,6 +200,61 @@ impl Interaction {
         }
     }
 
+    /// Remember the working directory, the semantic prompt marks, the capability queries and the
+    /// replies to other queries the program sent
+    pub fn collect_reports(&mut self) {
+        let reported = self
+            .tui_screen
+            .take_working_directory()
+            .or_else(|| self.output.screen.take_working_directory())
+            .or_else(|| self.errors.screen.take_working_directory());
+        if reported.is_some() {
+            self.working_directory = reported;
+        }
+        // The marks of the responses have already been placed on their lines
+        let tui_marks = self.tui_screen.take_semantic_marks();
+        let marks = tui_marks
+            .iter()
+            .chain(self.output.take_new_marks())
+            .chain(self.errors.take_new_marks());
+        for (mark, _) in marks {
+            if let SemanticMark::CommandEnd(Some(code)) = mark {
+                self.reported_exit_code = Some(*code);
+            }
+        }
+        self.termcap_queries
+            .extend(self.tui_screen.take_termcap_queries());
+        self.termcap_queries
+            .extend(self.output.screen.take_termcap_queries());
+        self.termcap_queries
+            .extend(self.errors.screen.take_termcap_queries());
+        self.replies.extend(self.tui_screen.take_replies());
+        self.replies.extend(self.output.screen.take_replies());
+        self.replies.extend(self.errors.screen.take_replies());
+    }
+
+    /// Send the replies to queries to the program, if it is still running
+    pub fn send_replies(&mut self) {
+        let replies = std::mem::take(&mut self.replies);
+        if let Some(ref mut job) = self.job {
+            if !replies.is_empty() {
+                job.write_stdin(&replies);
+            }
+        }
+    }
+
+    /// Running status to decorate the command with.
+    ///
+    /// A program that runs commands itself, like a shell on another host, can report the exit
+    /// code of the last one with OSC 133;D. Once the program has exited, that code is shown
+    /// instead of its own.
+    pub fn shown_status(&self) -> RunningStatus {
+        match (&self.running_status, self.reported_exit_code) {
+            (RunningStatus::Exited(_), Some(code)) => RunningStatus::Exited(code),
+            (status, _) => status.clone(),
+        }
+    }
+
     /// If there is data in the TUI screen, add it to the end of output
     pub fn exit_cleanup(&mut self) {
         trace!("exit cleanup on interaction");
@@ -137,6 +267,24 @@ impl Interaction {
         }
     }
 
+    /// Bytes to send to the program when the window gained or lost the focus, if it asked for them
+    pub fn focus_report(&self, gained: bool) -> Option<&'static [u8]> {
+        if self.tui_mode {
+            self.tui_screen.focus_report(gained)
+        } else {
+            self.output.screen.focus_report(gained)
+        }
+    }
+
+    /// Bytes a cursor key sends to the program, given the final byte of its sequence
+    pub fn cursor_key(&self, final_byte: u8) -> [u8; 3] {
+        if self.tui_mode {
+            self.tui_screen.cursor_key(final_byte)
+        } else {
+            self.output.screen.cursor_key(final_byte)
+        }
+    }
+
     pub fn set_tui_size(&mut self, w: usize, h: usize) {
         self.tui_mode = true;
         self.tui_screen.fixed_size(w, h);
@@ -34,6 +34,8 @@ pub enum LineType {
     Output,
     /// The input line.
     Input,
+    /// Further lines of an incomplete input.
+    Continuation,
     /// Additional info about the input line, e.g. predictions, history
     InputInfo,
 
@@ -65,18 +67,23 @@ pub struct LineItem<'a> {
     /// Cursor position, if any
     pub cursor_col: Option<usize>,
 
-    /// Hash value of the prompt for coloring
-    pub prompt_hash: u64,
+    /// Index of the color of the prompt seam
+    pub prompt_color: usize,
 }
 
 impl<'a> LineItem<'a> {
     /// Create a new line item.
-    pub fn new(l: &'a [Cell], is_a: LineType, cursor_col: Option<usize>, prompt_hash: u64) -> Self {
+    pub fn new(
+        l: &'a [Cell],
+        is_a: LineType,
+        cursor_col: Option<usize>,
+        prompt_color: usize,
+    ) -> Self {
         Self {
             text: Cow::Borrowed(l),
             is_a,
             cursor_col,
-            prompt_hash,
+            prompt_color,
         }
     }
 
@@ -85,13 +92,13 @@ impl<'a> LineItem<'a> {
         l: Vec<Cell>,
         is_a: LineType,
         cursor_col: Option<usize>,
-        prompt_hash: u64,
+        prompt_color: usize,
     ) -> Self {
         Self {
             text: Cow::Owned(l),
             is_a,
             cursor_col,
-            prompt_hash,
+            prompt_color,
         }
     }
 }
@@ -107,4 +107,23 @@ impl SessionLocator {
             ) => line == 0,
         }
     }
+
+    /// Position of the line in the order the session is drawn.
+    ///
+    /// Locators can be compared by their positions.
+    pub fn position(&self) -> (usize, usize, usize, usize, usize) {
+        match self.in_conversation {
+            ConversationLocator::Interaction(index, ref in_interaction) => {
+                let (part, line) = match *in_interaction {
+                    InteractionLocator::Command(line) => (0, line),
+                    InteractionLocator::Tui(line)
+                    | InteractionLocator::Response(ResponseLocator::Lines(line)) => (1, line),
+                    InteractionLocator::Response(ResponseLocator::Screen(line)) => (2, line),
+                };
+                (self.conversation, 0, index, part, line)
+            }
+            // The prompt is drawn below the interactions of its conversation
+            ConversationLocator::Prompt(line) => (self.conversation, 1, 0, 0, line),
+        }
+    }
 }
@@ -26,10 +26,15 @@ mod response;
 #[cfg(test)]
 pub mod test;
 
+use std::io::Write;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use model::control_sequence::action::SemanticMark;
 use model::interpreter::jobs::Job;
-use model::screen::{AddBytesResult, Matrix, Screen};
+use model::screen::{AddBytesResult, Matrix, Screen, CELL_UTF8_LEN};
+use tools::polling::WakeupPipe;
 use tools::shared_item;
 
 pub use self::interaction::{OutputVisibility, RunningStatus};
@@ -43,6 +48,12 @@ use self::interaction::Interaction;
 pub const DEFAULT_TUI_WIDTH: usize = 80;
 pub const DEFAULT_TUI_HEIGHT: usize = 25;
 
+/// Width the output of a command wraps at unless configured otherwise
+pub const DEFAULT_MAX_OUTPUT_WIDTH: usize = 4096;
+
+/// Number of colors to mark the conversations with unless configured otherwise
+pub const NUM_PROMPT_COLORS: usize = 20;
+
 /// Session that can be shared between threads
 #[derive(Clone)]
 pub struct SharedSession(pub Arc<Mutex<Session>>);
@@ -55,17 +66,57 @@ pub struct Session {
     /// History of conversations, oldest first.
     conversations: Vec<Conversation>,
 
+    /// Index of the first conversation to show at the end of the session. The ones before have
+    /// been cleared from view, but can still be reached by scrolling up.
+    first_conversation: usize,
+
     /// History of interactions, oldest first.
     interactions: Vec<Interaction>,
 
     /// Marker if the session has been changed since the last redraw
     needs_redraw: bool,
 
-    /// Width of window in characters
+    /// Width of window in characters. Zero until the window size is known.
     window_width: usize,
 
-    /// Height of window in characters
+    /// Height of window in characters. Zero until the window size is known.
     window_height: usize,
+
+    /// Pipe to wake up the GUI when the session changed. None until the GUI registers one.
+    wakeup: Option<WakeupPipe>,
+
+    /// Number of colors the prompts are marked with
+    num_prompt_colors: usize,
+
+    /// Marker if the interpreter changed its working directory since the prompt was updated
+    cwd_changed: bool,
+
+    /// Keep the bytes received by the interactions for debugging
+    keep_raw_bytes: bool,
+
+    /// Width the output of a command wraps at. None lets long lines grow without limit.
+    max_output_width: Option<usize>,
+
+    /// Hold back output that looks binary and show a summary instead
+    suppress_binary: bool,
+
+    /// Copy the output of the interactions to stdout and stderr of BiTE as it arrives
+    pass_through: bool,
+
+    /// Exit code requested by the *exit* builtin. None while the application should keep running.
+    exit_request: Option<i32>,
+
+    /// Files the *transcript* builtin asked the GUI to write the session to
+    transcript_requests: Vec<(PathBuf, TranscriptFormat)>,
+}
+
+/// File format of a session transcript
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TranscriptFormat {
+    /// Plain text without colors
+    Text,
+    /// HTML page with inline styles for the colors
+    Html,
 }
 
 /// Index of an interaction in a session.
@@ -84,11 +135,63 @@ impl Session {
     /// Create a new session.
     pub fn new(prompt: Matrix) -> Self {
         Session {
-            conversations: vec![Conversation::new(prompt)],
+            conversations: vec![Conversation::new(prompt, None, NUM_PROMPT_COLORS)],
+            first_conversation: 0,
             interactions: vec![],
             needs_redraw: true,
-            window_width: DEFAULT_TUI_WIDTH,
-            window_height: DEFAULT_TUI_HEIGHT,
+            window_width: 0,
+            window_height: 0,
+            wakeup: None,
+            num_prompt_colors: NUM_PROMPT_COLORS,
+            cwd_changed: false,
+            keep_raw_bytes: false,
+            max_output_width: Some(DEFAULT_MAX_OUTPUT_WIDTH),
+            suppress_binary: true,
+            pass_through: false,
+            exit_request: None,
+            transcript_requests: Vec::new(),
+        }
+    }
+
+    /// Start a new conversation with a color that differs from the previous one
+    fn push_conversation(&mut self, prompt: Matrix) {
+        let previous_color = self.conversations.last().map(|c| c.prompt_color);
+        self.conversations.push(Conversation::new(
+            prompt,
+            previous_color,
+            self.num_prompt_colors,
+        ));
+    }
+
+    /// Change the number of prompt colors and pick new colors for all conversations
+    fn set_num_prompt_colors(&mut self, num_colors: usize) {
+        self.num_prompt_colors = num_colors;
+        let mut previous_color = None;
+        for conversation in self.conversations.iter_mut() {
+            let color =
+                Conversation::pick_color(conversation.prompt_hash, previous_color, num_colors);
+            conversation.prompt_color = color;
+            previous_color = Some(color);
+        }
+        self.needs_redraw = true;
+    }
+
+    /// Size of the window in characters or the default TUI size if the window size is unknown
+    fn window_size(&self) -> (usize, usize) {
+        if self.window_width == 0 || self.window_height == 0 {
+            (DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT)
+        } else {
+            (self.window_width, self.window_height)
+        }
+    }
+
+    /// Remember the size of the window and let running TUIs use all of it
+    fn set_window_size(&mut self, w: usize, h: usize) {
+        self.window_width = w;
+        self.window_height = h;
+        let (tui_width, tui_height) = self.window_size();
+        for interaction in self.interactions.iter_mut().filter(|i| i.tui_mode) {
+            interaction.set_tui_size(tui_width, tui_height);
         }
     }
 
@@ -341,6 +444,76 @@ impl Session {
         None
     }
 
+    /// Return a locator at the start of the last command before the given line.
+    ///
+    /// Besides the commands of the interactions, these are the commands of shells running inside
+    /// an interaction. The shells mark them as prompt start (OSC 133;A) or output start
+    /// (OSC 133;C).
+    pub fn locate_at_previous_command(&self, loc: &SessionLocator) -> MaybeSessionLocator {
+        let here = loc.position();
+        self.command_starts()
+            .into_iter()
+            .rev()
+            .find(|start| start.position() < here)
+    }
+
+    /// Return a locator at the start of the first command after the given line.
+    ///
+    /// See locate_at_previous_command for what counts as command.
+    pub fn locate_at_next_command(&self, loc: &SessionLocator) -> MaybeSessionLocator {
+        let here = loc.position();
+        self.command_starts()
+            .into_iter()
+            .find(|start| start.position() > here)
+    }
+
+    /// Locators at the starts of all commands, in the order they are drawn
+    fn command_starts(&self) -> Vec<SessionLocator> {
+        let mut starts = Vec::new();
+        for (conversation_index, conversation) in self.conversations.iter().enumerate() {
+            for (interaction_index, handle) in conversation.interactions.iter().enumerate() {
+                let at = |in_interaction| SessionLocator {
+                    conversation: conversation_index,
+                    in_conversation: ConversationLocator::Interaction(
+                        interaction_index,
+                        in_interaction,
+                    ),
+                };
+                starts.push(at(InteractionLocator::Command(0)));
+
+                let interaction = &self.interactions[handle.0];
+                if interaction.tui_mode {
+                    continue;
+                }
+                if let Some(response) = interaction.visible_response() {
+                    let archived = response.lines.len();
+                    let shown = archived + response.screen.height() as usize;
+                    let mut lines: Vec<usize> = response
+                        .marks
+                        .iter()
+                        .filter(|(mark, line)| {
+                            *line < shown
+                                && (*mark == SemanticMark::PromptStart
+                                    || *mark == SemanticMark::OutputStart)
+                        })
+                        .map(|(_, line)| *line)
+                        .collect();
+                    lines.sort();
+                    lines.dedup();
+                    for line in lines {
+                        let in_response = if line < archived {
+                            ResponseLocator::Lines(line)
+                        } else {
+                            ResponseLocator::Screen(line - archived)
+                        };
+                        starts.push(at(InteractionLocator::Response(in_response)));
+                    }
+                }
+            }
+        }
+        starts
+    }
+
     /// Return a locator at the start of the prompt of the current conversation
     pub fn locate_at_prompt_start(&self, loc: &SessionLocator) -> MaybeSessionLocator {
         if loc.conversation < self.conversations.len() {
@@ -487,11 +660,58 @@ impl Session {
         }
     }
 
+    /// Move a locator in the conversations that have been cleared from view to the first one
+    /// after them.
+    pub fn skip_cleared(&self, loc: SessionLocator) -> SessionLocator {
+        if loc.conversation < self.first_conversation {
+            SessionLocator {
+                conversation: self.first_conversation,
+                in_conversation: ConversationLocator::Prompt(0),
+            }
+        } else {
+            loc
+        }
+    }
+
     /// Determine if locator is at last conversation
     pub fn locator_is_last_conversation(&self, loc: &SessionLocator) -> bool {
         (loc.conversation + 1) == self.conversations.len()
     }
 
+    /// Number of interactions in view whose output has not been displayed yet
+    pub fn unseen_count(&self) -> usize {
+        self.conversations[self.first_conversation..]
+            .iter()
+            .flat_map(|c| c.interactions.iter())
+            .filter(|handle| self.interaction(**handle, false, |i| !i.seen))
+            .count()
+    }
+
+    /// Mark the output of the interactions between two locators as displayed
+    pub fn mark_seen(&mut self, first: &SessionLocator, last: &SessionLocator) {
+        let end = std::cmp::min(last.conversation + 1, self.conversations.len());
+        for index in first.conversation..end {
+            let conversation = &self.conversations[index];
+            // The prompt is shown below the interactions of its conversation
+            let from = match first.in_conversation {
+                ConversationLocator::Interaction(i, _) if index == first.conversation => i,
+                ConversationLocator::Prompt(_) if index == first.conversation => {
+                    conversation.interactions.len()
+                }
+                _ => 0,
+            };
+            let to = match last.in_conversation {
+                ConversationLocator::Interaction(i, _) if index == last.conversation => i + 1,
+                _ => conversation.interactions.len(),
+            };
+            for handle in conversation.interactions.iter().take(to).skip(from) {
+                if let Some(interaction) = self.interactions.get_mut(handle.0) {
+                    interaction.seen = true;
+                }
+            }
+        }
+    }
+
     /// Quick access to an interaction by handle.
     ///
     /// Returns the default for illegal handles.
@@ -542,7 +762,7 @@ impl Session {
     pub fn display_line<'a>(&'a self, loc: &SessionLocator) -> Option<LineItem<'a>> {
         if loc.conversation < self.conversations.len() {
             let conversation = &self.conversations[loc.conversation];
-            let prompt_hash = conversation.prompt_hash;
+            let prompt_color = conversation.prompt_color;
             match &loc.in_conversation {
                 ConversationLocator::Prompt(line) => {
                     if *line < (conversation.prompt.rows() as usize) {
@@ -550,7 +770,7 @@ impl Session {
                             conversation.prompt.compacted_row_slice(*line as isize),
                             LineType::Prompt,
                             None,
-                            prompt_hash,
+                            prompt_color,
                         ));
                     }
                 }
@@ -565,14 +785,24 @@ impl Session {
                                         let lt = LineType::Command(
                                             interaction.visible,
                                             *interaction_handle,
-                                            interaction.running_status.clone(),
+                                            interaction.shown_status(),
                                         );
-                                        return Some(LineItem::new(
-                                            interaction.command.compacted_row_slice(*line as isize),
-                                            lt,
-                                            None,
-                                            prompt_hash,
-                                        ));
+                                        let text =
+                                            interaction.command.compacted_row_slice(*line as isize);
+                                        // Tell a killed program from one that exited on its own
+                                        if *line + 1 == interaction.command.rows() as usize {
+                                            if let Some(suffix) = interaction.signal_suffix() {
+                                                let mut text = text.to_vec();
+                                                text.extend(suffix);
+                                                return Some(LineItem::new_owned(
+                                                    text,
+                                                    lt,
+                                                    None,
+                                                    prompt_color,
+                                                ));
+                                            }
+                                        }
+                                        return Some(LineItem::new(text, lt, None, prompt_color));
                                     }
                                 }
                                 InteractionLocator::Tui(line) => {
@@ -581,7 +811,7 @@ impl Session {
                                             interaction.tui_screen.row_slice(*line as isize),
                                             LineType::Output,
                                             None,
-                                            prompt_hash,
+                                            prompt_color,
                                         ));
                                     }
                                 }
@@ -592,7 +822,7 @@ impl Session {
                                                 &r.lines[*line][..],
                                                 LineType::Output,
                                                 None,
-                                                conversation.prompt_hash,
+                                                conversation.prompt_color,
                                             ))
                                         } else {
                                             None
@@ -606,7 +836,7 @@ impl Session {
                                                 &r.screen.compacted_row_slice(*line as isize),
                                                 LineType::Output,
                                                 None,
-                                                conversation.prompt_hash,
+                                                conversation.prompt_color,
                                             ))
                                         } else {
                                             None
@@ -622,6 +852,34 @@ impl Session {
         None
     }
 
+    /// Number of the output line at the locator in the visible response of the given interaction.
+    ///
+    /// Returns the 1-based number and the number of lines in the response. None if the locator
+    /// doesn't point to an output line of that interaction.
+    pub fn output_line_number(
+        &self,
+        handle: InteractionHandle,
+        loc: &SessionLocator,
+    ) -> Option<(usize, usize)> {
+        let conversation = self.conversations.get(loc.conversation)?;
+        let (index, line) = match &loc.in_conversation {
+            ConversationLocator::Interaction(index, InteractionLocator::Response(line)) => {
+                (*index, line)
+            }
+            _ => return None,
+        };
+        if conversation.interactions.get(index) != Some(&handle) {
+            return None;
+        }
+        let response = self.interactions.get(handle.0)?.visible_response()?;
+        let archived = response.lines.len();
+        let number = match line {
+            ResponseLocator::Lines(line) => *line,
+            ResponseLocator::Screen(line) => archived + *line,
+        };
+        Some((number + 1, archived + response.screen.height() as usize))
+    }
+
     /// Add a new interaction to the latest conversation.
     fn add_interaction_to_last(&mut self, command: Matrix) -> InteractionHandle {
         let handle = InteractionHandle(self.interactions.len());
@@ -632,6 +890,18 @@ impl Session {
         handle
     }
 
+    /// Text of the command of an interaction. Multiple lines are joined by spaces.
+    pub fn command_text(&self, handle: InteractionHandle) -> Option<String> {
+        self.interaction(handle, None, |i| {
+            let lines: Vec<String> = i
+                .command
+                .line_iter()
+                .map(|l| l.iter().flat_map(|c| c.chars()).collect())
+                .collect();
+            Some(lines.join(" "))
+        })
+    }
+
     pub fn tui_screen<'a>(&'a self, handle: &InteractionHandle) -> Option<&'a Screen> {
         if handle.0 < self.interactions.len() {
             let interaction = &self.interactions[handle.0];
@@ -744,18 +1014,88 @@ impl SharedSession {
                     return;
                 }
             }
-            s.conversations.push(Conversation::new(prompt));
+            s.push_conversation(prompt);
+        });
+    }
+
+    /// Clear the view, i.e. hide all conversations before the current prompt.
+    ///
+    /// Nothing is deleted. The hidden conversations can be reached by scrolling up. If the last conversation already has interactions, a new one with the
+    /// same prompt is started, so that the prompt will become the first line of the view.
+    pub fn clear_view(&mut self) {
+        self.session_mut((), |s| {
+            if let Some(current) = s.conversations.last() {
+                if !current.interactions.is_empty() {
+                    let prompt = current.prompt.clone();
+                    s.push_conversation(prompt);
+                }
+            }
+            s.first_conversation = s.conversations.len() - 1;
+            s.needs_redraw = true;
         });
     }
 
     /// Add bytes to selected stream of interaction
     ///
-    /// If the interaction is already in TUI mode, use that response instead.
+    /// If the interaction is already in TUI mode, use that response instead. Incomplete
+    /// characters and control sequences at the end are held back until the stream completes them,
+    /// so that the other stream can't split them.
     pub fn add_bytes(&mut self, stream: OutputVisibility, handle: InteractionHandle, bytes: &[u8]) {
         let mut needs_redraw = false;
+        let (tui_width, tui_height) = self.window_size();
+        let keep_raw_bytes = self.session(false, |s| s.keep_raw_bytes);
+        let max_output_width = self.session(None, |s| s.max_output_width);
+        let suppress_binary = self.session(false, |s| s.suppress_binary);
+        if self.session(false, |s| s.pass_through) {
+            let _ = match stream {
+                OutputVisibility::None => Ok(()),
+                OutputVisibility::Output => {
+                    let mut out = std::io::stdout();
+                    out.write_all(bytes).and_then(|_| out.flush())
+                }
+                OutputVisibility::Error => std::io::stderr().write_all(bytes),
+            };
+        }
         self.interaction_mut(handle, (), |interaction| {
+            if !bytes.is_empty() {
+                interaction.last_output = Some(Instant::now());
+                interaction.seen = false;
+            }
+            if keep_raw_bytes {
+                match stream {
+                    OutputVisibility::None => {}
+                    OutputVisibility::Output => {
+                        interaction.output.raw_bytes.extend_from_slice(bytes)
+                    }
+                    OutputVisibility::Error => {
+                        interaction.errors.raw_bytes.extend_from_slice(bytes)
+                    }
+                }
+            }
+            let complete = match stream {
+                OutputVisibility::None => bytes.to_vec(),
+                OutputVisibility::Output => interaction.output.sequence.complete(bytes),
+                OutputVisibility::Error => interaction.errors.sequence.complete(bytes),
+            };
+            // Binary output is held back
+            if !interaction.tui_mode {
+                let held = match stream {
+                    OutputVisibility::None => false,
+                    OutputVisibility::Output => {
+                        interaction.output.hold_binary(&complete, suppress_binary)
+                    }
+                    OutputVisibility::Error => {
+                        interaction.errors.hold_binary(&complete, suppress_binary)
+                    }
+                };
+                if held {
+                    needs_redraw = true;
+                    interaction.visible = stream;
+                    return;
+                }
+            }
             // TUI mode overrides stream
-            let mut work = bytes;
+            let mut work = &complete[..];
             while work.len() != 0 {
                 if interaction.tui_mode {
                     // Add the bytes to the screen
@@ -772,6 +1112,7 @@ impl SharedSession {
                         OutputVisibility::Error => &mut interaction.errors,
                     };
                     // Process the bytes
+                    response.screen.set_max_width(max_output_width);
                     match response.add_bytes(work) {
                         AddBytesResult::AllDone => break,
                         AddBytesResult::ShowStream(new_work) => {
@@ -779,7 +1120,10 @@ impl SharedSession {
                             work = new_work;
                         }
                         AddBytesResult::StartTui(new_work) => {
-                            interaction.set_tui_size(DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+                            // Modes set by the sequence that started TUI mode stay in effect
+                            let modes = response.screen.private_modes();
+                            interaction.tui_screen.set_private_modes(modes);
+                            interaction.set_tui_size(tui_width, tui_height);
                             work = new_work;
                         }
                     }
@@ -788,7 +1132,14 @@ impl SharedSession {
             // Make new output show up
             interaction.visible = stream;
         });
+        self.interaction_mut(handle, (), |i| {
+            i.collect_reports();
+            i.send_replies();
+        });
         self.session_mut((), |s| s.needs_redraw |= needs_redraw);
+        if needs_redraw {
+            self.wake_up();
+        }
     }
 
     /// Set the running status of an interaction
@@ -798,19 +1149,166 @@ impl SharedSession {
             i.running_status = status;
             i.show_potential_errors();
         });
+        self.wake_up();
+    }
+
+    /// Check if an interaction received output less than idle_time ago
+    pub fn received_output_within(&self, handle: InteractionHandle, idle_time: Duration) -> bool {
+        let now = Instant::now();
+        self.interaction(handle, false, |i| i.received_output_within(now, idle_time))
     }
 
     /// Check if the given interaction is still running
     pub fn has_exited(&self, handle: InteractionHandle) -> bool {
+        self.interaction(handle, false, |i| match i.running_status {
+            RunningStatus::Exited(_) | RunningStatus::Signaled(_) => true,
+            _ => false,
+        })
+    }
+
+    /// Exit status of an interaction, None while it is running
+    pub fn exit_status(&self, handle: InteractionHandle) -> Option<i32> {
+        self.interaction(handle, None, |i| i.running_status.exit_code())
+    }
+
+    /// Working directory the program of an interaction reported last
+    pub fn working_directory(&self, handle: InteractionHandle) -> Option<PathBuf> {
+        self.session(None, |s| {
+            s.interaction(handle, None, |i| i.working_directory.clone())
+        })
+    }
+
+    /// Take the capability queries all interactions received since the last call
+    ///
+    /// Interactions without queries are left out.
+    pub fn take_termcap_queries(&mut self) -> Vec<(InteractionHandle, Vec<String>)> {
+        self.session_mut(Vec::new(), |s| {
+            s.interactions
+                .iter_mut()
+                .enumerate()
+                .filter(|(_, i)| !i.termcap_queries.is_empty())
+                .map(|(index, i)| {
+                    (
+                        InteractionHandle(index),
+                        std::mem::take(&mut i.termcap_queries),
+                    )
+                })
+                .collect()
+        })
+    }
+
+    /// Bytes to send to an interaction when the window gained or lost the focus
+    pub fn focus_report(&self, handle: InteractionHandle, gained: bool) -> Option<&'static [u8]> {
+        self.interaction(handle, None, |i| i.focus_report(gained))
+    }
+
+    /// Bytes a cursor key sends to an interaction, given the final byte of its sequence
+    pub fn cursor_key(&self, handle: InteractionHandle, final_byte: u8) -> [u8; 3] {
+        self.interaction(handle, [0x1b, b'[', final_byte], |i| {
+            i.cursor_key(final_byte)
+        })
+    }
+
+    /// Check if any threads still feed data into an interaction
+    pub fn has_feeding_threads(&self, handle: InteractionHandle) -> bool {
+        self.interaction(handle, false, |i| i.threads != 0)
+    }
+
+    /// Text of stdout and stderr of an interaction
+    pub fn response_texts(&self, handle: InteractionHandle) -> (String, String) {
+        self.session(Default::default(), |s| {
+            s.interaction(handle, Default::default(), |i| {
+                (i.output.text(), i.errors.text())
+            })
+        })
+    }
+
+    /// Keep the bytes the interactions receive from now on, e.g. to replay them in a test
+    pub fn set_keep_raw_bytes(&mut self, keep: bool) {
+        self.session_mut((), |s| s.keep_raw_bytes = keep);
+    }
+
+    /// Set the width the output of commands wraps at. None lets long lines grow without limit.
+    pub fn set_max_output_width(&mut self, width: Option<usize>) {
+        self.session_mut((), |s| s.max_output_width = width);
+    }
+
+    /// Hold back output that looks binary and show a summary instead
+    pub fn set_suppress_binary(&mut self, suppress: bool) {
+        self.session_mut((), |s| s.suppress_binary = suppress);
+    }
+
+    /// Copy the output of the interactions to stdout and stderr of BiTE as it arrives
+    pub fn set_pass_through(&mut self, pass_through: bool) {
+        self.session_mut((), |s| s.pass_through = pass_through);
+    }
+
+    /// Check if binary output of an interaction is held back
+    pub fn is_binary_suppressed(&self, handle: InteractionHandle) -> bool {
         self.interaction(handle, false, |i| {
-            if let RunningStatus::Exited(_) = i.running_status {
-                true
-            } else {
-                false
+            i.output.is_binary() || i.errors.is_binary()
+        })
+    }
+
+    /// Show the binary output of an interaction that has been held back
+    pub fn show_binary_output(&mut self, handle: InteractionHandle) {
+        self.interaction_mut(handle, (), |i| {
+            i.output.show_binary();
+            i.errors.show_binary();
+        });
+        self.session_mut((), |s| s.needs_redraw = true);
+        self.wake_up();
+    }
+
+    /// Bytes an interaction received on a stream while they were kept
+    pub fn raw_bytes(&self, handle: InteractionHandle, stream: OutputVisibility) -> Vec<u8> {
+        self.session(Vec::new(), |s| {
+            s.interaction(handle, Vec::new(), |i| match stream {
+                OutputVisibility::None => Vec::new(),
+                OutputVisibility::Output => i.output.raw_bytes.clone(),
+                OutputVisibility::Error => i.errors.raw_bytes.clone(),
+            })
+        })
+    }
+
+    /// Register the pipe that wakes up the GUI when the session changes
+    pub fn set_wakeup(&mut self, wakeup: WakeupPipe) {
+        self.session_mut((), |s| s.wakeup = Some(wakeup));
+    }
+
+    /// Wake up the GUI, e.g. because new output arrived
+    pub fn wake_up(&self) {
+        self.session((), |s| {
+            if let Some(ref wakeup) = s.wakeup {
+                wakeup.wake();
             }
         })
     }
 
+    /// Ask the application to shut down with the given exit code
+    pub fn request_exit(&mut self, code: i32) {
+        self.session_mut((), |s| s.exit_request = Some(code));
+        self.wake_up();
+    }
+
+    /// Exit code the application should shut down with, if requested
+    pub fn exit_request(&self) -> Option<i32> {
+        self.session(None, |s| s.exit_request)
+    }
+
+    /// Ask the GUI to write the shown session to a file
+    pub fn request_transcript(&mut self, path: PathBuf, format: TranscriptFormat) {
+        self.session_mut((), |s| s.transcript_requests.push((path, format)));
+        self.wake_up();
+    }
+
+    /// Take the files the session should be written to, oldest request first
+    pub fn take_transcript_requests(&mut self) -> Vec<(PathBuf, TranscriptFormat)> {
+        self.session_mut(Vec::new(), |s| {
+            std::mem::replace(&mut s.transcript_requests, Vec::new())
+        })
+    }
+
     /// Mark the session as redrawn
     pub fn mark_drawn(&mut self) {
         self.session_mut((), |s| s.needs_redraw = false)
@@ -825,6 +1323,26 @@ impl SharedSession {
         })
     }
 
+    /// Mark that the interpreter changed its working directory
+    pub fn mark_cwd_changed(&mut self) {
+        self.session_mut((), |s| s.cwd_changed = true);
+        self.wake_up();
+    }
+
+    /// Check if the working directory of the interpreter changed and reset that
+    pub fn check_cwd_changed(&mut self) -> bool {
+        self.session_mut(false, |s| {
+            let res = s.cwd_changed;
+            s.cwd_changed = false;
+            res
+        })
+    }
+
+    /// Number of interactions in view whose output has not been displayed yet
+    pub fn unseen_count(&self) -> usize {
+        self.session(0, |s| s.unseen_count())
+    }
+
     /// Check if the given interaction is in TUI mode
     pub fn is_tui(&self, handle: InteractionHandle) -> bool {
         self.interaction(handle, false, |i| i.tui_mode)
@@ -892,36 +1410,50 @@ impl SharedSession {
         });
     }
 
-    /// Print the interaction to the respective streams
-    pub fn print_interaction(&mut self, handle: InteractionHandle) {
+    /// Print the interaction to the given streams
+    pub fn print_interaction(
+        &mut self,
+        handle: InteractionHandle,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) {
         self.interaction(handle, (), |interaction| {
-            use std::io::Write;
-            let mut b = [0; 4];
-            {
-                let stdout = std::io::stdout();
-                let mut stdout = stdout.lock();
-                let _ = stdout.write(b"BiTE startup stdout output\n");
-                for l in interaction.output.lines.iter() {
-                    for c in l {
-                        let _ = stdout.write(c.code_point().encode_utf8(&mut b).as_bytes());
-                    }
-                    let _ = stdout.write(b"\n");
+            let mut b = [0; CELL_UTF8_LEN];
+            let _ = stdout.write(b"BiTE startup stdout output\n");
+            for l in interaction.output.lines.iter() {
+                for c in l {
+                    let _ = stdout.write(c.encode_utf8(&mut b).as_bytes());
                 }
+                let _ = stdout.write(b"\n");
             }
-            {
-                let stderr = std::io::stderr();
-                let mut stderr = stderr.lock();
-                let _ = stderr.write(b"BiTE startup stderr output\n");
-                for l in interaction.errors.lines.iter() {
-                    for c in l {
-                        let _ = stderr.write(c.code_point().encode_utf8(&mut b).as_bytes());
-                    }
-                    let _ = stderr.write(b"\n");
+            let _ = stderr.write(b"BiTE startup stderr output\n");
+            for l in interaction.errors.lines.iter() {
+                for c in l {
+                    let _ = stderr.write(c.encode_utf8(&mut b).as_bytes());
                 }
+                let _ = stderr.write(b"\n");
             }
         });
     }
 
+    /// Deal with the output of the init script, which ran before there was a GUI.
+    ///
+    /// If `debug_init` is set, the output is printed to the given streams. Otherwise, it is hidden
+    /// in the session, where clicking the command shows it.
+    pub fn report_init_output(
+        &mut self,
+        handle: InteractionHandle,
+        debug_init: bool,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) {
+        if debug_init {
+            self.print_interaction(handle, stdout, stderr);
+        } else {
+            self.set_visibility(handle, OutputVisibility::None);
+        }
+    }
+
     /// Set the current job of an interaction
     pub fn set_job(&mut self, handle: InteractionHandle, job: Option<Job>) {
         self.interaction_mut(handle, (), |i| i.job = job)
@@ -978,15 +1510,25 @@ impl SharedSession {
         })
     }
 
+    /// Check if any interaction has not exited yet
+    pub fn has_running_interactions(&self) -> bool {
+        self.session(false, |s| {
+            s.interactions.iter().any(|i| i.running_status.is_running())
+        })
+    }
+
     pub fn set_window_size(&mut self, w: usize, h: usize) {
-        self.session_mut((), |s| {
-            s.window_width = w;
-            s.window_height = h;
-        });
+        self.session_mut((), |s| s.set_window_size(w, h));
+    }
+
+    /// Set the number of colors the prompts are marked with
+    pub fn set_num_prompt_colors(&mut self, num_colors: usize) {
+        self.session_mut((), |s| s.set_num_prompt_colors(num_colors));
     }
 
+    /// Size of the window in characters, see Session::window_size
     pub fn window_size(&self) -> (usize, usize) {
-        self.session((80, 25), |s| (s.window_width, s.window_height))
+        self.session((DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT), |s| s.window_size())
     }
 
     pub fn set_tui_size(&mut self, handle: InteractionHandle, w: usize, h: usize) {
@@ -20,16 +20,103 @@
 //!
 //! Consists of the lines are read from either stdout or stderr.
 
+use std::cmp;
+
+use model::control_sequence::action::SemanticMark;
+use model::control_sequence::parser::Parser;
 use model::screen::{AddBytesResult, Cell, Event, Screen};
 
+/// Share of control characters in percent above which output is considered binary
+const BINARY_CONTROL_PERCENT: usize = 30;
+
+/// Number of bytes of binary output that are kept to be shown on request. Further bytes are only
+/// counted.
+const MAX_BINARY_BYTES: usize = 64 * 1024;
+
+/// Check if the bytes look like the content of a binary file.
+///
+/// That is the case if many of them are NULs or control characters other than the ones text
+/// output uses, i.e. line breaks, tabs, backspace, bell and escape.
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    let control = bytes
+        .iter()
+        .filter(|b| match **b {
+            b'\n' | b'\r' | b'\t' | 0x07 | 0x08 | 0x1b => false,
+            0..=0x1f | 0x7f => true,
+            _ => false,
+        })
+        .count();
+    !bytes.is_empty() && control * 100 >= bytes.len() * BINARY_CONTROL_PERCENT
+}
+
+/// Holds back incomplete characters and control sequences of a stream.
+///
+/// If two streams feed the same screen, their bytes must only be interleaved between complete
+/// sequences.
+pub struct SequenceBuffer {
+    /// Parser that tracks where sequences end
+    parser: Parser,
+    /// Bytes of the incomplete sequence
+    pending: Vec<u8>,
+}
+
+impl SequenceBuffer {
+    pub fn new() -> Self {
+        Self {
+            parser: Parser::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Add bytes and return those that form complete sequences, including earlier pending ones.
+    pub fn complete(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut complete = 0;
+        for b in bytes {
+            self.pending.push(*b);
+            let _ = self.parser.add_byte(*b);
+            if self.parser.is_ground() {
+                complete = self.pending.len();
+            }
+        }
+        self.pending.drain(..complete).collect()
+    }
+}
+
 /// The full output of a program
-#[derive(PartialEq)]
 pub struct Response {
     /// Lines to be shown. Each line in a normal response is just a sequence of cells.
     pub lines: Vec<Vec<Cell>>,
 
     /// A temporary screen we add data to until they can be archived in *lines*.
     pub screen: Screen,
+
+    /// Bytes received so far. Only kept if the session is told so, for debugging.
+    pub raw_bytes: Vec<u8>,
+
+    /// Incomplete sequence at the end of the bytes received so far
+    pub sequence: SequenceBuffer,
+
+    /// Binary output that is not shown. None if the output is shown.
+    binary: Option<Vec<u8>>,
+
+    /// Number of bytes of binary output, including the ones that have not been kept
+    binary_len: usize,
+
+    /// Has the response received any bytes yet?
+    started: bool,
+
+    /// Semantic prompt marks (OSC 133) with the index of the line they were received on. Lines
+    /// past the archived ones are on the screen.
+    pub marks: Vec<(SemanticMark, usize)>,
+
+    /// Number of marks that have been taken by take_new_marks
+    taken_marks: usize,
+}
+
+impl PartialEq for Response {
+    fn eq(&self, other: &Response) -> bool {
+        self.lines == other.lines && self.screen == other.screen
+    }
 }
 
 impl Response {
@@ -38,6 +125,76 @@ impl Response {
         Response {
             lines: vec![],
             screen: Screen::new(),
+            raw_bytes: vec![],
+            sequence: SequenceBuffer::new(),
+            binary: None,
+            binary_len: 0,
+            started: false,
+            marks: Vec::new(),
+            taken_marks: 0,
+        }
+    }
+
+    /// Hold back the bytes if the response receives binary output.
+    ///
+    /// The first bytes decide: if `detect` is set and they look binary, they and all further
+    /// bytes are kept instead of shown. A summary with their number is shown instead. Only the
+    /// first MAX_BINARY_BYTES are kept, the rest is counted. Returns true if the bytes have been
+    /// held back.
+    pub fn hold_binary(&mut self, bytes: &[u8], detect: bool) -> bool {
+        if !self.started && !bytes.is_empty() {
+            self.started = true;
+            if detect && looks_binary(bytes) {
+                self.binary = Some(Vec::new());
+            }
+        }
+        match self.binary {
+            Some(ref mut held) => {
+                let keep = cmp::min(bytes.len(), MAX_BINARY_BYTES - held.len());
+                held.extend_from_slice(&bytes[..keep]);
+                self.binary_len += bytes.len();
+                let summary = if self.binary_len == held.len() {
+                    format!(
+                        "\x1b[2m[binary output suppressed: {} bytes, click the command to show it]",
+                        self.binary_len
+                    )
+                } else {
+                    format!(
+                        "\x1b[2m[binary output suppressed: {} bytes, truncated to {} bytes, click \
+                         the command to show them]",
+                        self.binary_len,
+                        held.len()
+                    )
+                };
+                self.lines = vec![Screen::one_line_cell_vec(summary.as_bytes())];
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Check if binary output is held back
+    pub fn is_binary(&self) -> bool {
+        self.binary.is_some()
+    }
+
+    /// Show the binary output that has been held back as it is.
+    ///
+    /// Requests to switch to TUI mode are ignored.
+    pub fn show_binary(&mut self) {
+        if let Some(bytes) = self.binary.take() {
+            self.lines.clear();
+            self.marks.clear();
+            self.taken_marks = 0;
+            let mut work = &bytes[..];
+            while !work.is_empty() {
+                match self.add_bytes(work) {
+                    AddBytesResult::AllDone => break,
+                    AddBytesResult::ShowStream(rest) | AddBytesResult::StartTui(rest) => {
+                        work = rest
+                    }
+                }
+            }
         }
     }
 
@@ -52,19 +209,57 @@ impl Response {
                     return AddBytesResult::ShowStream(&bytes[(i + 1)..]);
                 }
                 Event::Cr => {
+                    // The line isn't archived. Progress updates overwrite it until a newline ends
+                    // it, so only the last one is kept.
+                    self.collect_marks();
                     return AddBytesResult::ShowStream(&bytes[(i + 1)..]);
                 }
                 Event::StartTui => {
+                    self.collect_marks();
                     return AddBytesResult::StartTui(&bytes[(i + 1)..]);
                 }
                 _ => {}
             };
         }
+        self.collect_marks();
         AddBytesResult::AllDone
     }
 
+    /// Take the marks of the screen and remember the lines they were received on
+    fn collect_marks(&mut self) {
+        let archived = self.lines.len();
+        for (mark, row) in self.screen.take_semantic_marks() {
+            self.marks
+                .push((mark, archived + cmp::max(row, 0) as usize));
+        }
+    }
+
+    /// Marks that have been received since the last call
+    pub fn take_new_marks(&mut self) -> &[(SemanticMark, usize)] {
+        let start = self.taken_marks;
+        self.taken_marks = self.marks.len();
+        &self.marks[start..]
+    }
+
+    /// Text of the response, including the lines that are not archived yet
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for l in self.lines.iter() {
+            text.extend(l.iter().flat_map(|c| c.chars()));
+            text.push('\n');
+        }
+        for l in self.screen.line_iter() {
+            if !l.is_empty() {
+                text.extend(l.iter().flat_map(|c| c.chars()));
+                text.push('\n');
+            }
+        }
+        text
+    }
+
     /// Add all the lines on the screen to the archived lines
     pub fn archive_screen(&mut self) {
+        self.collect_marks();
         for l in self.screen.line_iter() {
             self.lines.push(l.to_vec());
         }
@@ -18,8 +18,13 @@
 
 //! Module tests for Session, mostly for the locator code
 
-use model::screen::Screen;
+use model::control_sequence::action::SemanticMark;
+use model::interpreter::jobs::Job;
+use model::screen::{Attributes, Cell, Screen};
 use model::session::*;
+use nix::unistd::{close, pipe, read};
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
 
 pub fn new_test_session(prompt: &[u8]) -> SharedSession {
     SharedSession(Arc::new(Mutex::new(Session::new(Screen::one_line_matrix(
@@ -130,3 +135,560 @@ fn locator() {
         );
     }
 }
+
+#[test]
+fn clear_view() {
+    let mut session = new_test_session(b"prompt 1");
+    let _ = session.add_interaction(Screen::one_line_matrix(b"command 1.1"));
+
+    session.clear_view();
+    session.session_mut((), |s| {
+        assert_eq!(s.conversations.len(), 2);
+        assert_eq!(s.first_conversation, 1);
+        assert!(s.conversations[0].prompt == s.conversations[1].prompt);
+        let loc = s.locate_at_last_prompt_end().unwrap();
+        assert_eq!(s.skip_cleared(loc.clone()), loc);
+        // The cleared conversation is still there
+        let prev = s.locate_at_previous_conversation(&loc).unwrap();
+        assert_eq!(prev.conversation, 0);
+        assert_eq!(
+            s.skip_cleared(prev),
+            SessionLocator {
+                conversation: 1,
+                in_conversation: ConversationLocator::Prompt(0),
+            }
+        );
+    });
+
+    // Clearing an empty conversation doesn't start another one
+    session.clear_view();
+    session.session_mut((), |s| {
+        assert_eq!(s.conversations.len(), 2);
+        assert_eq!(s.first_conversation, 1);
+    });
+}
+
+#[test]
+fn received_output_within() {
+    let mut session = new_test_session(b"prompt 1");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"command 1.1"));
+    let idle_time = Duration::from_millis(500);
+    assert!(!session.received_output_within(handle, idle_time));
+
+    // Empty writes don't count as output
+    session.add_bytes(OutputVisibility::Output, handle, b"");
+    assert!(!session.received_output_within(handle, idle_time));
+
+    session.add_bytes(OutputVisibility::Output, handle, b"output");
+    assert!(session.received_output_within(handle, idle_time));
+    session.session((), |s| {
+        let interaction = &s.interactions[handle.0];
+        let later = Instant::now() + idle_time;
+        assert!(!interaction.received_output_within(later, idle_time));
+    });
+}
+
+#[test]
+fn working_directory_from_osc7() {
+    let mut session = new_test_session(b"prompt 1");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"command 1.1"));
+    assert_eq!(session.working_directory(handle), None);
+
+    session.add_bytes(
+        OutputVisibility::Output,
+        handle,
+        b"\x1b]7;file://host/home/user/My%20Files\x07output\n",
+    );
+    assert_eq!(
+        session.working_directory(handle),
+        Some(PathBuf::from("/home/user/My Files"))
+    );
+
+    // Reports in TUI mode are seen as well
+    session.set_tui_size(handle, 10, 10);
+    session.add_bytes(
+        OutputVisibility::Output,
+        handle,
+        b"\x1b]7;file://host/tmp\x1b\\",
+    );
+    assert_eq!(
+        session.working_directory(handle),
+        Some(PathBuf::from("/tmp"))
+    );
+}
+
+/// Exit code the program of an interaction reported with a semantic prompt mark
+fn reported_exit_code(session: &SharedSession, handle: InteractionHandle) -> Option<i32> {
+    session.interaction(handle, None, |i| i.reported_exit_code)
+}
+
+#[test]
+fn semantic_prompt_marks() {
+    let mut session = new_test_session(b"prompt 1");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"command 1.1"));
+    assert_eq!(reported_exit_code(&session, handle), None);
+
+    session.add_bytes(
+        OutputVisibility::Output,
+        handle,
+        b"\x1b]133;A\x07$ \x1b]133;B\x07false\n\x1b]133;C\x07\x1b]133;D;1\x07",
+    );
+    assert_eq!(reported_exit_code(&session, handle), Some(1));
+    session.session((), |s| {
+        assert_eq!(
+            s.interactions[handle.0].output.marks,
+            vec![
+                (SemanticMark::PromptStart, 0),
+                (SemanticMark::PromptEnd, 0),
+                (SemanticMark::OutputStart, 1),
+                (SemanticMark::CommandEnd(Some(1)), 1),
+            ]
+        );
+    });
+
+    // A later mark without exit code doesn't hide the earlier one
+    session.add_bytes(OutputVisibility::Output, handle, b"\x1b]133;D\x07");
+    assert_eq!(reported_exit_code(&session, handle), Some(1));
+    session.add_bytes(OutputVisibility::Error, handle, b"\x1b]133;D;0\x07");
+    assert_eq!(reported_exit_code(&session, handle), Some(0));
+}
+
+#[test]
+fn reported_exit_code_decorates_command() {
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"ssh host"));
+    session.add_bytes(OutputVisibility::Output, handle, b"\x1b]133;D;1\x07");
+    let loc = SessionLocator {
+        conversation: 0,
+        in_conversation: ConversationLocator::Interaction(0, InteractionLocator::Command(0)),
+    };
+    let shown_status = |session: &SharedSession| {
+        session.session(None, |s| match s.display_line(&loc).unwrap().is_a {
+            LineType::Command(_, _, status) => Some(status),
+            _ => None,
+        })
+    };
+
+    // While the program runs, that is shown
+    session.set_running_status(handle, RunningStatus::Running);
+    assert_eq!(shown_status(&session), Some(RunningStatus::Running));
+
+    // Afterwards, the reported code replaces the one of the program
+    session.set_running_status(handle, RunningStatus::Exited(0));
+    assert_eq!(shown_status(&session), Some(RunningStatus::Exited(1)));
+    assert_eq!(session.exit_status(handle), Some(0));
+}
+
+#[test]
+fn locate_commands_marked_in_output() {
+    let mut session = new_test_session(b"prompt");
+    let shell = session.add_interaction(Screen::one_line_matrix(b"ssh host"));
+    session.add_bytes(
+        OutputVisibility::Output,
+        shell,
+        b"motd\n\x1b]133;A\x07$ ls\n\x1b]133;C\x07a\nb\n\x1b]133;A\x07$ ",
+    );
+    session.add_interaction(Screen::one_line_matrix(b"true"));
+
+    let at = |interaction, in_interaction| SessionLocator {
+        conversation: 0,
+        in_conversation: ConversationLocator::Interaction(interaction, in_interaction),
+    };
+    let commands = vec![
+        at(0, InteractionLocator::Command(0)),
+        at(0, InteractionLocator::Response(ResponseLocator::Lines(1))),
+        at(0, InteractionLocator::Response(ResponseLocator::Lines(2))),
+        at(0, InteractionLocator::Response(ResponseLocator::Screen(0))),
+        at(1, InteractionLocator::Command(0)),
+    ];
+    session.session((), |s| {
+        // Step back from the prompt and forth from the first command
+        let mut loc = SessionLocator {
+            conversation: 0,
+            in_conversation: ConversationLocator::Prompt(0),
+        };
+        for expected in commands.iter().rev() {
+            loc = s.locate_at_previous_command(&loc).unwrap();
+            assert_eq!(&loc, expected);
+        }
+        assert_eq!(s.locate_at_previous_command(&loc), None);
+        for expected in commands.iter().skip(1) {
+            loc = s.locate_at_next_command(&loc).unwrap();
+            assert_eq!(&loc, expected);
+        }
+        assert_eq!(s.locate_at_next_command(&loc), None);
+
+        // Lines between the commands
+        let output = at(0, InteractionLocator::Response(ResponseLocator::Lines(3)));
+        assert_eq!(
+            s.locate_at_previous_command(&output),
+            Some(commands[2].clone())
+        );
+        assert_eq!(s.locate_at_next_command(&output), Some(commands[3].clone()));
+    });
+}
+
+#[test]
+fn tui_size_follows_window() {
+    let start_tui = b"\x1b[?1049h";
+
+    // Unknown window size
+    let mut session = new_test_session(b"prompt 1");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"command 1.1"));
+    session.add_bytes(OutputVisibility::Output, handle, start_tui);
+    assert!(session.is_tui(handle));
+    session.session((), |s| {
+        let screen = &s.interactions[handle.0].tui_screen;
+        assert_eq!((screen.width(), screen.height()), (80, 25));
+    });
+
+    // Known window size
+    let mut session = new_test_session(b"prompt 1");
+    session.set_window_size(132, 43);
+    let handle = session.add_interaction(Screen::one_line_matrix(b"command 1.1"));
+    session.add_bytes(OutputVisibility::Output, handle, start_tui);
+    assert!(session.is_tui(handle));
+    session.session((), |s| {
+        let screen = &s.interactions[handle.0].tui_screen;
+        assert_eq!((screen.width(), screen.height()), (132, 43));
+    });
+}
+
+#[test]
+fn tui_resizes_with_window() {
+    let mut session = new_test_session(b"prompt 1");
+    session.set_window_size(80, 25);
+    let handle = session.add_interaction(Screen::one_line_matrix(b"command 1.1"));
+    session.add_bytes(
+        OutputVisibility::Output,
+        handle,
+        b"\x1b[?1049htop\x1b[25;1H",
+    );
+
+    // The TUI gets the whole window and can use the new lines
+    session.set_window_size(80, 50);
+    session.add_bytes(OutputVisibility::Output, handle, b"\x1b[50;1H");
+    session.session((), |s| {
+        let screen = &s.interactions[handle.0].tui_screen;
+        assert_eq!((screen.width(), screen.height()), (80, 50));
+        assert_eq!(screen.cursor_y(), 49);
+    });
+
+    // Shrinking keeps the top lines and moves the cursor onto the screen
+    session.set_window_size(40, 10);
+    session.session((), |s| {
+        let screen = &s.interactions[handle.0].tui_screen;
+        assert_eq!((screen.width(), screen.height()), (40, 10));
+        assert_eq!((screen.cursor_x(), screen.cursor_y()), (0, 9));
+        let top: String = screen
+            .line_iter()
+            .next()
+            .unwrap()
+            .iter()
+            .map(|c| c.code_point())
+            .collect();
+        assert_eq!(top.trim_end(), "top");
+    });
+
+    // Interactions that are not in TUI mode are left alone
+    let other = session.add_interaction(Screen::one_line_matrix(b"command 1.2"));
+    session.set_window_size(100, 30);
+    assert!(!session.is_tui(other));
+}
+
+#[test]
+fn adjacent_prompt_colors_differ() {
+    // Colliding hashes get the next color
+    assert_eq!(Conversation::pick_color(23, None, 20), 3);
+    assert_eq!(Conversation::pick_color(23, Some(3), 20), 4);
+    assert_eq!(Conversation::pick_color(19, Some(19), 20), 0);
+    assert_eq!(Conversation::pick_color(19, Some(19), 1), 0);
+
+    let mut session = new_test_session(b"prompt 0");
+    for i in 1..100 {
+        session.new_conversation(Screen::one_line_matrix(format!("prompt {}", i).as_bytes()));
+    }
+    // The same prompt again after clearing the view
+    let handle = session.add_interaction(Screen::one_line_matrix(b"command"));
+    session.set_running_status(handle, RunningStatus::Exited(0));
+    session.clear_view();
+
+    session.session((), |s| {
+        assert_eq!(s.conversations.len(), 101);
+        for pair in s.conversations.windows(2) {
+            assert!(pair[0].prompt_color < NUM_PROMPT_COLORS);
+            assert_ne!(pair[0].prompt_color, pair[1].prompt_color);
+        }
+    });
+}
+
+#[test]
+fn signal_terminated_status() {
+    // Raw wait status of a program killed by SIGKILL
+    let killed = RunningStatus::from(ExitStatus::from_raw(9));
+    assert_eq!(killed, RunningStatus::Signaled(9));
+    assert_eq!(killed.exit_code(), Some(137));
+    assert_eq!(killed.description().unwrap(), "terminated by signal 9");
+
+    // Raw wait status of a program that exited with code 1
+    let failed = RunningStatus::from(ExitStatus::from_raw(1 << 8));
+    assert_eq!(failed, RunningStatus::Exited(1));
+    assert_eq!(failed.description().unwrap(), "exited with code 1");
+
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"sleep 100"));
+    session.set_running_status(handle, killed);
+    assert!(session.has_exited(handle));
+    assert_eq!(session.exit_status(handle), Some(137));
+
+    let loc = SessionLocator {
+        conversation: 0,
+        in_conversation: ConversationLocator::Interaction(0, InteractionLocator::Command(0)),
+    };
+    session.session((), |s| {
+        let line = s.display_line(&loc).unwrap();
+        let text: String = line.text.iter().flat_map(|c| c.chars()).collect();
+        assert_eq!(text, "sleep 100 [terminated by signal 9]");
+    });
+}
+
+#[test]
+fn keep_raw_bytes() {
+    let input: &[u8] = b"\x1b[1mbold\x1b[0m\r\nplain\n";
+
+    // Not kept by default
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"command"));
+    session.add_bytes(OutputVisibility::Output, handle, input);
+    assert!(session
+        .raw_bytes(handle, OutputVisibility::Output)
+        .is_empty());
+
+    // Kept per stream when enabled
+    session.set_keep_raw_bytes(true);
+    let handle = session.add_interaction(Screen::one_line_matrix(b"command"));
+    session.add_bytes(OutputVisibility::Output, handle, &input[..7]);
+    session.add_bytes(OutputVisibility::Error, handle, b"oops\n");
+    session.add_bytes(OutputVisibility::Output, handle, &input[7..]);
+    assert_eq!(session.raw_bytes(handle, OutputVisibility::Output), input);
+    assert_eq!(
+        session.raw_bytes(handle, OutputVisibility::Error),
+        b"oops\n"
+    );
+
+    // Replaying the kept bytes gives the same screen
+    let mut replayed = Screen::new();
+    let _ = replayed.add_bytes(&session.raw_bytes(handle, OutputVisibility::Output));
+    let mut original = Screen::new();
+    let _ = original.add_bytes(input);
+    assert_eq!(replayed.extract_text(), original.extract_text());
+}
+
+#[test]
+fn interleaved_streams_keep_sequences() {
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"tui"));
+    session.set_tui_size(handle, 20, 5);
+
+    // Partial sequences of stdout are completed before stderr bytes get in between
+    let chunks: &[(OutputVisibility, &[u8])] = &[
+        (OutputVisibility::Output, b"\x1b[1"),
+        (OutputVisibility::Error, b"e\xc3"),
+        (OutputVisibility::Output, b"mA\xc3"),
+        (OutputVisibility::Error, b"\xa4"),
+        (OutputVisibility::Output, b"\xb6\x1b[0"),
+        (OutputVisibility::Error, b"!"),
+        (OutputVisibility::Output, b"mB"),
+    ];
+    for (stream, bytes) in chunks {
+        session.add_bytes(*stream, handle, bytes);
+    }
+
+    session.session((), |s| {
+        let cells = s.tui_screen(&handle).unwrap().row_slice(0);
+        let text: String = cells
+            .iter()
+            .filter(|c| c.drawn())
+            .map(|c| c.code_point())
+            .collect();
+        assert_eq!(text, "eA\u{e4}\u{f6}!B");
+        let bold: Vec<bool> = cells
+            .iter()
+            .take(6)
+            .map(|c| c.attributes().contains(Attributes::BOLD))
+            .collect();
+        // Both streams share the attributes of the screen
+        assert_eq!(bold, vec![false, true, true, true, true, false]);
+    });
+}
+
+#[test]
+fn split_input_gives_same_output() {
+    // Multi-byte characters, a combining mark, CSI, OSC and DCS sequences and line ends
+    let input: &[u8] = b"plain \xc3\xa4\xe2\x82\xac\xf0\x9f\x98\x80 \x1b[1;31mred\x1b[0m\r\n\
+        \x1b]0;title\x07e\xcc\x81\tx\x1b[2Dy\n\x1bP+q544E\x1b\\z\x1b[Kend";
+
+    // Archived lines, unarchived lines, cursor position and text of the output
+    let feed = |chunks: &[&[u8]]| {
+        let mut session = new_test_session(b"prompt");
+        let handle = session.add_interaction(Screen::one_line_matrix(b"command"));
+        for chunk in chunks {
+            session.add_bytes(OutputVisibility::Output, handle, chunk);
+        }
+        let text = session.response_texts(handle).0;
+        session.session(Default::default(), |s| {
+            s.interaction(handle, Default::default(), |i| {
+                let screen = &i.output.screen;
+                let unarchived: Vec<Vec<Cell>> = screen.line_iter().map(|l| l.to_vec()).collect();
+                (
+                    i.output.lines.clone(),
+                    unarchived,
+                    (screen.cursor_x(), screen.cursor_y()),
+                    text,
+                )
+            })
+        })
+    };
+
+    let whole = feed(&[input]);
+    let text = &whole.3;
+    assert!(text.starts_with("plain \u{e4}\u{20ac}\u{1f600} red\ne\u{301}"));
+    for split in 1..input.len() {
+        let (first, second) = input.split_at(split);
+        assert!(feed(&[first, second]) == whole, "split at {}", split);
+    }
+}
+
+#[test]
+fn init_output_hidden_unless_debugging() {
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"Startup"));
+    session.add_bytes(OutputVisibility::Output, handle, b"hello\n");
+    session.add_bytes(OutputVisibility::Error, handle, b"oops\n");
+    session.set_running_status(handle, RunningStatus::Exited(0));
+
+    // Nothing is printed, the output stays hidden in the session
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    session.report_init_output(handle, false, &mut stdout, &mut stderr);
+    assert!(stdout.is_empty());
+    assert!(stderr.is_empty());
+    assert_eq!(session.get_visibility(handle), Some(OutputVisibility::None));
+    assert_eq!(
+        session.response_texts(handle),
+        ("hello\n".to_string(), "oops\n".to_string())
+    );
+
+    session.report_init_output(handle, true, &mut stdout, &mut stderr);
+    assert!(stdout.starts_with(b"BiTE startup stdout output\n"));
+    assert!(stderr.starts_with(b"BiTE startup stderr output\n"));
+}
+
+#[test]
+fn long_output_wraps_at_max_width() {
+    let mut session = new_test_session(b"prompt");
+    session.set_max_output_width(Some(100));
+    let handle = session.add_interaction(Screen::one_line_matrix(b"yes | tr -d '\\n'"));
+    for _ in 0..10 {
+        session.add_bytes(OutputVisibility::Output, handle, &[b'y'; 1000]);
+    }
+
+    session.session((), |s| {
+        s.interaction(handle, (), |i| {
+            let screen = &i.output.screen;
+            assert_eq!(screen.width(), 100);
+            assert_eq!(screen.height(), 100);
+            assert!(screen
+                .line_iter()
+                .all(|l| l.len() == 100 && l.iter().all(|c| c.code_point() == 'y')));
+        })
+    });
+
+    // Without a limit, the row grows
+    session.set_max_output_width(None);
+    let handle = session.add_interaction(Screen::one_line_matrix(b"more"));
+    session.add_bytes(OutputVisibility::Output, handle, &[b'y'; 1000]);
+    session.session((), |s| {
+        s.interaction(handle, (), |i| assert_eq!(i.output.screen.width(), 1000))
+    });
+}
+
+#[test]
+fn binary_output_is_suppressed() {
+    let mut session = new_test_session(b"prompt");
+    let text = session.add_interaction(Screen::one_line_matrix(b"cat notes.txt"));
+    session.add_bytes(
+        OutputVisibility::Output,
+        text,
+        b"\x1b[1mnotes\x1b[0m\tdone\n",
+    );
+    assert!(!session.is_binary_suppressed(text));
+
+    let binary = session.add_interaction(Screen::one_line_matrix(b"cat a.out"));
+    let mut chunk = vec![0u8; 90];
+    chunk.extend_from_slice(b"ELF text");
+    session.add_bytes(OutputVisibility::Output, binary, &chunk);
+    assert!(session.is_binary_suppressed(binary));
+    assert_eq!(
+        session.response_texts(binary).0,
+        "[binary output suppressed: 98 bytes, click the command to show it]\n"
+    );
+
+    // Later bytes are counted, even if they look like text
+    session.add_bytes(OutputVisibility::Output, binary, b"more\n");
+    assert!(session
+        .response_texts(binary)
+        .0
+        .contains("suppressed: 103 bytes"));
+
+    session.show_binary_output(binary);
+    assert!(!session.is_binary_suppressed(binary));
+    assert!(session.response_texts(binary).0.contains("ELF textmore"));
+
+    // Only the start of large output is kept
+    let large = session.add_interaction(Screen::one_line_matrix(b"cat big.out"));
+    session.add_bytes(OutputVisibility::Output, large, &vec![0u8; 100 * 1024]);
+    assert_eq!(
+        session.response_texts(large).0,
+        "[binary output suppressed: 102400 bytes, truncated to 65536 bytes, click the command to \
+         show them]\n"
+    );
+
+    // Without suppression, the bytes are shown at once
+    session.set_suppress_binary(false);
+    let shown = session.add_interaction(Screen::one_line_matrix(b"cat b.out"));
+    session.add_bytes(OutputVisibility::Output, shown, &chunk);
+    assert!(!session.is_binary_suppressed(shown));
+}
+
+#[test]
+fn carriage_return_overwrites_line() {
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"progress"));
+    session.add_bytes(OutputVisibility::Output, handle, b"foo\rbar\rbaz");
+    assert_eq!(session.response_texts(handle).0, "baz\n");
+
+    // Updates in separate chunks collapse as well and the final line is archived
+    session.add_bytes(OutputVisibility::Output, handle, b"\r 10%");
+    session.add_bytes(OutputVisibility::Output, handle, b"\r 55%\r");
+    session.add_bytes(OutputVisibility::Output, handle, b"100%\ndone\n");
+    assert_eq!(session.response_texts(handle).0, "100%\ndone\n");
+}
+
+#[test]
+fn queries_are_answered_in_background() {
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"kak &"));
+    let (program_side, bite_side) = pipe().unwrap();
+    session.set_job(handle, Some(Job::with_stdin(bite_side)));
+
+    // Keyboard protocol query
+    session.add_bytes(OutputVisibility::Output, handle, b"\x1b[?u");
+    let mut received = [0xff; 8];
+    assert_eq!(read(program_side, &mut received), Ok(5));
+    assert_eq!(&received[..5], b"\x1b[?0u");
+
+    session.set_job(handle, None);
+    close(bite_side).unwrap();
+    close(program_side).unwrap();
+}
@@ -0,0 +1,111 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Automatic pairing of brackets and quotes
+//!
+//! Typing `(`, `"` or `'` also inserts the closing character. Typing the closing character right
+//! in front of an identical one moves over it. Inside strings and after a backslash, the
+//! characters are inserted as typed.
+
+use model::screen::Screen;
+
+/// What to do with a typed character
+#[derive(Debug, PartialEq)]
+pub enum Typing {
+    /// Insert the character as typed
+    Insert,
+
+    /// Insert the character and the given closing one, place the cursor between them
+    Pair(char),
+
+    /// Move the cursor over the identical character
+    TypeOver,
+}
+
+/// Find the quote that is still open at the end of the text and if the last character escapes
+/// the next one.
+fn scan_quotes(text: &str) -> (Option<char>, bool) {
+    let mut quote = None;
+    let mut escaped = false;
+    for c in text.chars() {
+        if quote == Some('\'') {
+            // Nothing is escaped in single quotes
+            if c == '\'' {
+                quote = None;
+            }
+        } else if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if quote == Some(c) {
+            quote = None;
+        } else if quote.is_none() && (c == '"' || c == '\'') {
+            quote = Some(c);
+        }
+    }
+    (quote, escaped)
+}
+
+/// Decide what to do with the typed character.
+///
+/// `before` is the text of the line before the cursor, `next` the character under the cursor.
+pub fn typing(before: &str, next: Option<char>, c: char) -> Typing {
+    let (quote, escaped) = scan_quotes(before);
+    if escaped {
+        return Typing::Insert;
+    }
+    match (c, quote) {
+        ('(', None) => Typing::Pair(')'),
+        (')', None) if next == Some(')') => Typing::TypeOver,
+        ('"', None) | ('\'', None) => {
+            // Don't pair apostrophes as in "don't"
+            if before.chars().last().map_or(false, char::is_alphanumeric) {
+                Typing::Insert
+            } else {
+                Typing::Pair(c)
+            }
+        }
+        ('"', Some(q)) | ('\'', Some(q)) if q == c && next == Some(c) => Typing::TypeOver,
+        _ => Typing::Insert,
+    }
+}
+
+/// Type the text into the text input, pairing brackets and quotes.
+///
+/// Only single characters are paired. Longer texts, e.g. pasted ones, are inserted as they are.
+pub fn type_text(text_input: &mut Screen, s: &str) {
+    let mut chars = s.chars();
+    let c = match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ => {
+            text_input.insert_str(s);
+            return;
+        }
+    };
+    let before = text_input.text_before_cursor();
+    let next = text_input.text_after_cursor().chars().next();
+    match typing(&before, next, c) {
+        Typing::Insert => text_input.insert_str(s),
+        Typing::Pair(close) => {
+            text_input.insert_str(s);
+            text_input.insert_str(&close.to_string());
+            text_input.move_left(1);
+        }
+        Typing::TypeOver => text_input.move_right(1),
+    }
+}
@@ -0,0 +1,179 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Menu to pick one of several completions
+//!
+//! Tab opens the menu if there is more than one candidate. Up/Down and repeated Tab change the
+//! selection, Enter inserts the selected candidate and Escape closes the menu.
+//!
+//! If there are more candidates than fit on the screen, only a window of them is shown. The window
+//! scrolls to keep the selected candidate visible and the last line tells how many are hidden.
+
+use model::screen::Screen;
+
+/// A candidate to complete the input with: (start position, completion, help)
+pub type Candidate = (usize, String, String);
+
+/// Candidates and the selected one
+pub struct CompletionMenu {
+    /// Candidates in the order they are shown
+    candidates: Vec<Candidate>,
+
+    /// Text shown for each candidate
+    labels: Vec<String>,
+
+    /// Index of the selected candidate
+    selected: usize,
+
+    /// Index of the first visible candidate
+    offset: usize,
+
+    /// Number of lines available for the menu
+    height: usize,
+}
+
+impl CompletionMenu {
+    /// Open the menu with the last candidate selected as it is shown next to the input.
+    pub fn new(candidates: Vec<Candidate>) -> Self {
+        let selected = candidates.len().saturating_sub(1);
+        let labels = candidates.iter().map(|c| c.1.clone()).collect();
+        Self {
+            candidates,
+            labels,
+            selected,
+            offset: 0,
+            height: usize::max_value(),
+        }
+    }
+
+    /// Limit the menu to the given number of lines and scroll the selection into view
+    pub fn set_height(&mut self, height: usize) {
+        self.height = std::cmp::max(1, height);
+        self.scroll();
+    }
+
+    /// Number of candidates that are shown at once
+    ///
+    /// If not all candidates fit, one line is needed for the indicator.
+    fn window(&self) -> usize {
+        if self.len() <= self.height {
+            self.len()
+        } else {
+            std::cmp::max(1, self.height - 1)
+        }
+    }
+
+    /// Move the window so that the selected candidate is visible
+    fn scroll(&mut self) {
+        let window = self.window();
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + window {
+            self.offset = self.selected + 1 - window;
+        }
+        // Don't leave empty lines at the end if the window has grown
+        self.offset = std::cmp::min(self.offset, self.len() - window);
+    }
+
+    /// Range of the visible candidates
+    pub fn visible(&self) -> std::ops::Range<usize> {
+        self.offset..(self.offset + self.window())
+    }
+
+    /// Number of candidates that are not visible
+    pub fn hidden(&self) -> usize {
+        self.len() - self.window()
+    }
+
+    /// Line of the selected candidate in the rendered menu
+    pub fn selected_row(&self) -> usize {
+        self.selected - self.offset
+    }
+
+    /// Replace the shown text of each candidate, e.g. to highlight the matched characters
+    pub fn highlight<F>(&mut self, label: F)
+    where
+        F: Fn(&Candidate) -> String,
+    {
+        self.labels = self.candidates.iter().map(label).collect();
+    }
+
+    /// Number of candidates
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    /// Index of the selected candidate
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Selected candidate, None if there are no candidates
+    pub fn current(&self) -> Option<&Candidate> {
+        self.candidates.get(self.selected)
+    }
+
+    /// Select the previous candidate. Stays at the first one.
+    pub fn up(&mut self, steps: usize) {
+        self.selected = self.selected.saturating_sub(steps);
+        self.scroll();
+    }
+
+    /// Select the next candidate. Stays at the last one.
+    pub fn down(&mut self, steps: usize) {
+        self.selected = std::cmp::min(self.selected + steps, self.len().saturating_sub(1));
+        self.scroll();
+    }
+
+    /// Select the next candidate. After the last one, the first one is selected.
+    pub fn cycle(&mut self) {
+        self.selected = if self.selected + 1 < self.len() {
+            self.selected + 1
+        } else {
+            0
+        };
+        self.scroll();
+    }
+
+    /// Close the menu and return the selected candidate
+    pub fn accept(self) -> Option<Candidate> {
+        let selected = self.selected;
+        self.candidates.into_iter().nth(selected)
+    }
+
+    /// Draw one visible candidate per line, followed by its help
+    ///
+    /// If some candidates are hidden, their number is shown in the last line.
+    pub fn render(&self, screen: &mut Screen) {
+        screen.reset();
+        let visible = self.visible();
+        for (item, label) in self.candidates[visible.clone()]
+            .iter()
+            .zip(self.labels[visible].iter())
+        {
+            let _ = screen.add_bytes(label.as_bytes());
+            let _ = screen.add_bytes(b" -- \x1b[0;32m");
+            let _ = screen.add_bytes(item.2.as_bytes());
+            let _ = screen.add_bytes(b"\x1b[39m\n");
+        }
+        let hidden = self.hidden();
+        if hidden != 0 {
+            let _ = screen.add_bytes(format!("\x1b[2m{} more\x1b[22m\n", hidden).as_bytes());
+        }
+    }
+}
@@ -24,6 +24,7 @@ use std::collections::HashMap;
 use std::os::linux::fs::MetadataExt;
 
 use crate::model::interpreter::grammar::script2;
+use crate::presenter::CompletionMatching;
 
 /// Map a grammar symbol to the competler algo
 type CompleterMap = HashMap<SymbolId, Completer>;
@@ -43,7 +44,13 @@ enum Completer {
     Code(CompleterAlgo),
 }
 
-pub struct Completions(CompleterMap);
+pub struct Completions {
+    /// Completer for each grammar symbol
+    completers: CompleterMap,
+
+    /// How the typed word selects the completions
+    matching: CompletionMatching,
+}
 
 /// Check if path is executable
 ///
@@ -68,43 +75,57 @@ fn perm(c: char, mode: u32, permission: u32) -> char {
     }
 }
 
-/// Find files that begin with `word`.
+/// Find files whose name matches the last part of `word`.
 ///
 /// TODO: Filter files with known ignorable extensions
-fn file_completion(start: usize, word: &str) -> Vec<(usize, String, String)> {
+fn file_completion(
+    matching: CompletionMatching,
+    start: usize,
+    word: &str,
+) -> Vec<(usize, String, String)> {
     let mut completion = Vec::new();
-    // Remember if the word started with ./ because glob removes that.
-    let dot_slash = if word.starts_with("./") { "./" } else { "" };
-
-    // Find all files and folders that match '<word>*'
-    if let Ok(g) = glob::glob(&(word.to_string() + "*")) {
-        // Get the matches after word
-        for path in g.filter_map(std::result::Result::ok) {
-            let mut p = dot_slash.to_string();
-            p.push_str(&path.display().to_string());
-            // If the path is a directory, add a slash.
-            if path.is_dir() {
-                p.push_str("/");
-            }
-            let help = if let Ok(metadata) = path.metadata() {
-                let mode = metadata.st_mode();
-                format!(
-                    "{}{}{} {}{}{} {}{}{}",
-                    perm('r', mode, 0o400),
-                    perm('w', mode, 0o200),
-                    perm('x', mode, 0o100),
-                    perm('r', mode, 0o040),
-                    perm('w', mode, 0o020),
-                    perm('x', mode, 0o010),
-                    perm('r', mode, 0o004),
-                    perm('w', mode, 0o002),
-                    perm('x', mode, 0o001)
-                )
-            } else {
-                "\x1b[31m?????????\x1b[39m".to_string()
-            };
-            completion.push((start, p, help));
+    // Split the word into the folder to look in and the start of the file name
+    let (dir, name) = match word.rfind('/') {
+        Some(slash) => word.split_at(slash + 1),
+        None => ("", word),
+    };
+
+    // Find all files and folders in dir that match name
+    let folder = if dir.is_empty() { "." } else { dir };
+    let mut paths: Vec<std::path::PathBuf> = std::fs::read_dir(folder)
+        .map(|entries| {
+            entries
+                .filter_map(std::result::Result::ok)
+                .filter(|e| matching.matches(name, &e.file_name().to_string_lossy()))
+                .map(|e| std::path::Path::new(dir).join(e.file_name()))
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    for path in paths {
+        let mut p = path.display().to_string();
+        // If the path is a directory, add a slash.
+        if path.is_dir() {
+            p.push_str("/");
         }
+        let help = if let Ok(metadata) = path.metadata() {
+            let mode = metadata.st_mode();
+            format!(
+                "{}{}{} {}{}{} {}{}{}",
+                perm('r', mode, 0o400),
+                perm('w', mode, 0o200),
+                perm('x', mode, 0o100),
+                perm('r', mode, 0o040),
+                perm('w', mode, 0o020),
+                perm('x', mode, 0o010),
+                perm('r', mode, 0o004),
+                perm('w', mode, 0o002),
+                perm('x', mode, 0o001)
+            )
+        } else {
+            "\x1b[31m?????????\x1b[39m".to_string()
+        };
+        completion.push((start, p, help));
     }
     completion
 }
@@ -129,10 +150,14 @@ pub struct LookupState {
 }
 
 /// If a SIMPLE_COMMAND is to be completed, check if there is a space/tab/newline in the input. If
-/// not, find all executables that begin with the given string.
+/// not, find all executables that match the given string.
 ///
 /// Later, this will run a completion script in the interpreter.
-fn simple_command_completion(start: usize, word: &str) -> Vec<(usize, String, String)> {
+fn simple_command_completion(
+    matching: CompletionMatching,
+    start: usize,
+    word: &str,
+) -> Vec<(usize, String, String)> {
     trace!("simple_command_completion");
     let mut res = Vec::new();
     // Go through the PATH variable
@@ -147,10 +172,10 @@ fn simple_command_completion(start: usize, word: &str) -> Vec<(usize, String, St
                             let path = entry.path();
                             // if the file is executable
                             if is_executable(&path) {
-                                // If the file name starts with start, add it to the
+                                // If the file name matches the word, add it to the
                                 // completions
                                 let file_name = entry.file_name();
-                                if file_name.as_os_str().to_string_lossy().starts_with(word) {
+                                if matching.matches(word, &file_name.to_string_lossy()) {
                                     res.push((
                                         start,
                                         file_name.to_string_lossy().into_owned(),
@@ -188,7 +213,7 @@ fn command_update(state: &mut LookupState, pos: usize, _start: &str) -> Vec<(Str
 }
 
 impl Completions {
-    pub fn new() -> Self {
+    pub fn new(matching: CompletionMatching) -> Self {
         let mut map = CompleterMap::new();
 
         map.insert(script2::FOR, Completer::Text("for", "begin a for loop"));
@@ -278,7 +303,15 @@ impl Completions {
 
         map.insert(script2::COMMAND, Completer::Code(command_update));
 
-        Self(map)
+        Self {
+            completers: map,
+            matching,
+        }
+    }
+
+    /// How the typed word selects the completions
+    pub fn matching(&self) -> CompletionMatching {
+        self.matching
     }
 
     /// Begin tracking a new CST traversal
@@ -300,10 +333,12 @@ impl Completions {
         start: &str,
     ) -> Vec<(String, String)> {
         let mut res = Vec::new();
-        if let Some(c) = self.0.get(&sym) {
+        if let Some(c) = self.completers.get(&sym) {
             match c {
                 Completer::Text(s, h) => {
-                    res.push((s.to_string(), h.to_string()));
+                    if self.matching.matches(start, s) {
+                        res.push((s.to_string(), h.to_string()));
+                    }
                 }
                 Completer::Code(fun) => {
                     return fun(state, pos, start);
@@ -325,10 +360,14 @@ impl Completions {
                 let word = editor.span_string(simple_command_element_pos, cursor_position);
                 if simple_command_element_pos == command_pos {
                     // complete command
-                    return simple_command_completion(simple_command_element_pos, &word);
+                    return simple_command_completion(
+                        self.matching,
+                        simple_command_element_pos,
+                        &word,
+                    );
                 } else {
                     // Complete file
-                    return file_completion(simple_command_element_pos, &word);
+                    return file_completion(self.matching, simple_command_element_pos, &word);
                 }
             }
         }
@@ -19,12 +19,14 @@
 //! Sub presenter for composing commands. Variant shows history above prompt, based on bubble-up
 //! stack.
 
-use model::interpreter::parse_script;
-use model::screen::Screen;
+use model::interpreter::{parse_script, ScriptError};
+use model::screen::{Screen, WordBoundary};
 use model::session::{OutputVisibility, RunningStatus, Session};
+use presenter::readline::Edit;
+use presenter::reverse_search::ReverseSearch;
 use presenter::{
     check_response_clicked, DisplayLine, LineItem, LineType, ModifierState, NeedRedraw,
-    PresenterCommand, PresenterCommons, SpecialKey, SubPresenter,
+    PresenterCommand, PresenterCommons, SpecialKey, SubPresenter, VisibleRange,
 };
 
 /// Presenter to input and run commands.
@@ -37,6 +39,9 @@ pub struct ComposeCommandPresenter {
 
     /// Cache of rendered prediction
     prediction_screen: Screen,
+
+    /// Running incremental history search (Ctrl-R)
+    reverse_search: Option<ReverseSearch>,
 }
 
 const PREDICTION_RAD: usize = 2;
@@ -49,6 +54,7 @@ impl ComposeCommandPresenter {
             commons,
             selected_prediction: 0,
             prediction_screen: Screen::new(),
+            reverse_search: None,
         };
         presenter.predict();
         let items_len = presenter.prediction().len();
@@ -85,8 +91,16 @@ impl ComposeCommandPresenter {
                 self.commons.history.enter(&cwd.to_string_lossy(), &line);
                 // Run the compiled instructions
                 let _interaction_handle = self.commons.interpreter.run(line_with_nl, instructions);
+                self.commons.continue_input = false;
+            }
+            Err(ScriptError::Incomplete) if self.commons.continue_incomplete(&line) => {
+                // Put back the input and let the user continue in the next line
+                self.commons.text_input.replace(&line, false);
+                self.commons.text_input.break_line();
+                self.commons.continue_input = true;
             }
             Err(msg) => {
+                self.commons.continue_input = false;
                 // Create a fake interaction, print the error, set the return code to error
                 let interaction_handle = self
                     .commons
@@ -95,7 +109,7 @@ impl ComposeCommandPresenter {
                 self.commons.session.add_bytes(
                     OutputVisibility::Error,
                     interaction_handle,
-                    msg.as_bytes(),
+                    msg.to_string().as_bytes(),
                 );
                 self.commons
                     .session
@@ -140,6 +154,10 @@ impl ComposeCommandPresenter {
     }
 
     fn compute_predictions_from_to(&self) -> (usize, usize) {
+        if self.reverse_search.is_some() {
+            // The search line replaces the predictions
+            return (0, 0);
+        }
         let from = if self.selected_prediction > PREDICTION_RAD {
             self.selected_prediction - PREDICTION_RAD
         } else {
@@ -157,8 +175,65 @@ impl ComposeCommandPresenter {
     fn compute_session_height(&self) -> usize {
         let input_height = self.commons.text_input.height() as usize;
         let (from, to) = self.compute_predictions_from_to();
+        let search_height = if self.reverse_search.is_some() { 1 } else { 0 };
         // TODO: Handle window heights smaller than input_height
-        self.commons.window_height - input_height - (to - from)
+        self.commons.window_height - input_height - (to - from) - search_height
+    }
+
+    /// Start the incremental history search or step to the next older match
+    fn reverse_search_older(&mut self) {
+        match self.reverse_search {
+            Some(ref mut search) => search.older(),
+            None => self.reverse_search = Some(self.commons.start_reverse_search()),
+        }
+        if let Some(ref search) = self.reverse_search {
+            search.show(&mut self.commons.text_input);
+        }
+    }
+
+    /// Finish the history search.
+    ///
+    /// If cancelled, the input from before the search is restored. Otherwise the match is kept
+    /// for editing.
+    fn end_reverse_search(&mut self, cancel: bool) {
+        if let Some(search) = self.reverse_search.take() {
+            if cancel {
+                self.commons.text_input.replace(search.draft(), false);
+            }
+            self.predict();
+            let items_len = self.prediction().len();
+            self.fix_selected_prediction(items_len);
+        }
+    }
+
+    fn event_special_key_search(
+        &mut self,
+        mod_state: &ModifierState,
+        key: &SpecialKey,
+    ) -> PresenterCommand {
+        match (mod_state.as_tuple(), key) {
+            // (shift,control,meta)
+            ((false, false, false), SpecialKey::Escape) => {
+                self.end_reverse_search(true);
+                PresenterCommand::Redraw
+            }
+            ((false, false, false), SpecialKey::Enter) => {
+                self.end_reverse_search(false);
+                PresenterCommand::Redraw
+            }
+            ((false, false, false), SpecialKey::Backspace) => {
+                if let Some(ref mut search) = self.reverse_search {
+                    search.pop();
+                    search.show(&mut self.commons.text_input);
+                }
+                PresenterCommand::Redraw
+            }
+            _ => {
+                // Any other key takes the match and is handled as usual
+                self.end_reverse_search(false);
+                self.event_special_key(mod_state, key)
+            }
+        }
     }
 
     fn event_special_key_prediction(
@@ -187,7 +262,10 @@ impl ComposeCommandPresenter {
                 PresenterCommand::Redraw
             }
             ((false, true, false), SpecialKey::Left) => {
-                self.commons.text_input.delete_word_before_cursor();
+                let word_boundary = self.commons.word_boundary;
+                self.commons
+                    .text_input
+                    .delete_word_before_cursor(word_boundary);
                 self.predict();
                 let items_len = self.prediction().len();
                 self.fix_selected_prediction(items_len);
@@ -281,7 +359,8 @@ impl ComposeCommandPresenter {
         match (mod_state.as_tuple(), key) {
             // (shift,control,meta)
             ((false, false, false), SpecialKey::Enter) => {
-                if self.is_multi_line() {
+                // An incomplete script is continued until it can be run
+                if self.is_multi_line() && !self.commons.continue_input {
                     self.commons_mut().text_input.break_line();
                     self.predict();
                     PresenterCommand::Redraw
@@ -311,6 +390,16 @@ impl ComposeCommandPresenter {
                 self.commons_mut().text_input.move_right(1);
                 PresenterCommand::Redraw
             }
+            ((false, true, false), SpecialKey::Left) => {
+                let word_boundary = self.commons.word_boundary;
+                self.commons.text_input.move_word_left(word_boundary);
+                PresenterCommand::Redraw
+            }
+            ((false, true, false), SpecialKey::Right) => {
+                let word_boundary = self.commons.word_boundary;
+                self.commons.text_input.move_word_right(word_boundary);
+                PresenterCommand::Redraw
+            }
             ((true, false, false), SpecialKey::PageUp) => {
                 // Shift only -> Scroll
                 let middle = self.commons.window_height / 2;
@@ -340,6 +429,24 @@ impl ComposeCommandPresenter {
                 PresenterCommand::Redraw
             }
 
+            ((false, true, false), SpecialKey::Delete) => {
+                let word_boundary = self.commons.word_boundary;
+                self.commons
+                    .text_input
+                    .delete_word_after_cursor(word_boundary);
+                self.predict();
+                PresenterCommand::Redraw
+            }
+
+            ((false, true, false), SpecialKey::Backspace) => {
+                let word_boundary = self.commons.word_boundary;
+                self.commons
+                    .text_input
+                    .delete_word_before_cursor(word_boundary);
+                self.predict();
+                PresenterCommand::Redraw
+            }
+
             ((false, false, false), SpecialKey::Delete) => {
                 if self.text_input().cursor_at_end_of_line() {
                     self.text_input().join_next_line();
@@ -451,6 +558,11 @@ impl SubPresenter for ComposeCommandPresenter {
         &mut self.commons
     }
 
+    fn visible_range(&self, session: &Session) -> Option<VisibleRange> {
+        self.commons
+            .visible_range(session, true, self.compute_session_height())
+    }
+
     fn single_display_line<'a, 'b: 'a>(
         &'a self,
         session: &'b Session,
@@ -461,7 +573,9 @@ impl SubPresenter for ComposeCommandPresenter {
             if let Some(loc) = self.commons.start_line(session, true, session_height) {
                 if let Some(loc) = PresenterCommons::locate_down(session, &loc, true, y) {
                     if let Some(display_line) = session.display_line(&loc) {
-                        return Some(DisplayLine::from(display_line));
+                        return Some(DisplayLine::from(
+                            self.commons.shift_session_line(display_line),
+                        ));
                     }
                 }
             }
@@ -484,16 +598,34 @@ impl SubPresenter for ComposeCommandPresenter {
                     0,
                 )));
             } else {
+                let search_height = if let Some(ref search) = self.reverse_search {
+                    if y == session_height + prediction_height {
+                        // Draw the search prompt
+                        let cells = Screen::one_line_cell_vec(search.prompt().as_bytes());
+                        return Some(DisplayLine::from(LineItem::new_owned(
+                            cells,
+                            LineType::Search,
+                            None,
+                            0,
+                        )));
+                    }
+                    1
+                } else {
+                    0
+                };
                 let input_height = self.commons.text_input.height() as usize;
-                if y < session_height + prediction_height + input_height {
-                    let offs = y - session_height - prediction_height;
+                if y < session_height + prediction_height + search_height + input_height {
+                    let offs = y - session_height - prediction_height - search_height;
                     return self.commons.text_input.line_iter().nth(offs).map(|cells| {
                         let cursor_col = if offs == (self.commons.text_input.cursor_y() as usize) {
                             Some(self.commons.text_input.cursor_x() as usize)
                         } else {
                             None
                         };
-                        DisplayLine::from(LineItem::new(cells, LineType::Input, cursor_col, 0))
+                        DisplayLine::from(
+                            self.commons
+                                .input_line_item(session, offs, cells, cursor_col),
+                        )
                     });
                 }
             }
@@ -517,6 +649,9 @@ impl SubPresenter for ComposeCommandPresenter {
         mod_state: &ModifierState,
         key: &SpecialKey,
     ) -> PresenterCommand {
+        if self.reverse_search.is_some() {
+            return self.event_special_key_search(mod_state, key);
+        }
         if self.commons.text_input.cursor_at_end() {
             if !self.prediction().is_empty() {
                 return self.event_special_key_prediction(mod_state, key);
@@ -527,28 +662,54 @@ impl SubPresenter for ComposeCommandPresenter {
 
     /// Handle pressing modifier + letter.
     ///
-    /// If Ctrl-R is pressed, go to history browse mode with search for contained strings.
+    /// If Ctrl-R is pressed, start the incremental history search or step to the next older match.
+    /// If Ctrl-C is pressed, abort the input.
     /// If Ctrl-D is pressed, quit bite.
+    /// If Ctrl-L is pressed, clear the screen.
+    /// Emacs-style editing keys (Ctrl-A/E/W/U/K/Y/T, Alt-B/F/U/L/C) change the input line.
     fn event_normal_key(&mut self, mod_state: &ModifierState, letter: u8) -> PresenterCommand {
+        if let ((false, true, false), b'r') = (mod_state.as_tuple(), letter) {
+            self.reverse_search_older();
+            return PresenterCommand::Redraw;
+        }
+        // Any other key takes the match of the history search
+        self.end_reverse_search(false);
+        if let Some(edit) = Edit::from_key(mod_state, letter) {
+            self.commons.apply_edit(edit);
+            if edit.changes_text() {
+                self.predict();
+                let items_len = self.prediction().len();
+                self.fix_selected_prediction(items_len);
+            }
+            return PresenterCommand::Redraw;
+        }
         match (mod_state.as_tuple(), letter) {
+            ((false, true, false), b'c') => {
+                // Control-C -> Abort the input, even if it spans several lines
+                let input = self.commons.text_input.extract_text_without_last_nl();
+                self.commons.abort_input(&input);
+                self.predict();
+                let items_len = self.prediction().len();
+                self.fix_selected_prediction(items_len);
+                PresenterCommand::Redraw
+            }
             ((false, true, false), b'd') => PresenterCommand::Exit,
-            //           ((false, true, false), b'r') => {
-            //               // Control-R -> Start interactive history search
-            //               let prefix = {
-            //                   let ref mut text_input = self.commons.text_input;
-            //                   let prefix = text_input.text_before_cursor();
-            //                   text_input.reset();
-            //                   text_input.place_str(&prefix);
-            //                   prefix
-            //               };
-            //                   PresenterCommand::Redraw,
-            //           }
+            ((false, true, false), b'l') => {
+                // Control-L -> Clear screen
+                self.commons.clear_view();
+                PresenterCommand::Redraw
+            }
             _ => PresenterCommand::Unknown,
         }
     }
 
     fn event_text(&mut self, s: &str) -> PresenterCommand {
-        self.commons_mut().text_input_add_characters(s);
+        if let Some(ref mut search) = self.reverse_search {
+            search.push_str(s);
+            search.show(&mut self.commons.text_input);
+            return PresenterCommand::Redraw;
+        }
+        self.commons_mut().text_input_type(s);
         self.predict();
         let items_len = self.prediction().len();
         self.fix_selected_prediction(items_len);
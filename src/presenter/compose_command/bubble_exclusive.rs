@@ -21,12 +21,13 @@
 //! history.
 
 use model::completion;
-use model::interpreter::parse_script;
-use model::screen::Screen;
+use model::interpreter::{parse_script, ScriptError};
+use model::screen::{Screen, WordBoundary};
 use model::session::{OutputVisibility, RunningStatus, Session};
+use presenter::readline::Edit;
 use presenter::{
     check_response_clicked, DisplayLine, LineItem, LineType, ModifierState, NeedRedraw,
-    PresenterCommand, PresenterCommons, SpecialKey, SubPresenter,
+    PresenterCommand, PresenterCommons, SpecialKey, SubPresenter, VisibleRange,
 };
 
 /// Which selection to show
@@ -121,8 +122,16 @@ impl ComposeCommandPresenter {
                 self.commons.history.enter(&cwd.to_string_lossy(), &line);
                 // Run the compiled instructions
                 let _interaction_handle = self.commons.interpreter.run(line_with_nl, instructions);
+                self.commons.continue_input = false;
+            }
+            Err(ScriptError::Incomplete) if self.commons.continue_incomplete(&line) => {
+                // Put back the input and let the user continue in the next line
+                self.commons.text_input.replace(&line, false);
+                self.commons.text_input.break_line();
+                self.commons.continue_input = true;
             }
             Err(msg) => {
+                self.commons.continue_input = false;
                 // Create a fake interaction, print the error, set the return code to error
                 let interaction_handle = self
                     .commons
@@ -131,7 +140,7 @@ impl ComposeCommandPresenter {
                 self.commons.session.add_bytes(
                     OutputVisibility::Error,
                     interaction_handle,
-                    msg.as_bytes(),
+                    msg.to_string().as_bytes(),
                 );
                 self.commons
                     .session
@@ -306,7 +315,9 @@ impl ComposeCommandPresenter {
             ((_, _, _), SpecialKey::Enter) => {
                 let selected_item = self.selected_item;
                 // Insert the selected completion
-                let word = self.text_input().word_before_cursor();
+                let word = self
+                    .text_input()
+                    .word_before_cursor(WordBoundary::Whitespace);
                 let word_chars = word.chars().count();
                 // Delete the beginning
                 self.text_input().move_left(word_chars as isize);
@@ -332,7 +343,8 @@ impl ComposeCommandPresenter {
         match (mod_state.as_tuple(), key) {
             // (shift,control,meta)
             ((false, false, false), SpecialKey::Enter) => {
-                if self.is_multi_line() {
+                // An incomplete script is continued until it can be run
+                if self.is_multi_line() && !self.commons.continue_input {
                     self.commons_mut().text_input.break_line();
                     PresenterCommand::Redraw
                 } else {
@@ -434,7 +446,9 @@ impl ComposeCommandPresenter {
 
             // Tab: Completion
             ((false, false, false), SpecialKey::Tab) => {
-                let word = self.text_input().word_before_cursor();
+                let word = self
+                    .text_input()
+                    .word_before_cursor(WordBoundary::Whitespace);
 
                 let completions = completion::file_completion(&word);
                 let completion_len = completions.len();
@@ -502,6 +516,11 @@ impl SubPresenter for ComposeCommandPresenter {
         &mut self.commons
     }
 
+    fn visible_range(&self, session: &Session) -> Option<VisibleRange> {
+        self.commons
+            .visible_range(session, true, self.compute_session_height())
+    }
+
     fn single_display_line<'a, 'b: 'a>(
         &'a self,
         session: &'b Session,
@@ -515,7 +534,9 @@ impl SubPresenter for ComposeCommandPresenter {
             if let Some(loc) = self.commons.start_line(session, true, session_height) {
                 if let Some(loc) = PresenterCommons::locate_down(session, &loc, true, offs) {
                     if let Some(display_line) = session.display_line(&loc) {
-                        return Some(DisplayLine::from(display_line));
+                        return Some(DisplayLine::from(
+                            self.commons.shift_session_line(display_line),
+                        ));
                     }
                 }
             }
@@ -575,7 +596,10 @@ impl SubPresenter for ComposeCommandPresenter {
                     } else {
                         None
                     };
-                    return DisplayLine::from(LineItem::new(cells, LineType::Input, cursor_col, 0));
+                    return DisplayLine::from(
+                        self.commons
+                            .input_line_item(session, offs, cells, cursor_col),
+                    );
                 });
             }
         }
@@ -608,10 +632,32 @@ impl SubPresenter for ComposeCommandPresenter {
     /// Handle pressing modifier + letter.
     ///
     /// If Ctrl-R is pressed, go to history browse mode with search for contained strings.
+    /// If Ctrl-C is pressed, abort the input.
     /// If Ctrl-D is pressed, quit bite.
+    /// If Ctrl-L is pressed, clear the screen.
+    /// Emacs-style editing keys (Ctrl-A/E/W/U/K/Y/T, Alt-B/F/U/L/C) change the input line.
     fn event_normal_key(&mut self, mod_state: &ModifierState, letter: u8) -> PresenterCommand {
+        if let SelectionMode::None = self.selection_mode {
+            if let Some(edit) = Edit::from_key(mod_state, letter) {
+                self.commons.apply_edit(edit);
+                return PresenterCommand::Redraw;
+            }
+        }
         match (mod_state.as_tuple(), letter) {
+            ((false, true, false), b'c') => {
+                // Control-C -> Abort the input, even if it spans several lines
+                let input = self.commons.text_input.extract_text_without_last_nl();
+                self.commons.abort_input(&input);
+                self.selection_mode = SelectionMode::None;
+                self.search.clear();
+                PresenterCommand::Redraw
+            }
             ((false, true, false), b'd') => PresenterCommand::Exit,
+            ((false, true, false), b'l') => {
+                // Control-L -> Clear screen
+                self.commons.clear_view();
+                PresenterCommand::Redraw
+            }
             ((false, true, false), b'r') => {
                 // Control-R -> Start interactive history search
                 let prediction_len = self.commons.history.prediction().len();
@@ -629,7 +675,7 @@ impl SubPresenter for ComposeCommandPresenter {
     fn event_text(&mut self, s: &str) -> PresenterCommand {
         match self.selection_mode {
             SelectionMode::None => {
-                self.commons_mut().text_input_add_characters(s);
+                self.commons_mut().text_input_type(s);
             }
             SelectionMode::History => {
                 self.search.push_str(s);
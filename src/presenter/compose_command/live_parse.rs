@@ -24,12 +24,15 @@
 
 use sesd::{CompiledGrammar, CstIterItem, SymbolId};
 
-use model::interpreter::parse_script;
-use model::screen::Screen;
+use model::interpreter::{parse_script, ScriptError};
+use model::screen::{str_columns, Screen};
 use model::session::{OutputVisibility, RunningStatus, Session};
+use presenter::auto_pair::{self, Typing};
+use presenter::completion_menu::CompletionMenu;
+use presenter::readline::Edit;
 use presenter::{
     check_response_clicked, CursorMapping, DisplayLine, LineItem, LineType, ModifierState,
-    NeedRedraw, PresenterCommand, PresenterCommons, SpecialKey, SubPresenter,
+    NeedRedraw, PresenterCommand, PresenterCommons, SpecialKey, SubPresenter, VisibleRange,
 };
 
 use presenter::style_sheet::{LookedUp, Style};
@@ -55,9 +58,8 @@ pub struct ComposeCommandPresenter {
     /// Cache of rendered selection
     selection_screen: Screen,
 
-    /// List of completions
-    /// (start position, input, help)
-    completions: Vec<(usize, String, String)>,
+    /// Completions to pick from
+    completion_menu: CompletionMenu,
 
     /// Selection mode
     selection_mode: SelectionMode,
@@ -77,7 +79,7 @@ impl ComposeCommandPresenter {
             selected_item: 0,
             selection_screen: Screen::new(),
             search: String::new(),
-            completions: Vec::new(),
+            completion_menu: CompletionMenu::new(Vec::new()),
             selection_mode: SelectionMode::None,
             cursor_map_index: 0,
         };
@@ -123,8 +125,17 @@ impl ComposeCommandPresenter {
                 self.commons.history.enter(&cwd.to_string_lossy(), &line);
                 // Run the compiled instructions
                 let _interaction_handle = self.commons.interpreter.run(line_with_nl, instructions);
+                self.commons.continue_input = false;
+            }
+            Err(ScriptError::Incomplete) if self.commons.continue_incomplete(&line) => {
+                // Put back the input and let the user continue in the next line
+                self.commons.editor.enter_iter(line.chars());
+                self.commons.editor.enter('\n');
+                self.update_input_screen();
+                self.commons.continue_input = true;
             }
             Err(msg) => {
+                self.commons.continue_input = false;
                 // Create a fake interaction, print the error, set the return code to error
                 let interaction_handle = self
                     .commons
@@ -133,7 +144,7 @@ impl ComposeCommandPresenter {
                 self.commons.session.add_bytes(
                     OutputVisibility::Error,
                     interaction_handle,
-                    msg.as_bytes(),
+                    msg.to_string().as_bytes(),
                 );
                 self.commons
                     .session
@@ -198,6 +209,10 @@ impl ComposeCommandPresenter {
     }
 
     fn selections_from_to(&self) -> (usize, usize) {
+        if let SelectionMode::Completion = self.selection_mode {
+            // The completion menu only renders what fits
+            return (0, self.selection_screen.height() as usize);
+        }
         let selection_rad = std::cmp::max(SELECTION_RAD, self.commons.window_height / 4);
         let selected_item = self.selected_item;
         let from = if selected_item > selection_rad {
@@ -305,6 +320,126 @@ impl ComposeCommandPresenter {
         self.commons.text_input.move_cursor_to(cx, cy);
     }
 
+    /// Apply a readline-style editing operation to the editor, then update the screen
+    fn apply_edit(&mut self, edit: Edit) {
+        let boundary = self.commons.word_boundary;
+        let editor = &mut self.commons.editor;
+        let cursor = editor.cursor();
+        let line_start = editor
+            .search_backward(cursor, sesd::char::start_of_line)
+            .unwrap_or(0);
+        let line_end = editor
+            .search_forward(cursor, sesd::char::end_of_line)
+            .unwrap_or_else(|| editor.len());
+        let word_start =
+            cursor - boundary.word_length(editor.span_string(line_start, cursor).chars().rev());
+        let word_end = cursor + boundary.word_length(editor.span_string(cursor, line_end).chars());
+
+        // Move the cursor or find the range of text to kill
+        let kill = match edit {
+            Edit::LineStart => {
+                editor.set_cursor(line_start);
+                None
+            }
+            Edit::LineEnd => {
+                editor.set_cursor(line_end);
+                None
+            }
+            Edit::WordLeft => {
+                editor.set_cursor(word_start);
+                None
+            }
+            Edit::WordRight => {
+                editor.set_cursor(word_end);
+                None
+            }
+            Edit::KillWordBefore => Some((word_start, cursor)),
+            Edit::KillToLineStart => Some((line_start, cursor)),
+            Edit::KillToLineEnd => Some((cursor, line_end)),
+            Edit::Yank => {
+                if let Some(text) = self.commons.kill_ring.yank() {
+                    editor.enter_iter(text.chars());
+                }
+                self.commons.kill_ring.start_yank();
+                None
+            }
+            Edit::YankPop => {
+                // The text before the cursor must still be the one of the last yank
+                let yanked = self.commons.kill_ring.last_yanked().map_or(false, |text| {
+                    let len = text.chars().count();
+                    len <= cursor && editor.span_string(cursor - len, cursor) == text
+                });
+                if !yanked {
+                    self.commons.kill_ring.end_yank();
+                } else if let Some((len, text)) = self.commons.kill_ring.yank_pop() {
+                    editor.replace(cursor - len, cursor, text.chars());
+                    editor.set_cursor(cursor - len + text.chars().count());
+                }
+                None
+            }
+            Edit::TransposeCharacters => {
+                if cursor > line_start && line_end >= line_start + 2 {
+                    // At the end of the line, swap the last two characters
+                    let right = if cursor < line_end {
+                        cursor
+                    } else {
+                        line_end - 1
+                    };
+                    let swapped: String = editor
+                        .span_string(right - 1, right + 1)
+                        .chars()
+                        .rev()
+                        .collect();
+                    editor.replace(right - 1, right + 1, swapped.chars());
+                    editor.set_cursor(right + 1);
+                }
+                None
+            }
+            Edit::ChangeCase(change) => {
+                let text = change.apply(boundary, &editor.span_string(cursor, word_end));
+                editor.replace(cursor, word_end, text.chars());
+                editor.set_cursor(word_end);
+                None
+            }
+        };
+        if let Some((start, end)) = kill {
+            let text = editor.span_string(start, end);
+            editor.set_cursor(start);
+            editor.delete(end - start);
+            self.commons.kill_ring.kill(text);
+        }
+        self.update_input_screen();
+    }
+
+    /// Enter typed text into the editor, pairing brackets and quotes if configured
+    fn enter_text(&mut self, s: &str) {
+        let editor = &mut self.commons.editor;
+        let mut chars = s.chars();
+        if let (true, Some(c), None) = (self.commons.auto_pair, chars.next(), chars.next()) {
+            let cursor = editor.cursor();
+            let line_start = editor
+                .search_backward(cursor, sesd::char::start_of_line)
+                .unwrap_or(0);
+            let next = editor
+                .span_string(cursor, (cursor + 1).min(editor.len()))
+                .chars()
+                .next();
+            match auto_pair::typing(&editor.span_string(line_start, cursor), next, c) {
+                Typing::Insert => editor.enter(c),
+                Typing::Pair(close) => {
+                    editor.enter(c);
+                    editor.enter(close);
+                    editor.move_backward(1);
+                }
+                Typing::TypeOver => {
+                    editor.move_forward(1);
+                }
+            }
+        } else {
+            editor.enter_iter(s.chars());
+        }
+    }
+
     /// Move cursor down one line, return true if that worked
     fn move_cursor_down(&mut self) -> bool {
         let col = self.commons.text_input.cursor_x() as usize;
@@ -392,32 +527,62 @@ impl ComposeCommandPresenter {
             completions.push((start, s, h));
         }
 
-        if !completions.is_empty() {
-            let completion_len = completions.len();
-            // If there is only one match, insert that
-            if completion_len == 1 {
+        match completions.len() {
+            0 => {
+                // Nothing left to pick from
+                self.selection_mode = SelectionMode::None;
+            }
+            1 => {
+                // If there is only one match, insert that
                 self.commons.editor.replace(
                     completions[0].0,
                     cursor_position,
                     completions[0].1.chars(),
                 );
                 self.update_input_screen();
-            } else {
+                self.selection_mode = SelectionMode::None;
+            }
+            _ => {
                 // Otherwise make the user pick
-                self.completions = completions;
-                self.selection_screen.reset();
-                for item in self.completions.iter() {
-                    let _ = self.selection_screen.add_bytes(item.1.as_bytes());
-                    let _ = self.selection_screen.add_bytes(b" -- \x1b[0;32m");
-                    let _ = self.selection_screen.add_bytes(item.2.as_bytes());
-                    let _ = self.selection_screen.add_bytes(b"\x1b[39m\n");
-                }
-                self.selected_item = completion_len - 1;
-                self.selection_mode = SelectionMode::Completion;
+                let mut menu = CompletionMenu::new(completions);
+                let matching = self.commons.completions.matching();
+                let editor = &self.commons.editor;
+                menu.highlight(|c| {
+                    matching.highlight(&editor.span_string(c.0, cursor_position), &c.1)
+                });
+                self.completion_menu = menu;
+                self.show_completion_menu();
             }
         }
     }
 
+    /// Render the visible part of the completion menu and select the same item as the menu
+    ///
+    /// The menu may use all lines that are not needed for the input.
+    fn show_completion_menu(&mut self) {
+        let height = self
+            .commons
+            .window_height
+            .saturating_sub(self.commons.text_input.height() as usize);
+        self.completion_menu.set_height(height);
+        self.completion_menu.render(&mut self.selection_screen);
+        self.selected_item = self.completion_menu.selected_row();
+        self.selection_mode = SelectionMode::Completion;
+    }
+
+    /// Insert the selected completion and close the menu
+    fn accept_completion(&mut self) {
+        let menu = std::mem::replace(&mut self.completion_menu, CompletionMenu::new(Vec::new()));
+        if let Some((start, completion, _)) = menu.accept() {
+            let cursor_position = self.commons.editor.cursor();
+            self.commons
+                .editor
+                .replace(start, cursor_position, completion.chars());
+            self.update_input_screen();
+        }
+        self.selection_mode = SelectionMode::None;
+    }
+
     fn event_special_key_history(
         &mut self,
         mod_state: &ModifierState,
@@ -548,40 +713,23 @@ impl ComposeCommandPresenter {
                 PresenterCommand::Redraw
             }
             ((false, false, false), SpecialKey::Up) => {
-                let selected_item = &mut self.selected_item;
-                *selected_item = selected_item.saturating_sub(1);
+                self.completion_menu.up(1);
+                self.show_completion_menu();
                 PresenterCommand::Redraw
             }
             ((false, false, false), SpecialKey::Down) => {
-                let selected_item = &mut self.selected_item;
-                if *selected_item + 1 < self.selection_screen.height() as usize {
-                    *selected_item += 1;
-                }
+                self.completion_menu.down(1);
+                self.show_completion_menu();
                 PresenterCommand::Redraw
             }
             ((_, _, _), SpecialKey::Enter) => {
-                let selected_item = self.selected_item;
-                let completion = self.completions.remove(selected_item);
-                let cursor_position = self.commons.editor.cursor();
-                self.commons
-                    .editor
-                    .replace(completion.0, cursor_position, completion.1.chars());
-                self.update_input_screen();
-                // Go back to normal mode
-                self.selection_mode = SelectionMode::None;
+                self.accept_completion();
                 PresenterCommand::Redraw
             }
             ((false, false, false), SpecialKey::Tab) => {
-                // Same as Enter
-                let selected_item = self.selected_item;
-                let completion = self.completions.remove(selected_item);
-                let cursor_position = self.commons.editor.cursor();
-                self.commons
-                    .editor
-                    .replace(completion.0, cursor_position, completion.1.chars());
-                self.update_input_screen();
-                // Then normal mode tab
-                self.start_completion();
+                // Cycle through the candidates
+                self.completion_menu.cycle();
+                self.show_completion_menu();
                 PresenterCommand::Redraw
             }
             ((false, false, false), SpecialKey::Backspace) => {
@@ -648,23 +796,18 @@ impl ComposeCommandPresenter {
                 // No shift -> select
                 let (from, to) = self.selections_from_to();
                 let selection_height = to - from;
-                let selected_item = &mut self.selected_item;
-                *selected_item =
-                    selected_item.saturating_sub(std::cmp::max(1, selection_height / 2));
+                self.completion_menu
+                    .up(std::cmp::max(1, selection_height / 2));
+                self.show_completion_menu();
                 PresenterCommand::Redraw
             }
             ((false, false, false), SpecialKey::PageDown) => {
                 // No shift -> select
                 let (from, to) = self.selections_from_to();
                 let selection_height = to - from;
-                let steps = std::cmp::max(1, selection_height / 2);
-
-                let selected_item = &mut self.selected_item;
-                if *selected_item + steps < (self.selection_screen.height() as usize) {
-                    *selected_item += steps;
-                } else {
-                    *selected_item = (self.selection_screen.height() as usize).saturating_sub(1);
-                }
+                self.completion_menu
+                    .down(std::cmp::max(1, selection_height / 2));
+                self.show_completion_menu();
                 PresenterCommand::Redraw
             }
 
@@ -821,6 +964,35 @@ impl ComposeCommandPresenter {
         }
     }
 
+    /// Move the cursor to the clicked position if it is inside the input.
+    ///
+    /// Return true if the cursor has been moved.
+    fn click_input(&mut self, x: usize, y: usize) -> bool {
+        if let SelectionMode::None = self.selection_mode {
+            // Without a selection, the input follows the session directly
+            let input_start = self.compute_session_height();
+            let input_height = self.commons.text_input.height() as usize;
+            if input_start <= y && y < input_start + input_height {
+                let row = y - input_start;
+                let editor = &self.commons.editor;
+                let text: Vec<char> = editor.span_string(0, editor.len()).chars().collect();
+                if let Some(position) = self.commons.input_column(x, row).and_then(|col| {
+                    CursorMapping::buffer_position(
+                        &self.commons.cursor_map,
+                        &text,
+                        col,
+                        row as isize,
+                    )
+                }) {
+                    self.commons.editor.set_cursor(position);
+                    self.update_input_screen();
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
     /// Render a node of the parse tree.
     ///
     /// Return None, if the cursor is not inside this node. Return the x and y coordinate of the
@@ -860,7 +1032,7 @@ impl ComposeCommandPresenter {
         // string to catch the cursor at the end of the buffer. If two elements touch, the second
         // will overwrite it later.
         if start <= cursor_index && cursor_index <= end {
-            let offs = cursor_index - start;
+            let offs = str_columns(&self.commons.editor.span_string(start, cursor_index));
             // If the cursor is below the last line, update the size of the matrix to render it
             // correctly
             text_input.make_room();
@@ -997,6 +1169,11 @@ impl SubPresenter for ComposeCommandPresenter {
         &mut self.commons
     }
 
+    fn visible_range(&self, session: &Session) -> Option<VisibleRange> {
+        self.commons
+            .visible_range(session, true, self.compute_session_height())
+    }
+
     fn single_display_line<'a, 'b: 'a>(
         &'a self,
         session: &'b Session,
@@ -1010,7 +1187,9 @@ impl SubPresenter for ComposeCommandPresenter {
             if let Some(loc) = self.commons.start_line(session, true, session_height) {
                 if let Some(loc) = PresenterCommons::locate_down(session, &loc, true, offs) {
                     if let Some(display_line) = session.display_line(&loc) {
-                        return Some(DisplayLine::from(display_line));
+                        return Some(DisplayLine::from(
+                            self.commons.shift_session_line(display_line),
+                        ));
                     }
                 }
             }
@@ -1070,7 +1249,10 @@ impl SubPresenter for ComposeCommandPresenter {
                     } else {
                         None
                     };
-                    return DisplayLine::from(LineItem::new(cells, LineType::Input, cursor_col, 0));
+                    return DisplayLine::from(
+                        self.commons
+                            .input_line_item(session, offs, cells, cursor_col),
+                    );
                 });
             }
         }
@@ -1079,10 +1261,13 @@ impl SubPresenter for ComposeCommandPresenter {
 
     /// Handle a click.
     ///
-    /// If a command was clicked, cycle through the visibility of output and error.
+    /// If a command was clicked, cycle through the visibility of output and error. If the input
+    /// was clicked, move the cursor there.
     fn handle_click(&mut self, button: usize, x: usize, y: usize) -> NeedRedraw {
         if check_response_clicked(&mut *self, button, x, y) {
             NeedRedraw::Yes
+        } else if button == 1 && self.click_input(x, y) {
+            NeedRedraw::Yes
         } else {
             NeedRedraw::No
         }
@@ -1103,10 +1288,33 @@ impl SubPresenter for ComposeCommandPresenter {
     /// Handle pressing modifier + letter.
     ///
     /// If Ctrl-R is pressed, go to history browse mode with search for contained strings.
+    /// If Ctrl-C is pressed, abort the input.
     /// If Ctrl-D is pressed, quit bite.
+    /// If Ctrl-L is pressed, clear the screen.
+    /// Emacs-style editing keys (Ctrl-A/E/W/U/K/Y/T, Alt-B/F/U/L/C) change the input line.
     fn event_normal_key(&mut self, mod_state: &ModifierState, letter: u8) -> PresenterCommand {
+        if let SelectionMode::None = self.selection_mode {
+            if let Some(edit) = Edit::from_key(mod_state, letter) {
+                self.apply_edit(edit);
+                return PresenterCommand::Redraw;
+            }
+        }
         match (mod_state.as_tuple(), letter) {
+            ((false, true, false), b'c') => {
+                // Control-C -> Abort the input, even if it spans several lines
+                let input = self.commons.editor.as_string();
+                self.commons.abort_input(&input);
+                self.update_input_screen();
+                self.selection_mode = SelectionMode::None;
+                self.search.clear();
+                PresenterCommand::Redraw
+            }
             ((false, true, false), b'd') => PresenterCommand::Exit,
+            ((false, true, false), b'l') => {
+                // Control-L -> Clear screen
+                self.commons.clear_view();
+                PresenterCommand::Redraw
+            }
             ((false, true, false), b'r') => {
                 // Control-R -> Start interactive history search
                 let prediction_len = self.commons.history.prediction().len();
@@ -1124,7 +1332,7 @@ impl SubPresenter for ComposeCommandPresenter {
     fn event_text(&mut self, s: &str) -> PresenterCommand {
         match self.selection_mode {
             SelectionMode::None => {
-                self.commons.editor.enter_iter(s.chars());
+                self.enter_text(s);
                 self.update_input_screen();
             }
             SelectionMode::History => {
@@ -31,7 +31,7 @@ pub struct DisplayLine<'a> {
     pub prefix: &'a [Cell],
     pub line: Cow<'a, [Cell]>,
     pub cursor_col: Option<usize>,
-    pub prompt_hash: u64,
+    pub prompt_color: usize,
     pub is_a: LineType,
 }
 
@@ -49,6 +49,10 @@ lazy_static! {
         Screen::one_line_cell_vec( "\x1b[41mO» ".as_bytes());
     static ref ERROR_FAIL_PREFIX : Vec<Cell> = Screen::one_line_cell_vec( "\x1b[41mE» ".as_bytes());
 
+    static ref NONE_SIGNAL_PREFIX : Vec<Cell> = Screen::one_line_cell_vec( "\x1b[41m ! ".as_bytes());
+    static ref OUTPUT_SIGNAL_PREFIX : Vec<Cell> = Screen::one_line_cell_vec( "\x1b[41mO! ".as_bytes());
+    static ref ERROR_SIGNAL_PREFIX : Vec<Cell> = Screen::one_line_cell_vec( "\x1b[41mE! ".as_bytes());
+
     static ref NONE_RUNNING_PREFIX : Vec<Cell> = Screen::one_line_cell_vec( " » ".as_bytes());
     static ref OUTPUT_RUNNING_PREFIX : Vec<Cell> = Screen::one_line_cell_vec( "O» ".as_bytes());
     static ref ERROR_RUNNING_PREFIX : Vec<Cell> = Screen::one_line_cell_vec( "E» ".as_bytes());
@@ -59,6 +63,7 @@ lazy_static! {
 
     static ref TUI_PREFIX : Vec<Cell> = Vec::new();
     static ref INPUT_PREFIX : Vec<Cell>      = Screen::one_line_cell_vec( b" $ ");
+    static ref CONTINUATION_PREFIX : Vec<Cell> = Screen::one_line_cell_vec( b" > ");
     static ref INPUT_INFO_PREFIX : Vec<Cell> = Screen::one_line_cell_vec( b"-- ");
     static ref MENU_DECO_PREFIX : Vec<Cell> = Vec::new();
     static ref MENU_SELECT_PREFIX : Vec<Cell> = Screen::one_line_cell_vec(b"==> ");
@@ -74,7 +79,7 @@ impl<'a> DisplayLine<'a> {
         prefix: &'a [Cell],
         line: Cow<'a, [Cell]>,
         cursor_col: Option<usize>,
-        prompt_hash: u64,
+        prompt_color: usize,
         is_a: LineType,
     ) -> Self {
         let prefix_len = prefix.len();
@@ -82,11 +87,29 @@ impl<'a> DisplayLine<'a> {
             prefix,
             line,
             cursor_col: cursor_col.map(|l| l + prefix_len),
-            prompt_hash,
+            prompt_color,
             is_a,
         }
     }
 
+    /// Put the right-aligned 1-based line number in front of the text.
+    ///
+    /// Meant for the output of the running command, not the stacked session view.
+    pub fn with_line_number(mut self, number: usize, digits: usize) -> Self {
+        let gutter = format!("\x1b[2m{:>1$}\x1b[22m ", number, digits);
+        let mut text = Screen::one_line_cell_vec(gutter.as_bytes());
+        let gutter_len = text.len();
+        text.extend_from_slice(&self.line);
+        self.line = Cow::Owned(text);
+        self.cursor_col = self.cursor_col.map(|c| c + gutter_len);
+        self
+    }
+
+    /// Number of digits needed to number the given number of lines
+    pub fn line_number_digits(lines: usize) -> usize {
+        std::cmp::max(1, lines).to_string().len()
+    }
+
     /// Create a line to be displayed from an session item.
     ///
     /// Decorate the line according to its type and update the cursor position.
@@ -125,9 +148,14 @@ impl<'a> DisplayLine<'a> {
                         &*ERROR_FAIL_PREFIX
                     }
                 }
+
+                (OutputVisibility::None, RunningStatus::Signaled(_)) => &*NONE_SIGNAL_PREFIX,
+                (OutputVisibility::Output, RunningStatus::Signaled(_)) => &*OUTPUT_SIGNAL_PREFIX,
+                (OutputVisibility::Error, RunningStatus::Signaled(_)) => &*ERROR_SIGNAL_PREFIX,
             },
 
             LineType::Input => &*INPUT_PREFIX,
+            LineType::Continuation => &*CONTINUATION_PREFIX,
             LineType::InputInfo => &*INPUT_INFO_PREFIX,
             LineType::MenuDecoration => &*MENU_DECO_PREFIX,
             LineType::SelectedMenuItem(_) => &*MENU_SELECT_PREFIX,
@@ -142,7 +170,7 @@ impl<'a> DisplayLine<'a> {
             deco,
             line.text,
             line.cursor_col,
-            line.prompt_hash,
+            line.prompt_color,
             line.is_a,
         )
     }
@@ -18,11 +18,12 @@
 
 //! Sub presenter for executing programs.
 
-use model::screen::Matrix;
+use model::screen::{Matrix, Screen};
 use model::session::{InteractionHandle, Session};
+use presenter::status_line;
 use presenter::{
     check_response_clicked, DisplayLine, LineItem, LineType, ModifierState, NeedRedraw,
-    PresenterCommand, PresenterCommons, SpecialKey, SubPresenter,
+    PresenterCommand, PresenterCommons, SpecialKey, SubPresenter, VisibleRange,
 };
 
 /// Presenter to run commands and send input to their stdin.
@@ -53,10 +54,11 @@ impl ExecuteCommandPresenter {
         Box::new(presenter)
     }
 
+    /// Height of the session area. One line is reserved for the status line.
     fn compute_session_height(&self) -> usize {
         let input_height = self.commons.text_input.height() as usize;
         // TODO: Handle window heights smaller than input_height
-        self.commons.window_height - input_height
+        self.commons.window_height - input_height - 1
     }
 }
 
@@ -73,6 +75,11 @@ impl SubPresenter for ExecuteCommandPresenter {
         &mut self.commons
     }
 
+    fn visible_range(&self, session: &Session) -> Option<VisibleRange> {
+        self.commons
+            .visible_range(session, false, self.compute_session_height())
+    }
+
     fn single_display_line<'a, 'b: 'a>(
         &'a self,
         session: &'b Session,
@@ -83,14 +90,39 @@ impl SubPresenter for ExecuteCommandPresenter {
             if let Some(loc) = self.commons.start_line(session, false, session_height) {
                 if let Some(loc) = PresenterCommons::locate_down(session, &loc, false, y) {
                     if let Some(display_line) = session.display_line(&loc) {
-                        return Some(DisplayLine::from(display_line));
+                        // The gutter is added after the horizontal scrolling to keep it in place
+                        let line = DisplayLine::from(self.commons.shift_session_line(display_line));
+                        if self.commons.line_numbers {
+                            if let Some((number, lines)) =
+                                session.output_line_number(self.current_interaction, &loc)
+                            {
+                                let digits = DisplayLine::line_number_digits(lines);
+                                return Some(line.with_line_number(number, digits));
+                            }
+                        }
+                        return Some(line);
                     }
                 }
             }
+        } else if y == session_height {
+            // Status line: Show what the interpreter is running
+            let frame = status_line::spinner_frame(self.commons.now());
+            let mut text =
+                status_line::busy_status(session, self.commons.interpreter.is_busy(), frame)
+                    .unwrap_or_default();
+            if let Some(clock) = self.commons.clock_text() {
+                text = status_line::right_aligned(&text, &clock, self.commons.window_width);
+            }
+            return Some(DisplayLine::from(LineItem::new_owned(
+                Screen::one_line_cell_vec(text.as_bytes()),
+                LineType::InputInfo,
+                None,
+                0,
+            )));
         } else {
             let input_height = self.commons.text_input.height() as usize;
-            if y < session_height + input_height {
-                let offs = y - session_height;
+            if y < session_height + 1 + input_height {
+                let offs = y - session_height - 1;
                 return self.commons.text_input.line_iter().nth(offs).map(|cells| {
                     let cursor_col = if offs == (self.commons.text_input.cursor_y() as usize) {
                         Some(self.commons.text_input.cursor_x() as usize)
@@ -217,6 +249,11 @@ impl SubPresenter for ExecuteCommandPresenter {
                     _ => PresenterCommand::Unknown,
                 }
             }
+            // Alt-N: Show or hide the line numbers of the output
+            (false, false, true) if letter == b'n' => {
+                self.commons.line_numbers = !self.commons.line_numbers;
+                PresenterCommand::Redraw
+            }
             _ => PresenterCommand::Unknown,
         }
     }
@@ -21,17 +21,28 @@
 //! The presenter dispatches all events to sub-presenters that handle different views, e.g. command
 //! composition or history browsing.
 
+mod auto_pair;
+mod completion_menu;
 mod completions;
 mod compose_command;
 pub mod display_line;
 mod execute_command;
+mod readline;
+mod reverse_search;
+mod status_line;
 mod style_sheet;
+pub mod transcript;
 mod tui;
+mod xtgettcap;
 
 #[cfg(test)]
-mod test;
+pub mod test;
 
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use term::terminfo::TermInfo;
 
 use sesd::{char::CharMatcher, SynchronousEditor};
@@ -42,19 +53,23 @@ use self::compose_command::live_parse;
 use self::compose_command::markov_below;
 use self::display_line::*;
 use self::execute_command::ExecuteCommandPresenter;
+use self::readline::{Edit, KillRing};
+use self::reverse_search::ReverseSearch;
 use self::tui::TuiExecuteCommandPresenter;
 use model::error::*;
 use model::history::History;
 use model::interpreter::grammar;
-use model::interpreter::InteractiveInterpreter;
+use model::interpreter::{CommandLog, InteractiveInterpreter};
 use model::screen::*;
 use model::session::{
     ConversationLocator, InteractionHandle, InteractionLocator, LineItem, LineType,
-    MaybeSessionLocator, ResponseLocator, Session, SessionLocator, SharedSession,
+    MaybeSessionLocator, OutputVisibility, ResponseLocator, RunningStatus, Session, SessionLocator,
+    SharedSession, TranscriptFormat,
 };
+use tools::commandline::BackspaceSends;
 
 /// GUI agnostic representation of the modifier keys
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct ModifierState {
     pub shift_pressed: bool,
     pub control_pressed: bool,
@@ -62,7 +77,7 @@ pub struct ModifierState {
 }
 
 /// GUI agnostic representation of special keys, e.g. function, cursor
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum SpecialKey {
     Escape,
     Enter,
@@ -97,6 +112,9 @@ pub enum NeedRedraw {
 /// This is used to check if we clicked the prefix.
 const COMMAND_PREFIX_LEN: usize = 4;
 
+/// Number of characters in front of an input line
+const INPUT_PREFIX_LEN: usize = 3;
+
 #[derive(Debug, PartialEq)]
 pub enum PresenterCommand {
     /// Unknown key combination, not handled
@@ -112,6 +130,44 @@ pub enum PresenterCommand {
     Exit,
 }
 
+/// Section of the session that is currently visible.
+///
+/// Used to inspect the scroll position without rendering.
+#[derive(Debug, PartialEq)]
+pub struct VisibleRange {
+    /// Topmost session line on screen
+    pub first: SessionLocator,
+
+    /// Lowest session line on screen
+    pub last: SessionLocator,
+
+    /// Number of session lines that could be shown by scrolling
+    pub total_lines: usize,
+}
+
+/// What Enter does if the input is an incomplete script
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IncompleteEnter {
+    /// Always continue the script in a new line
+    Continue,
+    /// Continue in a new line, but run the script if the last line is blank. This reports the
+    /// parse error.
+    RunOnBlankLine,
+    /// Run the script at once and report the parse error
+    Run,
+}
+
+/// How the typed word selects the completion candidates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompletionMatching {
+    /// Candidates start with the word
+    Prefix,
+    /// Candidates start with the word, ignoring case
+    IgnoreCase,
+    /// The characters of the word appear in the candidate in the same order, ignoring case
+    Fuzzy,
+}
+
 /// Feature flag
 #[derive(Debug)]
 pub enum ComposeVariant {
@@ -121,6 +177,48 @@ pub enum ComposeVariant {
     LiveParse,
 }
 
+/// Settings of the presenter, taken from the command line and the shell variables
+#[derive(Debug)]
+pub struct PresenterConfig {
+    /// Presenter to compose commands with
+    pub compose_variant: ComposeVariant,
+    /// Characters that separate words when editing word-wise
+    pub word_boundary: WordBoundary,
+    /// Insert closing brackets and quotes automatically
+    pub auto_pair: bool,
+    /// What Enter does if the input is an incomplete script
+    pub incomplete_enter: IncompleteEnter,
+    /// How the typed word selects the completion candidates
+    pub completion_matching: CompletionMatching,
+    /// Number of trailing directory components shown in the prompt. None for all.
+    pub prompt_dir_depth: Option<usize>,
+    /// strftime format of the clock in the status area. None for no clock.
+    pub clock_format: Option<String>,
+    /// What the Backspace key sends to TUI programs. None for the terminfo entry.
+    pub backspace_sends: Option<BackspaceSends>,
+    /// Send Alt + key to TUI programs as ESC + key instead of setting the 8th bit
+    pub alt_sends_escape: bool,
+    /// Source of the current time for the clock and the spinner
+    pub time_source: fn() -> SystemTime,
+}
+
+impl Default for PresenterConfig {
+    fn default() -> Self {
+        Self {
+            compose_variant: ComposeVariant::BubbleAbove,
+            word_boundary: WordBoundary::Whitespace,
+            auto_pair: false,
+            incomplete_enter: IncompleteEnter::RunOnBlankLine,
+            completion_matching: CompletionMatching::Prefix,
+            prompt_dir_depth: None,
+            clock_format: None,
+            backspace_sends: None,
+            alt_sends_escape: true,
+            time_source: SystemTime::now,
+        }
+    }
+}
+
 /// Trait to split the big presenter into several small ones.
 ///
 /// Each SubPresenter handles a different kind of interaction mode, e.g. command composition or
@@ -155,6 +253,39 @@ trait SubPresenter {
     /// Handle scrolling down
     fn event_scroll_down(&mut self, mod_state: &ModifierState) -> PresenterCommand;
 
+    /// Handle scrolling to the left
+    fn event_scroll_left(&mut self, mod_state: &ModifierState) -> PresenterCommand {
+        if mod_state.none_pressed() {
+            self.commons_mut().scroll_left(1);
+            PresenterCommand::Redraw
+        } else {
+            PresenterCommand::Unknown
+        }
+    }
+
+    /// Handle scrolling to the right
+    ///
+    /// Stops when the end of the widest visible output line is at the right edge of the window.
+    fn event_scroll_right(&mut self, mod_state: &ModifierState) -> PresenterCommand {
+        if mod_state.none_pressed() {
+            let overhang = {
+                let session = self.commons().session.clone();
+                let session = session.0.lock().unwrap();
+                let window_width = self.commons().window_width;
+                (0..self.commons().window_height)
+                    .filter_map(|row| self.single_display_line(&session, row))
+                    .filter(|line| line.is_a == LineType::Output)
+                    .map(|line| (line.prefix.len() + line.line.len()).saturating_sub(window_width))
+                    .max()
+                    .unwrap_or(0)
+            };
+            self.commons_mut().scroll_right(std::cmp::min(1, overhang));
+            PresenterCommand::Redraw
+        } else {
+            PresenterCommand::Unknown
+        }
+    }
+
     /// Handle the event when a modifier and a letter/number is pressed.
     fn event_normal_key(&mut self, mod_state: &ModifierState, letter: u8) -> PresenterCommand;
 
@@ -163,6 +294,11 @@ trait SubPresenter {
 
     /// Handle the event when the mouse was pushed and released at the same position.
     fn handle_click(&mut self, button: usize, x: usize, y: usize) -> NeedRedraw;
+
+    /// Section of the session that is shown. None if no session lines are shown.
+    fn visible_range(&self, _session: &Session) -> Option<VisibleRange> {
+        None
+    }
 }
 
 type Editor = SynchronousEditor<char, CharMatcher, grammar::script2::Grammar>;
@@ -180,6 +316,44 @@ pub struct CursorMapping {
     y: isize,
 }
 
+impl CursorMapping {
+    /// Find the buffer position that is shown at (x,y) in text_input.
+    ///
+    /// Each mapping starts a span that ends at the next one. The characters of a span follow each
+    /// other on screen, wide ones taking two columns, and a newline continues in the next row. A
+    /// position right of the end of a row is mapped to the end of that row. Returns None if there
+    /// is no such row.
+    pub fn buffer_position(
+        map: &[CursorMapping],
+        text: &[char],
+        x: isize,
+        y: isize,
+    ) -> Option<usize> {
+        let (mut cx, mut cy) = (0, 0);
+        for (index, span) in map.iter().enumerate() {
+            let end = map.get(index + 1).map_or(text.len(), |next| next.position);
+            cx = span.x;
+            cy = span.y;
+            for position in span.position..end {
+                if cy == y && (cx >= x || text[position] == '\n') {
+                    return Some(position);
+                }
+                if text[position] == '\n' {
+                    cx = 0;
+                    cy += 1;
+                } else {
+                    cx += char_columns(text[position]) as isize;
+                }
+            }
+        }
+        if cy == y {
+            Some(text.len())
+        } else {
+            None
+        }
+    }
+}
+
 /// Data that is common to all presenter views.
 pub struct PresenterCommons {
     /// The current and previous commands and the outputs of them.
@@ -210,6 +384,9 @@ pub struct PresenterCommons {
     /// In order to simplify the most common case (show everything), this is marked as None.
     session_end_line: Option<SessionLocator>,
 
+    /// Number of columns the output in the session is scrolled to the right.
+    session_column: usize,
+
     /// Currently edited input line
     text_input: Screen,
 
@@ -230,6 +407,40 @@ pub struct PresenterCommons {
 
     /// Completion algos
     completions: completions::Completions,
+
+    /// The input is an incomplete script. Enter adds lines until it is complete.
+    continue_input: bool,
+
+    /// Characters that separate words for word-wise editing
+    word_boundary: WordBoundary,
+
+    /// Texts deleted by the kill commands
+    kill_ring: KillRing,
+
+    /// Position in the text input set by Ctrl-Space and the text before it. Alt-W copies from
+    /// there to the cursor.
+    mark: Option<(isize, isize, String)>,
+
+    /// Insert closing brackets and quotes automatically
+    auto_pair: bool,
+
+    /// What Enter does if the input is an incomplete script
+    incomplete_enter: IncompleteEnter,
+
+    /// strftime format of the clock at the right edge of the status. None hides the clock.
+    clock_format: Option<String>,
+
+    /// What Backspace sends to programs. None uses the terminfo entry.
+    backspace_sends: Option<BackspaceSends>,
+
+    /// Alt + key sends ESC before the key instead of setting the 8th bit
+    alt_sends_escape: bool,
+
+    /// Source of the current time
+    time_source: fn() -> SystemTime,
+
+    /// Number the output lines of the running command. Off by default, toggled by Alt-N.
+    line_numbers: bool,
 }
 
 /// The top-level presenter dispatches events to the sub-presenters.
@@ -251,6 +462,27 @@ pub struct Presenter {
 
     /// Feature flag: ComposeCommandPresenter Variant
     feat_compose_variant: ComposeVariant,
+
+    /// Frame of the busy spinner that was drawn last
+    spinner_frame: usize,
+
+    /// Numeric argument for the next editing command, typed as Alt + digits
+    numeric_argument: Option<usize>,
+
+    /// Number of trailing directory components shown in the prompt. None shows all.
+    prompt_dir_depth: Option<usize>,
+
+    /// Text of the clock that was drawn last
+    clock: Option<String>,
+
+    /// Is the user asked to confirm closing the window while commands are running?
+    confirm_close: bool,
+
+    /// Does the window have the keyboard focus?
+    has_focus: bool,
+
+    /// Rows that showed a cursor when all lines were displayed the last time
+    cursor_rows: RefCell<Vec<usize>>,
 }
 
 /// Enum to fake C++'s typeof
@@ -312,6 +544,7 @@ impl PresenterCommons {
         interpreter: InteractiveInterpreter,
         history: History,
         term_info: TermInfo,
+        config: &PresenterConfig,
     ) -> Result<Self> {
         // let history = History::new(bash.get_current_user_home_dir());
         let mut text_input = Screen::new();
@@ -319,7 +552,7 @@ impl PresenterCommons {
 
         let compiled_grammar = crate::model::interpreter::grammar::script2::grammar();
         let style_sheet = style_sheet::script();
-        let completions = completions::Completions::new();
+        let completions = completions::Completions::new(config.completion_matching);
         Ok(PresenterCommons {
             session,
             interpreter,
@@ -328,12 +561,24 @@ impl PresenterCommons {
             button_down: None,
             text_input,
             session_end_line: None,
+            session_column: 0,
             history,
             term_info,
             editor: Editor::new(compiled_grammar),
             style_sheet,
             cursor_map: Vec::new(),
             completions,
+            continue_input: false,
+            word_boundary: config.word_boundary,
+            kill_ring: KillRing::new(),
+            mark: None,
+            auto_pair: config.auto_pair,
+            incomplete_enter: config.incomplete_enter,
+            clock_format: config.clock_format.clone(),
+            backspace_sends: config.backspace_sends,
+            alt_sends_escape: config.alt_sends_escape,
+            time_source: config.time_source,
+            line_numbers: false,
         })
     }
 
@@ -487,14 +732,39 @@ impl PresenterCommons {
                 } else {
                     None
                 };
-                LineItem::new(cells, LineType::Input, cursor_col, 0)
+                let (cells, cursor_col) = self.scroll_input_row(cells, cursor_col);
+                LineItem::new(cells, self.input_line_type(row as usize), cursor_col, 0)
             })
     }
 
+    /// Check if Enter on the given incomplete script adds a new line instead of running it.
+    fn continue_incomplete(&self, input: &str) -> bool {
+        self.incomplete_enter.continues(input)
+    }
+
+    /// Line type of the given row of the input. The rows after the first one of an incomplete
+    /// script get the continuation prompt.
+    fn input_line_type(&self, row: usize) -> LineType {
+        if self.continue_input && row > 0 {
+            LineType::Continuation
+        } else {
+            LineType::Input
+        }
+    }
+
     fn text_input_add_characters(&mut self, s: &str) {
         self.text_input.insert_str(s);
     }
 
+    /// Insert typed text, pairing brackets and quotes if configured
+    fn text_input_type(&mut self, s: &str) {
+        if self.auto_pair {
+            auto_pair::type_text(&mut self.text_input, s);
+        } else {
+            self.text_input_add_characters(s);
+        }
+    }
+
     /// Change session_end_line by going up n lines. This encodes the order of lines.
     pub fn scroll_up<F>(&mut self, show_last_prompt: bool, n: usize, f: F)
     where
@@ -532,10 +802,307 @@ impl PresenterCommons {
         }
     }
 
+    /// Scroll the previous or next command into the middle of the window.
+    ///
+    /// This includes the commands of shells that mark them, see
+    /// Session::locate_at_previous_command. Return false if there is no such command.
+    pub fn scroll_to_command(&mut self, show_last_prompt: bool, forward: bool) -> bool {
+        let middle = self.window_height / 2;
+        let session = self.session.0.lock().unwrap();
+        let end = match self.session_end_line {
+            Some(ref loc) => loc.clone(),
+            None => match Self::locate_end(&session, show_last_prompt) {
+                Some(loc) => loc,
+                None => return false,
+            },
+        };
+        // Search from the middle of the window, where the last command has been put
+        let here = Self::locate_up(&session, &end, middle).unwrap_or(end);
+        let command = if forward {
+            session.locate_at_next_command(&here)
+        } else {
+            session.locate_at_previous_command(&here)
+        };
+        match command {
+            Some(command) => {
+                self.session_end_line =
+                    Self::locate_down(&session, &command, show_last_prompt, middle);
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn to_last_line(&mut self) {
         self.session_end_line = None;
     }
 
+    /// Scroll the output in the session n columns to the left, i.e. towards the start of the lines.
+    pub fn scroll_left(&mut self, n: usize) {
+        self.session_column = self.session_column.saturating_sub(n);
+    }
+
+    /// Scroll the output in the session n columns to the right, i.e. towards the end of the lines.
+    pub fn scroll_right(&mut self, n: usize) {
+        self.session_column += n;
+    }
+
+    /// Shift an output line of the session by the horizontal scroll offset.
+    ///
+    /// Prompts and commands stay in place to keep the structure of the session visible.
+    fn shift_session_line<'a>(&self, line: LineItem<'a>) -> LineItem<'a> {
+        match line.is_a {
+            LineType::Output if self.session_column > 0 => {
+                let start = std::cmp::min(self.session_column, line.text.len());
+                let text = match line.text {
+                    Cow::Borrowed(cells) => Cow::Borrowed(&cells[start..]),
+                    Cow::Owned(mut cells) => {
+                        cells.drain(..start);
+                        Cow::Owned(cells)
+                    }
+                };
+                LineItem { text, ..line }
+            }
+            _ => line,
+        }
+    }
+
+    /// Scroll a row of the text input horizontally so that the cursor stays visible.
+    ///
+    /// Returns the visible part of the row and the cursor column in it. Rows without the cursor
+    /// are not scrolled.
+    fn scroll_input_row<'a>(
+        &self,
+        cells: &'a [Cell],
+        cursor_col: Option<usize>,
+    ) -> (&'a [Cell], Option<usize>) {
+        match cursor_col {
+            Some(col) => {
+                let offset = self.input_scroll(col);
+                let start = std::cmp::min(offset, cells.len());
+                (&cells[start..], Some(col - offset))
+            }
+            None => (cells, None),
+        }
+    }
+
+    /// Number of columns the row of the text input with the cursor in the given column is
+    /// scrolled to the left.
+    fn input_scroll(&self, cursor_col: usize) -> usize {
+        let width = self.window_width.saturating_sub(INPUT_PREFIX_LEN);
+        if width > 0 && cursor_col >= width {
+            cursor_col + 1 - width
+        } else {
+            0
+        }
+    }
+
+    /// Column of the text input that is shown at column x of the given row of the input.
+    ///
+    /// Returns None if x is left of the input.
+    pub fn input_column(&self, x: usize, row: usize) -> Option<isize> {
+        let col = x.checked_sub(INPUT_PREFIX_LEN)?;
+        let offset = if row as isize == self.text_input.cursor_y() {
+            self.input_scroll(self.text_input.cursor_x() as usize)
+        } else {
+            0
+        };
+        Some((col + offset) as isize)
+    }
+
+    /// Current time
+    fn now(&self) -> SystemTime {
+        (self.time_source)()
+    }
+
+    /// Current time as shown by the clock or None if there is no clock
+    fn clock_text(&self) -> Option<String> {
+        self.clock_format
+            .as_ref()
+            .map(|format| status_line::clock(format, &status_line::local_time(self.now())))
+    }
+
+    /// Line item for a row of the text input.
+    ///
+    /// If the row is too long for the window, it is scrolled to keep the cursor visible. If
+    /// interactions received output that has not been displayed yet, the first row shows a badge
+    /// with their number at the right edge of the window, followed by the clock if there is one.
+    /// In private mode, the badge says so.
+    fn input_line_item<'a>(
+        &self,
+        session: &Session,
+        offs: usize,
+        cells: &'a [Cell],
+        cursor_col: Option<usize>,
+    ) -> LineItem<'a> {
+        let (cells, cursor_col) = self.scroll_input_row(cells, cursor_col);
+        let is_a = self.input_line_type(offs);
+        if offs == 0 {
+            let badge = status_line::unseen_badge(session.unseen_count());
+            let badge = if self.c().history.is_private() {
+                Some(badge.map_or(status_line::PRIVATE_BADGE.to_string(), |b| {
+                    format!("{} {}", status_line::PRIVATE_BADGE, b)
+                }))
+            } else {
+                badge
+            };
+            let clock = self.clock_text();
+            if badge.is_some() || clock.is_some() {
+                let right_len = badge.as_ref().map_or(0, |b| b.chars().count())
+                    + clock.as_ref().map_or(0, |c| c.chars().count())
+                    + if badge.is_some() && clock.is_some() {
+                        1
+                    } else {
+                        0
+                    };
+                let used = cells.iter().rposition(|c| c.drawn()).map_or(0, |p| p + 1);
+                let width = self.window_width.saturating_sub(INPUT_PREFIX_LEN);
+                if used + right_len < width {
+                    let mut line = cells[..used].to_vec();
+                    let padding = width - used - right_len;
+                    let mut right = format!("{:padding$}", "", padding = padding);
+                    if let Some(badge) = badge {
+                        right.push_str(&format!("\x1b[7m{}\x1b[27m", badge));
+                        if clock.is_some() {
+                            right.push(' ');
+                        }
+                    }
+                    if let Some(clock) = clock {
+                        right.push_str(&clock);
+                    }
+                    line.extend(Screen::one_line_cell_vec(right.as_bytes()));
+                    return LineItem::new_owned(line, is_a, cursor_col, 0);
+                }
+            }
+        }
+        LineItem::new(cells, is_a, cursor_col, 0)
+    }
+
+    /// Abort the current input like bash does on Ctrl-C.
+    ///
+    /// The input is shown as a command that ended with exit status 130 and the input is cleared.
+    fn abort_input(&mut self, input: &str) {
+        let mut command = input.to_string();
+        command.push_str("^C");
+        let handle = self
+            .session
+            .add_interaction(Screen::one_line_matrix(command.as_bytes()));
+        self.session
+            .set_running_status(handle, RunningStatus::Exited(130));
+        self.editor.clear();
+        self.text_input.reset();
+        self.text_input.make_room();
+        self.continue_input = false;
+        self.to_last_line();
+    }
+
+    /// Apply a readline-style editing operation to the text input
+    fn apply_edit(&mut self, edit: Edit) {
+        edit.apply(
+            &mut self.text_input,
+            &mut self.kill_ring,
+            self.word_boundary,
+        );
+    }
+
+    /// Remember the cursor position in the text input as the mark
+    fn set_mark(&mut self) {
+        let x = self.text_input.cursor_x();
+        let y = self.text_input.cursor_y();
+        self.mark = Some((x, y, self.text_before(x, y)));
+    }
+
+    /// Text of the input before the given position
+    fn text_before(&self, x: isize, y: isize) -> String {
+        let text = self.text_input.extract_text_without_last_nl();
+        let mut lines: Vec<String> = text
+            .split('\n')
+            .take(y as usize + 1)
+            .map(|line| line.to_string())
+            .collect();
+        if let Some(line) = lines.last_mut() {
+            *line = line.chars().take(x as usize).collect();
+        }
+        lines.join("\n")
+    }
+
+    /// Put the text between the mark and the cursor into the kill ring.
+    ///
+    /// Nothing is copied unless the mark is in the line of the cursor. If the text before the mark
+    /// has been edited, the mark doesn't point to the same place any more and is dropped.
+    fn copy_region(&mut self) {
+        if let Some((x, y, before)) = self.mark.take() {
+            if self.text_before(x, y) != before {
+                return;
+            }
+            if y == self.text_input.cursor_y() {
+                let text = self.text_input.text_to_column(x);
+                self.kill_ring.kill(text);
+            }
+            self.mark = Some((x, y, before));
+        }
+    }
+
+    /// Start an incremental reverse search through the history, remembering the current input
+    fn start_reverse_search(&self) -> ReverseSearch {
+        ReverseSearch::new(
+            self.text_input.extract_text_without_last_nl(),
+            self.history.command_log().commands(),
+        )
+    }
+
+    /// Clear the screen by scrolling the current prompt to the top
+    pub fn clear_view(&mut self) {
+        self.session.clear_view();
+        self.to_last_line();
+    }
+
+    /// Count the lines of the session that can be shown by scrolling.
+    fn count_lines(session: &Session, show_last_prompt: bool) -> usize {
+        let mut count = 0;
+        if let Some(mut loc) = Self::locate_end(session, show_last_prompt) {
+            while let Some(new_loc) = Self::locate_up(session, &loc, 1) {
+                if new_loc == loc {
+                    break;
+                }
+                loc = new_loc;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Compute the visible section of the session if n lines end before end_line.
+    ///
+    /// If end_line is None, the session is shown up to the end.
+    fn locate_visible(
+        session: &Session,
+        end_line: Option<SessionLocator>,
+        show_last_prompt: bool,
+        n: usize,
+    ) -> Option<VisibleRange> {
+        let total_lines = Self::count_lines(session, show_last_prompt);
+        if n == 0 || total_lines == 0 {
+            return None;
+        }
+        let end = end_line.or_else(|| Self::locate_end(session, show_last_prompt))?;
+        Some(VisibleRange {
+            first: Self::locate_up(session, &end, n)?,
+            last: Self::locate_up(session, &end, 1)?,
+            total_lines,
+        })
+    }
+
+    /// Section of the session that is visible when drawing n lines
+    fn visible_range(
+        &self,
+        session: &Session,
+        show_last_prompt: bool,
+        n: usize,
+    ) -> Option<VisibleRange> {
+        Self::locate_visible(session, self.session_end_line.clone(), show_last_prompt, n)
+    }
+
     /// Return a session locator that refers to the first of n lines to draw
     fn start_line(
         &self,
@@ -543,10 +1110,106 @@ impl PresenterCommons {
         show_last_prompt: bool,
         n: usize,
     ) -> MaybeSessionLocator {
-        self.session_end_line
-            .clone()
-            .or_else(|| Self::locate_end(&session, show_last_prompt))
-            .and_then(|loc| Self::locate_up(&session, &loc, n))
+        match self.session_end_line {
+            Some(ref loc) => Self::locate_up(&session, loc, n),
+            // At the end of the session, the part that has been cleared is not shown
+            None => Self::locate_end(&session, show_last_prompt)
+                .and_then(|loc| Self::locate_up(&session, &loc, n))
+                .map(|loc| session.skip_cleared(loc)),
+        }
+    }
+}
+
+impl IncompleteEnter {
+    /// Check if Enter on the given incomplete script adds a new line instead of running it.
+    ///
+    /// A blank line is only recognized after a line break, i.e. in a continuation line.
+    pub fn continues(self, input: &str) -> bool {
+        match self {
+            IncompleteEnter::Continue => true,
+            IncompleteEnter::Run => false,
+            IncompleteEnter::RunOnBlankLine => match input.rfind('\n') {
+                Some(last_nl) => !input[last_nl + 1..].trim().is_empty(),
+                None => true,
+            },
+        }
+    }
+}
+
+/// Compare two characters ignoring case
+fn same_ignoring_case(a: char, b: char) -> bool {
+    a == b || a.to_lowercase().eq(b.to_lowercase())
+}
+
+impl CompletionMatching {
+    /// Indices of the characters of the candidate that match the word.
+    ///
+    /// Returns None if the candidate does not match.
+    pub fn matched_chars(self, word: &str, candidate: &str) -> Option<Vec<usize>> {
+        let mut chars = candidate.chars().enumerate();
+        let mut matched = Vec::new();
+        for w in word.chars() {
+            let (i, _) = match self {
+                CompletionMatching::Prefix => chars.next().filter(|(_, c)| *c == w)?,
+                CompletionMatching::IgnoreCase => {
+                    chars.next().filter(|(_, c)| same_ignoring_case(*c, w))?
+                }
+                CompletionMatching::Fuzzy => chars.find(|(_, c)| same_ignoring_case(*c, w))?,
+            };
+            matched.push(i);
+        }
+        Some(matched)
+    }
+
+    /// Check if the candidate matches the word
+    pub fn matches(self, word: &str, candidate: &str) -> bool {
+        self.matched_chars(word, candidate).is_some()
+    }
+
+    /// Candidate with the matched characters in bold for fuzzy matching.
+    ///
+    /// The prefix modes return the candidate as it is.
+    pub fn highlight(self, word: &str, candidate: &str) -> String {
+        let matched = match self {
+            CompletionMatching::Fuzzy => self.matched_chars(word, candidate).unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        let mut res = String::new();
+        for (i, c) in candidate.chars().enumerate() {
+            if matched.contains(&i) {
+                res.push_str("\x1b[1m");
+                res.push(c);
+                res.push_str("\x1b[22m");
+            } else {
+                res.push(c);
+            }
+        }
+        res
+    }
+}
+
+/// Shorten a directory for the prompt.
+///
+/// The home directory is shown as `~`. If `depth` is given, only that many trailing components
+/// are shown after a `…`.
+fn prompt_dir(dir: &Path, home: &Path, depth: Option<usize>) -> String {
+    // Collapsing the root directory would make everything look like home
+    let in_home = match dir.strip_prefix(home) {
+        Ok(rest) if home.parent().is_some() => Some(rest),
+        _ => None,
+    };
+    let rest = in_home.unwrap_or_else(|| dir.strip_prefix("/").unwrap_or(dir));
+    let parts: Vec<String> = rest
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    match (depth, in_home) {
+        (Some(depth), _) if depth > 0 && parts.len() > depth => {
+            format!("…/{}", parts[parts.len() - depth..].join("/"))
+        }
+        (_, Some(_)) if parts.is_empty() => "~".to_string(),
+        (_, Some(_)) => format!("~/{}", parts.join("/")),
+        (_, None) => dir.to_string_lossy().into_owned(),
     }
 }
 
@@ -568,20 +1231,28 @@ impl Presenter {
         interpreter: InteractiveInterpreter,
         history: History,
         term_info: TermInfo,
-        feat_compose_variant: ComposeVariant,
+        config: PresenterConfig,
     ) -> Result<Self> {
         let commons = Box::new(PresenterCommons::new(
             session,
             interpreter,
             history,
             term_info,
+            &config,
         )?);
-        let subpresenter = feat_compose_variant.new_subpresenter(commons);
+        let subpresenter = config.compose_variant.new_subpresenter(commons);
         let presenter = Presenter {
             focused_interaction: None,
             subpresenter: Some(subpresenter),
             sp_type: SubPresenterType::ComposeCommandPresenter,
-            feat_compose_variant,
+            feat_compose_variant: config.compose_variant,
+            spinner_frame: 0,
+            numeric_argument: None,
+            prompt_dir_depth: config.prompt_dir_depth,
+            clock: None,
+            confirm_close: false,
+            has_focus: true,
+            cursor_rows: RefCell::new(Vec::new()),
         };
         Ok(presenter)
     }
@@ -592,6 +1263,69 @@ impl Presenter {
         (commons.interpreter, commons.history)
     }
 
+    /// Check if the *exit* builtin asked the application to shut down
+    pub fn exit_requested(&self) -> bool {
+        self.c().session.exit_request().is_some()
+    }
+
+    /// Take the files the *transcript* builtin asked to write the session to
+    pub fn take_transcript_requests(&mut self) -> Vec<(PathBuf, TranscriptFormat)> {
+        self.cm().session.take_transcript_requests()
+    }
+
+    /// Show that a transcript could not be written as a failed command
+    pub fn report_transcript_failure(&mut self, path: &Path, msg: &str) {
+        let session = &mut self.cm().session;
+        let command = format!("transcript {}", path.to_string_lossy());
+        let handle = session.add_interaction(Screen::one_line_matrix(command.as_bytes()));
+        session.add_bytes(
+            OutputVisibility::Error,
+            handle,
+            format!(
+                "BiTE: transcript can't write »{}«: {}\n",
+                path.display(),
+                msg
+            )
+            .as_bytes(),
+        );
+        session.set_running_status(handle, RunningStatus::Exited(1));
+    }
+
+    /// All lines of the session as they are shown, for a transcript
+    pub fn transcript_lines(&self) -> Vec<Vec<Cell>> {
+        let session = self.c().session.clone();
+        let session = session.0.lock().unwrap();
+        transcript::session_lines(&session)
+    }
+
+    /// Check if closing the window would kill commands that are still running
+    pub fn has_running_jobs(&self) -> bool {
+        self.c().session.has_running_interactions()
+    }
+
+    /// Handle the view event that the window manager wants to close the window.
+    ///
+    /// If `confirm` is set and commands are still running, the user is asked first. The next key
+    /// answers the question. Closing the window again while asking closes it.
+    pub fn event_close(&mut self, confirm: bool) -> PresenterCommand {
+        if confirm && !self.confirm_close && self.has_running_jobs() {
+            self.confirm_close = true;
+            PresenterCommand::Redraw
+        } else {
+            PresenterCommand::Exit
+        }
+    }
+
+    /// Answer the question whether to close the window. Only `y` closes it.
+    fn answer_close(&mut self, answer: &str) -> PresenterCommand {
+        self.confirm_close = false;
+        if answer == "y" || answer == "Y" {
+            PresenterCommand::Exit
+        } else {
+            PresenterCommand::Redraw
+        }
+    }
+
     /// Access sub-presenter read-only for dynamic dispatch
     fn d(&self) -> &Box<dyn SubPresenter> {
         trace!("d(): {:?}", self.subpresenter.is_some());
@@ -614,6 +1348,32 @@ impl Presenter {
         self.dm().commons_mut().as_mut()
     }
 
+    /// Check if the shown command received output less than idle_time ago
+    pub fn output_streaming(&self, idle_time: Duration) -> bool {
+        match self.sp_type {
+            SubPresenterType::ComposeCommandPresenter => false,
+            SubPresenterType::ExecuteCommandPresenter(handle)
+            | SubPresenterType::TuiExecuteCommandPresenter(handle) => {
+                self.c().session.received_output_within(handle, idle_time)
+            }
+        }
+    }
+
+    /// Current working directory.
+    ///
+    /// While a command is shown, this is the directory it reported last (OSC 7). Otherwise, it is
+    /// the one of the interpreter.
+    pub fn current_dir(&self) -> PathBuf {
+        match self.sp_type {
+            SubPresenterType::ComposeCommandPresenter => None,
+            SubPresenterType::ExecuteCommandPresenter(handle)
+            | SubPresenterType::TuiExecuteCommandPresenter(handle) => {
+                self.c().session.working_directory(handle)
+            }
+        }
+        .unwrap_or_else(|| self.c().interpreter.get_cwd())
+    }
+
     /// Create a new prompt string and check if a new conversation needs to be created.
     pub fn update_prompt(&mut self) {
         let user_name = {
@@ -623,21 +1383,33 @@ impl Presenter {
                 std::ffi::CStr::from_ptr((*pw).pw_name as *const libc::c_char)
             }
         };
-        let cwd = match nix::unistd::getcwd() {
-            Ok(cwd) => cwd,
-            _ => std::path::PathBuf::new(),
-        };
+        let cwd = prompt_dir(
+            &self.current_dir(),
+            &::tools::home_dir::home_dir(),
+            self.prompt_dir_depth,
+        );
         let prompt_string = format!(
             "{}@{} {}",
             user_name.to_string_lossy(),
             nix::sys::utsname::uname().nodename(),
-            cwd.to_string_lossy()
+            cwd
         );
 
         let screen = Screen::one_line_matrix(prompt_string.as_bytes());
         self.cm().session.new_conversation(screen);
     }
 
+    /// Answer the capability queries (XTGETTCAP) of all commands
+    fn answer_queries(&mut self) {
+        let commons = self.cm();
+        for (handle, queries) in commons.session.take_termcap_queries() {
+            for query in queries {
+                let reply = xtgettcap::replies(&commons.term_info, &query);
+                commons.session.write_stdin(handle, &reply);
+            }
+        }
+    }
+
     /// Prepare the presenter for the new cycle.
     ///
     /// Return true if a redraw is required.
@@ -689,8 +1461,16 @@ impl Presenter {
             }
         };
 
+        self.answer_queries();
+
         // The GUI needs to be redrawn if the session has been changed.
         let mut redraw = self.dm().commons_mut().session.check_redraw();
+        // Advance the clock
+        let clock = self.c().clock_text();
+        if clock != self.clock {
+            self.clock = clock;
+            redraw = true;
+        }
         // If the new sp_type is different from the old one, transfer ownership from one to the
         // other.
         if sp_type != self.sp_type {
@@ -718,9 +1498,60 @@ impl Presenter {
                 self.update_prompt();
             }
         }
+        // Animate the spinner in the status line while a command runs.
+        if self.spinner_visible() {
+            let frame = status_line::spinner_frame(self.c().now());
+            if frame != self.spinner_frame {
+                self.spinner_frame = frame;
+                redraw = true;
+            }
+        }
+        // A command that changed the directory might have been too quick to switch the
+        // sub-presenter.
+        if self.sp_type == SubPresenterType::ComposeCommandPresenter
+            && self.cm().session.check_cwd_changed()
+        {
+            self.update_prompt();
+            redraw = true;
+        }
         redraw
     }
 
+    /// Check if the status line shows the spinner of a running command
+    fn spinner_visible(&self) -> bool {
+        match self.sp_type {
+            SubPresenterType::ExecuteCommandPresenter(_) => {
+                self.c().interpreter.is_busy().is_some()
+            }
+            _ => false,
+        }
+    }
+
+    /// Time at which the presenter needs to be redrawn even if nothing else happens.
+    ///
+    /// Used to animate the spinner in the status line and to advance the clock when its text
+    /// changes. The clock stands still while the window doesn't have the focus.
+    pub fn animation_deadline(&self, now: SystemTime) -> Option<SystemTime> {
+        let spinner = if self.spinner_visible() {
+            Some(status_line::next_frame_time(now))
+        } else {
+            None
+        };
+        let clock = match self.sp_type {
+            SubPresenterType::TuiExecuteCommandPresenter(_) => None,
+            _ if !self.has_focus => None,
+            _ => self
+                .c()
+                .clock_format
+                .as_ref()
+                .map(|format| status_line::next_clock_change(format, now)),
+        };
+        match (spinner, clock) {
+            (Some(s), Some(c)) => Some(std::cmp::min(s, c)),
+            (s, c) => s.or(c),
+        }
+    }
+
     /// Handle the View event when the window size changes.
     pub fn event_window_resize(&mut self, width: usize, height: usize) {
         let commons = self.cm();
@@ -732,12 +1563,29 @@ impl Presenter {
 
     /// Handle the view event when the window regained focus.
     pub fn event_focus_gained(&mut self) {
+        self.has_focus = true;
         self.cm().button_down = None;
+        self.report_focus(true);
     }
 
     /// Handle the view event when the window lost focus.
     pub fn event_focus_lost(&mut self) {
+        self.has_focus = false;
         self.cm().button_down = None;
+        self.report_focus(false);
+    }
+
+    /// Tell the shown command about a focus change if it enabled focus events
+    fn report_focus(&mut self, gained: bool) {
+        let handle = match self.sp_type {
+            SubPresenterType::ComposeCommandPresenter => return,
+            SubPresenterType::ExecuteCommandPresenter(handle)
+            | SubPresenterType::TuiExecuteCommandPresenter(handle) => handle,
+        };
+        let session = &mut self.cm().session;
+        if let Some(bytes) = session.focus_report(handle, gained) {
+            session.write_stdin(handle, bytes);
+        }
     }
 
     /// Handle the event that the window was scrolled down.
@@ -750,11 +1598,26 @@ impl Presenter {
         self.dm().event_scroll_up(mod_state)
     }
 
+    /// Handle the event that the window was scrolled to the left.
+    pub fn event_scroll_left(&mut self, mod_state: &ModifierState) -> PresenterCommand {
+        self.dm().event_scroll_left(mod_state)
+    }
+
+    /// Handle the event that the window was scrolled to the right.
+    pub fn event_scroll_right(&mut self, mod_state: &ModifierState) -> PresenterCommand {
+        self.dm().event_scroll_right(mod_state)
+    }
+
     pub fn event_special_key(
         &mut self,
         mod_state: &ModifierState,
         key: &SpecialKey,
     ) -> PresenterCommand {
+        if self.confirm_close {
+            return self.answer_close("");
+        }
+        let count = self.take_numeric_argument(readline::repeats_special_key(mod_state, key));
+        self.cm().kill_ring.end_yank();
         match (mod_state.as_tuple(), key) {
             // Ctrl-Tab => Switch to next running TUI if there is one
             ((false, true, false), SpecialKey::Tab) => {
@@ -764,23 +1627,125 @@ impl Presenter {
                 self.focused_interaction = next_focus;
                 return PresenterCommand::Redraw;
             }
+            // Shift-Ctrl-Up/Down outside of a TUI => Scroll to the previous/next command
+            ((true, true, false), SpecialKey::Up) if !self.shows_tui() => {
+                return self.scroll_to_command(false);
+            }
+            ((true, true, false), SpecialKey::Down) if !self.shows_tui() => {
+                return self.scroll_to_command(true);
+            }
+            // Ctrl-Space while composing a command => Set the mark. On an empty input, the
+            // sub-presenter toggles the output of the last command instead.
+            ((false, true, false), SpecialKey::Space)
+                if self.sp_type == SubPresenterType::ComposeCommandPresenter
+                    && !self
+                        .c()
+                        .text_input
+                        .extract_text_without_last_nl()
+                        .is_empty() =>
+            {
+                self.cm().set_mark();
+                return PresenterCommand::Redraw;
+            }
             _ => {}
         }
-        self.dm().event_special_key(mod_state, key)
+        self.repeat_event(count, |sp| sp.event_special_key(mod_state, key))
+    }
+
+    /// Check if a TUI is shown, which receives all keys
+    fn shows_tui(&self) -> bool {
+        match self.sp_type {
+            SubPresenterType::TuiExecuteCommandPresenter(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Scroll to the previous or next command
+    fn scroll_to_command(&mut self, forward: bool) -> PresenterCommand {
+        let show_last_prompt = self.sp_type == SubPresenterType::ComposeCommandPresenter;
+        if self.cm().scroll_to_command(show_last_prompt, forward) {
+            PresenterCommand::Redraw
+        } else {
+            PresenterCommand::Ignored
+        }
     }
 
     /// Dispatch the event that Modifier+Letter was pressed.
+    ///
+    /// While composing a command, Alt + digits enter a numeric argument for the next editing
+    /// command, Alt-W copies the text between the mark and the cursor and Alt-P toggles private
+    /// mode, in which commands are not recorded in the history.
     pub fn event_normal_key(&mut self, mod_state: &ModifierState, letter: u8) -> PresenterCommand {
-        self.dm().event_normal_key(mod_state, letter)
+        if self.confirm_close {
+            return self.answer_close("");
+        }
+        if self.sp_type == SubPresenterType::ComposeCommandPresenter {
+            match (mod_state.as_tuple(), letter) {
+                ((false, false, true), b'0'..=b'9') => {
+                    self.numeric_argument =
+                        Some(readline::numeric_argument(self.numeric_argument, letter));
+                    return PresenterCommand::Ignored;
+                }
+                ((false, false, true), b'w') => {
+                    self.cm().copy_region();
+                    return PresenterCommand::Redraw;
+                }
+                ((false, false, true), b'p') => {
+                    let history = &mut self.cm().history;
+                    let private = !history.is_private();
+                    history.set_private(private);
+                    return PresenterCommand::Redraw;
+                }
+                _ => {}
+            }
+        }
+        let edit = Edit::from_key(mod_state, letter);
+        if edit != Some(Edit::YankPop) {
+            self.cm().kill_ring.end_yank();
+        }
+        let count = self.take_numeric_argument(edit.is_some());
+        self.repeat_event(count, |sp| sp.event_normal_key(mod_state, letter))
     }
 
     /// Handle the event that some text was entered.
     ///
+    /// Typed text drops the numeric argument and ends a yank.
+    ///
     /// TODO: Handle escape sequences
     pub fn event_text(&mut self, s: &str) -> PresenterCommand {
+        if self.confirm_close {
+            return self.answer_close(s);
+        }
+        self.numeric_argument = None;
+        self.cm().kill_ring.end_yank();
         self.dm().event_text(s)
     }
 
+    /// Take the numeric argument and return how often the current key should be handled.
+    ///
+    /// Only editing commands are repeated.
+    fn take_numeric_argument(&mut self, repeatable: bool) -> usize {
+        match self.numeric_argument.take() {
+            Some(count) if repeatable => count,
+            _ => 1,
+        }
+    }
+
+    /// Pass a key event to the sub-presenter `count` times. Stop as soon as it isn't handled.
+    fn repeat_event<F>(&mut self, count: usize, mut f: F) -> PresenterCommand
+    where
+        F: FnMut(&mut Box<dyn SubPresenter>) -> PresenterCommand,
+    {
+        let mut cmd = PresenterCommand::Ignored;
+        for _ in 0..count {
+            cmd = f(self.dm());
+            if cmd != PresenterCommand::Redraw {
+                break;
+            }
+        }
+        cmd
+    }
+
     /// Handle the event that a mouse button was pressed.
     pub fn event_button_down(
         &mut self,
@@ -789,6 +1754,8 @@ impl Presenter {
         x: usize,
         y: usize,
     ) -> NeedRedraw {
+        // Clicking might move the cursor away from the yanked text
+        self.cm().kill_ring.end_yank();
         self.cm().button_down = Some((btn, x, y));
         NeedRedraw::No
     }
@@ -813,18 +1780,67 @@ impl Presenter {
         NeedRedraw::No
     }
 
+    /// Call the drawing function for the rows that showed a cursor the last time all lines were
+    /// displayed, and for the rows below them.
+    ///
+    /// Used to blink the cursor without building all lines. The cursor reaches into the row below,
+    /// which is why that one is drawn again, too.
+    pub fn display_cursor_lines(&self, draw_line: &dyn DrawLineTrait) {
+        let session = self.c().session.clone();
+        let session = session.0.lock().unwrap();
+        // The question whether to close the window covers the last row
+        let rows = if self.confirm_close {
+            self.c().window_height.saturating_sub(1)
+        } else {
+            self.c().window_height
+        };
+        let mut last_drawn = None;
+        for cursor_row in self.cursor_rows.borrow().iter() {
+            for row in *cursor_row..std::cmp::min(cursor_row + 2, rows) {
+                if last_drawn.map_or(true, |last| last < row) {
+                    if let Some(l) = self.d().single_display_line(&session, row) {
+                        draw_line.draw_line(row, &l);
+                    }
+                    last_drawn = Some(row);
+                }
+            }
+        }
+    }
+
     /// Call the drawing function for the given screen rows
     ///
     /// This is required as the session mutex must be locked and thus an iterator cannot be
     /// returned.
     pub fn display_lines(&self, draw_line: &dyn DrawLineTrait) {
         let session = self.c().session.clone();
-        let session = session.0.lock().unwrap();
+        let mut session = session.0.lock().unwrap();
+        // Everything in view is going to be displayed now
+        if let Some(range) = self.d().visible_range(&session) {
+            session.mark_seen(&range.first, &range.last);
+        }
+        let mut cursor_rows = self.cursor_rows.borrow_mut();
+        cursor_rows.clear();
         for row in 0..self.c().window_height {
             if let Some(l) = self.d().single_display_line(&session, row) {
+                if l.cursor_col.is_some() {
+                    cursor_rows.push(row);
+                }
                 draw_line.draw_line(row, &l);
             }
         }
+        // The question whether to close the window covers the last row
+        if self.confirm_close && self.c().window_height > 0 {
+            let text = format!("\x1b[7m{}\x1b[27m", status_line::CLOSE_QUESTION);
+            draw_line.draw_line(
+                self.c().window_height - 1,
+                &DisplayLine::from(LineItem::new_owned(
+                    Screen::one_line_cell_vec(text.as_bytes()),
+                    LineType::InputInfo,
+                    None,
+                    0,
+                )),
+            );
+        }
     }
 }
 
@@ -837,7 +1853,7 @@ fn clicked_line_type<T: SubPresenter>(pres: &mut T, y: usize) -> Option<LineType
 }
 
 /// Check if the response selector has been clicked and update the visibility flags
-/// accordingly.
+/// accordingly. Clicking the rest of a command shows its binary output if that is held back.
 ///
 /// This is used by ComposeCommandPresenter and ExecuteCommandPresenter.
 fn check_response_clicked<T: SubPresenter>(
@@ -853,6 +1869,10 @@ fn check_response_clicked<T: SubPresenter>(
                 pres.commons_mut().session.cycle_visibility(handle);
                 return true;
             }
+            if pres.commons().session.is_binary_suppressed(handle) {
+                pres.commons_mut().session.show_binary_output(handle);
+                return true;
+            }
         }
         _ => {
             // Unhandled combination, ignore
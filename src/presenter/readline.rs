@@ -0,0 +1,219 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Emacs-style line editing as known from readline
+//!
+//! Text deleted with the kill commands (Ctrl-W, Ctrl-U, Ctrl-K) is kept in a kill ring and can be
+//! inserted again with Ctrl-Y. Alt-Y right after that replaces the inserted text with the previous
+//! entry of the kill ring.
+//!
+//! While composing a command, Ctrl-Space sets the mark and Alt-W copies the text between the mark
+//! and the cursor into the kill ring.
+//!
+//! Alt + digits enter a numeric argument that repeats the next editing command, e.g. Alt-3 Delete
+//! deletes three characters.
+
+use std::collections::VecDeque;
+
+use super::{ModifierState, SpecialKey};
+use model::screen::{CaseChange, Screen, WordBoundary};
+
+/// Maximal number of entries in the kill ring. Older ones are dropped.
+const KILL_RING_SIZE: usize = 16;
+
+/// Largest numeric argument. Larger ones are clamped.
+const MAX_NUMERIC_ARGUMENT: usize = 9999;
+
+/// List of killed texts, most recent last
+pub struct KillRing {
+    entries: VecDeque<String>,
+
+    /// Entry inserted by the last yank and its length in characters.
+    ///
+    /// None if the last key wasn't a yank.
+    last_yank: Option<(usize, usize)>,
+}
+
+/// Editing operation bound to a key
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Edit {
+    /// Ctrl-A
+    LineStart,
+    /// Ctrl-E
+    LineEnd,
+    /// Alt-B
+    WordLeft,
+    /// Alt-F
+    WordRight,
+    /// Ctrl-W
+    KillWordBefore,
+    /// Ctrl-U
+    KillToLineStart,
+    /// Ctrl-K
+    KillToLineEnd,
+    /// Ctrl-Y
+    Yank,
+    /// Alt-Y
+    YankPop,
+    /// Ctrl-T
+    TransposeCharacters,
+    /// Alt-U, Alt-L, Alt-C
+    ChangeCase(CaseChange),
+}
+
+impl KillRing {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            last_yank: None,
+        }
+    }
+
+    /// Remember a killed text. Empty texts are ignored.
+    pub fn kill(&mut self, text: String) {
+        if !text.is_empty() {
+            if self.entries.len() == KILL_RING_SIZE {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(text);
+        }
+    }
+
+    /// Text to insert on yank, i.e. the most recently killed one
+    pub fn yank(&self) -> Option<&str> {
+        self.entries.back().map(|s| s.as_str())
+    }
+
+    /// Remember that the most recently killed text has been inserted
+    pub fn start_yank(&mut self) {
+        self.last_yank = self
+            .entries
+            .back()
+            .map(|s| (self.entries.len() - 1, s.chars().count()));
+    }
+
+    /// Text inserted by the last yank. None if the last key wasn't a yank.
+    pub fn last_yanked(&self) -> Option<&str> {
+        self.last_yank
+            .map(|(index, _)| self.entries[index].as_str())
+    }
+
+    /// Forget the last yank, e.g. because another key was pressed
+    pub fn end_yank(&mut self) {
+        self.last_yank = None;
+    }
+
+    /// Step to the previous entry after a yank.
+    ///
+    /// Returns the number of characters inserted by the last yank and the text to insert
+    /// instead. After the oldest entry, the most recent one follows.
+    pub fn yank_pop(&mut self) -> Option<(usize, &str)> {
+        let (index, len) = self.last_yank?;
+        let index = if index == 0 {
+            self.entries.len() - 1
+        } else {
+            index - 1
+        };
+        self.last_yank = Some((index, self.entries[index].chars().count()));
+        Some((len, self.entries[index].as_str()))
+    }
+}
+
+/// Add a typed digit to the numeric argument
+pub fn numeric_argument(current: Option<usize>, digit: u8) -> usize {
+    let value = current.unwrap_or(0) * 10 + (digit - b'0') as usize;
+    std::cmp::min(value, MAX_NUMERIC_ARGUMENT)
+}
+
+/// Check if a special key is an editing command that can be repeated by a numeric argument
+pub fn repeats_special_key(mod_state: &ModifierState, key: &SpecialKey) -> bool {
+    match (mod_state.as_tuple(), key) {
+        // (shift,control,meta)
+        ((false, _, false), SpecialKey::Left)
+        | ((false, _, false), SpecialKey::Right)
+        | ((false, _, false), SpecialKey::Delete)
+        | ((false, _, false), SpecialKey::Backspace) => true,
+        _ => false,
+    }
+}
+
+impl Edit {
+    /// Find the editing operation for modifier + letter
+    pub fn from_key(mod_state: &ModifierState, letter: u8) -> Option<Self> {
+        match (mod_state.as_tuple(), letter) {
+            // (shift,control,meta)
+            ((false, true, false), b'a') => Some(Edit::LineStart),
+            ((false, true, false), b'e') => Some(Edit::LineEnd),
+            ((false, false, true), b'b') => Some(Edit::WordLeft),
+            ((false, false, true), b'f') => Some(Edit::WordRight),
+            ((false, true, false), b'w') => Some(Edit::KillWordBefore),
+            ((false, true, false), b'u') => Some(Edit::KillToLineStart),
+            ((false, true, false), b'k') => Some(Edit::KillToLineEnd),
+            ((false, true, false), b'y') => Some(Edit::Yank),
+            ((false, false, true), b'y') => Some(Edit::YankPop),
+            ((false, true, false), b't') => Some(Edit::TransposeCharacters),
+            ((false, false, true), b'u') => Some(Edit::ChangeCase(CaseChange::Upper)),
+            ((false, false, true), b'l') => Some(Edit::ChangeCase(CaseChange::Lower)),
+            ((false, false, true), b'c') => Some(Edit::ChangeCase(CaseChange::Capitalize)),
+            _ => None,
+        }
+    }
+
+    /// Check if the operation changes the text or only moves the cursor
+    pub fn changes_text(&self) -> bool {
+        match self {
+            Edit::LineStart | Edit::LineEnd | Edit::WordLeft | Edit::WordRight => false,
+            Edit::KillWordBefore
+            | Edit::KillToLineStart
+            | Edit::KillToLineEnd
+            | Edit::Yank
+            | Edit::YankPop
+            | Edit::TransposeCharacters
+            | Edit::ChangeCase(_) => true,
+        }
+    }
+
+    /// Apply the operation to the text input
+    pub fn apply(&self, text_input: &mut Screen, kill_ring: &mut KillRing, boundary: WordBoundary) {
+        match self {
+            Edit::LineStart => text_input.move_left_edge(),
+            Edit::LineEnd => text_input.move_end_of_line(),
+            Edit::WordLeft => text_input.move_word_left(boundary),
+            Edit::WordRight => text_input.move_word_right(boundary),
+            Edit::KillWordBefore => kill_ring.kill(text_input.delete_word_before_cursor(boundary)),
+            Edit::KillToLineStart => kill_ring.kill(text_input.delete_to_line_start()),
+            Edit::KillToLineEnd => kill_ring.kill(text_input.delete_to_line_end()),
+            Edit::Yank => {
+                if let Some(text) = kill_ring.yank() {
+                    text_input.insert_str(text);
+                }
+                kill_ring.start_yank();
+            }
+            Edit::YankPop => {
+                if let Some((len, text)) = kill_ring.yank_pop() {
+                    for _ in 0..len {
+                        text_input.delete_left();
+                    }
+                    text_input.insert_str(text);
+                }
+            }
+            Edit::TransposeCharacters => text_input.transpose_characters(),
+            Edit::ChangeCase(change) => text_input.change_case_of_word(boundary, *change),
+        }
+    }
+}
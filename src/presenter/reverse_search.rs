@@ -0,0 +1,128 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Incremental reverse search through the history as known from readline (Ctrl-R)
+//!
+//! Every typed character narrows the search to the commands that contain the query. Pressing
+//! Ctrl-R again steps to the next older match.
+
+use model::screen::Screen;
+
+/// State of a running reverse search
+pub struct ReverseSearch {
+    /// Input before the search started. It is restored if the search is cancelled.
+    draft: String,
+
+    /// Commands to search, newest first
+    commands: Vec<String>,
+
+    /// Text to search for
+    query: String,
+
+    /// Indices of the commands that contain the query, newest first
+    matches: Vec<usize>,
+
+    /// Index into matches of the shown command
+    selected: usize,
+}
+
+impl ReverseSearch {
+    /// Start a search with an empty query.
+    ///
+    /// The commands are expected oldest first, as delivered by the history.
+    pub fn new(draft: String, mut commands: Vec<String>) -> Self {
+        commands.reverse();
+        let mut search = Self {
+            draft,
+            commands,
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+        };
+        search.find_matches();
+        search
+    }
+
+    /// Recompute the matches for the current query and show the newest one.
+    fn find_matches(&mut self) {
+        let query = &self.query;
+        self.matches = self
+            .commands
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.contains(query.as_str()))
+            .map(|(i, _)| i)
+            .collect();
+        self.selected = 0;
+    }
+
+    /// Input before the search started
+    pub fn draft(&self) -> &str {
+        &self.draft
+    }
+
+    /// Text to search for
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Extend the query
+    pub fn push_str(&mut self, s: &str) {
+        self.query.push_str(s);
+        self.find_matches();
+    }
+
+    /// Remove the last character of the query
+    pub fn pop(&mut self) {
+        let _ = self.query.pop();
+        self.find_matches();
+    }
+
+    /// Step to the next older match. Stays at the oldest one.
+    pub fn older(&mut self) {
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    /// Command that is currently matched
+    pub fn current(&self) -> Option<&str> {
+        self.matches
+            .get(self.selected)
+            .map(|i| self.commands[*i].as_str())
+    }
+
+    /// Number of commands that contain the query
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    /// Text shown in front of the match
+    pub fn prompt(&self) -> String {
+        if self.matches.is_empty() {
+            format!("(failed reverse-i-search)`{}': ", self.query)
+        } else {
+            format!("(reverse-i-search)`{}': ", self.query)
+        }
+    }
+
+    /// Show the current match in the text input. If nothing matches, show the draft.
+    pub fn show(&self, text_input: &mut Screen) {
+        text_input.replace(self.current().unwrap_or(&self.draft), false);
+    }
+}
@@ -0,0 +1,150 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Status line that shows which command the interpreter is running and the badge for unseen
+//! output. An optional clock is shown at the right edge.
+
+use std::ffi::CString;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use model::screen::str_columns;
+use model::session::{InteractionHandle, Session};
+
+/// Characters of the spinner animation
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Time each frame of the spinner is shown in milliseconds
+const SPINNER_FRAME_MS: u128 = 250;
+
+/// Frame of the spinner to show at the given time
+pub fn spinner_frame(now: SystemTime) -> usize {
+    let ms = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    (ms / SPINNER_FRAME_MS) as usize % SPINNER.len()
+}
+
+/// Time at which the spinner shows the frame after the one at the given time
+pub fn next_frame_time(now: SystemTime) -> SystemTime {
+    let ms = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let to_next = SPINNER_FRAME_MS - ms % SPINNER_FRAME_MS;
+    now + Duration::from_millis(to_next as u64)
+}
+
+/// Time at which the clock shows the next second
+pub fn next_second(now: SystemTime) -> SystemTime {
+    let ms = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    now + Duration::from_millis((1000 - ms % 1000) as u64)
+}
+
+/// Time at which the clock with the given format shows another text than at the given time.
+///
+/// Looks at most a minute ahead.
+pub fn next_clock_change(format: &str, now: SystemTime) -> SystemTime {
+    let text = clock(format, &local_time(now));
+    let mut next = next_second(now);
+    for _ in 1..60 {
+        if clock(format, &local_time(next)) != text {
+            break;
+        }
+        next += Duration::from_secs(1);
+    }
+    next
+}
+
+/// Local calendar time of a point in time
+pub fn local_time(time: SystemTime) -> libc::tm {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0) as libc::time_t;
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&secs, &mut tm);
+        tm
+    }
+}
+
+/// Text of the clock, formatted like strftime does, e.g. `%H:%M:%S`
+pub fn clock(format: &str, tm: &libc::tm) -> String {
+    let format = match CString::new(format) {
+        Ok(format) => format,
+        Err(_) => return String::new(),
+    };
+    let mut buf = [0u8; 128];
+    let len = unsafe {
+        libc::strftime(
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            format.as_ptr(),
+            tm,
+        )
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Put a text at the right edge of a line of the given width.
+///
+/// If both don't fit, only the left text is returned.
+pub fn right_aligned(left: &str, right: &str, width: usize) -> String {
+    let used = str_columns(left) + str_columns(right);
+    if used < width {
+        format!("{}{:padding$}{}", left, "", right, padding = width - used)
+    } else {
+        left.to_string()
+    }
+}
+
+/// Text of the status line or None if the interpreter is idle.
+///
+/// `busy` is the interaction the interpreter is running, as returned by
+/// `InteractiveInterpreter::is_busy`.
+pub fn busy_status(
+    session: &Session,
+    busy: Option<InteractionHandle>,
+    frame: usize,
+) -> Option<String> {
+    let command = session.command_text(busy?)?;
+    Some(format!(
+        "{} Running: {}",
+        SPINNER[frame % SPINNER.len()],
+        command
+    ))
+}
+
+/// Question shown when the window is closed while commands are still running
+pub const CLOSE_QUESTION: &str = "Commands are still running. Close anyway? (y/n)";
+
+/// Badge shown while the entered commands are not recorded in the history
+pub const PRIVATE_BADGE: &str = "[private]";
+
+/// Text of the badge for interactions with unseen output or None if there are none.
+pub fn unseen_badge(unseen: usize) -> Option<String> {
+    if unseen == 0 {
+        None
+    } else {
+        Some(format!("[{} new]", unseen))
+    }
+}
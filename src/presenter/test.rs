@@ -19,13 +19,36 @@
 //! Module tests for Presenter, mostly for the locator code
 
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
 
-use model::screen::{Cell, Screen};
+use model::history::History;
+use model::interpreter::jobs::Job;
+use model::interpreter::{parse_script, ScriptError, StartupInterpreter};
+use model::screen::{Cell, Screen, WordBoundary};
 use model::session::test::new_test_session;
-use model::session::OutputVisibility;
+use model::session::{
+    InteractionHandle, LineItem, LineType, OutputVisibility, RunningStatus, Session, SharedSession,
+};
+use nix::unistd::{close, pipe, read};
+use presenter::auto_pair::{type_text, typing, Typing};
+use presenter::completion_menu::CompletionMenu;
+use presenter::display_line::DisplayLine;
+use presenter::execute_command::ExecuteCommandPresenter;
+use presenter::readline::{Edit, KillRing};
+use presenter::reverse_search::ReverseSearch;
+use presenter::status_line;
+use presenter::tui::{self, TuiExecuteCommandPresenter};
+use presenter::xtgettcap;
 use presenter::{
-    ConversationLocator, InteractionLocator, PresenterCommons, ResponseLocator, SessionLocator,
+    prompt_dir, CompletionMatching, ComposeVariant, ConversationLocator, CursorMapping,
+    DrawLineTrait, IncompleteEnter, InteractionLocator, ModifierState, NeedRedraw, Presenter,
+    PresenterCommand, PresenterCommons, PresenterConfig, ResponseLocator, SessionLocator,
+    SpecialKey, SubPresenter, SubPresenterType, VisibleRange,
 };
+use term::terminfo::TermInfo;
+use tools::commandline::BackspaceSends;
 
 fn c2s(cells: Cow<[Cell]>) -> String {
     let mut s = String::new();
@@ -314,3 +337,1685 @@ fn locator() {
         );
     }
 }
+
+/// After clearing the view, the current prompt is the first line that can be shown.
+#[test]
+fn clear_view() {
+    let mut session = new_test_session(b"prompt 1");
+
+    let inter_1_1 = session.add_interaction(Screen::one_line_matrix(b"command 1.1"));
+    session.add_bytes(
+        OutputVisibility::Output,
+        inter_1_1,
+        b"output 1.1.1\noutput 1.1.2\n",
+    );
+    session.clear_view();
+    let mut commons = test_commons(&session);
+
+    {
+        let session = session.0.lock().unwrap();
+        let first_loc = commons
+            .start_line(&session, true, 1000)
+            .expect("going to the start should have worked");
+        assert_eq!(
+            first_loc,
+            SessionLocator {
+                conversation: 1,
+                in_conversation: ConversationLocator::Prompt(0),
+            }
+        );
+        assert_eq!(
+            c2s(session
+                .display_line(&first_loc)
+                .expect("display_line should work")
+                .text),
+            "prompt 1"
+        );
+    }
+
+    // The cleared part can still be reached by scrolling up
+    commons.scroll_up(true, 1, |_, _| None);
+    {
+        let session = session.0.lock().unwrap();
+        let first_loc = commons
+            .start_line(&session, true, 1000)
+            .expect("going to the start should have worked");
+        assert_eq!(first_loc.conversation, 0);
+    }
+    commons.to_last_line();
+
+    // Commands after the clear are shown below the cleared part
+    let inter_2_1 = session.add_interaction(Screen::one_line_matrix(b"command 2.1"));
+    session.add_bytes(OutputVisibility::Output, inter_2_1, b"output 2.1.1\n");
+    {
+        let session = session.0.lock().unwrap();
+        let first_loc = commons
+            .start_line(&session, true, 1000)
+            .expect("going to the start should have worked");
+        assert_eq!(
+            c2s(session
+                .display_line(&first_loc)
+                .expect("display_line should work")
+                .text),
+            "command 2.1"
+        );
+    }
+
+    commons.interpreter.shutdown();
+}
+
+/// Press Ctrl + letter on the text input
+fn press_ctrl(text_input: &mut Screen, kill_ring: &mut KillRing, letter: u8) {
+    let mod_state = ModifierState {
+        shift_pressed: false,
+        control_pressed: true,
+        meta_pressed: false,
+    };
+    Edit::from_key(&mod_state, letter)
+        .expect("key should be bound")
+        .apply(text_input, kill_ring, WordBoundary::Whitespace);
+}
+
+#[test]
+fn readline_kill_and_yank() {
+    let mut text_input = Screen::new();
+    let mut kill_ring = KillRing::new();
+    text_input.insert_str("git commit -m message");
+
+    // Ctrl-W removes the last word
+    press_ctrl(&mut text_input, &mut kill_ring, b'w');
+    assert_eq!(text_input.extract_text_without_last_nl(), "git commit -m ");
+    assert_eq!(kill_ring.yank(), Some("message"));
+
+    // Ctrl-Y puts it back
+    press_ctrl(&mut text_input, &mut kill_ring, b'y');
+    assert_eq!(
+        text_input.extract_text_without_last_nl(),
+        "git commit -m message"
+    );
+
+    // Ctrl-U removes everything before the cursor
+    text_input.move_left(7);
+    press_ctrl(&mut text_input, &mut kill_ring, b'u');
+    assert_eq!(text_input.extract_text_without_last_nl(), "message");
+    assert_eq!(text_input.cursor_x(), 0);
+
+    // Ctrl-E, Ctrl-Y appends the last kill
+    press_ctrl(&mut text_input, &mut kill_ring, b'e');
+    press_ctrl(&mut text_input, &mut kill_ring, b'y');
+    assert_eq!(
+        text_input.extract_text_without_last_nl(),
+        "messagegit commit -m "
+    );
+
+    // Ctrl-A, Ctrl-K removes the whole line
+    press_ctrl(&mut text_input, &mut kill_ring, b'a');
+    press_ctrl(&mut text_input, &mut kill_ring, b'k');
+    assert_eq!(text_input.extract_text_without_last_nl(), "");
+    assert_eq!(kill_ring.yank(), Some("messagegit commit -m "));
+}
+
+#[test]
+fn readline_yank_pop() {
+    let mut text_input = Screen::new();
+    let mut kill_ring = KillRing::new();
+    kill_ring.kill("one".to_string());
+    kill_ring.kill("two".to_string());
+    kill_ring.kill("three".to_string());
+    text_input.insert_str("echo ");
+    let meta = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: true,
+    };
+    let yank_pop = Edit::from_key(&meta, b'y').expect("Alt-Y should be bound");
+
+    // Without a yank, Alt-Y does nothing
+    yank_pop.apply(&mut text_input, &mut kill_ring, WordBoundary::Whitespace);
+    assert_eq!(text_input.extract_text_without_last_nl(), "echo ");
+
+    // Each Alt-Y replaces the yanked text by the previous kill
+    press_ctrl(&mut text_input, &mut kill_ring, b'y');
+    assert_eq!(text_input.extract_text_without_last_nl(), "echo three");
+    for expected in ["echo two", "echo one", "echo three"].iter() {
+        yank_pop.apply(&mut text_input, &mut kill_ring, WordBoundary::Whitespace);
+        assert_eq!(text_input.extract_text_without_last_nl(), *expected);
+        assert!(text_input.cursor_at_end());
+    }
+
+    // Once the yank has ended, Alt-Y does nothing
+    kill_ring.end_yank();
+    yank_pop.apply(&mut text_input, &mut kill_ring, WordBoundary::Whitespace);
+    assert_eq!(text_input.extract_text_without_last_nl(), "echo three");
+}
+
+fn history_commands() -> Vec<String> {
+    vec![
+        "cargo build".to_string(),
+        "git status".to_string(),
+        "cargo test".to_string(),
+        "git commit".to_string(),
+    ]
+}
+
+#[test]
+fn reverse_search_narrows() {
+    let mut search = ReverseSearch::new("draft".to_string(), history_commands());
+    assert_eq!(search.match_count(), 4);
+    assert_eq!(search.current(), Some("git commit"));
+
+    search.push_str("car");
+    assert_eq!(search.match_count(), 2);
+    assert_eq!(search.current(), Some("cargo test"));
+    assert_eq!(search.prompt(), "(reverse-i-search)`car': ");
+
+    search.push_str("go b");
+    assert_eq!(search.match_count(), 1);
+    assert_eq!(search.current(), Some("cargo build"));
+
+    search.push_str("x");
+    assert_eq!(search.match_count(), 0);
+    assert_eq!(search.current(), None);
+    assert_eq!(search.prompt(), "(failed reverse-i-search)`cargo bx': ");
+
+    // Backspace widens the search again
+    search.pop();
+    search.pop();
+    assert_eq!(search.query(), "cargo ");
+    assert_eq!(search.match_count(), 2);
+
+    // Without a match, the draft is shown
+    let mut text_input = Screen::new();
+    search.show(&mut text_input);
+    assert_eq!(text_input.extract_text_without_last_nl(), "cargo test");
+    search.push_str("x");
+    search.show(&mut text_input);
+    assert_eq!(text_input.extract_text_without_last_nl(), "draft");
+}
+
+#[test]
+fn reverse_search_cycles() {
+    let mut search = ReverseSearch::new(String::new(), history_commands());
+    search.push_str("git");
+    assert_eq!(search.current(), Some("git commit"));
+    search.older();
+    assert_eq!(search.current(), Some("git status"));
+    // The oldest match stays selected
+    search.older();
+    assert_eq!(search.current(), Some("git status"));
+
+    // Typing starts again at the newest match
+    search.push_str(" ");
+    assert_eq!(search.current(), Some("git commit"));
+}
+
+#[test]
+fn auto_pair_brackets() {
+    let mut text_input = Screen::new();
+    type_text(&mut text_input, "echo $");
+
+    // Typing ( inserts the closer behind the cursor
+    type_text(&mut text_input, "(");
+    assert_eq!(text_input.extract_text_without_last_nl(), "echo $()");
+    assert_eq!(text_input.cursor_x(), 7);
+
+    // Typing ) moves over the inserted closer
+    type_text(&mut text_input, "ls");
+    type_text(&mut text_input, ")");
+    assert_eq!(text_input.extract_text_without_last_nl(), "echo $(ls)");
+    assert_eq!(text_input.cursor_x(), 10);
+
+    // Pasted text is not paired
+    type_text(&mut text_input, " (a");
+    assert_eq!(text_input.extract_text_without_last_nl(), "echo $(ls) (a");
+}
+
+#[test]
+fn auto_pair_quotes() {
+    assert_eq!(typing("echo ", None, '"'), Typing::Pair('"'));
+    assert_eq!(typing("echo \"abc", Some('"'), '"'), Typing::TypeOver);
+    assert_eq!(typing("echo \"abc", None, '"'), Typing::Insert);
+    // No pairing inside strings or after a backslash
+    assert_eq!(typing("echo \"a", Some('"'), '('), Typing::Insert);
+    assert_eq!(typing("echo 'a", Some('\''), '"'), Typing::Insert);
+    assert_eq!(typing("echo \\", None, '('), Typing::Insert);
+    // Apostrophes stay alone
+    assert_eq!(typing("echo don", None, '\''), Typing::Insert);
+}
+
+/// Terminfo entry with one capability of each kind
+fn test_term_info() -> TermInfo {
+    let mut term_info = TermInfo {
+        names: vec!["xterm".to_string()],
+        bools: HashMap::new(),
+        numbers: HashMap::new(),
+        strings: HashMap::new(),
+    };
+    let _ = term_info.bools.insert("am", true);
+    let _ = term_info.numbers.insert("colors", 256);
+    let _ = term_info.strings.insert("kcuu1", b"\x1bOA".to_vec());
+    term_info
+}
+
+/// Presenter in compose mode with a window of the given size in characters
+pub fn test_presenter(session: &SharedSession, width: usize, height: usize) -> Presenter {
+    let interpreter = StartupInterpreter::new(session.clone()).complete_startup();
+    let mut presenter = Presenter::new(
+        session.clone(),
+        interpreter,
+        History::new(),
+        test_term_info(),
+        PresenterConfig {
+            compose_variant: ComposeVariant::MarkovBelow,
+            ..PresenterConfig::default()
+        },
+    )
+    .unwrap();
+    presenter.event_window_resize(width, height);
+    presenter
+}
+
+#[test]
+fn xtgettcap_replies() {
+    let term_info = test_term_info();
+
+    // colors = 256
+    assert_eq!(
+        xtgettcap::reply(&term_info, "636F6C6F7273"),
+        b"\x1bP1+r636F6C6F7273=323536\x1b\\".to_vec()
+    );
+    assert_eq!(
+        xtgettcap::reply(&term_info, "6B63757531"),
+        b"\x1bP1+r6B63757531=1B4F41\x1b\\".to_vec()
+    );
+    assert_eq!(
+        xtgettcap::reply(&term_info, "616D"),
+        b"\x1bP1+r616D\x1b\\".to_vec()
+    );
+
+    // Unknown capabilities and invalid names are rejected
+    assert_eq!(
+        xtgettcap::reply(&term_info, "78797A"),
+        b"\x1bP0+r78797A\x1b\\".to_vec()
+    );
+    assert_eq!(
+        xtgettcap::reply(&term_info, "7"),
+        b"\x1bP0+r7\x1b\\".to_vec()
+    );
+
+    // Several capabilities in one query
+    assert_eq!(
+        xtgettcap::replies(&term_info, "616D;78797A"),
+        b"\x1bP1+r616D\x1b\\\x1bP0+r78797A\x1b\\".to_vec()
+    );
+}
+
+#[test]
+fn background_command_gets_capability_replies() {
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"fzf &"));
+    let (program_side, bite_side) = pipe().unwrap();
+    session.set_job(handle, Some(Job::with_stdin(bite_side)));
+    session.add_bytes(OutputVisibility::Output, handle, b"\x1bP+q616D\x1b\\");
+
+    // The command isn't shown, but still gets its answer
+    let mut presenter = test_presenter(&session, 80, 24);
+    presenter.prepare_cycle();
+    assert_eq!(presenter.sp_type, SubPresenterType::ComposeCommandPresenter);
+    let mut received = [0xff; 16];
+    assert_eq!(read(program_side, &mut received), Ok(11));
+    assert_eq!(&received[..11], b"\x1bP1+r616D\x1b\\");
+
+    session.set_job(handle, None);
+    close(bite_side).unwrap();
+    close(program_side).unwrap();
+    presenter.finish().0.shutdown();
+}
+
+#[test]
+fn status_line_shows_busy_command() {
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"sleep 10"));
+    let session = session.0.lock().unwrap();
+
+    assert_eq!(
+        status_line::busy_status(&session, Some(handle), 0),
+        Some("| Running: sleep 10".to_string())
+    );
+    assert_eq!(
+        status_line::busy_status(&session, Some(handle), 1),
+        Some("/ Running: sleep 10".to_string())
+    );
+    assert_eq!(status_line::busy_status(&session, None, 0), None);
+}
+
+/// Text of the first and last visible line
+fn visible_texts(session: &Session, range: &VisibleRange) -> (String, String) {
+    let text = |loc: &SessionLocator| {
+        c2s(session
+            .display_line(loc)
+            .expect("display_line should work")
+            .text)
+    };
+    (text(&range.first), text(&range.last))
+}
+
+#[test]
+fn visible_range_after_scrolling() {
+    let mut session = new_test_session(b"prompt 1");
+    let inter_1_1 = session.add_interaction(Screen::one_line_matrix(b"command 1.1"));
+    session.add_bytes(
+        OutputVisibility::Output,
+        inter_1_1,
+        b"output 1.1.1\noutput 1.1.2\n",
+    );
+    session.new_conversation(Screen::one_line_matrix(b"prompt 2"));
+
+    let session = session.0.lock().unwrap();
+    assert_eq!(PresenterCommons::count_lines(&session, true), 5);
+
+    // Not scrolled: The end of the session is visible
+    let range = PresenterCommons::locate_visible(&session, None, true, 3)
+        .expect("there should be visible lines");
+    assert_eq!(range.total_lines, 5);
+    assert_eq!(
+        visible_texts(&session, &range),
+        ("output 1.1.2".to_string(), "prompt 2".to_string())
+    );
+
+    // Scrolled up by 2 lines, as done by PresenterCommons::scroll_up
+    let end = PresenterCommons::locate_end(&session, true).expect("session should have an end");
+    let end_line = PresenterCommons::locate_up(&session, &end, 2);
+    let range = PresenterCommons::locate_visible(&session, end_line, true, 3)
+        .expect("there should be visible lines");
+    assert_eq!(
+        visible_texts(&session, &range),
+        ("command 1.1".to_string(), "output 1.1.2".to_string())
+    );
+
+    // A window larger than the session starts at the first line
+    let range = PresenterCommons::locate_visible(&session, None, true, 100)
+        .expect("there should be visible lines");
+    assert_eq!(
+        visible_texts(&session, &range),
+        ("command 1.1".to_string(), "prompt 2".to_string())
+    );
+
+    assert_eq!(
+        PresenterCommons::locate_visible(&session, None, true, 0),
+        None
+    );
+}
+
+#[test]
+fn spinner_next_frame_time() {
+    use std::time::{Duration, UNIX_EPOCH};
+    let at = |ms| UNIX_EPOCH + Duration::from_millis(ms);
+
+    assert_eq!(status_line::next_frame_time(at(1000)), at(1250));
+    assert_eq!(status_line::next_frame_time(at(1100)), at(1250));
+    assert_eq!(status_line::next_frame_time(at(1249)), at(1250));
+    assert_ne!(
+        status_line::spinner_frame(at(1249)),
+        status_line::spinner_frame(at(1250))
+    );
+}
+
+#[test]
+fn idle_presenter_has_no_deadline() {
+    let session = new_test_session(b"prompt");
+    let mut presenter = test_presenter(&session, 80, 24);
+    presenter.prepare_cycle();
+    assert_eq!(
+        presenter.animation_deadline(std::time::SystemTime::now()),
+        None
+    );
+
+    presenter.finish().0.shutdown();
+}
+
+#[test]
+fn alt_key_encoding() {
+    assert_eq!(tui::alt_key(true, b'a'), b"\x1ba".to_vec());
+    assert_eq!(tui::alt_key(true, b'A'), b"\x1bA".to_vec());
+    assert_eq!(tui::alt_key(false, b'a'), vec![0xe1]);
+    assert_eq!(tui::alt_key(false, b'1'), vec![0xb1]);
+}
+
+#[test]
+fn clock_shows_supplied_time() {
+    use std::time::{Duration, UNIX_EPOCH};
+    let at = |ms| UNIX_EPOCH + Duration::from_millis(ms);
+
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_hour = 13;
+    tm.tm_min = 5;
+    tm.tm_sec = 9;
+    assert_eq!(status_line::clock("%H:%M:%S", &tm), "13:05:09");
+    assert_eq!(status_line::clock("%H:%M", &tm), "13:05");
+    assert_eq!(status_line::clock("", &tm), "");
+
+    // The clock is refreshed on the next full second
+    assert_eq!(status_line::next_second(at(1000)), at(2000));
+    assert_eq!(status_line::next_second(at(1999)), at(2000));
+
+    // The clock goes to the right edge if there is room
+    assert_eq!(
+        status_line::right_aligned("| Running", "13:05", 16),
+        "| Running  13:05"
+    );
+    assert_eq!(
+        status_line::right_aligned("| Running", "13:05", 15),
+        "| Running 13:05"
+    );
+    assert_eq!(
+        status_line::right_aligned("| Running", "13:05", 14),
+        "| Running"
+    );
+    // Wide characters take two columns
+    assert_eq!(
+        status_line::right_aligned("| 中", "13:05", 11),
+        "| 中  13:05"
+    );
+}
+
+/// Fixed point in time for the clock tests
+fn fixed_time() -> std::time::SystemTime {
+    std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000)
+}
+
+#[test]
+fn clock_uses_time_source() {
+    let session = new_test_session(b"prompt");
+    let interpreter = StartupInterpreter::new(session.clone()).complete_startup();
+    let mut presenter = Presenter::new(
+        session.clone(),
+        interpreter,
+        History::new(),
+        test_term_info(),
+        PresenterConfig {
+            compose_variant: ComposeVariant::MarkovBelow,
+            clock_format: Some("%H:%M".to_string()),
+            time_source: fixed_time,
+            ..PresenterConfig::default()
+        },
+    )
+    .unwrap();
+    presenter.event_window_resize(80, 24);
+    presenter.prepare_cycle();
+
+    let expected = status_line::clock("%H:%M", &status_line::local_time(fixed_time()));
+    let lines = CollectLines(RefCell::new(Vec::new()));
+    presenter.display_lines(&lines);
+    assert!(lines
+        .0
+        .into_inner()
+        .iter()
+        .any(|l| l.trim_end().ends_with(&expected)));
+
+    // A clock without seconds wakes up once a minute, not every second
+    let deadline = presenter.animation_deadline(fixed_time()).unwrap();
+    assert_ne!(
+        status_line::clock("%H:%M", &status_line::local_time(deadline)),
+        expected
+    );
+    assert!(deadline > status_line::next_second(fixed_time()));
+
+    // The clock stands still while the window doesn't have the focus
+    presenter.event_focus_lost();
+    assert_eq!(presenter.animation_deadline(fixed_time()), None);
+    presenter.event_focus_gained();
+    assert_eq!(presenter.animation_deadline(fixed_time()), Some(deadline));
+
+    presenter.finish().0.shutdown();
+}
+
+/// Check if the script would be continued by Enter
+fn is_incomplete(script: &str) -> bool {
+    match parse_script(&format!("{}\n", script)) {
+        Err(ScriptError::Incomplete) => true,
+        _ => false,
+    }
+}
+
+#[test]
+fn enter_on_incomplete_script() {
+    let first = "if true; then";
+    let second = "if true; then\necho hi";
+    let blank = "if true; then\necho hi\n";
+    assert!(is_incomplete(first));
+    assert!(is_incomplete(second));
+    assert!(is_incomplete(blank));
+
+    // Incomplete script -> Continue in the next line
+    let mode = IncompleteEnter::RunOnBlankLine;
+    assert!(mode.continues(first));
+    assert!(mode.continues(second));
+    // Enter again on the blank continuation line -> Run and report the error
+    assert!(!mode.continues(blank));
+    assert!(!mode.continues("if true; then\n   "));
+
+    assert!(IncompleteEnter::Continue.continues(blank));
+    assert!(!IncompleteEnter::Run.continues(first));
+
+    assert_eq!(
+        ScriptError::Incomplete.to_string(),
+        "Syntax error: unexpected end of script\n"
+    );
+}
+
+/// Presenter commons with a running interpreter
+fn test_commons(session: &SharedSession) -> Box<PresenterCommons> {
+    test_commons_with(session, &PresenterConfig::default())
+}
+
+/// Presenter commons with a running interpreter and the given configuration
+fn test_commons_with(session: &SharedSession, config: &PresenterConfig) -> Box<PresenterCommons> {
+    let interpreter = StartupInterpreter::new(session.clone()).complete_startup();
+    Box::new(
+        PresenterCommons::new(
+            session.clone(),
+            interpreter,
+            History::new(),
+            test_term_info(),
+            config,
+        )
+        .unwrap(),
+    )
+}
+
+#[test]
+fn enter_on_incomplete_input() {
+    let no_mod = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: false,
+    };
+    for variant in [
+        ComposeVariant::MarkovBelow,
+        ComposeVariant::BubbleAbove,
+        ComposeVariant::BubbleExclusive,
+        ComposeVariant::LiveParse,
+    ]
+    .iter()
+    {
+        // Incomplete -> continue, Enter on the blank continuation line runs and shows the error
+        let session = new_test_session(b"prompt");
+        let mut sp = variant.new_subpresenter(test_commons_with(
+            &session,
+            &PresenterConfig {
+                incomplete_enter: IncompleteEnter::RunOnBlankLine,
+                ..PresenterConfig::default()
+            },
+        ));
+        sp.event_text("if true; then");
+        sp.event_special_key(&no_mod, &SpecialKey::Enter);
+        assert!(sp.commons().continue_input, "{:?}", variant);
+        assert!(session.last_interaction().is_none(), "{:?}", variant);
+        sp.event_special_key(&no_mod, &SpecialKey::Enter);
+        assert!(!sp.commons().continue_input, "{:?}", variant);
+        let handle = session
+            .last_interaction()
+            .expect("the blank line should run the input");
+        assert_eq!(session.exit_status(handle), Some(1), "{:?}", variant);
+        assert_eq!(
+            session.response_texts(handle).1,
+            "Syntax error: unexpected end of script\n",
+            "{:?}",
+            variant
+        );
+        sp.finish().interpreter.shutdown();
+
+        // Run right away
+        let session = new_test_session(b"prompt");
+        let mut sp = variant.new_subpresenter(test_commons_with(
+            &session,
+            &PresenterConfig {
+                incomplete_enter: IncompleteEnter::Run,
+                ..PresenterConfig::default()
+            },
+        ));
+        sp.event_text("if true; then");
+        sp.event_special_key(&no_mod, &SpecialKey::Enter);
+        assert!(!sp.commons().continue_input, "{:?}", variant);
+        let handle = session
+            .last_interaction()
+            .expect("Enter should run the input");
+        assert_eq!(session.exit_status(handle), Some(1), "{:?}", variant);
+        sp.finish().interpreter.shutdown();
+    }
+}
+
+#[test]
+fn ctrl_c_aborts_input() {
+    let no_mod = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: false,
+    };
+    let ctrl = ModifierState {
+        shift_pressed: false,
+        control_pressed: true,
+        meta_pressed: false,
+    };
+    for variant in [
+        ComposeVariant::MarkovBelow,
+        ComposeVariant::BubbleAbove,
+        ComposeVariant::BubbleExclusive,
+        ComposeVariant::LiveParse,
+    ]
+    .iter()
+    {
+        let session = new_test_session(b"prompt");
+        let mut sp = variant.new_subpresenter(test_commons(&session));
+
+        // Start a multi-line command
+        sp.event_text("if true; then");
+        sp.event_special_key(&no_mod, &SpecialKey::Enter);
+        sp.event_text("echo hi");
+
+        assert_eq!(sp.event_normal_key(&ctrl, b'c'), PresenterCommand::Redraw);
+        assert_eq!(sp.commons().text_input.extract_text_without_last_nl(), "");
+        assert!(!sp.commons().continue_input);
+
+        let handle = session
+            .last_interaction()
+            .expect("Ctrl-C should add an interaction");
+        let command = session.0.lock().unwrap().command_text(handle).unwrap();
+        assert!(command.starts_with("if true; then"), "{:?}", variant);
+        assert!(command.ends_with("echo hi^C"), "{:?}", variant);
+        assert_eq!(session.exit_status(handle), Some(130));
+
+        sp.finish().interpreter.shutdown();
+    }
+}
+
+#[test]
+fn compose_continues_incomplete_script() {
+    let no_mod = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: false,
+    };
+    for variant in [
+        ComposeVariant::MarkovBelow,
+        ComposeVariant::BubbleAbove,
+        ComposeVariant::BubbleExclusive,
+        ComposeVariant::LiveParse,
+    ]
+    .iter()
+    {
+        let session = new_test_session(b"prompt");
+        let mut sp = variant.new_subpresenter(test_commons(&session));
+
+        // An unterminated for loop keeps accepting lines
+        sp.event_text("for a in x y; do");
+        sp.event_special_key(&no_mod, &SpecialKey::Enter);
+        assert!(sp.commons().continue_input, "{:?}", variant);
+        sp.event_text("echo $a");
+        sp.event_special_key(&no_mod, &SpecialKey::Enter);
+        assert!(sp.commons().continue_input, "{:?}", variant);
+        assert!(session.last_interaction().is_none(), "{:?}", variant);
+
+        // Once closed, the script runs
+        sp.event_text("done");
+        sp.event_special_key(&no_mod, &SpecialKey::Enter);
+        assert!(!sp.commons().continue_input, "{:?}", variant);
+        let handle = session
+            .last_interaction()
+            .expect("the closed loop should run");
+        let command = session.0.lock().unwrap().command_text(handle).unwrap();
+        assert!(command.starts_with("for a in x y; do"), "{:?}", variant);
+        assert!(command.ends_with("done"), "{:?}", variant);
+        while session.exit_status(handle).is_none() || session.has_feeding_threads(handle) {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(session.response_texts(handle).0, "x\ny\n", "{:?}", variant);
+
+        sp.finish().interpreter.shutdown();
+    }
+}
+
+#[test]
+fn live_parse_yank_pop_needs_yanked_text() {
+    let ctrl = ModifierState {
+        shift_pressed: false,
+        control_pressed: true,
+        meta_pressed: false,
+    };
+    let meta = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: true,
+    };
+    let session = new_test_session(b"prompt");
+    let mut sp = ComposeVariant::LiveParse.new_subpresenter(test_commons(&session));
+    sp.event_text("first");
+    sp.event_normal_key(&ctrl, b'u');
+    sp.event_text("second");
+    sp.event_normal_key(&ctrl, b'u');
+
+    sp.event_normal_key(&ctrl, b'y');
+    sp.event_normal_key(&meta, b'y');
+    assert_eq!(sp.commons().editor.as_string(), "first");
+
+    // The cursor left the yanked text, so there is nothing to replace
+    sp.event_normal_key(&ctrl, b'a');
+    sp.event_normal_key(&meta, b'y');
+    assert_eq!(sp.commons().editor.as_string(), "first");
+    assert_eq!(sp.commons().kill_ring.last_yanked(), None);
+
+    sp.finish().interpreter.shutdown();
+}
+
+#[test]
+fn click_ends_yank() {
+    let session = new_test_session(b"prompt");
+    let mut presenter = test_presenter(&session, 40, 5);
+    let no_mod = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: false,
+    };
+    presenter.cm().kill_ring.kill("text".to_string());
+    presenter.cm().kill_ring.start_yank();
+    presenter.event_button_down(no_mod, 1, 3, 4);
+    assert_eq!(presenter.c().kill_ring.last_yanked(), None);
+
+    presenter.finish().0.shutdown();
+}
+
+/// Collect the text of the drawn lines
+struct CollectLines(RefCell<Vec<String>>);
+
+impl DrawLineTrait for CollectLines {
+    fn draw_line(&self, _row: usize, line: &DisplayLine) {
+        self.0.borrow_mut().push(c2s(line.line.clone()));
+    }
+}
+
+/// Collect the rows of the drawn lines and whether they show a cursor
+struct CollectRows(RefCell<Vec<(usize, bool)>>);
+
+impl DrawLineTrait for CollectRows {
+    fn draw_line(&self, row: usize, line: &DisplayLine) {
+        self.0.borrow_mut().push((row, line.cursor_col.is_some()));
+    }
+}
+
+#[test]
+fn cursor_repaint_draws_only_cursor_rows() {
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"command"));
+    session.add_bytes(OutputVisibility::Output, handle, b"output 1\noutput 2\n");
+    let mut presenter = test_presenter(&session, 80, 24);
+    presenter.event_text("ls");
+
+    // Nothing has been displayed yet, so there is no cursor to draw
+    let rows = CollectRows(RefCell::new(Vec::new()));
+    presenter.display_cursor_lines(&rows);
+    assert!(rows.0.into_inner().is_empty());
+
+    let rows = CollectRows(RefCell::new(Vec::new()));
+    presenter.display_lines(&rows);
+    let all_rows = rows.0.into_inner();
+    assert!(all_rows.len() > 3);
+    let cursor_row = all_rows
+        .iter()
+        .find(|(_, cursor)| *cursor)
+        .expect("the input should show the cursor")
+        .0;
+
+    // Only the cursor row and the one below, which the cursor reaches into, are drawn again
+    let rows = CollectRows(RefCell::new(Vec::new()));
+    presenter.display_cursor_lines(&rows);
+    let expected: Vec<(usize, bool)> = all_rows
+        .into_iter()
+        .filter(|(row, _)| *row == cursor_row || *row == cursor_row + 1)
+        .collect();
+    assert_eq!(rows.0.into_inner(), expected);
+
+    presenter.finish().0.shutdown();
+}
+
+/// Draw the presenter and return the text of the last line
+fn draw_last_line(presenter: &Presenter) -> String {
+    let lines = CollectLines(RefCell::new(Vec::new()));
+    presenter.display_lines(&lines);
+    let lines = lines.0.into_inner();
+    lines.last().cloned().unwrap_or_default()
+}
+
+#[test]
+fn shift_ctrl_up_down_scroll_to_commands() {
+    let mut session = new_test_session(b"prompt");
+    let shell = session.add_interaction(Screen::one_line_matrix(b"ssh host"));
+    let mut output = b"\x1b]133;A\x07$ ls\n\x1b]133;C\x07".to_vec();
+    for i in 0..20 {
+        output.extend_from_slice(format!("file {}\n", i).as_bytes());
+    }
+    output.extend_from_slice(b"\x1b]133;A\x07$ exit\n");
+    session.add_bytes(OutputVisibility::Output, shell, &output);
+    session.set_running_status(shell, RunningStatus::Exited(0));
+    let mut presenter = test_presenter(&session, 40, 10);
+    let shift_ctrl = ModifierState {
+        shift_pressed: true,
+        control_pressed: true,
+        meta_pressed: false,
+    };
+    let shows = |presenter: &Presenter, text: &str| {
+        let lines = CollectLines(RefCell::new(Vec::new()));
+        presenter.display_lines(&lines);
+        let shown = lines
+            .0
+            .into_inner()
+            .iter()
+            .any(|l| l.trim_end().ends_with(text));
+        shown
+    };
+    assert!(!shows(&presenter, "file 0"));
+
+    // Up to the output of ls, the prompt of the shell and the command of BiTE
+    for expected in ["file 0", "$ ls", "ssh host"].iter() {
+        assert_eq!(
+            presenter.event_special_key(&shift_ctrl, &SpecialKey::Up),
+            PresenterCommand::Redraw
+        );
+        assert!(shows(&presenter, expected));
+    }
+    assert_eq!(
+        presenter.event_special_key(&shift_ctrl, &SpecialKey::Up),
+        PresenterCommand::Ignored
+    );
+
+    // And down again
+    assert_eq!(
+        presenter.event_special_key(&shift_ctrl, &SpecialKey::Down),
+        PresenterCommand::Redraw
+    );
+    assert!(shows(&presenter, "$ ls"));
+    assert!(!shows(&presenter, "ssh host"));
+
+    presenter.finish().0.shutdown();
+}
+
+#[test]
+fn unseen_output_badge() {
+    let mut session = new_test_session(b"prompt");
+    let mut presenter = test_presenter(&session, 40, 5);
+
+    let background = session.add_interaction(Screen::one_line_matrix(b"background &"));
+    let long = session.add_interaction(Screen::one_line_matrix(b"long"));
+    session.add_bytes(OutputVisibility::Output, long, b"1\n2\n3\n4\n5\n6\n7\n8\n");
+    assert_eq!(session.unseen_count(), 1);
+    assert_eq!(draw_last_line(&presenter).trim_end(), "");
+    assert_eq!(session.unseen_count(), 0);
+
+    // Output of the background job is above the view and the view stays where it is
+    session.add_bytes(OutputVisibility::Output, background, b"done\n");
+    assert_eq!(session.unseen_count(), 1);
+    assert!(draw_last_line(&presenter).ends_with("[1 new]"));
+    assert_eq!(session.unseen_count(), 1);
+
+    // Scrolling up to the output marks it as seen
+    let no_mod = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: false,
+    };
+    for _ in 0..20 {
+        presenter.event_scroll_up(&no_mod);
+    }
+    assert_eq!(draw_last_line(&presenter).trim_end(), "");
+    assert_eq!(session.unseen_count(), 0);
+
+    presenter.finish().0.shutdown();
+}
+
+#[test]
+fn alt_p_toggles_private_mode() {
+    let session = new_test_session(b"prompt");
+    let mut presenter = test_presenter(&session, 40, 5);
+    let alt = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: true,
+    };
+
+    assert!(!draw_last_line(&presenter).ends_with(status_line::PRIVATE_BADGE));
+    assert_eq!(
+        presenter.event_normal_key(&alt, b'p'),
+        PresenterCommand::Redraw
+    );
+    assert!(presenter.c().history.is_private());
+    assert!(draw_last_line(&presenter).ends_with(status_line::PRIVATE_BADGE));
+
+    assert_eq!(
+        presenter.event_normal_key(&alt, b'p'),
+        PresenterCommand::Redraw
+    );
+    assert!(!presenter.c().history.is_private());
+    assert!(!draw_last_line(&presenter).ends_with(status_line::PRIVATE_BADGE));
+
+    presenter.finish().0.shutdown();
+}
+
+#[test]
+fn confirm_close_while_jobs_run() {
+    let mut session = new_test_session(b"prompt");
+    let mut presenter = test_presenter(&session, 60, 5);
+    assert!(!presenter.has_running_jobs());
+    assert_eq!(presenter.event_close(true), PresenterCommand::Exit);
+
+    let build = session.add_interaction(Screen::one_line_matrix(b"make"));
+    assert!(session.has_running_interactions());
+    assert!(presenter.has_running_jobs());
+
+    // Without confirmation, the window closes at once
+    assert_eq!(presenter.event_close(false), PresenterCommand::Exit);
+
+    // Any key other than y keeps the window open
+    assert_eq!(presenter.event_close(true), PresenterCommand::Redraw);
+    assert!(draw_last_line(&presenter).starts_with(status_line::CLOSE_QUESTION));
+    assert_eq!(presenter.event_text("n"), PresenterCommand::Redraw);
+    assert!(!draw_last_line(&presenter).starts_with(status_line::CLOSE_QUESTION));
+
+    // y or a second close closes it
+    assert_eq!(presenter.event_close(true), PresenterCommand::Redraw);
+    assert_eq!(presenter.event_text("y"), PresenterCommand::Exit);
+    assert_eq!(presenter.event_close(true), PresenterCommand::Redraw);
+    assert_eq!(presenter.event_close(true), PresenterCommand::Exit);
+
+    session.set_running_status(build, RunningStatus::Exited(0));
+    assert!(!presenter.has_running_jobs());
+
+    presenter.finish().0.shutdown();
+}
+
+#[test]
+fn ctrl_space_handlers() {
+    let ctrl = ModifierState {
+        shift_pressed: false,
+        control_pressed: true,
+        meta_pressed: false,
+    };
+    let no_mod = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: false,
+    };
+
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"emacs"));
+    session.add_bytes(OutputVisibility::Output, handle, b"output\n");
+
+    // Compose mode: Ctrl-Space cycles the output of the last command
+    let mut sp = ComposeVariant::MarkovBelow.new_subpresenter(test_commons(&session));
+    assert_eq!(
+        sp.event_special_key(&ctrl, &SpecialKey::Space),
+        PresenterCommand::Redraw
+    );
+    assert_eq!(
+        session.get_visibility(handle),
+        Some(OutputVisibility::Error)
+    );
+    // A plain space is text
+    assert_eq!(
+        sp.event_special_key(&no_mod, &SpecialKey::Space),
+        PresenterCommand::Unknown
+    );
+
+    // TUI mode: Ctrl-Space is sent to the program
+    let (program_side, bite_side) = pipe().unwrap();
+    session.set_job(handle, Some(Job::with_stdin(bite_side)));
+    let mut sp = TuiExecuteCommandPresenter::new(sp.finish(), handle);
+    assert_eq!(
+        sp.event_special_key(&ctrl, &SpecialKey::Space),
+        PresenterCommand::Redraw
+    );
+    assert_eq!(
+        sp.event_special_key(&no_mod, &SpecialKey::Space),
+        PresenterCommand::Unknown
+    );
+    let mut received = [0xff; 4];
+    assert_eq!(read(program_side, &mut received), Ok(1));
+    assert_eq!(received[0], 0);
+
+    session.set_job(handle, None);
+    close(bite_side).unwrap();
+    close(program_side).unwrap();
+    sp.finish().interpreter.shutdown();
+}
+
+#[test]
+fn focus_change_is_reported_to_program() {
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"vim"));
+    // Full screen program that asks for focus events
+    session.add_bytes(OutputVisibility::Output, handle, b"\x1b[?1049h\x1b[?1004h");
+    assert!(session.is_tui(handle));
+    let (program_side, bite_side) = pipe().unwrap();
+    session.set_job(handle, Some(Job::with_stdin(bite_side)));
+
+    let mut presenter = test_presenter(&session, 80, 24);
+    presenter.focused_interaction = Some(handle);
+    presenter.prepare_cycle();
+    assert_eq!(
+        presenter.sp_type,
+        SubPresenterType::TuiExecuteCommandPresenter(handle)
+    );
+
+    let mut received = [0xff; 8];
+    presenter.event_focus_lost();
+    assert_eq!(read(program_side, &mut received), Ok(3));
+    assert_eq!(&received[..3], b"\x1b[O");
+    presenter.event_focus_gained();
+    assert_eq!(read(program_side, &mut received), Ok(3));
+    assert_eq!(&received[..3], b"\x1b[I");
+
+    session.set_job(handle, None);
+    close(bite_side).unwrap();
+    close(program_side).unwrap();
+    presenter.finish().0.shutdown();
+}
+
+#[test]
+fn tui_backspace_sends_configured_byte() {
+    let no_mod = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: false,
+    };
+    for (sends, expected) in [
+        (BackspaceSends::Delete, b"\x7f"),
+        (BackspaceSends::Backspace, b"\x08"),
+    ]
+    .iter()
+    {
+        let mut session = new_test_session(b"prompt");
+        let handle = session.add_interaction(Screen::one_line_matrix(b"vim"));
+        let (program_side, bite_side) = pipe().unwrap();
+        session.set_job(handle, Some(Job::with_stdin(bite_side)));
+
+        let commons = test_commons_with(
+            &session,
+            &PresenterConfig {
+                backspace_sends: Some(*sends),
+                ..PresenterConfig::default()
+            },
+        );
+        let mut sp = TuiExecuteCommandPresenter::new(commons, handle);
+        sp.event_special_key(&no_mod, &SpecialKey::Backspace);
+        let mut received = [0xff; 4];
+        assert_eq!(read(program_side, &mut received), Ok(1));
+        assert_eq!(&received[..1], &expected[..]);
+
+        session.set_job(handle, None);
+        close(bite_side).unwrap();
+        close(program_side).unwrap();
+        sp.finish().interpreter.shutdown();
+    }
+}
+
+#[test]
+fn tui_follows_private_modes() {
+    let no_mod = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: false,
+    };
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"vim"));
+    let (program_side, bite_side) = pipe().unwrap();
+    session.set_job(handle, Some(Job::with_stdin(bite_side)));
+    let mut sp = TuiExecuteCommandPresenter::new(test_commons(&session), handle);
+    let mut received = [0xff; 4];
+    let has_cursor = |sp: &TuiExecuteCommandPresenter| {
+        let s = session.0.lock().unwrap();
+        sp.single_display_line(&s, 0).unwrap().cursor_col.is_some()
+    };
+
+    // Normal cursor keys and a visible cursor by default
+    sp.event_special_key(&no_mod, &SpecialKey::Up);
+    assert_eq!(read(program_side, &mut received), Ok(3));
+    assert_eq!(&received[..3], b"\x1b[A");
+    assert!(has_cursor(&sp));
+
+    // Application cursor keys (DECCKM) and a hidden cursor (DECTCEM)
+    session.add_bytes(OutputVisibility::Output, handle, b"\x1b[?1h\x1b[?25l");
+    sp.event_special_key(&no_mod, &SpecialKey::Left);
+    assert_eq!(read(program_side, &mut received), Ok(3));
+    assert_eq!(&received[..3], b"\x1bOD");
+    assert!(!has_cursor(&sp));
+
+    session.set_job(handle, None);
+    close(bite_side).unwrap();
+    close(program_side).unwrap();
+    sp.finish().interpreter.shutdown();
+}
+
+#[test]
+fn ctrl_space_sets_mark() {
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"ls"));
+    session.add_bytes(OutputVisibility::Output, handle, b"output\n");
+    let mut presenter = test_presenter(&session, 80, 24);
+    let ctrl = ModifierState {
+        shift_pressed: false,
+        control_pressed: true,
+        meta_pressed: false,
+    };
+    let meta = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: true,
+    };
+    let input = |presenter: &Presenter| presenter.c().text_input.extract_text_without_last_nl();
+
+    // On an empty input, Ctrl-Space still cycles the output of the last command
+    presenter.event_special_key(&ctrl, &SpecialKey::Space);
+    assert_eq!(
+        session.get_visibility(handle),
+        Some(OutputVisibility::Error)
+    );
+
+    // With text, it sets the mark and leaves the output alone
+    presenter.event_text("echo ");
+    assert_eq!(
+        presenter.event_special_key(&ctrl, &SpecialKey::Space),
+        PresenterCommand::Redraw
+    );
+    assert_eq!(
+        session.get_visibility(handle),
+        Some(OutputVisibility::Error)
+    );
+
+    // Alt-W copies from the mark to the cursor, Ctrl-Y pastes it
+    presenter.event_text("hello");
+    assert_eq!(
+        presenter.event_normal_key(&meta, b'w'),
+        PresenterCommand::Redraw
+    );
+    assert_eq!(input(&presenter), "echo hello");
+    presenter.event_normal_key(&ctrl, b'y');
+    assert_eq!(input(&presenter), "echo hellohello");
+
+    // Editing the text before the mark drops it
+    presenter.event_normal_key(&ctrl, b'a');
+    presenter.event_text("x");
+    presenter.event_normal_key(&ctrl, b'e');
+    assert_eq!(input(&presenter), "xecho hellohello");
+    presenter.event_normal_key(&meta, b'w');
+    assert_eq!(presenter.c().mark, None);
+
+    presenter.finish().0.shutdown();
+}
+
+/// Candidates that complete `ec` at position 4
+fn completion_candidates() -> Vec<(usize, String, String)> {
+    vec![
+        (4, "echo".to_string(), "print".to_string()),
+        (4, "ecryptfs".to_string(), "encrypt".to_string()),
+        (4, "ed".to_string(), "edit".to_string()),
+    ]
+}
+
+#[test]
+fn completion_menu_navigation() {
+    let mut menu = CompletionMenu::new(completion_candidates());
+    // The candidate next to the input is selected first
+    assert_eq!(menu.selected(), 2);
+
+    menu.up(1);
+    assert_eq!(menu.current().map(|c| c.1.as_str()), Some("ecryptfs"));
+    menu.up(5);
+    assert_eq!(menu.selected(), 0);
+    menu.down(1);
+    assert_eq!(menu.selected(), 1);
+    menu.down(5);
+    assert_eq!(menu.selected(), 2);
+
+    // Repeated Tab wraps around
+    menu.cycle();
+    assert_eq!(menu.selected(), 0);
+    menu.cycle();
+    assert_eq!(menu.selected(), 1);
+
+    let mut screen = Screen::new();
+    menu.render(&mut screen);
+    let first: String = screen
+        .line_iter()
+        .next()
+        .unwrap()
+        .iter()
+        .map(|c| c.code_point())
+        .collect();
+    assert_eq!(first.trim_end(), "echo -- print");
+}
+
+#[test]
+fn completion_menu_accept() {
+    let mut menu = CompletionMenu::new(completion_candidates());
+    menu.cycle();
+    assert_eq!(
+        menu.accept(),
+        Some((4, "echo".to_string(), "print".to_string()))
+    );
+
+    let empty = CompletionMenu::new(Vec::new());
+    assert_eq!(empty.len(), 0);
+    assert_eq!(empty.current(), None);
+    assert_eq!(empty.accept(), None);
+}
+
+/// Rendered lines of the menu without trailing spaces
+fn menu_lines(menu: &CompletionMenu) -> Vec<String> {
+    let mut screen = Screen::new();
+    menu.render(&mut screen);
+    screen
+        .line_iter()
+        .map(|l| l.iter().map(|c| c.code_point()).collect::<String>())
+        .map(|l| l.trim_end().to_string())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+#[test]
+fn completion_menu_scrolling() {
+    let candidates = (0..10)
+        .map(|i| (0, format!("c{}", i), "h".to_string()))
+        .collect();
+    let mut menu = CompletionMenu::new(candidates);
+    menu.set_height(4);
+
+    // Three candidates and the indicator fit, the selected last one is visible
+    assert_eq!(menu.visible(), 7..10);
+    assert_eq!(menu.hidden(), 7);
+    assert_eq!(menu.selected_row(), 2);
+    assert_eq!(
+        menu_lines(&menu),
+        vec!["c7 -- h", "c8 -- h", "c9 -- h", "7 more"]
+    );
+
+    menu.up(9);
+    assert_eq!(menu.visible(), 0..3);
+    assert_eq!(menu.selected_row(), 0);
+
+    // Scrolling down reveals later candidates
+    menu.down(4);
+    assert_eq!(menu.visible(), 2..5);
+    assert_eq!(menu.selected_row(), 2);
+    assert_eq!(
+        menu_lines(&menu),
+        vec!["c2 -- h", "c3 -- h", "c4 -- h", "7 more"]
+    );
+
+    // Wrapping around jumps back to the top
+    menu.down(9);
+    menu.cycle();
+    assert_eq!(menu.visible(), 0..3);
+
+    // Everything fits without an indicator, even when scrolled to the end before
+    menu.down(9);
+    menu.set_height(20);
+    assert_eq!(menu.visible(), 0..10);
+    assert_eq!(menu.hidden(), 0);
+    assert_eq!(menu_lines(&menu).len(), 10);
+}
+
+/// Candidates that match the word in the given mode
+fn matching_candidates(matching: CompletionMatching, word: &str) -> Vec<&'static str> {
+    ["make", "Makefile", "mkdir", "cmake", "tar"]
+        .iter()
+        .cloned()
+        .filter(|c| matching.matches(word, c))
+        .collect()
+}
+
+#[test]
+fn completion_matching_modes() {
+    assert_eq!(
+        matching_candidates(CompletionMatching::Prefix, "ma"),
+        vec!["make"]
+    );
+    assert_eq!(
+        matching_candidates(CompletionMatching::IgnoreCase, "ma"),
+        vec!["make", "Makefile"]
+    );
+    assert_eq!(
+        matching_candidates(CompletionMatching::Fuzzy, "ma"),
+        vec!["make", "Makefile", "cmake"]
+    );
+    assert_eq!(
+        matching_candidates(CompletionMatching::Fuzzy, "mkd"),
+        vec!["mkdir"]
+    );
+    // An empty word matches everything
+    assert_eq!(matching_candidates(CompletionMatching::Prefix, "").len(), 5);
+
+    assert_eq!(
+        CompletionMatching::Fuzzy.matched_chars("mf", "Makefile"),
+        Some(vec![0, 4])
+    );
+    assert_eq!(
+        CompletionMatching::Fuzzy.highlight("mf", "Makef"),
+        "\x1b[1mM\x1b[22make\x1b[1mf\x1b[22m"
+    );
+    assert_eq!(CompletionMatching::Prefix.highlight("ma", "make"), "make");
+}
+
+#[test]
+fn line_number_gutter() {
+    let text = Screen::one_line_cell_vec(b"output");
+    let item = LineItem::new(&text, LineType::Output, Some(2), 0);
+    let line = DisplayLine::from(item).with_line_number(7, DisplayLine::line_number_digits(120));
+    let content: String = line.line.iter().map(|c| c.code_point()).collect();
+    assert_eq!(content, "  7 output");
+    // The cursor stays on the same character
+    assert_eq!(line.cursor_col, Some(3 + 4 + 2));
+
+    assert_eq!(DisplayLine::line_number_digits(0), 1);
+    assert_eq!(DisplayLine::line_number_digits(9), 1);
+    assert_eq!(DisplayLine::line_number_digits(10), 2);
+}
+
+#[test]
+fn execute_command_numbers_output() {
+    let mut session = new_test_session(b"prompt");
+    let earlier = session.add_interaction(Screen::one_line_matrix(b"earlier"));
+    session.add_bytes(OutputVisibility::Output, earlier, b"old\n");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"seq 10"));
+    session.add_bytes(
+        OutputVisibility::Output,
+        handle,
+        b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n",
+    );
+    let mut commons = test_commons(&session);
+    commons.window_width = 40;
+    commons.window_height = 20;
+    let mut sp = ExecuteCommandPresenter::new(commons, handle);
+    let lines = |sp: &ExecuteCommandPresenter| -> Vec<String> {
+        let s = session.0.lock().unwrap();
+        (0..20)
+            .filter_map(|y| sp.single_display_line(&s, y))
+            .map(|l| c2s(l.line))
+            .collect()
+    };
+    let meta = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: true,
+    };
+
+    // No numbers by default
+    let plain = lines(&sp);
+    assert!(plain.contains(&"1".to_string()));
+    assert!(plain.contains(&"10".to_string()));
+
+    // Alt-N shows them, only for the output of the running command
+    assert_eq!(sp.event_normal_key(&meta, b'n'), PresenterCommand::Redraw);
+    let numbered = lines(&sp);
+    assert!(numbered.contains(&" 1 1".to_string()));
+    assert!(numbered.contains(&"10 10".to_string()));
+    assert!(numbered.contains(&"old".to_string()));
+    assert!(numbered.contains(&"seq 10".to_string()));
+
+    // Alt-N again hides them
+    assert_eq!(sp.event_normal_key(&meta, b'n'), PresenterCommand::Redraw);
+    assert_eq!(lines(&sp), plain);
+
+    sp.finish().interpreter.shutdown();
+}
+
+#[test]
+fn click_to_buffer_position() {
+    // "if true\nthen ls\nfi" rendered as three spans, the second one spans two rows and starts
+    // one column right due to the style.
+    let text: Vec<char> = "if true\nthen ls\nfi".chars().collect();
+    let map = vec![
+        CursorMapping {
+            position: 0,
+            x: 0,
+            y: 0,
+        },
+        CursorMapping {
+            position: 3,
+            x: 4,
+            y: 0,
+        },
+        CursorMapping {
+            position: 16,
+            x: 0,
+            y: 2,
+        },
+    ];
+    let at = |x, y| CursorMapping::buffer_position(&map, &text, x, y);
+
+    assert_eq!(at(0, 0), Some(0));
+    assert_eq!(at(1, 0), Some(1));
+    // The gap before the styled span
+    assert_eq!(at(3, 0), Some(3));
+    assert_eq!(at(5, 0), Some(4));
+    // Right of the first row's end
+    assert_eq!(at(40, 0), Some(7));
+    // The wrapped part of the second span
+    assert_eq!(at(0, 1), Some(8));
+    assert_eq!(at(5, 1), Some(13));
+    assert_eq!(at(40, 1), Some(15));
+    assert_eq!(at(1, 2), Some(17));
+    assert_eq!(at(40, 2), Some(18));
+    assert_eq!(at(0, 3), None);
+
+    assert_eq!(CursorMapping::buffer_position(&[], &[], 5, 0), Some(0));
+
+    // Wide characters take two columns
+    let text: Vec<char> = "a中b".chars().collect();
+    let map = vec![CursorMapping {
+        position: 0,
+        x: 0,
+        y: 0,
+    }];
+    let at = |x, y| CursorMapping::buffer_position(&map, &text, x, y);
+    assert_eq!(at(1, 0), Some(1));
+    assert_eq!(at(3, 0), Some(2));
+    assert_eq!(at(4, 0), Some(3));
+}
+
+#[test]
+fn long_input_scrolls_horizontally() {
+    let session = new_test_session(b"prompt");
+    let mut commons = test_commons(&session);
+    // Ten columns for the input
+    commons.window_width = 13;
+    let mut sp = ComposeVariant::MarkovBelow.new_subpresenter(commons);
+    sp.event_text("0123456789abcdef");
+
+    {
+        let commons = sp.commons();
+        let locked = session.0.lock().unwrap();
+        let cells = commons.text_input.line_iter().next().unwrap();
+        let cursor_col = Some(commons.text_input.cursor_x() as usize);
+
+        // The end with the cursor is visible
+        let item = commons.input_line_item(&locked, 0, cells, cursor_col);
+        assert_eq!(c2s(item.text), "789abcdef");
+        assert_eq!(item.cursor_col, Some(9));
+
+        // Short enough or with the cursor at the start, nothing is scrolled
+        let item = commons.input_line_item(&locked, 0, cells, Some(0));
+        assert_eq!(c2s(item.text), "0123456789abcdef");
+        assert_eq!(item.cursor_col, Some(0));
+        let item = commons.input_line_item(&locked, 0, cells, Some(9));
+        assert_eq!(item.cursor_col, Some(9));
+        let item = commons.input_line_item(&locked, 0, cells, Some(12));
+        assert_eq!(c2s(item.text), "3456789abcdef");
+        assert_eq!(item.cursor_col, Some(9));
+    }
+
+    sp.finish().interpreter.shutdown();
+}
+
+#[test]
+fn click_into_scrolled_input() {
+    let session = new_test_session(b"prompt");
+    let mut commons = test_commons(&session);
+    // Ten columns for the input
+    commons.window_width = 13;
+    commons.window_height = 10;
+    let mut sp = ComposeVariant::LiveParse.new_subpresenter(commons);
+    sp.event_text("0123456789abcdef");
+
+    // The input row shows "789abcdef" with the cursor at its end
+    let row = {
+        let locked = session.0.lock().unwrap();
+        (0..10)
+            .find(|y| {
+                sp.single_display_line(&locked, *y)
+                    .map_or(false, |l| l.cursor_col.is_some())
+            })
+            .expect("the input should be shown")
+    };
+    assert!(sp.handle_click(1, 3, row) == NeedRedraw::Yes);
+    assert_eq!(sp.commons().editor.cursor(), 7);
+    assert_eq!(sp.commons().input_column(2, 0), None);
+
+    sp.finish().interpreter.shutdown();
+}
+
+#[test]
+fn horizontal_wheel_scrolls_output() {
+    let mut session = new_test_session(b"prompt");
+    let handle = session.add_interaction(Screen::one_line_matrix(b"command"));
+    session.add_bytes(OutputVisibility::Output, handle, b"0123456789abcdefghij\n");
+    let mut commons = test_commons(&session);
+    // The output line with its prefix is ten columns wider than the window
+    commons.window_width = 13;
+    commons.window_height = 10;
+    let mut sp = ComposeVariant::MarkovBelow.new_subpresenter(commons);
+    let no_mod = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: false,
+    };
+    let ctrl = ModifierState {
+        shift_pressed: false,
+        control_pressed: true,
+        meta_pressed: false,
+    };
+
+    assert_eq!(sp.event_scroll_right(&no_mod), PresenterCommand::Redraw);
+    assert_eq!(sp.event_scroll_right(&no_mod), PresenterCommand::Redraw);
+    assert_eq!(sp.event_scroll_right(&no_mod), PresenterCommand::Redraw);
+    assert_eq!(sp.commons().session_column, 3);
+    assert_eq!(sp.event_scroll_left(&no_mod), PresenterCommand::Redraw);
+    assert_eq!(sp.commons().session_column, 2);
+    assert_eq!(sp.event_scroll_left(&ctrl), PresenterCommand::Unknown);
+    assert_eq!(sp.commons().session_column, 2);
+
+    {
+        let commons = sp.commons();
+        let cells = Screen::one_line_cell_vec(b"0123456789");
+
+        // Output is shifted, commands stay in place
+        let item = commons.shift_session_line(LineItem::new(&cells, LineType::Output, None, 0));
+        assert_eq!(c2s(item.text), "23456789");
+        let item = commons.shift_session_line(LineItem::new(
+            &cells,
+            LineType::Command(
+                OutputVisibility::Output,
+                InteractionHandle::INVALID,
+                RunningStatus::Running,
+            ),
+            None,
+            0,
+        ));
+        assert_eq!(c2s(item.text), "0123456789");
+    }
+
+    // Can't scroll past the start of the lines
+    sp.event_scroll_left(&no_mod);
+    sp.event_scroll_left(&no_mod);
+    sp.event_scroll_left(&no_mod);
+    assert_eq!(sp.commons().session_column, 0);
+
+    // Can't scroll past the end of the widest line
+    for _ in 0..20 {
+        sp.event_scroll_right(&no_mod);
+    }
+    assert_eq!(sp.commons().session_column, 10);
+
+    sp.finish().interpreter.shutdown();
+}
+
+#[test]
+fn numeric_argument_repeats_edits() {
+    let session = new_test_session(b"prompt");
+    let mut presenter = test_presenter(&session, 80, 24);
+    let no_mod = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: false,
+    };
+    let meta = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: true,
+    };
+    let input = |presenter: &Presenter| presenter.c().text_input.extract_text_without_last_nl();
+
+    presenter.event_text("abcdefgh");
+    presenter.event_special_key(&no_mod, &SpecialKey::Home);
+
+    // Alt-3 Delete deletes three characters
+    assert_eq!(
+        presenter.event_normal_key(&meta, b'3'),
+        PresenterCommand::Ignored
+    );
+    assert_eq!(input(&presenter), "abcdefgh");
+    assert_eq!(
+        presenter.event_special_key(&no_mod, &SpecialKey::Delete),
+        PresenterCommand::Redraw
+    );
+    assert_eq!(input(&presenter), "defgh");
+
+    // The argument is used only once
+    presenter.event_special_key(&no_mod, &SpecialKey::Delete);
+    assert_eq!(input(&presenter), "efgh");
+
+    // Several digits form one number, moving by 12 stops at the end
+    presenter.event_normal_key(&meta, b'1');
+    presenter.event_normal_key(&meta, b'2');
+    presenter.event_special_key(&no_mod, &SpecialKey::Right);
+    assert!(presenter.c().text_input.cursor_at_end());
+
+    // Typed text drops the argument
+    presenter.event_special_key(&no_mod, &SpecialKey::Home);
+    presenter.event_normal_key(&meta, b'2');
+    presenter.event_text("x");
+    presenter.event_special_key(&no_mod, &SpecialKey::Delete);
+    assert_eq!(input(&presenter), "xfgh");
+
+    presenter.finish().0.shutdown();
+}
+
+#[test]
+fn prompt_dir_abbreviation() {
+    let home = Path::new("/home/user");
+    let dir = |d: &str, depth: Option<usize>| prompt_dir(Path::new(d), home, depth);
+
+    // Home is collapsed to ~
+    assert_eq!(dir("/home/user", None), "~");
+    assert_eq!(dir("/home/user/src/bite", None), "~/src/bite");
+    assert_eq!(dir("/home/username", None), "/home/username");
+    assert_eq!(dir("/usr/share/doc", None), "/usr/share/doc");
+
+    // Only the last components are shown
+    assert_eq!(dir("/usr/share/doc/bite", Some(2)), "…/doc/bite");
+    assert_eq!(dir("/home/user/src/bite/src", Some(2)), "…/bite/src");
+    assert_eq!(dir("/usr/share", Some(2)), "/usr/share");
+    assert_eq!(dir("/home/user/src", Some(1)), "~/src");
+    assert_eq!(dir("/", Some(1)), "/");
+
+    // A home directory of / isn't collapsed
+    assert_eq!(prompt_dir(Path::new("/usr"), Path::new("/"), None), "/usr");
+}
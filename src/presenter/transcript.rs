@@ -0,0 +1,50 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Lines of the whole session for a transcript, decorated as they are shown.
+
+use super::display_line::DisplayLine;
+use super::PresenterCommons;
+use model::screen::Cell;
+use model::session::Session;
+
+/// All lines of the session from top to bottom, including the last prompt.
+///
+/// Each line starts with the decoration of its type, e.g. the exit status of a command.
+pub fn session_lines(session: &Session) -> Vec<Vec<Cell>> {
+    let mut lines = Vec::new();
+    let end = match PresenterCommons::locate_end(session, true) {
+        Some(end) => end,
+        None => return lines,
+    };
+    let mut loc = PresenterCommons::locate_up(session, &end, std::usize::MAX);
+    while let Some(current) = loc {
+        if current == end {
+            break;
+        }
+        if let Some(item) = session.display_line(&current) {
+            let line = DisplayLine::from(item);
+            let mut cells = line.prefix.to_vec();
+            cells.extend_from_slice(&line.line);
+            lines.push(cells);
+        }
+        loc = PresenterCommons::locate_down(session, &current, true, 1)
+            .filter(|next| *next != current);
+    }
+    lines
+}
@@ -24,6 +24,18 @@
 use super::*;
 use model::session::{InteractionHandle, LineItem, LineType};
 
+/// Bytes sent for a key pressed together with Alt.
+///
+/// The key is either prefixed with ESC or sent with the 8th bit set, like the xterm resource
+/// `metaSendsEscape`.
+pub fn alt_key(alt_sends_escape: bool, key: u8) -> Vec<u8> {
+    if alt_sends_escape {
+        vec![0x1b, key]
+    } else {
+        vec![key | 0x80]
+    }
+}
+
 /// Presenter to run commands and send input to their stdin.
 pub struct TuiExecuteCommandPresenter {
     /// Common data.
@@ -48,9 +60,13 @@ impl TuiExecuteCommandPresenter {
     }
 
     fn send_string(&mut self, send: &str) -> PresenterCommand {
+        self.send_bytes(send.as_bytes())
+    }
+
+    fn send_bytes(&mut self, send: &[u8]) -> PresenterCommand {
         self.commons
             .session
-            .write_stdin(self.current_interaction, send.as_bytes());
+            .write_stdin(self.current_interaction, send);
         PresenterCommand::Redraw
     }
 
@@ -65,6 +81,15 @@ impl TuiExecuteCommandPresenter {
         }
     }
 
+    /// Send a cursor key as the program expects it, see Screen::cursor_key
+    fn send_cursor_key(&mut self, final_byte: u8) -> PresenterCommand {
+        let bytes = self
+            .commons
+            .session
+            .cursor_key(self.current_interaction, final_byte);
+        self.send_bytes(&bytes)
+    }
+
     fn send_term_info_shift(
         &mut self,
         shifted: bool,
@@ -102,7 +127,7 @@ impl SubPresenter for TuiExecuteCommandPresenter {
         if let Some(screen) = session.tui_screen(&self.current_interaction) {
             if y < screen.height() as usize {
                 let cells = screen.row_slice(y as isize);
-                let cursor_col = if y == (screen.cursor_y() as usize) {
+                let cursor_col = if screen.cursor_visible() && y == (screen.cursor_y() as usize) {
                     Some(screen.cursor_x() as usize)
                 } else {
                     None
@@ -130,27 +155,28 @@ impl SubPresenter for TuiExecuteCommandPresenter {
                 PresenterCommand::Redraw
             }
             ((_, _, _), SpecialKey::Enter) => self.send_term_info("kent"),
-            ((shifted, _, _), SpecialKey::Left) => {
-                self.send_term_info_shift(shifted, "kcub1", "kLFT")
-            }
-            ((shifted, _, _), SpecialKey::Right) => {
-                self.send_term_info_shift(shifted, "kcuf1", "kRIT")
-            }
-            ((_, _, _), SpecialKey::Up) => self.send_term_info("kcuu1"),
-            ((_, _, _), SpecialKey::Down) => self.send_term_info("kcud1"),
-            ((shifted, _, _), SpecialKey::Home) => {
-                self.send_term_info_shift(shifted, "khome", "kHOM")
-            }
-            ((shifted, _, _), SpecialKey::End) => {
-                self.send_term_info_shift(shifted, "kend", "kEND")
-            }
+            ((true, _, _), SpecialKey::Left) => self.send_term_info("kLFT"),
+            ((true, _, _), SpecialKey::Right) => self.send_term_info("kRIT"),
+            ((true, _, _), SpecialKey::Home) => self.send_term_info("kHOM"),
+            ((true, _, _), SpecialKey::End) => self.send_term_info("kEND"),
+            ((_, _, _), SpecialKey::Up) => self.send_cursor_key(b'A'),
+            ((_, _, _), SpecialKey::Down) => self.send_cursor_key(b'B'),
+            ((_, _, _), SpecialKey::Right) => self.send_cursor_key(b'C'),
+            ((_, _, _), SpecialKey::Left) => self.send_cursor_key(b'D'),
+            ((_, _, _), SpecialKey::Home) => self.send_cursor_key(b'H'),
+            ((_, _, _), SpecialKey::End) => self.send_cursor_key(b'F'),
             ((_, _, _), SpecialKey::PageUp) => self.send_term_info("kpp"),
             ((_, _, _), SpecialKey::PageDown) => self.send_term_info("knp"),
             ((shifted, _, _), SpecialKey::Delete) => {
                 self.send_term_info_shift(shifted, "kdch1", "kDC")
             }
-            ((_, _, _), SpecialKey::Backspace) => self.send_term_info("kbs"),
+            ((_, _, _), SpecialKey::Backspace) => match self.commons.backspace_sends {
+                Some(sends) => self.send_bytes(sends.bytes()),
+                None => self.send_term_info("kbs"),
+            },
             ((false, false, false), SpecialKey::Tab) => self.send_term_info("tab"),
+            // Ctrl-Space sends NUL like a terminal does, e.g. to set the mark in Emacs
+            ((_, true, false), SpecialKey::Space) => self.send_string("\0"),
 
             //           // Ctrl-Tab => Switch to next running TUI if there is one
             //           ((false, true, false), SpecialKey::Tab) => {
@@ -207,6 +233,16 @@ impl SubPresenter for TuiExecuteCommandPresenter {
     /// Handle the event when a modifier and a letter/number is pressed.
     fn event_normal_key(&mut self, mod_state: &ModifierState, letter: u8) -> PresenterCommand {
         match (mod_state.as_tuple(), letter) {
+            // The letter has been normalized to lower case
+            ((shifted, false, true), b'a'..=b'z') | ((shifted, false, true), b'0'..=b'9') => {
+                let key = if shifted {
+                    letter.to_ascii_uppercase()
+                } else {
+                    letter
+                };
+                let bytes = alt_key(self.commons.alt_sends_escape, key);
+                self.send_bytes(&bytes)
+            }
             _ => PresenterCommand::Unknown,
         }
     }
@@ -230,4 +266,12 @@ impl SubPresenter for TuiExecuteCommandPresenter {
     fn event_scroll_down(&mut self, _mod_state: &ModifierState) -> PresenterCommand {
         PresenterCommand::Unknown
     }
+
+    fn event_scroll_left(&mut self, _mod_state: &ModifierState) -> PresenterCommand {
+        PresenterCommand::Unknown
+    }
+
+    fn event_scroll_right(&mut self, _mod_state: &ModifierState) -> PresenterCommand {
+        PresenterCommand::Unknown
+    }
 }
@@ -0,0 +1,70 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Answers to capability queries (XTGETTCAP, `DCS + q <hex> ST`)
+//!
+//! Capability names and values are sent hex-encoded. Known capabilities are answered with
+//! `DCS 1 + r <name>=<value> ST`, unknown ones with `DCS 0 + r <name> ST`.
+
+use term::terminfo::TermInfo;
+
+/// Encode bytes as upper case hex digits
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Decode a string of hex digits. Returns None if it isn't one.
+fn hex_decode(hex: &str) -> Option<String> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Build the reply to a single query for the hex-encoded capability name
+pub fn reply(term_info: &TermInfo, hex_name: &str) -> Vec<u8> {
+    let value = hex_decode(hex_name).and_then(|name| {
+        let name = name.as_str();
+        if let Some(s) = term_info.strings.get(name) {
+            Some(format!("={}", hex_encode(s)))
+        } else if let Some(n) = term_info.numbers.get(name) {
+            Some(format!("={}", hex_encode(n.to_string().as_bytes())))
+        } else if let Some(true) = term_info.bools.get(name) {
+            Some(String::new())
+        } else {
+            None
+        }
+    });
+    match value {
+        Some(value) => format!("\x1bP1+r{}{}\x1b\\", hex_name, value),
+        None => format!("\x1bP0+r{}\x1b\\", hex_name),
+    }
+    .into_bytes()
+}
+
+/// Build the replies to a query, which may ask for several capabilities separated by `;`.
+pub fn replies(term_info: &TermInfo, query: &str) -> Vec<u8> {
+    query
+        .split(';')
+        .flat_map(|hex_name| reply(term_info, hex_name))
+        .collect()
+}
@@ -20,31 +20,283 @@
 //!
 //! Currently only reads the command line arguments.
 
-use argparse::{ArgumentParser, List};
+use std::io::Write;
+use std::str::FromStr;
+
+use argparse::{ArgumentParser, Store, StoreOption, StoreTrue};
+
+/// What the Backspace key sends to programs, like the xterm resource `backspaceSends`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackspaceSends {
+    /// Send DEL (`\x7f`)
+    Delete,
+    /// Send BS (`\x08`)
+    Backspace,
+}
+
+impl BackspaceSends {
+    /// Bytes sent for the Backspace key
+    pub fn bytes(self) -> &'static [u8] {
+        match self {
+            BackspaceSends::Delete => b"\x7f",
+            BackspaceSends::Backspace => b"\x08",
+        }
+    }
+}
+
+impl FromStr for BackspaceSends {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delete" => Ok(BackspaceSends::Delete),
+            "backspace" => Ok(BackspaceSends::Backspace),
+            _ => Err(format!("expected delete or backspace, got {}", s)),
+        }
+    }
+}
 
 /// All parameters passed to the executable on the command line.
 #[derive(Debug)]
 pub struct CommandLine {
-    pub single_program: Vec<String>,
+    /// Command to run in the GUI on startup
+    pub execute: Option<String>,
+    /// Command to run without opening a window
+    pub command: Option<String>,
+    /// Comma-separated list of fonts to try for glyphs missing in the main font
+    pub font_chain: String,
+    /// Number of pixels between text and next line
+    pub line_padding: i32,
+    /// Time in milliseconds the cursor is shown when blinking
+    pub cursor_blink_on: u64,
+    /// Time in milliseconds the cursor is hidden when blinking
+    pub cursor_blink_off: u64,
+    /// Show a steady cursor
+    pub no_cursor_blink: bool,
+    /// What Backspace sends to programs. None uses the terminfo entry.
+    pub backspace_sends: Option<BackspaceSends>,
+    /// Alt + key sends ESC before the key instead of setting the 8th bit
+    pub alt_sends_escape: bool,
+    /// Print the output of the init script to the terminal bite was started from
+    pub debug_init: bool,
+    /// File to load the history from and save it to instead of ~/.bitehistory
+    pub history_file: Option<String>,
+    /// Start in private mode, i.e. don't record entered commands in the history
+    pub no_history: bool,
+    /// Print version information and exit
+    pub version: bool,
+}
+
+/// Version and build information as printed by --version
+pub fn version_info() -> String {
+    format!(
+        "BiTE {}\n\
+         Display: X11 (xlib)\n\
+         Compose variants: bubble_above (default), markov_below, bubble_exclusive, live_parse\n",
+        env!("CARGO_PKG_VERSION")
+    )
 }
 
 impl CommandLine {
     /// Parse the command line arguments and fill a CommandLine struct.
+    ///
+    /// Exits the program if the arguments are invalid or help was requested.
     pub fn parse() -> CommandLine {
+        let stdout = std::io::stdout();
+        let stderr = std::io::stderr();
+        match Self::parse_from(
+            std::env::args().collect(),
+            &mut stdout.lock(),
+            &mut stderr.lock(),
+        ) {
+            Ok(result) => result,
+            Err(ret_code) => std::process::exit(ret_code),
+        }
+    }
+
+    /// Parse the given arguments, including the program name.
+    ///
+    /// Returns the exit code if the program should end, e.g. after printing the help.
+    pub fn parse_from(
+        args: Vec<String>,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<CommandLine, i32> {
         let mut result = CommandLine {
-            single_program: vec![],
+            execute: None,
+            command: None,
+            font_chain: String::new(),
+            line_padding: 1,
+            cursor_blink_on: 1000,
+            cursor_blink_off: 500,
+            no_cursor_blink: false,
+            backspace_sends: None,
+            alt_sends_escape: true,
+            debug_init: false,
+            history_file: None,
+            no_history: false,
+            version: false,
         };
         {
             let mut ap = ArgumentParser::new();
             ap.set_description("BiTE - Bash-Integrated Terminal Emulator");
-            ap.refer(&mut result.single_program).add_option(
-                &["-e"],
-                List,
-                "Single program to be run. bite will exit after it is completed.",
+            ap.refer(&mut result.execute).add_option(
+                &["-e", "--execute"],
+                StoreOption,
+                "Command to run on startup.",
+            );
+            ap.refer(&mut result.command).add_option(
+                &["-c"],
+                StoreOption,
+                "Command to run without opening a window. bite exits with its status.",
+            );
+            ap.refer(&mut result.font_chain).add_option(
+                &["--font-chain"],
+                Store,
+                "Comma-separated list of fonts to use for characters missing in the main font.",
+            );
+            ap.refer(&mut result.line_padding).add_option(
+                &["--line-padding"],
+                Store,
+                "Number of pixels between the lines of text.",
+            );
+            ap.refer(&mut result.cursor_blink_on).add_option(
+                &["--cursor-blink-on"],
+                Store,
+                "Milliseconds the cursor is shown when blinking.",
+            );
+            ap.refer(&mut result.cursor_blink_off).add_option(
+                &["--cursor-blink-off"],
+                Store,
+                "Milliseconds the cursor is hidden when blinking. 0 disables blinking.",
+            );
+            ap.refer(&mut result.no_cursor_blink).add_option(
+                &["--no-cursor-blink"],
+                StoreTrue,
+                "Don't blink the cursor.",
+            );
+            ap.refer(&mut result.backspace_sends).add_option(
+                &["--backspace-sends"],
+                StoreOption,
+                "What Backspace sends to programs: delete (DEL) or backspace (BS).",
+            );
+            ap.refer(&mut result.alt_sends_escape).add_option(
+                &["--alt-sends-escape"],
+                Store,
+                "Alt + key sends ESC before the key (true) or sets the 8th bit (false).",
+            );
+            ap.refer(&mut result.debug_init).add_option(
+                &["--debug-init"],
+                StoreTrue,
+                "Print the output of the init script to this terminal.",
+            );
+            ap.refer(&mut result.history_file).add_option(
+                &["--history-file"],
+                StoreOption,
+                "File to load the history from and save it to. Default is ~/.bitehistory.",
             );
-            ap.parse_args_or_exit();
+            ap.refer(&mut result.no_history).add_option(
+                &["--no-history"],
+                StoreTrue,
+                "Don't record entered commands in the history. Alt-P toggles this.",
+            );
+            ap.refer(&mut result.version).add_option(
+                &["--version"],
+                StoreTrue,
+                "Print version information and exit.",
+            );
+            ap.parse(args, stdout, stderr)?;
         }
 
-        result
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CommandLine, i32> {
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let mut all_args = vec!["bite".to_string()];
+        all_args.extend(args.iter().map(|s| s.to_string()));
+        CommandLine::parse_from(all_args, &mut stdout, &mut stderr)
+    }
+
+    #[test]
+    fn version_flag() {
+        assert_eq!(parse(&[]).map(|p| p.version), Ok(false));
+        assert_eq!(parse(&["--version"]).map(|p| p.version), Ok(true));
+    }
+
+    #[test]
+    fn command_options() {
+        let params = parse(&["-c", "echo hi"]).unwrap();
+        assert_eq!(params.command, Some("echo hi".to_string()));
+        assert_eq!(params.execute, None);
+
+        let params = parse(&["--execute", "ls"]).unwrap();
+        assert_eq!(params.command, None);
+        assert_eq!(params.execute, Some("ls".to_string()));
+    }
+
+    #[test]
+    fn backspace_sends_option() {
+        assert_eq!(parse(&[]).unwrap().backspace_sends, None);
+
+        let params = parse(&["--backspace-sends", "delete"]).unwrap();
+        assert_eq!(params.backspace_sends, Some(BackspaceSends::Delete));
+        assert_eq!(params.backspace_sends.unwrap().bytes(), b"\x7f");
+
+        let params = parse(&["--backspace-sends", "backspace"]).unwrap();
+        assert_eq!(params.backspace_sends, Some(BackspaceSends::Backspace));
+        assert_eq!(params.backspace_sends.unwrap().bytes(), b"\x08");
+
+        assert!(parse(&["--backspace-sends", "erase"]).is_err());
+    }
+
+    #[test]
+    fn alt_sends_escape_option() {
+        assert_eq!(parse(&[]).map(|p| p.alt_sends_escape), Ok(true));
+        assert_eq!(
+            parse(&["--alt-sends-escape", "false"]).map(|p| p.alt_sends_escape),
+            Ok(false)
+        );
+        assert!(parse(&["--alt-sends-escape", "maybe"]).is_err());
+    }
+
+    #[test]
+    fn debug_init_flag() {
+        assert_eq!(parse(&[]).map(|p| p.debug_init), Ok(false));
+        assert_eq!(parse(&["--debug-init"]).map(|p| p.debug_init), Ok(true));
+    }
+
+    #[test]
+    fn history_file_option() {
+        assert_eq!(parse(&[]).unwrap().history_file, None);
+        assert_eq!(
+            parse(&["--history-file", "/tmp/hist"])
+                .unwrap()
+                .history_file,
+            Some("/tmp/hist".to_string())
+        );
+    }
+
+    #[test]
+    fn no_history_flag() {
+        assert_eq!(parse(&[]).map(|p| p.no_history), Ok(false));
+        assert_eq!(parse(&["--no-history"]).map(|p| p.no_history), Ok(true));
+    }
+
+    #[test]
+    fn version_info_format() {
+        let info = version_info();
+        let first = info.lines().next().unwrap();
+        assert!(first.starts_with("BiTE "));
+        let numbers: Vec<&str> = first["BiTE ".len()..].split('.').collect();
+        assert_eq!(numbers.len(), 3);
+        assert!(numbers.iter().all(|n| n.parse::<u32>().is_ok()));
+        assert!(info.contains("X11"));
     }
 }
@@ -0,0 +1,113 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Crash reports written by the panic hook
+//!
+//! When BiTE is started from a desktop, stderr is not visible. The panic information is therefore
+//! also written to a file in the home directory.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Where the panic happened
+pub struct Location<'a> {
+    pub file: &'a str,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Create the text of the crash report.
+///
+/// `time` is the time of the crash in seconds since the epoch.
+pub fn format_report(
+    time: u64,
+    message: Option<&str>,
+    location: Option<Location>,
+    backtrace: &str,
+) -> String {
+    let mut report = String::from("BiTE crash report\n");
+    report.push_str(&format!("Time: {}\n", time));
+    report.push_str(&format!("Version: {}\n", env!("CARGO_PKG_VERSION")));
+    report.push_str(&format!("Message: {}\n", message.unwrap_or("<none>")));
+    match location {
+        Some(loc) => report.push_str(&format!(
+            "Location: {}:{}:{}\n",
+            loc.file, loc.line, loc.column
+        )),
+        None => report.push_str("Location: <unknown>\n"),
+    }
+    report.push_str("Backtrace:\n");
+    report.push_str(backtrace);
+    if !backtrace.ends_with('\n') {
+        report.push('\n');
+    }
+    report
+}
+
+/// Name of the crash report file for the given time in the given directory
+pub fn report_path(directory: &Path, time: u64) -> PathBuf {
+    directory.join(format!(".bite-crash-{}.log", time))
+}
+
+/// Write the report to a file. Errors are returned, never raised as panic.
+pub fn write_report(path: &Path, report: &str) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(report.as_bytes())?;
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crash_report_fields() {
+        let report = format_report(
+            1234,
+            Some("index out of bounds"),
+            Some(Location {
+                file: "src/model/screen/mod.rs",
+                line: 42,
+                column: 7,
+            }),
+            "   0: bite::main",
+        );
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "BiTE crash report");
+        assert_eq!(lines[1], "Time: 1234");
+        assert!(lines[2].starts_with("Version: "));
+        assert_eq!(lines[3], "Message: index out of bounds");
+        assert_eq!(lines[4], "Location: src/model/screen/mod.rs:42:7");
+        assert_eq!(lines[5], "Backtrace:");
+        assert_eq!(lines[6], "   0: bite::main");
+        assert!(report.ends_with('\n'));
+
+        let report = format_report(1234, None, None, "");
+        assert!(report.contains("Message: <none>\n"));
+        assert!(report.contains("Location: <unknown>\n"));
+    }
+
+    #[test]
+    fn crash_report_path() {
+        assert_eq!(
+            report_path(Path::new("/home/user"), 1234),
+            PathBuf::from("/home/user/.bite-crash-1234.log")
+        );
+    }
+}
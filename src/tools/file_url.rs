@@ -0,0 +1,91 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Decoding of file URLs as sent by shells to report their working directory (OSC 7)
+
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+/// Value of a hex digit
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Replace the %XX escapes by the bytes they stand for.
+///
+/// Invalid escapes are kept as they are.
+pub fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(h), Some(l)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                decoded.push(h * 16 + l);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    decoded
+}
+
+/// Extract the path from a URL of the form file://host/path.
+///
+/// The host is ignored. Returns None if the URL is not a file URL with an absolute path.
+pub fn path_from_file_url(url: &str) -> Option<PathBuf> {
+    let rest = url.strip_prefix("file://")?;
+    let path_start = rest.find('/')?;
+    let path = percent_decode(&rest[path_start..]);
+    Some(PathBuf::from(OsString::from_vec(path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_percent_escapes() {
+        assert_eq!(percent_decode("a%20b"), b"a b".to_vec());
+        assert_eq!(percent_decode("%c3%a4%C3%B6"), "äö".as_bytes().to_vec());
+        assert_eq!(percent_decode("50%"), b"50%".to_vec());
+        assert_eq!(percent_decode("%zz%4"), b"%zz%4".to_vec());
+    }
+
+    #[test]
+    fn extract_path_from_file_url() {
+        assert_eq!(
+            path_from_file_url("file://myhost/home/user/My%20Files"),
+            Some(PathBuf::from("/home/user/My Files"))
+        );
+        assert_eq!(
+            path_from_file_url("file:///tmp"),
+            Some(PathBuf::from("/tmp"))
+        );
+        assert_eq!(path_from_file_url("file://myhost"), None);
+        assert_eq!(path_from_file_url("http://myhost/tmp"), None);
+    }
+}
@@ -0,0 +1,80 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Find the home directory of the user
+//!
+//! The history and the init script are stored there.
+
+use std::ffi::CStr;
+use std::path::PathBuf;
+
+/// Pick the home directory.
+///
+/// The HOME variable takes precedence over the password database. If neither is available, the
+/// current directory is used.
+pub fn resolve(home_var: Option<String>, passwd_home: Option<PathBuf>) -> PathBuf {
+    home_var
+        .filter(|h| !h.is_empty())
+        .map(PathBuf::from)
+        .or(passwd_home)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Home directory of the current user as stored in the password database
+fn passwd_home() -> Option<PathBuf> {
+    let uid = nix::unistd::getuid().as_raw();
+    unsafe {
+        let pw = libc::getpwuid(uid);
+        if pw.is_null() || (*pw).pw_dir.is_null() {
+            None
+        } else {
+            let dir = CStr::from_ptr((*pw).pw_dir).to_string_lossy().into_owned();
+            if dir.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(dir))
+            }
+        }
+    }
+}
+
+/// Home directory of the current user
+pub fn home_dir() -> PathBuf {
+    resolve(std::env::var("HOME").ok(), passwd_home())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolution_order() {
+        let passwd = Some(PathBuf::from("/home/passwd"));
+        assert_eq!(
+            resolve(Some("/home/env".to_string()), passwd.clone()),
+            PathBuf::from("/home/env")
+        );
+        // Unset or empty HOME
+        assert_eq!(resolve(None, passwd.clone()), PathBuf::from("/home/passwd"));
+        assert_eq!(
+            resolve(Some(String::new()), passwd),
+            PathBuf::from("/home/passwd")
+        );
+        assert_eq!(resolve(None, None), PathBuf::from("."));
+    }
+}
@@ -0,0 +1,105 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2018  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Lock files to keep several processes from writing the same file at once
+//!
+//! The lock is an advisory lock (flock) on a file next to the protected one. The kernel releases
+//! it when the process ends, so a crashed process can't leave a stale lock behind. The lock file
+//! itself is never removed, as another process might be waiting for it.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+
+/// Time to wait before trying again to lock the file
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Lock that is held as long as this object exists
+#[derive(Debug)]
+pub struct LockFile {
+    file: File,
+}
+
+impl LockFile {
+    /// Lock the lock file for the given file, i.e. `<path>.lock`. Create it if necessary.
+    ///
+    /// Fails if another process holds the lock for longer than `timeout`.
+    pub fn acquire(path: &Path, timeout: Duration) -> Result<LockFile> {
+        let mut lock_name = path.as_os_str().to_owned();
+        lock_name.push(".lock");
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&lock_name)?;
+
+        let start = Instant::now();
+        loop {
+            match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+                Ok(()) => return Ok(LockFile { file }),
+                Err(nix::Error::Sys(Errno::EWOULDBLOCK)) if start.elapsed() < timeout => {
+                    thread::sleep(RETRY_INTERVAL);
+                }
+                Err(nix::Error::Sys(Errno::EWOULDBLOCK)) => {
+                    return Err(Error::new(
+                        ErrorKind::TimedOut,
+                        format!("{:?} is locked by another process", lock_name),
+                    ));
+                }
+                Err(e) => return Err(Error::new(ErrorKind::Other, e.to_string())),
+            }
+        }
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = flock(self.file.as_raw_fd(), FlockArg::Unlock);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_waits_for_holder() {
+        let path = std::env::temp_dir().join(format!("bite-lock-test-{}", std::process::id()));
+
+        let lock = LockFile::acquire(&path, Duration::from_secs(1)).unwrap();
+
+        // A second lock times out while the first one is held
+        let start = Instant::now();
+        let second = LockFile::acquire(&path, Duration::from_millis(50));
+        assert_eq!(second.unwrap_err().kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // Once it is released, the lock can be taken again
+        drop(lock);
+        let third = LockFile::acquire(&path, Duration::from_millis(50));
+        assert!(third.is_ok());
+
+        drop(third);
+        let _ = std::fs::remove_file(format!("{}.lock", path.to_string_lossy()));
+    }
+}
@@ -18,6 +18,100 @@
 
 //! Tools that help with logging
 
+use flexi_logger::{FlexiLoggerError, Logger};
+
+/// Default size of the log file in MiB before it is rotated
+const DEFAULT_LOG_SIZE_MIB: usize = 10;
+
+/// Default number of rotated log files to keep
+const DEFAULT_LOG_KEEP: usize = 5;
+
+/// When to rotate the log file and how many of the old ones to keep
+#[derive(Debug, PartialEq)]
+pub struct Rotation {
+    /// Size in bytes at which the log file is rotated
+    pub max_size: usize,
+    /// Number of rotated log files to keep
+    pub keep_files: usize,
+}
+
+impl Rotation {
+    /// Build the rotation settings from the values of BITE_LOG_SIZE (in MiB) and BITE_LOG_KEEP.
+    ///
+    /// Missing or invalid values are replaced by the defaults.
+    pub fn from_values(size_mib: Option<String>, keep_files: Option<String>) -> Self {
+        fn parse_or(value: Option<String>, default: usize) -> usize {
+            value
+                .and_then(|v| v.trim().parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(default)
+        }
+        Self {
+            max_size: parse_or(size_mib, DEFAULT_LOG_SIZE_MIB) * 1024 * 1024,
+            keep_files: parse_or(keep_files, DEFAULT_LOG_KEEP),
+        }
+    }
+
+    /// Read the rotation settings from the environment.
+    ///
+    /// The logger is started before the init script runs, so .biterc can't be used here.
+    pub fn from_env() -> Self {
+        Self::from_values(
+            std::env::var("BITE_LOG_SIZE").ok(),
+            std::env::var("BITE_LOG_KEEP").ok(),
+        )
+    }
+}
+
+/// Remove all but the newest *keep_files* log files of this program in the given directory.
+///
+/// flexi_logger 0.10 doesn't clean up the rotated files itself, so this is done at startup.
+fn remove_old_logs(directory: &str, keep_files: usize) {
+    let program = match std::env::current_exe()
+        .ok()
+        .and_then(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()))
+    {
+        Some(program) => program,
+        None => return,
+    };
+    let mut logs: Vec<(std::time::SystemTime, std::path::PathBuf)> =
+        match std::fs::read_dir(directory) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .filter(|e| {
+                    let name = e.file_name().to_string_lossy().into_owned();
+                    name.starts_with(&program) && name.ends_with(".log")
+                })
+                .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+                .collect(),
+            Err(_) => return,
+        };
+    // Newest first
+    logs.sort_by(|a, b| b.0.cmp(&a.0));
+    for (_, path) in logs.into_iter().skip(keep_files) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Start logging to a file in the given directory with the given log specification.
+///
+/// The file is rotated when it grows beyond the configured size. Old log files are removed when
+/// the logger starts.
+pub fn start_file_logger(
+    spec: &str,
+    directory: String,
+    rotation: &Rotation,
+) -> Result<(), FlexiLoggerError> {
+    remove_old_logs(&directory, rotation.keep_files);
+    Logger::with_str(spec)
+        .format(flexi_logger::with_thread)
+        .log_to_file()
+        .directory(directory)
+        .rotate_over_size(rotation.max_size)
+        .start()
+        .map(|_| ())
+}
+
 /// Unwrap a Result, print debug message and return default value in case of error.
 pub fn unwrap_log<T, E>(result: Result<T, E>, desc: &str, default: T) -> T
 where
@@ -31,3 +125,71 @@ where
         Ok(x) => x,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_from_values() {
+        assert_eq!(
+            Rotation::from_values(None, None),
+            Rotation {
+                max_size: 10 * 1024 * 1024,
+                keep_files: 5
+            }
+        );
+        assert_eq!(
+            Rotation::from_values(Some("2".to_string()), Some(" 3 ".to_string())),
+            Rotation {
+                max_size: 2 * 1024 * 1024,
+                keep_files: 3
+            }
+        );
+        assert_eq!(
+            Rotation::from_values(Some("lots".to_string()), Some("0".to_string())),
+            Rotation {
+                max_size: 10 * 1024 * 1024,
+                keep_files: 5
+            }
+        );
+    }
+
+    #[test]
+    fn start_file_logger_with_rotation() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("bite-log-test-{}", std::process::id()));
+        let rotation = Rotation {
+            max_size: 1024,
+            keep_files: 2,
+        };
+        let res = start_file_logger("info", dir.to_string_lossy().into_owned(), &rotation);
+        assert!(res.is_ok());
+        info!("Logging is ready");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn remove_old_logs_keeps_newest() {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("bite-log-cleanup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = std::env::current_exe().unwrap();
+        let program = program.file_stem().unwrap().to_string_lossy();
+        for i in 0..4 {
+            std::fs::write(dir.join(format!("{}_r{:05}.log", program, i)), "").unwrap();
+        }
+        std::fs::write(dir.join("other.log"), "").unwrap();
+
+        remove_old_logs(&dir.to_string_lossy(), 2);
+
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 3);
+        assert!(names.contains(&"other.log".to_string()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -20,6 +20,10 @@
 //! pattern.
 
 pub mod commandline;
+pub mod crash_report;
+pub mod file_url;
+pub mod home_dir;
+pub mod lock_file;
 pub mod logging;
 pub mod polling;
 pub mod shared_item;
@@ -16,59 +16,146 @@
     along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-//! Provide a rate-limited polling mechanism.
+//! Sleep until something happens instead of polling in a busy loop.
 //!
-//! Assumes that if one event has been processed, another one will follow immediately. If no event
-//! was obtained during that polling cycle, wait for a predetermined time to try again. This
-//! ensures that events that come in quick succession can be processed at maximum speed (one per
-//! cycle), but the CPU doesn't have to spin at 100% to do the polling.
-//!
-//! # Errors
-//!
-//! If the polling reads from a limited buffer and that fills up (e.g. due to a too long polling
-//! cycle), the sender might block.
-
-use std::thread::sleep;
-use std::time::Duration;
-
-/// State and parameters of the rate-limiting mechanism.
-pub struct Gate {
-    /// Did we get an event during the last cycle.
-    had_event: bool,
-    /// If now, how long shall we sleep until the next try.
-    time_between_polls: Duration,
+//! The GUI thread blocks on a set of file descriptors (e.g. the X11 connection) with a timeout
+//! that ends at the next deadline (e.g. the cursor blink). Other threads wake it up early by
+//! writing to a WakeupPipe.
+
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::unistd::{close, pipe2, read, write};
+use std::cmp;
+use std::os::raw::c_int;
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// File descriptors of the pipe. Closed when the last WakeupPipe is dropped.
+#[derive(Debug)]
+struct PipeFds {
+    read_fd: RawFd,
+    write_fd: RawFd,
 }
 
-impl Gate {
-    /// Allocate a gate with the giving polling rate
-    pub fn new(time_between_polls: Duration) -> Self {
-        Self {
-            had_event: true,
-            time_between_polls,
-        }
+impl Drop for PipeFds {
+    fn drop(&mut self) {
+        let _ = close(self.read_fd);
+        let _ = close(self.write_fd);
     }
+}
+
+/// Pipe to wake up a thread that waits in wait_readable.
+///
+/// Clones share the same pipe, so one clone can be handed to the threads that produce events
+/// while the waiting thread keeps another one.
+#[derive(Clone, Debug)]
+pub struct WakeupPipe(Arc<PipeFds>);
 
-    /// Wait to test for the next event.
+impl WakeupPipe {
+    /// Create a new non-blocking pipe.
+    pub fn new() -> nix::Result<Self> {
+        let (read_fd, write_fd) = pipe2(OFlag::O_NONBLOCK | OFlag::O_CLOEXEC)?;
+        Ok(WakeupPipe(Arc::new(PipeFds { read_fd, write_fd })))
+    }
+
+    /// File descriptor to wait on.
+    pub fn fd(&self) -> RawFd {
+        self.0.read_fd
+    }
+
+    /// Wake up the waiting thread.
     ///
-    /// If we have seen an event in the last cycle, we assume, there's another one. If not, sleep
-    /// to free the CPU.
-    pub fn wait(&mut self) {
-        if self.had_event {
-            self.had_event = false;
-        } else {
-            sleep(self.time_between_polls);
+    /// If the pipe is full, there are enough wake-ups pending already.
+    pub fn wake(&self) {
+        let _ = write(self.0.write_fd, &[0]);
+    }
+
+    /// Remove all pending wake-ups.
+    pub fn drain(&self) {
+        let mut buf = [0; 64];
+        while let Ok(n) = read(self.0.read_fd, &mut buf) {
+            if n < buf.len() {
+                break;
+            }
         }
     }
+}
+
+/// Time from now until the earliest deadline, but not longer than max_wait.
+///
+/// Deadlines that have already passed result in no waiting at all.
+pub fn time_until_next(
+    now: SystemTime,
+    deadlines: &[Option<SystemTime>],
+    max_wait: Duration,
+) -> Duration {
+    deadlines
+        .iter()
+        .filter_map(|d| *d)
+        .map(|d| d.duration_since(now).unwrap_or(Duration::from_millis(0)))
+        .fold(max_wait, cmp::min)
+}
 
-    /// Mark that we had an event in this cycle.
-    pub fn mark(&mut self) {
-        self.had_event = true;
+/// Sleep until one of the file descriptors becomes readable or the timeout expires.
+///
+/// Returns true if a file descriptor is readable.
+pub fn wait_readable(fds: &[RawFd], timeout: Duration) -> bool {
+    let mut poll_fds: Vec<PollFd> = fds
+        .iter()
+        .map(|fd| PollFd::new(*fd, PollFlags::POLLIN))
+        .collect();
+    // Round up to avoid spinning for timeouts below one millisecond.
+    let timeout_ms = cmp::min((timeout.as_micros() + 999) / 1000, c_int::max_value() as u128);
+    match poll(&mut poll_fds, timeout_ms as c_int) {
+        Ok(n) => n > 0,
+        Err(_) => false,
     }
+}
 
-    /// Return true if we can exit the polling loop.
-    ///
-    /// If there was no event in the last iteration, we assume we read all pending ones.
-    pub fn can_exit(&self) -> bool {
-        !self.had_event
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_until_earliest_deadline() {
+        let now = SystemTime::now();
+        let ms = Duration::from_millis;
+        let max_wait = ms(500);
+
+        assert_eq!(time_until_next(now, &[], max_wait), max_wait);
+        assert_eq!(time_until_next(now, &[None, None], max_wait), max_wait);
+        assert_eq!(
+            time_until_next(now, &[Some(now + ms(300)), None], max_wait),
+            ms(300)
+        );
+        assert_eq!(
+            time_until_next(now, &[Some(now + ms(300)), Some(now + ms(16))], max_wait),
+            ms(16)
+        );
+        assert_eq!(
+            time_until_next(now, &[Some(now + ms(1000))], max_wait),
+            max_wait
+        );
+
+        // Missed deadlines do not wait
+        assert_eq!(
+            time_until_next(now, &[Some(now - ms(10)), Some(now + ms(16))], max_wait),
+            ms(0)
+        );
+    }
+
+    #[test]
+    fn wakeup_pipe() {
+        let pipe = WakeupPipe::new().unwrap();
+        assert!(!wait_readable(&[pipe.fd()], Duration::from_millis(0)));
+
+        let other = pipe.clone();
+        other.wake();
+        other.wake();
+        assert!(wait_readable(&[pipe.fd()], Duration::from_millis(1000)));
+
+        pipe.drain();
+        assert!(!wait_readable(&[pipe.fd()], Duration::from_millis(0)));
     }
 }
@@ -0,0 +1,192 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Window system independent part of the view.
+//!
+//! A backend translates the input of its window system to events and draws the lines of the
+//! presenter. Passing the events to the presenter is the same for all backends.
+
+use presenter::{ModifierState, NeedRedraw, Presenter, PresenterCommand, SpecialKey};
+use view::transcript;
+
+/// Key that was pressed
+#[derive(Debug, Clone, Copy)]
+pub enum Key {
+    /// Cursor, editing and other keys without a letter
+    Special(SpecialKey),
+    /// Letter or digit, letters in lower case
+    Letter(u8),
+}
+
+/// Direction the mouse wheel was turned in
+#[derive(Debug, Clone, Copy)]
+pub enum Scroll {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Input to the view, measured in characters
+#[derive(Debug)]
+pub enum Event {
+    /// The view has now the given width and height
+    Resize(usize, usize),
+    /// The view has to be drawn again right now
+    Expose,
+    /// The view gained (true) or lost (false) the keyboard focus
+    Focus(bool),
+    /// A key was pressed. The text is entered if the presenter doesn't handle the key.
+    Key(ModifierState, Option<Key>, Option<String>),
+    /// A mouse button was pressed (true) or released (false) at the given column and row
+    Button(bool, ModifierState, usize, usize, usize),
+    /// The mouse wheel was turned
+    Scroll(ModifierState, Scroll),
+    /// The user wants to close the view
+    Close,
+    /// Something happened that the presenter doesn't need to know about
+    Other,
+}
+
+/// Surface the presenter is shown on
+pub trait Backend {
+    /// Presenter that receives the events
+    fn presenter(&mut self) -> &mut Presenter;
+
+    /// Take the next event. None if nothing happened.
+    fn poll_event(&mut self) -> Option<Event>;
+
+    /// Draw all lines of the presenter
+    fn render(&mut self);
+
+    /// Draw the lines again soon
+    fn mark_redraw(&mut self);
+
+    /// Draw the lines again right now
+    fn force_redraw(&mut self) {
+        self.render();
+    }
+
+    /// Show or hide the cursor and start its blink cycle anew
+    fn cursor_now(&mut self, _on: bool) {}
+
+    /// Remember if the view has the keyboard focus
+    fn set_focus(&mut self, _focus: bool) {}
+
+    /// Should the user be asked before closing the view while commands are running?
+    fn confirm_exit(&self) -> bool;
+
+    /// Colors of the 256 palette entries
+    fn colors(&self) -> &[u32; 256];
+
+    /// Write the session to the files the *transcript* builtin asked for.
+    ///
+    /// Failures are shown in the session.
+    fn save_transcripts(&mut self) {
+        for (path, format) in self.presenter().take_transcript_requests() {
+            let lines = self.presenter().transcript_lines();
+            match transcript::write(&path, format, &lines, self.colors()) {
+                Ok(()) => info!("Wrote transcript to »{:?}«", path),
+                Err(err) => {
+                    error!("Can't write transcript to »{:?}«: {}", path, err);
+                    self.presenter()
+                        .report_transcript_failure(&path, &err.to_string());
+                }
+            }
+        }
+    }
+
+    /// Pass an event to the presenter.
+    ///
+    /// Returns false if the view should be closed.
+    fn handle_event(&mut self, event: Event) -> bool {
+        match event {
+            Event::Resize(width, height) => self.presenter().event_window_resize(width, height),
+            Event::Expose => self.force_redraw(),
+            Event::Focus(true) => {
+                self.cursor_now(true);
+                self.set_focus(true);
+                self.mark_redraw();
+                self.presenter().event_focus_gained();
+            }
+            Event::Focus(false) => {
+                self.set_focus(false);
+                self.mark_redraw();
+                self.presenter().event_focus_lost();
+            }
+            Event::Key(mod_state, key, text) => {
+                let cmd = match key {
+                    Some(Key::Special(key)) => self.presenter().event_special_key(&mod_state, &key),
+                    Some(Key::Letter(letter)) => {
+                        self.presenter().event_normal_key(&mod_state, letter)
+                    }
+                    None => PresenterCommand::Unknown,
+                };
+                match cmd {
+                    PresenterCommand::Unknown => {
+                        // Insert text
+                        if let Some(text) = text {
+                            match self.presenter().event_text(&text) {
+                                PresenterCommand::Redraw => {
+                                    self.cursor_now(true);
+                                    self.mark_redraw();
+                                }
+                                PresenterCommand::Exit => return false,
+                                PresenterCommand::Unknown | PresenterCommand::Ignored => {}
+                            }
+                        }
+                    }
+                    PresenterCommand::Redraw => self.mark_redraw(),
+                    PresenterCommand::Exit => return false,
+                    PresenterCommand::Ignored => {}
+                }
+            }
+            Event::Button(pressed, mod_state, button, x, y) => {
+                let need_redraw = if pressed {
+                    self.presenter().event_button_down(mod_state, button, x, y)
+                } else {
+                    self.presenter().event_button_up(mod_state, button, x, y)
+                };
+                if need_redraw == NeedRedraw::Yes {
+                    self.mark_redraw();
+                }
+            }
+            Event::Scroll(mod_state, scroll) => {
+                let cmd = match scroll {
+                    Scroll::Up => self.presenter().event_scroll_up(&mod_state),
+                    Scroll::Down => self.presenter().event_scroll_down(&mod_state),
+                    Scroll::Left => self.presenter().event_scroll_left(&mod_state),
+                    Scroll::Right => self.presenter().event_scroll_right(&mod_state),
+                };
+                if cmd == PresenterCommand::Redraw {
+                    self.mark_redraw();
+                }
+            }
+            Event::Close => {
+                // Close the view, unless the user wants to keep running commands
+                let confirm_exit = self.confirm_exit();
+                match self.presenter().event_close(confirm_exit) {
+                    PresenterCommand::Redraw => self.mark_redraw(),
+                    _ => return false,
+                }
+            }
+            Event::Other => {}
+        }
+        true
+    }
+}
@@ -18,6 +18,69 @@
 
 //! Color table handling
 
+use model::screen::Cell;
+
+/// Color of text whose foreground color has not been set
+pub const DEFAULT_FOREGROUND: u32 = 0xFFD700;
+
+/// Color behind text whose background color has not been set
+pub const DEFAULT_BACKGROUND: u32 = 0x000000;
+
+/// Colors to mark the prompts with if none are configured
+pub const DEFAULT_PROMPT_COLORS: [u32; 20] = [
+    0xFF1313, 0xFF6C6C, 0xFF4242, 0xD40000, 0xA90000, 0xFF9C13, 0xFFC16C, 0xFFB042, 0xD47B00,
+    0xA96200, 0x1766A7, 0x5992C2, 0x3779B0, 0x094F89, 0x063E6D, 0x0FCD0F, 0x5DDC5D, 0x37D237,
+    0x00AA00, 0x008700,
+];
+
+/// Parse a list of prompt colors, e.g. `#ff0000,#00ff00 0000ff`.
+///
+/// Colors are six hex digits, optionally starting with `#`, separated by commas or spaces.
+/// Returns None if the list is empty or any entry is invalid.
+pub fn parse_prompt_colors(spec: &str) -> Option<Vec<u32>> {
+    let colors = spec
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let hex = s.trim_start_matches('#');
+            if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                u32::from_str_radix(hex, 16).ok()
+            } else {
+                None
+            }
+        })
+        .collect::<Option<Vec<u32>>>()?;
+    if colors.is_empty() {
+        None
+    } else {
+        Some(colors)
+    }
+}
+
+/// Prompt colors from the configuration or the default ones if there are none or they are invalid
+pub fn prompt_colors(spec: Option<&str>) -> Vec<u32> {
+    spec.and_then(|spec| {
+        let colors = parse_prompt_colors(spec);
+        if colors.is_none() {
+            warn!("Invalid prompt colors »{}«, using the default ones", spec);
+        }
+        colors
+    })
+    .unwrap_or_else(|| DEFAULT_PROMPT_COLORS.to_vec())
+}
+
+/// Foreground and background color of a cell as RGB values
+pub fn cell_colors(cell: &Cell, colors: &[u32; 256]) -> (u32, u32) {
+    // TODO: Configure default colors
+    let fg_color = cell
+        .foreground_color()
+        .map_or(DEFAULT_FOREGROUND, |c| colors[c as usize]);
+    let bg_color = cell
+        .background_color()
+        .map_or(DEFAULT_BACKGROUND, |c| colors[c as usize]);
+    (fg_color, bg_color)
+}
+
 pub fn setupColors(col: &mut [u32; 256]) {
     col[0] = 0x000000; // black
     col[1] = 0xcd0000; // red3
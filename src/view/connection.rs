@@ -0,0 +1,104 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2018  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Survive the loss of the connection to the X server
+//!
+//! By default, Xlib terminates the process if the connection breaks. Instead, BiTE only raises a
+//! flag, so that the main loop can end and the history is saved and the interpreter stopped as
+//! usual.
+
+use libc::{c_void, dlsym, RTLD_DEFAULT};
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+use x11::xlib::{Display, XSetIOErrorHandler};
+
+/// Set when Xlib reported that the connection to the server is gone.
+static CONNECTION_LOST: AtomicBool = AtomicBool::new(false);
+
+/// Signature of XSetIOErrorExitHandler, which is available from libX11 1.7 on
+type SetIOErrorExitHandler = unsafe extern "C" fn(
+    *mut Display,
+    Option<unsafe extern "C" fn(*mut Display, *mut c_void)>,
+    *mut c_void,
+);
+
+/// Check if the connection to the server has been lost.
+///
+/// Once this returns true, no Xlib functions must be called for the display anymore.
+pub fn connection_lost() -> bool {
+    CONNECTION_LOST.load(Ordering::Acquire)
+}
+
+/// Tell the main loop that the connection to the server has been lost.
+fn signal_connection_lost() {
+    CONNECTION_LOST.store(true, Ordering::Release);
+}
+
+/// Called by Xlib when the connection to the server breaks.
+unsafe extern "C" fn io_error_handler(_display: *mut Display) -> c_int {
+    error!("Lost connection to X server");
+    signal_connection_lost();
+    0
+}
+
+/// Called by Xlib after io_error_handler. Returning from it keeps the process alive.
+unsafe extern "C" fn io_error_exit_handler(_display: *mut Display, _user_data: *mut c_void) {
+    signal_connection_lost();
+}
+
+/// Install the handlers for a lost connection.
+///
+/// Returns false if Xlib is too old to continue after the connection broke. In that case, the
+/// process will still be terminated by Xlib.
+pub fn install_handlers(display: *mut Display) -> bool {
+    unsafe {
+        XSetIOErrorHandler(Some(io_error_handler));
+
+        // Look up the exit handler at runtime to keep working with older versions of libX11.
+        let set_exit_handler = dlsym(RTLD_DEFAULT, cstr!("XSetIOErrorExitHandler").as_ptr());
+        if set_exit_handler.is_null() {
+            false
+        } else {
+            let set_exit_handler: SetIOErrorExitHandler = ::std::mem::transmute(set_exit_handler);
+            set_exit_handler(display, Some(io_error_exit_handler), ::std::ptr::null_mut());
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr::null_mut;
+
+    #[test]
+    fn io_error_stops_main_loop() {
+        assert!(!connection_lost());
+
+        // Xlib would pass the broken display, the handlers don't use it.
+        unsafe {
+            assert_eq!(io_error_handler(null_mut()), 0);
+        }
+        assert!(connection_lost());
+
+        unsafe {
+            io_error_exit_handler(null_mut(), null_mut());
+        }
+        assert!(connection_lost());
+    }
+}
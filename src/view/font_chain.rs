@@ -0,0 +1,127 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Selection of fallback fonts for glyphs the main font doesn't have
+//!
+//! Probing a font is expensive, so the chosen font is remembered per character.
+
+use std::collections::HashMap;
+
+/// Cache of the font chosen for each character
+pub struct FontChoice {
+    /// Index into the font chain or None if no font covers the character
+    chosen: HashMap<char, Option<usize>>,
+}
+
+impl FontChoice {
+    pub fn new() -> Self {
+        Self {
+            chosen: HashMap::new(),
+        }
+    }
+
+    /// Find the first of the fonts that covers the character.
+    ///
+    /// `covers(i, c)` checks if font i has a glyph for c. It is only called for characters that
+    /// have not been seen before. Returns None if no font covers the character.
+    pub fn font_for<F>(&mut self, c: char, font_count: usize, mut covers: F) -> Option<usize>
+    where
+        F: FnMut(usize, char) -> bool,
+    {
+        *self
+            .chosen
+            .entry(c)
+            .or_insert_with(|| (0..font_count).find(|i| covers(*i, c)))
+    }
+}
+
+/// Range of character codes a core X font has metrics for
+pub struct CharRange {
+    pub min_byte1: u32,
+    pub max_byte1: u32,
+    pub min_byte2: u32,
+    pub max_byte2: u32,
+}
+
+impl CharRange {
+    /// Index of the metrics of a character in an ISO 10646 font.
+    ///
+    /// Core fonts address the characters with two bytes, so only the basic multilingual plane can
+    /// be found. Returns None if the font has no metrics for the character.
+    pub fn glyph_index(&self, c: char) -> Option<usize> {
+        let code = c as u32;
+        let (byte1, byte2) = (code >> 8, code & 0xff);
+        if code > 0xffff
+            || byte1 < self.min_byte1
+            || byte1 > self.max_byte1
+            || byte2 < self.min_byte2
+            || byte2 > self.max_byte2
+        {
+            return None;
+        }
+        let row_len = self.max_byte2 - self.min_byte2 + 1;
+        Some(((byte1 - self.min_byte1) * row_len + byte2 - self.min_byte2) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn font_selection_is_cached() {
+        // Font 0 covers ASCII, font 1 covers CJK
+        let probes = Cell::new(0);
+        let mut covers = |i: usize, c: char| {
+            probes.set(probes.get() + 1);
+            match i {
+                0 => c.is_ascii(),
+                _ => ('\u{4e00}'..='\u{9fff}').contains(&c),
+            }
+        };
+        let mut choice = FontChoice::new();
+
+        assert_eq!(choice.font_for('a', 2, &mut covers), Some(0));
+        assert_eq!(choice.font_for('中', 2, &mut covers), Some(1));
+        assert_eq!(choice.font_for('😀', 2, &mut covers), None);
+        assert_eq!(probes.get(), 1 + 2 + 2);
+
+        // Known characters are not probed again
+        assert_eq!(choice.font_for('a', 2, &mut covers), Some(0));
+        assert_eq!(choice.font_for('中', 2, &mut covers), Some(1));
+        assert_eq!(choice.font_for('😀', 2, &mut covers), None);
+        assert_eq!(probes.get(), 5);
+    }
+    #[test]
+    fn glyph_index_in_range() {
+        let range = CharRange {
+            min_byte1: 0,
+            max_byte1: 0x4f,
+            min_byte2: 0x20,
+            max_byte2: 0xff,
+        };
+        assert_eq!(range.glyph_index('a'), Some(0x61 - 0x20));
+        assert_eq!(range.glyph_index('\u{120}'), Some(0xe0));
+        assert_eq!(range.glyph_index('\u{4e20}'), Some(0x4e * 0xe0));
+        // Outside of the rows and columns of the font
+        assert_eq!(range.glyph_index('\u{1f}'), None);
+        assert_eq!(range.glyph_index('\u{5000}'), None);
+        assert_eq!(range.glyph_index('😀'), None);
+    }
+}
@@ -0,0 +1,260 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! View without a window.
+//!
+//! Events are queued by the caller and passed to the presenter by the same backend code as the
+//! X11 view. The lines the presenter draws are captured as text. This allows testing the whole
+//! presenter loop without a display server.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use model::history::History;
+use model::interpreter::InteractiveInterpreter;
+use presenter::display_line::DisplayLine;
+use presenter::{DrawLineTrait, Presenter};
+use view::backend::{Backend, Event};
+use view::colors;
+
+/// Lines drawn in one frame
+struct Capture {
+    /// Text of each row, including the decoration
+    rows: RefCell<Vec<String>>,
+    /// Row and column of the cursor, if any has been drawn
+    cursor: std::cell::Cell<Option<(usize, usize)>>,
+}
+
+impl DrawLineTrait for Capture {
+    fn draw_line(&self, row: usize, line: &DisplayLine) {
+        let mut text = String::new();
+        for cell in line.prefix.iter().chain(line.line.iter()) {
+            text.extend(cell.chars());
+        }
+        let mut rows = self.rows.borrow_mut();
+        if row < rows.len() {
+            rows[row] = text;
+        }
+        if let Some(col) = line.cursor_col {
+            self.cursor.set(Some((row, col)));
+        }
+    }
+}
+
+/// View that renders into a buffer instead of a window
+pub struct HeadlessView {
+    presenter: Presenter,
+
+    /// Height of the view in characters
+    height: usize,
+
+    /// Events that haven't been passed to the presenter yet
+    events: VecDeque<Event>,
+
+    /// Does the presenter need to be drawn again?
+    needs_redraw: bool,
+
+    /// Text of each row of the last frame
+    rows: Vec<String>,
+
+    /// Cursor position of the last frame
+    cursor: Option<(usize, usize)>,
+
+    /// Colors of the palette entries, for transcripts
+    colors: [u32; 256],
+}
+
+impl HeadlessView {
+    /// Show the presenter in a view of the given size in characters
+    pub fn new(presenter: Presenter, width: usize, height: usize) -> Self {
+        let mut events = VecDeque::new();
+        events.push_back(Event::Resize(width, height));
+        let mut colors = [0; 256];
+        colors::setupColors(&mut colors);
+        Self {
+            presenter,
+            height,
+            events,
+            needs_redraw: true,
+            rows: Vec::new(),
+            cursor: None,
+            colors,
+        }
+    }
+
+    /// Clean up and get back the interpreter
+    pub fn finish(self) -> (InteractiveInterpreter, History) {
+        self.presenter.finish()
+    }
+
+    /// Queue an event for the next cycle
+    pub fn push_event(&mut self, event: Event) {
+        self.events.push_back(event);
+    }
+
+    /// Pass all queued events to the presenter and draw a frame if anything changed.
+    ///
+    /// Returns false if the view was closed.
+    pub fn cycle(&mut self) -> bool {
+        self.save_transcripts();
+        if self.presenter.prepare_cycle() {
+            self.mark_redraw();
+        }
+        while let Some(event) = self.poll_event() {
+            if !self.handle_event(event) {
+                return false;
+            }
+        }
+        if self.needs_redraw {
+            self.needs_redraw = false;
+            self.render();
+        }
+        true
+    }
+
+    /// Text of each row of the last frame. Rows that haven't been drawn are empty.
+    pub fn rows(&self) -> &[String] {
+        &self.rows
+    }
+
+    /// Row and column of the cursor in the last frame
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        self.cursor
+    }
+}
+
+impl Backend for HeadlessView {
+    fn presenter(&mut self) -> &mut Presenter {
+        &mut self.presenter
+    }
+
+    fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    fn render(&mut self) {
+        let capture = Capture {
+            rows: RefCell::new(vec![String::new(); self.height]),
+            cursor: std::cell::Cell::new(None),
+        };
+        self.presenter.display_lines(&capture);
+        self.cursor = capture.cursor.get();
+        self.rows = capture.rows.into_inner();
+    }
+
+    fn mark_redraw(&mut self) {
+        self.needs_redraw = true;
+    }
+
+    fn confirm_exit(&self) -> bool {
+        true
+    }
+
+    fn colors(&self) -> &[u32; 256] {
+        &self.colors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::session::test::new_test_session;
+    use model::session::TranscriptFormat;
+    use presenter::test::test_presenter;
+    use presenter::{ModifierState, SpecialKey};
+    use std::path::PathBuf;
+    use view::backend::{Key, Scroll};
+
+    const NO_MOD: ModifierState = ModifierState {
+        shift_pressed: false,
+        control_pressed: false,
+        meta_pressed: false,
+    };
+
+    fn headless_view() -> HeadlessView {
+        let session = new_test_session(b"prompt");
+        HeadlessView::new(test_presenter(&session, 40, 5), 40, 5)
+    }
+
+    /// Queue the key presses to type a text
+    fn type_text(view: &mut HeadlessView, text: &str) {
+        for c in text.chars() {
+            view.push_event(Event::Key(NO_MOD, None, Some(c.to_string())));
+        }
+    }
+
+    fn press(view: &mut HeadlessView, key: SpecialKey) {
+        view.push_event(Event::Key(NO_MOD, Some(Key::Special(key)), None));
+    }
+
+    #[test]
+    fn keystrokes_are_rendered() {
+        let mut view = headless_view();
+
+        type_text(&mut view, "echo hi");
+        assert!(view.cycle());
+        assert_eq!(view.rows().len(), 5);
+        let input_row = view
+            .rows()
+            .iter()
+            .position(|r| r.trim_end() == " $ echo hi")
+            .unwrap();
+        assert_eq!(view.cursor(), Some((input_row, 10)));
+
+        for _ in 0..3 {
+            press(&mut view, SpecialKey::Backspace);
+        }
+        press(&mut view, SpecialKey::Home);
+        assert!(view.cycle());
+        assert_eq!(view.rows()[input_row].trim_end(), " $ echo");
+        assert_eq!(view.cursor(), Some((input_row, 3)));
+
+        // Scrolling an empty session changes nothing
+        view.push_event(Event::Scroll(NO_MOD, Scroll::Up));
+        assert!(view.cycle());
+        assert_eq!(view.rows()[input_row].trim_end(), " $ echo");
+
+        // Without running commands, the view closes right away
+        view.push_event(Event::Close);
+        assert!(!view.cycle());
+
+        view.finish().0.shutdown();
+    }
+
+    #[test]
+    fn transcript_requests_are_written() {
+        let mut session = new_test_session(b"prompt");
+        let mut view = HeadlessView::new(test_presenter(&session, 40, 5), 40, 5);
+
+        let path = std::env::temp_dir().join(format!("bite-headless-{}.txt", std::process::id()));
+        session.request_transcript(path.clone(), TranscriptFormat::Text);
+        assert!(view.cycle());
+        assert!(std::fs::read_to_string(&path).unwrap().contains("prompt"));
+        std::fs::remove_file(&path).unwrap();
+
+        // Failures are shown in the session
+        session.request_transcript(PathBuf::from("/nonexistent/x.txt"), TranscriptFormat::Text);
+        assert!(view.cycle());
+        assert!(view
+            .rows()
+            .iter()
+            .any(|r| r.contains("BiTE: transcript can't write")));
+
+        view.finish().0.shutdown();
+    }
+}
@@ -18,8 +18,10 @@
 
 //! View component of the model-view-presenter pattern.
 //!
-//! Currently only available for X11.
+//! Currently only available for X11. The events are passed to the presenter by the backend trait,
+//! which the headless view of the tests implements as well.
 
+use std::cell::RefCell;
 use std::cmp;
 use std::collections::HashMap;
 use std::ffi::CStr;
@@ -31,18 +33,25 @@ use x11::xlib::*;
 
 use model::history::History;
 use model::interpreter::InteractiveInterpreter;
-use model::screen::Cell;
+use model::screen::{Cell, CELL_UTF8_LEN};
 use model::session::{LineType, SharedSession};
 use presenter;
 use presenter::display_line::*;
-use presenter::{
-    DrawLineTrait, ModifierState, NeedRedraw, Presenter, PresenterCommand, SpecialKey,
-};
+use presenter::{DrawLineTrait, ModifierState, Presenter, SpecialKey};
 use tools::polling;
 
 use term::terminfo::TermInfo;
 
-mod colors;
+pub mod backend;
+pub mod colors;
+mod connection;
+mod font_chain;
+#[cfg(test)]
+pub mod headless;
+pub mod transcript;
+
+use self::backend::{Backend, Event, Key, Scroll};
+use self::font_chain::{CharRange, FontChoice};
 
 /// Initial width of the window in pixels
 const WIDTH: i32 = 400;
@@ -50,9 +59,6 @@ const WIDTH: i32 = 400;
 /// Initial height of the window in pixels
 const HEIGHT: i32 = 200;
 
-/// How many colors to use for different prompts
-const NUM_PROMPT_COLORS: usize = 20;
-
 /// Number of pixels to reserve for prompt seam
 const COLOR_SEAM_WIDTH: i32 = 20;
 
@@ -68,13 +74,98 @@ const COMMAND_SEAM_WIDTH: u32 = 12;
 /// Width in pixel of colored seam to draw the prompt color for an input line.
 const INPUT_SEAM_WIDTH: u32 = 0;
 
-/// Number of pixels between text and next line
-const LINE_PADDING: i32 = 1;
+/// Milliseconds without output after which the cursor starts blinking again
+const OUTPUT_IDLE_TIME_MS: u64 = 300;
+
+/// Minimum milliseconds between two redraws
+const REDRAW_INTERVAL_MS: u64 = 16;
+
+/// Longest time in milliseconds the main loop sleeps without checking for changes
+const MAX_WAIT_MS: u64 = 1000;
+
+/// Compute the total height of a line in pixel.
+///
+/// The padding is added above and below the text. A line is at least one pixel high.
+fn line_height(font_height: i32, line_padding: i32) -> i32 {
+    cmp::max(1, font_height + 2 * line_padding)
+}
+
+/// Characters whose advances are compared to detect a proportional font
+const FIXED_WIDTH_PROBE: &str = "iWm.0_";
+
+/// Check if the advances of the probe characters are the same, give or take a pixel.
+fn is_fixed_width(advances: &[i32]) -> bool {
+    match (advances.iter().min(), advances.iter().max()) {
+        (Some(min), Some(max)) => max - min <= 1,
+        _ => true,
+    }
+}
+
+/// Compute the initial window size in pixels from the values of COLUMNS and LINES.
+///
+/// Missing or invalid values fall back to the default size.
+fn initial_window_size(
+    columns: Option<String>,
+    lines: Option<String>,
+    font_width: i32,
+    line_height: i32,
+) -> (i32, i32) {
+    let chars = |value: Option<String>| {
+        value
+            .and_then(|v| v.trim().parse::<i32>().ok())
+            .filter(|n| *n > 0)
+    };
+    (
+        chars(columns).map_or(WIDTH, |c| c * font_width),
+        chars(lines).map_or(HEIGHT, |l| l * line_height),
+    )
+}
+
+/// Times the cursor stays on and off when blinking
+#[derive(Debug, Clone, Copy)]
+pub struct CursorBlink {
+    /// Time the cursor is shown
+    pub on: Duration,
+    /// Time the cursor is hidden. Zero means the cursor does not blink.
+    pub off: Duration,
+}
+
+impl CursorBlink {
+    /// Time until the cursor changes its state. None if it stays as it is.
+    fn flip_duration(&self, cursor_on: bool) -> Option<Duration> {
+        if !cursor_on {
+            Some(self.off)
+        } else if self.off == Duration::from_millis(0) {
+            None
+        } else {
+            Some(self.on)
+        }
+    }
+}
+
+/// Part of the window that needs to be drawn again, ordered by size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Repaint {
+    /// Nothing changed
+    Nothing,
+    /// Only the cursor changed its state
+    Cursor,
+    /// The content changed
+    Window,
+}
+
+impl Repaint {
+    /// Combine two requests, the larger part wins.
+    fn and(self, other: Repaint) -> Repaint {
+        cmp::max(self, other)
+    }
+}
 
 /// Handles all interaction with the X11 system.
 ///
-/// This struct represents the view component of the model-view-presenter pattern. It sends events
-/// to the presenter via method calls and obtains the items to draw via an iterator of strings.
+/// This struct represents the view component of the model-view-presenter pattern. It translates
+/// the X11 events and passes them to the presenter via the Backend trait and obtains the items to
+/// draw via an iterator of strings.
 pub struct Gui {
     /// X11 server connection
     display: *mut Display,
@@ -97,6 +188,10 @@ pub struct Gui {
 
     /// Selected fontset to draw the output
     font_set: XFontSet,
+    /// Fontsets to try if the selected one has no glyph for a character
+    fallback_font_sets: Vec<XFontSet>,
+    /// Fontset chosen for each character drawn so far
+    font_choice: RefCell<FontChoice>,
 
     /// Height of the font above base line in pixel
     font_ascent: i32,
@@ -108,6 +203,8 @@ pub struct Gui {
 
     /// Total height of a line in pixel
     line_height: i32,
+    /// Number of pixels between text and next line
+    line_padding: i32,
 
     /// Current width of the window in pixels
     window_width: i32,
@@ -120,14 +217,19 @@ pub struct Gui {
     cursor_on: bool,
     /// When was the last time, the cursor changed state?
     cursor_flip_time: SystemTime,
+    /// How fast the cursor blinks
+    cursor_blink: CursorBlink,
+
+    /// Ask before closing the window while commands are running
+    confirm_exit: bool,
 
-    /// Do we need to redraw the window ASAP?
-    needs_redraw: bool,
+    /// What do we need to redraw ASAP?
+    repaint: Repaint,
     /// When was the last time we rendered the window contents?
     redraw_time: SystemTime,
 
-    /// Do we need to check for events or can we wait a bit?
-    gate: polling::Gate,
+    /// Pipe that wakes up the main loop when the session changes
+    wakeup: polling::WakeupPipe,
 
     /// Presenter in the model-view-presenter.
     ///
@@ -141,8 +243,8 @@ pub struct Gui {
     /// TODO: Check if that should be better different GCs.
     colors: [u32; 256],
 
-    /// Prompt colors
-    prompt_colors: [u32; NUM_PROMPT_COLORS],
+    /// Prompt colors, never empty
+    prompt_colors: Vec<u32>,
 }
 
 /// Default font to draw the output
@@ -220,6 +322,42 @@ fn create_font_set(display: *mut Display, font_name: &str) -> XFontSet {
     font_set
 }
 
+/// Check if one of the ISO 10646 fonts of the font set has a glyph for the character.
+///
+/// X draws the default character for missing glyphs, so the advance of the text can't tell. The
+/// per-character metrics of a missing glyph are all zero instead.
+fn font_set_has_glyph(font_set: XFontSet, c: char) -> bool {
+    let mut xfonts: *mut *mut XFontStruct = null_mut();
+    let mut font_names: *mut *mut c_char = null_mut();
+    let fnum = unsafe { XFontsOfFontSet(font_set, &mut xfonts, &mut font_names) };
+    (0..fnum as isize).any(|i| unsafe {
+        let name = CStr::from_ptr(*font_names.offset(i)).to_string_lossy();
+        if !name.to_lowercase().ends_with("iso10646-1") {
+            return false;
+        }
+        let xfp = *xfonts.offset(i);
+        let range = CharRange {
+            min_byte1: (*xfp).min_byte1,
+            max_byte1: (*xfp).max_byte1,
+            min_byte2: (*xfp).min_char_or_byte2,
+            max_byte2: (*xfp).max_char_or_byte2,
+        };
+        match range.glyph_index(c) {
+            None => false,
+            // All characters in the range have the same metrics
+            Some(_) if (*xfp).per_char.is_null() => true,
+            Some(index) => {
+                let cs = &*(*xfp).per_char.offset(index as isize);
+                cs.width != 0
+                    || cs.lbearing != 0
+                    || cs.rbearing != 0
+                    || cs.ascent != 0
+                    || cs.descent != 0
+            }
+        }
+    })
+}
+
 struct DrawLine<'a>(&'a Gui);
 
 impl<'a> DrawLineTrait for DrawLine<'a> {
@@ -229,6 +367,31 @@ impl<'a> DrawLineTrait for DrawLine<'a> {
     }
 }
 
+/// Draws only the cursors of the lines, see Gui::render_cursor
+struct DrawCursor<'a> {
+    gui: &'a Gui,
+    /// Row and screen column of the last cursor that has been drawn
+    last: std::cell::Cell<Option<(i32, i32)>>,
+}
+
+impl<'a> DrawLineTrait for DrawCursor<'a> {
+    fn draw_line(&self, row: usize, line: &DisplayLine) {
+        let row = row as i32;
+        // The cursor reaches into the padding of the next row, which covers it in a full render.
+        if let Some((last_row, column)) = self.last.get() {
+            if last_row + 1 == row {
+                self.gui.draw_cell_at(row, line, column);
+            }
+        }
+        if let Some(cursor_col) = line.cursor_col {
+            let column = cursor_col as i32;
+            self.gui.draw_cell_at(row, line, column);
+            self.gui.draw_cursor(row, line);
+            self.last.set(Some((row, column)));
+        }
+    }
+}
+
 impl Gui {
     /// Open a server connection and prepare for event processing.
     ///
@@ -248,29 +411,40 @@ impl Gui {
     /// Not all return codes are checked (yet), so might cause crashes that could have been
     /// detected at startup.
     pub fn new(
-        session: SharedSession,
+        mut session: SharedSession,
         interpreter: InteractiveInterpreter,
         history: History,
         user_font_name: Option<String>,
-        feat_compose_variant: presenter::ComposeVariant,
+        font_chain: Vec<String>,
+        presenter_config: presenter::PresenterConfig,
+        prompt_colors: Vec<u32>,
+        line_padding: i32,
+        cursor_blink: CursorBlink,
+        confirm_exit: bool,
     ) -> Result<Gui, String> {
         let WM_PROTOCOLS = cstr!("WM_PROTOCOLS");
         let WM_DELETE_WINDOW = cstr!("WM_DELETE_WINDOW");
         let EMPTY = cstr!("");
         let IMNONE = cstr!("@im=none");
 
+        // Let the session wake up the main loop when output arrives
+        let wakeup = polling::WakeupPipe::new().map_err(|e| format!("{}", e))?;
+        session.set_wakeup(wakeup.clone());
+
+        // Pick the colors of new conversations from the configured ones
+        let prompt_colors = if prompt_colors.is_empty() {
+            colors::DEFAULT_PROMPT_COLORS.to_vec()
+        } else {
+            prompt_colors
+        };
+        session.set_num_prompt_colors(prompt_colors.len());
+
         // Create initial presenter
         let mut presenter = {
             // Only the presenter needs to know the term info for TUI applications.
             let term_info = TermInfo::from_name("xterm").map_err(|e| format!("{}", e))?;
-            Presenter::new(
-                session,
-                interpreter,
-                history,
-                term_info,
-                feat_compose_variant,
-            )
-            .or_else(|e| Err(e.readable("during initialisation")))
+            Presenter::new(session, interpreter, history, term_info, presenter_config)
+                .or_else(|e| Err(e.readable("during initialisation")))
         }?;
 
         presenter.update_prompt();
@@ -280,6 +454,9 @@ impl Gui {
             if display.is_null() {
                 return Err("Can't open display".to_string());
             }
+            if !connection::install_handlers(display) {
+                warn!("Xlib will terminate BiTE if the connection to the X server is lost");
+            }
             let screen = XDefaultScreen(display);
             let root = XRootWindow(display, screen);
             let black_pixel = XBlackPixel(display, screen);
@@ -360,6 +537,20 @@ impl Gui {
                 return Err("Can't find any usable font.".to_string());
             }
 
+            // Load the fallback fonts. Those that can't be found are ignored.
+            let fallback_font_sets = font_chain
+                .into_iter()
+                .filter_map(|mut name| {
+                    name.push('\0');
+                    let fs = create_font_set(display, &name);
+                    if fs == null_mut() {
+                        None
+                    } else {
+                        Some(fs)
+                    }
+                })
+                .collect();
+
             let mut xfonts: *mut *mut XFontStruct = null_mut();
             let mut font_names: *mut *mut c_char = null_mut();
             let font_extents = XExtentsOfFontSet(font_set);
@@ -377,16 +568,38 @@ impl Gui {
             let font_height = (*font_extents).max_logical_extent.height;
             let font_width = (*font_extents).max_logical_extent.width;
 
+            // Column math assumes that all characters have the same width
+            let advances: Vec<i32> = FIXED_WIDTH_PROBE
+                .chars()
+                .map(|c| {
+                    let mut buf = [0; 4];
+                    let s = c.encode_utf8(&mut buf[..]);
+                    Xutf8TextEscapement(font_set, s.as_ptr() as *const i8, s.len() as i32)
+                })
+                .collect();
+            if !is_fixed_width(&advances) {
+                warn!(
+                    "Font is not fixed-width (advances {:?}), text will not line up",
+                    advances
+                );
+            }
+
+            // The window was created before the font was known. Now the size requested in the
+            // environment can be applied.
+            let (window_width, window_height) = initial_window_size(
+                std::env::var("COLUMNS").ok(),
+                std::env::var("LINES").ok(),
+                font_width as i32,
+                line_height(font_height as i32, line_padding),
+            );
+            if (window_width, window_height) != (WIDTH, HEIGHT) {
+                XResizeWindow(display, window, window_width as u32, window_height as u32);
+            }
+
             let mut colors: [u32; 256] = ::std::mem::uninitialized();
 
             colors::setupColors(&mut colors);
 
-            let prompt_colors: [u32; NUM_PROMPT_COLORS] = [
-                0xFF1313, 0xFF6C6C, 0xFF4242, 0xD40000, 0xA90000, 0xFF9C13, 0xFFC16C, 0xFFB042,
-                0xD47B00, 0xA96200, 0x1766A7, 0x5992C2, 0x3779B0, 0x094F89, 0x063E6D, 0x0FCD0F,
-                0x5DDC5D, 0x37D237, 0x00AA00, 0x008700,
-            ];
-
             let gui = Gui {
                 display,
                 window,
@@ -397,23 +610,28 @@ impl Gui {
                 wm_delete_window,
                 wm_protocols,
                 font_set,
+                fallback_font_sets,
+                font_choice: RefCell::new(FontChoice::new()),
                 font_ascent: asc as i32,
                 font_height: font_height as i32,
                 font_width: font_width as i32,
 
-                line_height: font_height as i32 + 2 * LINE_PADDING,
-                window_width: WIDTH,
-                window_height: HEIGHT,
+                line_height: line_height(font_height as i32, line_padding),
+                line_padding,
+                window_width,
+                window_height,
 
                 presenter,
                 have_focus: false,
                 cursor_on: false,
                 cursor_flip_time: SystemTime::now(),
+                cursor_blink,
+                confirm_exit,
 
-                needs_redraw: true,
+                repaint: Repaint::Window,
                 redraw_time: SystemTime::now(),
 
-                gate: polling::Gate::new(::std::time::Duration::from_millis(20)),
+                wakeup,
 
                 colors,
                 prompt_colors,
@@ -463,6 +681,7 @@ impl Gui {
             LineType::Prompt => (Some(PROMPT_SEAM_WIDTH), false),
             LineType::Command(_, _, _) => (Some(COMMAND_SEAM_WIDTH), true),
             LineType::Input => (Some(INPUT_SEAM_WIDTH), false),
+            LineType::Continuation => (Some(INPUT_SEAM_WIDTH), false),
             LineType::InputInfo => (Some(INPUT_SEAM_WIDTH), false),
             LineType::MenuDecoration => (None, false),
             LineType::SelectedMenuItem(_) => (None, false),
@@ -475,7 +694,7 @@ impl Gui {
         if let Some(w) = prefixWidth {
             unsafe {
                 let y = self.line_height * row;
-                let color_index = (line.prompt_hash % (NUM_PROMPT_COLORS as u64)) as usize;
+                let color_index = line.prompt_color % self.prompt_colors.len();
                 XSetForeground(
                     self.display,
                     self.gc,
@@ -510,13 +729,60 @@ impl Gui {
             COLOR_SEAM_WIDTH
         };
         let mut col = 0;
-        for cell in line.prefix {
-            self.draw_cell(x_offs, col as i32, row, cell);
-            col += 1;
+        let mut cells = line.prefix.iter().chain(line.line.iter()).peekable();
+        while let Some(cell) = cells.next() {
+            let columns = Self::cell_columns(cell, &mut cells);
+            self.draw_cell(x_offs, col, columns, row, cell);
+            col += columns;
         }
-        for cell in line.line.iter() {
-            self.draw_cell(x_offs, col as i32, row, cell);
-            col += 1;
+    }
+
+    /// Number of columns the cell covers on screen.
+    ///
+    /// A wide character covers the following cell, which holds its right half. That cell is
+    /// taken from the iterator. The right half of a wide character without its left half, e.g.
+    /// due to horizontal scrolling, only draws its background.
+    fn cell_columns<'a, I>(cell: &Cell, cells: &mut std::iter::Peekable<I>) -> i32
+    where
+        I: Iterator<Item = &'a Cell>,
+    {
+        if !cell.is_wide_tail() && cells.peek().map_or(false, |next| next.is_wide_tail()) {
+            cells.next();
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Draw the cell of the line that covers the given screen column again.
+    ///
+    /// Past the end of the line, the column is cleared.
+    fn draw_cell_at(&self, row: i32, line: &DisplayLine, column: i32) {
+        let x_offs = if line.is_a == LineType::Tui {
+            0
+        } else {
+            COLOR_SEAM_WIDTH
+        };
+        let mut col = 0;
+        let mut cells = line.prefix.iter().chain(line.line.iter()).peekable();
+        while let Some(cell) = cells.next() {
+            let columns = Self::cell_columns(cell, &mut cells);
+            if column < col + columns {
+                self.draw_cell(x_offs, col, columns, row, cell);
+                return;
+            }
+            col += columns;
+        }
+        unsafe {
+            XClearArea(
+                self.display,
+                self.window,
+                self.font_width * column + x_offs,
+                self.line_height * row,
+                self.font_width as u32,
+                self.line_height as u32,
+                0,
+            );
         }
     }
 
@@ -529,7 +795,7 @@ impl Gui {
         if let Some(cursor_col) = line.cursor_col {
             // Draw a cursor if requested
             let x = self.font_width * (cursor_col as i32) + x_offs;
-            let y = self.line_height * row + LINE_PADDING;
+            let y = self.line_height * row + self.line_padding;
 
             if self.cursor_on && self.have_focus {
                 unsafe {
@@ -559,19 +825,41 @@ impl Gui {
         }
     }
 
+    /// Find the fontset to draw the character with.
+    ///
+    /// Tries the selected fontset first, then the fallback fonts in order. If none of them has a
+    /// glyph, the selected fontset is used, which draws its default string instead.
+    fn font_set_for(&self, c: char) -> XFontSet {
+        if c.is_ascii() || self.fallback_font_sets.is_empty() {
+            return self.font_set;
+        }
+        let chain_len = 1 + self.fallback_font_sets.len();
+        let chosen = self
+            .font_choice
+            .borrow_mut()
+            .font_for(c, chain_len, |i, c| {
+                let font_set = if i == 0 {
+                    self.font_set
+                } else {
+                    self.fallback_font_sets[i - 1]
+                };
+                font_set_has_glyph(font_set, c)
+            });
+        match chosen {
+            Some(i) if i > 0 => self.fallback_font_sets[i - 1],
+            _ => self.font_set,
+        }
+    }
+
     /// Draw a single colored cell at the given character position
-    pub fn draw_cell(&self, x_offs: i32, column: i32, row: i32, cell: &Cell) {
+    ///
+    /// The cell covers the given number of columns.
+    pub fn draw_cell(&self, x_offs: i32, column: i32, columns: i32, row: i32, cell: &Cell) {
         let x = self.font_width * column + x_offs;
         let y = self.line_height * row;
 
         // TODO: Cache colors
-        // TODO: Configure default colors
-        let fg_color = cell
-            .foreground_color()
-            .map_or(0xFFD700, |c| self.colors[c as usize]);
-        let bg_color = cell
-            .background_color()
-            .map_or(0x000000, |c| self.colors[c as usize]);
+        let (fg_color, bg_color) = colors::cell_colors(cell, &self.colors);
 
         unsafe {
             XSetForeground(self.display, self.gc, bg_color as u64);
@@ -581,37 +869,38 @@ impl Gui {
                 self.gc,
                 x,
                 y,
-                self.font_width as u32,
+                (columns * self.font_width) as u32,
                 self.line_height as u32,
             );
 
             XSetForeground(self.display, self.gc, fg_color as u64);
-            let mut buf = [0; 4];
+            let mut buf = [0; CELL_UTF8_LEN];
             let s = cell.encode_utf8(&mut buf[..]);
 
             Xutf8DrawString(
                 self.display,
                 self.window,
-                self.font_set,
+                self.font_set_for(cell.code_point()),
                 self.gc,
                 x,
-                y + self.font_ascent + LINE_PADDING,
+                y + self.font_ascent + self.line_padding,
                 s.as_ptr() as *const i8,
                 s.len() as i32,
             )
         };
     }
 
-    /// Render the current presentation to the window.
+    /// Render only the cursors to the window.
     ///
-    /// Redraws the whole window, not just the exposed rectangle.
-    pub fn render(&mut self) {
-        unsafe { XClearWindow(self.display, self.window) };
-        // TODO: Set colors
-
-        // Draw the text
+    /// Used when the cursor blinks. The cells under the cursors are drawn again to erase their
+    /// previous state, everything else stays as it is. If the cursor moved, the content changed
+    /// as well and the whole window is rendered instead.
+    fn render_cursor(&mut self) {
         let p = &self.presenter;
-        p.display_lines(&DrawLine(self));
+        p.display_cursor_lines(&DrawCursor {
+            gui: self,
+            last: std::cell::Cell::new(None),
+        });
     }
 
     /// Compute the number of lines in the window, rounded down.
@@ -619,24 +908,37 @@ impl Gui {
         (self.window_height / self.line_height) as usize
     }
 
-    /// Redraw right now and remember it.
-    pub fn force_redraw(&mut self) {
-        self.render();
-        self.flush();
-        self.needs_redraw = false;
-        self.redraw_time = SystemTime::now();
+    /// Redraw what has been marked and remember it.
+    fn redraw(&mut self) {
+        if self.repaint == Repaint::Cursor {
+            self.render_cursor();
+            self.flush();
+            self.repaint = Repaint::Nothing;
+            self.redraw_time = SystemTime::now();
+        } else {
+            self.force_redraw();
+        }
     }
 
-    /// Mark the GUI to be redrawn in the next frame.
-    pub fn mark_redraw(&mut self) {
-        self.needs_redraw = true;
+    /// Mark the cursor to be redrawn in the next frame.
+    fn mark_cursor_redraw(&mut self) {
+        self.repaint = self.repaint.and(Repaint::Cursor);
+    }
+
+    /// Earliest time a marked redraw may happen. None if no redraw is needed.
+    fn redraw_deadline(&self) -> Option<SystemTime> {
+        if self.repaint != Repaint::Nothing {
+            Some(self.redraw_time + Duration::from_millis(REDRAW_INTERVAL_MS))
+        } else {
+            None
+        }
     }
 
     /// Check if we should redraw in this iteration.
     pub fn should_redraw(&self) -> bool {
-        if self.needs_redraw {
+        if self.repaint != Repaint::Nothing {
             if let Ok(dur) = self.redraw_time.elapsed() {
-                dur >= Duration::from_millis(16)
+                dur >= Duration::from_millis(REDRAW_INTERVAL_MS)
             } else {
                 // Problems getting the time? Redraw to fix it.
                 true
@@ -646,29 +948,176 @@ impl Gui {
         }
     }
 
-    /// Set the cursor to a state and start the blink cycle anew.
-    pub fn cursor_now(&mut self, on: bool) {
-        self.cursor_on = on;
-        self.cursor_flip_time = SystemTime::now();
+    /// Time at which the cursor changes its state. None if it stays as it is.
+    fn cursor_flip_deadline(&self) -> Option<SystemTime> {
+        self.cursor_blink
+            .flip_duration(self.cursor_on)
+            .map(|duration| self.cursor_flip_time + duration)
+    }
+
+    /// Sleep until an X event or a session change arrives, or the next deadline has passed.
+    fn wait_for_wakeup(&mut self) {
+        self.flush();
+        if unsafe { XPending(self.display) } == 0 {
+            let now = SystemTime::now();
+            let timeout = polling::time_until_next(
+                now,
+                &[
+                    self.cursor_flip_deadline(),
+                    self.redraw_deadline(),
+                    self.presenter.animation_deadline(now),
+                ],
+                Duration::from_millis(MAX_WAIT_MS),
+            );
+            let connection = unsafe { XConnectionNumber(self.display) };
+            polling::wait_readable(&[connection, self.wakeup.fd()], timeout);
+        }
+        self.wakeup.drain();
     }
 
     /// Checks if we need to flip the cursor state.
     pub fn check_cursor_flip(&mut self) {
-        let cursor_on_time = Duration::from_millis(1000);
-        let cursor_off_time = Duration::from_millis(500);
+        // Keep the cursor steady while output is arriving
+        if self
+            .presenter
+            .output_streaming(Duration::from_millis(OUTPUT_IDLE_TIME_MS))
+        {
+            if !self.cursor_on {
+                self.cursor_on = true;
+                self.mark_cursor_redraw();
+            }
+            self.cursor_flip_time = SystemTime::now();
+            return;
+        }
 
-        let cursor_flip_duration = if self.cursor_on {
-            cursor_on_time
-        } else {
-            cursor_off_time
+        let cursor_flip_duration = match self.cursor_blink.flip_duration(self.cursor_on) {
+            Some(duration) => duration,
+            None => return,
         };
 
         if let Ok(elapsed) = self.cursor_flip_time.elapsed() {
             if elapsed >= cursor_flip_duration {
                 self.cursor_on = !self.cursor_on;
                 self.cursor_flip_time = SystemTime::now();
-                self.mark_redraw();
+                self.mark_cursor_redraw();
+            }
+        }
+    }
+
+    /// Translate an event of the server to an event of the view.
+    ///
+    /// Keys are looked up in the input context, positions are converted to characters.
+    fn translate_event(&mut self, event: XEvent) -> Event {
+        match event.get_type() {
+            ConfigureNotify => {
+                let info = unsafe { &event.configure };
+                self.window_width = info.width;
+                self.window_height = info.height;
+                Event::Resize(
+                    (self.window_width / self.font_width) as usize,
+                    (self.window_height / self.line_height) as usize,
+                )
+            }
+            Expose => Event::Expose,
+            FocusIn => Event::Focus(true),
+            FocusOut => Event::Focus(false),
+            KeyPress => {
+                let mut info = unsafe { event.key };
+                trace!("KeyPress: {:?}", info);
+                let mut keysym: c_ulong = 0;
+                let mut buf: [c_char; 20] = unsafe { ::std::mem::uninitialized() };
+                let mut status = 0;
+                let count = unsafe {
+                    Xutf8LookupString(
+                        self.xic,
+                        &mut info,
+                        &mut buf[0],
+                        (::std::mem::size_of_val(&buf) - 1) as c_int,
+                        &mut keysym,
+                        &mut status,
+                    )
+                };
+                assert!((count as usize) < ::std::mem::size_of_val(&buf));
+                buf[count as usize] = 0;
+                trace!("KeyPress: {}, »{:?}«", status, buf);
+
+                // Handle movement and delete. They are all keysyms
+                let mut key = None;
+                let mod_state = modifier_state_from_event(info.state);
+                if status == XLookupKeySym || status == XLookupBoth {
+                    match KEYSYM2KEY.get(&keysym) {
+                        Some(special) => {
+                            key = Some(Key::Special(*special));
+                        }
+                        None => {
+                            let maybe_letter = keysym;
+                            let is_digit =
+                                '0' as c_ulong <= maybe_letter && maybe_letter <= '9' as c_ulong;
+                            if ((('a' as c_ulong <= maybe_letter
+                                && maybe_letter <= 'z' as c_ulong)
+                                || ('A' as c_ulong <= maybe_letter
+                                    && maybe_letter <= 'Z' as c_ulong))
+                                && mod_state.not_only_shift())
+                                || (is_digit && mod_state.meta_pressed)
+                            {
+                                // A letter and not only shift was pressed. Might be a control key
+                                // we're interested in. Alt + digit enters a numeric argument.
+
+                                // Normalize to lower case
+                                let letter = if 'A' as c_ulong <= maybe_letter
+                                    && maybe_letter <= 'Z' as c_ulong
+                                {
+                                    maybe_letter + 32
+                                } else {
+                                    maybe_letter
+                                };
+                                key = Some(Key::Letter(letter as u8));
+                            }
+                        }
+                    }
+                }
+                let text = if status == XLookupChars || status == XLookupBoth {
+                    unsafe { CStr::from_ptr(&buf[0]) }
+                        .to_str()
+                        .ok()
+                        .map(|s| s.to_string())
+                } else {
+                    None
+                };
+                Event::Key(mod_state, key, text)
+            }
+            ButtonPress | ButtonRelease => {
+                let pressed = event.get_type() == ButtonPress;
+                let info = unsafe { &event.button };
+                let mod_state = modifier_state_from_event(info.state);
+                match info.button {
+                    1 | 2 | 3 => {
+                        if 0 <= info.y
+                            && info.y < self.window_height
+                            && 0 <= info.x
+                            && info.x < self.window_width
+                        {
+                            // TODO: The X coordinate is incorrect in TUI lines
+                            Event::Button(
+                                pressed,
+                                mod_state,
+                                info.button as usize,
+                                ((info.x - COLOR_SEAM_WIDTH) / self.font_width) as usize,
+                                (info.y / self.line_height) as usize,
+                            )
+                        } else {
+                            Event::Other
+                        }
+                    }
+                    4 if pressed => Event::Scroll(mod_state, Scroll::Up),
+                    5 if pressed => Event::Scroll(mod_state, Scroll::Down),
+                    6 if pressed => Event::Scroll(mod_state, Scroll::Left),
+                    7 if pressed => Event::Scroll(mod_state, Scroll::Right),
+                    _ => Event::Other,
+                }
             }
+            ClientMessage => Event::Close,
+            _ => Event::Other,
         }
     }
 
@@ -676,221 +1125,196 @@ impl Gui {
     ///
     /// Waits for events and dispatches then to the presenter or to itself.
     pub fn main_loop(&mut self) {
+        // If something happened in the last iteration, assume more will follow and don't sleep.
+        let mut had_event = true;
         loop {
-            trace!("main_loop: Wait before gate");
-            self.gate.wait();
-            trace!("main_loop: Gate passed");
+            if connection::connection_lost() || self.presenter.exit_requested() {
+                break;
+            }
+            if !had_event {
+                trace!("main_loop: Wait for wakeup");
+                self.wait_for_wakeup();
+                trace!("main_loop: Woke up");
+            }
+            had_event = false;
 
             if self.presenter.prepare_cycle() {
-                self.gate.mark();
+                had_event = true;
                 self.mark_redraw();
             }
 
+            self.save_transcripts();
+
             self.check_cursor_flip();
 
-            let event = self.poll_for_event();
-            match event {
-                None => {
-                    // Nothing received
-                }
-                Some(event) => {
-                    self.gate.mark();
-                    match event.get_type() {
-                        ConfigureNotify => {
-                            let info = unsafe { &event.configure };
-                            self.window_width = info.width;
-                            self.window_height = info.height;
-                            self.presenter.event_window_resize(
-                                (self.window_width / self.font_width) as usize,
-                                (self.window_height / self.line_height) as usize,
-                            );
-                        }
-                        Expose => {
-                            self.force_redraw();
-                        }
-                        FocusIn => {
-                            self.cursor_now(true);
-                            self.have_focus = true;
-                            self.mark_redraw();
-                            self.presenter.event_focus_gained();
-                        }
-                        FocusOut => {
-                            self.have_focus = false;
-                            self.mark_redraw();
-                            self.presenter.event_focus_lost();
-                        }
-                        KeyPress => {
-                            let mut info = unsafe { event.key };
-                            trace!("KeyPress: {:?}", info);
-                            let mut keysym: c_ulong = 0;
-                            let mut buf: [c_char; 20] = unsafe { ::std::mem::uninitialized() };
-                            let mut status = 0;
-                            let count = unsafe {
-                                Xutf8LookupString(
-                                    self.xic,
-                                    &mut info,
-                                    &mut buf[0],
-                                    (::std::mem::size_of_val(&buf) - 1) as c_int,
-                                    &mut keysym,
-                                    &mut status,
-                                )
-                            };
-                            assert!((count as usize) < ::std::mem::size_of_val(&buf));
-                            buf[count as usize] = 0;
-                            trace!("KeyPress: {}, »{:?}«", status, buf);
-
-                            // Handle movement and delete. They are all keysyms
-                            let mut cmd = PresenterCommand::Unknown;
-                            let mod_state = modifier_state_from_event(info.state);
-                            if status == XLookupKeySym || status == XLookupBoth {
-                                match KEYSYM2KEY.get(&keysym) {
-                                    Some(key) => {
-                                        cmd = self.presenter.event_special_key(&mod_state, key);
-                                    }
-                                    None => {
-                                        let maybe_letter = keysym;
-                                        if (('a' as c_ulong <= maybe_letter
-                                            && maybe_letter <= 'z' as c_ulong)
-                                            || ('A' as c_ulong <= maybe_letter
-                                                && maybe_letter <= 'Z' as c_ulong))
-                                            && mod_state.not_only_shift()
-                                        {
-                                            // A letter and not only shift was pressed. Might
-                                            // be a control key we're interested in.
-
-                                            // Normalize to lower case
-                                            let letter = if 'A' as c_ulong <= maybe_letter
-                                                && maybe_letter <= 'Z' as c_ulong
-                                            {
-                                                maybe_letter + 32
-                                            } else {
-                                                maybe_letter
-                                            };
-
-                                            cmd = self
-                                                .presenter
-                                                .event_normal_key(&mod_state, letter as u8);
-                                        }
-                                    }
-                                }
-                            }
-                            match cmd {
-                                PresenterCommand::Unknown => {
-                                    if status == XLookupChars || status == XLookupBoth {
-                                        // Insert text
-                                        match unsafe { CStr::from_ptr(&buf[0]).to_str() } {
-                                            Ok(s) => match self.presenter.event_text(s) {
-                                                PresenterCommand::Unknown => {}
-                                                PresenterCommand::Redraw => {
-                                                    self.cursor_now(true);
-                                                    self.mark_redraw();
-                                                }
-                                                PresenterCommand::Exit => return,
-                                                PresenterCommand::Ignored => {
-                                                    // Do nothing
-                                                }
-                                            },
-                                            _ => {}
-                                        }
-                                    }
-                                }
-                                PresenterCommand::Redraw => {
-                                    self.mark_redraw();
-                                }
-                                PresenterCommand::Exit => return,
-                                PresenterCommand::Ignored => {
-                                    // Do nothing
-                                }
-                            }
-                        }
-                        ButtonPress => {
-                            let info = unsafe { &event.button };
-                            let mod_state = modifier_state_from_event(info.state);
-                            match info.button {
-                                1 | 2 | 3 => {
-                                    if 0 <= info.y
-                                        && info.y < self.window_height
-                                        && 0 <= info.x
-                                        && info.x < self.window_width
-                                    {
-                                        // TODO: The X coordinate is incorrect in TUI lines
-                                        if NeedRedraw::Yes
-                                            == self.presenter.event_button_down(
-                                                mod_state,
-                                                info.button as usize,
-                                                ((info.x - COLOR_SEAM_WIDTH) / self.font_width)
-                                                    as usize,
-                                                (info.y / self.line_height) as usize,
-                                            )
-                                        {
-                                            self.mark_redraw();
-                                        }
-                                    }
-                                }
-                                5 => {
-                                    if PresenterCommand::Redraw
-                                        == self.presenter.event_scroll_down(&mod_state)
-                                    {
-                                        self.mark_redraw();
-                                    }
-                                }
-                                4 => {
-                                    if PresenterCommand::Redraw
-                                        == self.presenter.event_scroll_up(&mod_state)
-                                    {
-                                        self.mark_redraw();
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                        ButtonRelease => {
-                            let info = unsafe { &event.button };
-                            let mod_state = modifier_state_from_event(info.state);
-                            match info.button {
-                                1 | 2 | 3 => {
-                                    if 0 <= info.y
-                                        && info.y < self.window_height
-                                        && 0 <= info.x
-                                        && info.x < self.window_width
-                                    {
-                                        if NeedRedraw::Yes
-                                            == self.presenter.event_button_up(
-                                                mod_state,
-                                                info.button as usize,
-                                                ((info.x - COLOR_SEAM_WIDTH) / self.font_width)
-                                                    as usize,
-                                                (info.y / self.line_height) as usize,
-                                            )
-                                        {
-                                            self.mark_redraw();
-                                        }
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                        ClientMessage => {
-                            // Close the window
-                            break;
-                        }
-                        _ => {}
-                    }
+            if let Some(event) = self.poll_event() {
+                had_event = true;
+                if !self.handle_event(event) {
+                    break;
                 }
             }
-            if self.should_redraw() {
-                self.force_redraw();
+            if self.should_redraw() && !connection::connection_lost() {
+                self.redraw();
             }
         }
     }
 
     /// Frees all X resources and get back the owned objects
+    ///
+    /// If the connection to the server is lost, the resources are gone already.
     pub fn finish(self) -> (InteractiveInterpreter, History) {
-        unsafe {
-            XDestroyIC(self.xic);
-            XCloseIM(self.xim);
-            XCloseDisplay(self.display);
+        if !connection::connection_lost() {
+            unsafe {
+                XDestroyIC(self.xic);
+                XCloseIM(self.xim);
+                XCloseDisplay(self.display);
+            }
         }
         self.presenter.finish()
     }
 }
+
+impl Backend for Gui {
+    fn presenter(&mut self) -> &mut Presenter {
+        &mut self.presenter
+    }
+
+    fn poll_event(&mut self) -> Option<Event> {
+        self.poll_for_event()
+            .map(|event| self.translate_event(event))
+    }
+
+    /// Render the current presentation to the window.
+    ///
+    /// Redraws the whole window, not just the exposed rectangle.
+    fn render(&mut self) {
+        unsafe { XClearWindow(self.display, self.window) };
+        // TODO: Set colors
+
+        // Draw the text
+        let p = &self.presenter;
+        p.display_lines(&DrawLine(self));
+    }
+
+    /// Mark the GUI to be redrawn in the next frame.
+    fn mark_redraw(&mut self) {
+        self.repaint = Repaint::Window;
+    }
+
+    /// Redraw right now and remember it.
+    fn force_redraw(&mut self) {
+        self.render();
+        self.flush();
+        self.repaint = Repaint::Nothing;
+        self.redraw_time = SystemTime::now();
+    }
+
+    /// Set the cursor to a state and start the blink cycle anew.
+    fn cursor_now(&mut self, on: bool) {
+        self.cursor_on = on;
+        self.cursor_flip_time = SystemTime::now();
+    }
+
+    fn set_focus(&mut self, focus: bool) {
+        self.have_focus = focus;
+    }
+
+    fn confirm_exit(&self) -> bool {
+        self.confirm_exit
+    }
+
+    fn colors(&self) -> &[u32; 256] {
+        &self.colors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_height_includes_padding() {
+        assert_eq!(line_height(20, 1), 22);
+        assert_eq!(line_height(20, 0), 20);
+        assert_eq!(line_height(20, 3), 26);
+        // Negative padding never makes a line vanish
+        assert_eq!(line_height(20, -4), 12);
+        assert_eq!(line_height(20, -10), 1);
+        assert_eq!(line_height(20, -50), 1);
+    }
+
+    #[test]
+    fn initial_window_size_from_env() {
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(
+            initial_window_size(some("100"), some("40"), 10, 22),
+            (1000, 880)
+        );
+        assert_eq!(initial_window_size(None, None, 10, 22), (WIDTH, HEIGHT));
+        assert_eq!(
+            initial_window_size(some("wide"), some("0"), 10, 22),
+            (WIDTH, HEIGHT)
+        );
+        assert_eq!(
+            initial_window_size(some(" 80 "), None, 10, 22),
+            (800, HEIGHT)
+        );
+    }
+
+    #[test]
+    fn cursor_blink_repaints_only_cursor() {
+        // A blinking cursor alone doesn't need a full render
+        assert_eq!(Repaint::Nothing.and(Repaint::Cursor), Repaint::Cursor);
+        assert_eq!(Repaint::Cursor.and(Repaint::Cursor), Repaint::Cursor);
+        // Changed content always does
+        assert_eq!(Repaint::Cursor.and(Repaint::Window), Repaint::Window);
+        assert_eq!(Repaint::Window.and(Repaint::Cursor), Repaint::Window);
+        assert_eq!(Repaint::Window.and(Repaint::Nothing), Repaint::Window);
+    }
+
+    #[test]
+    fn cursor_flip_duration() {
+        let blink = CursorBlink {
+            on: Duration::from_millis(1000),
+            off: Duration::from_millis(500),
+        };
+        assert_eq!(blink.flip_duration(true), Some(Duration::from_millis(1000)));
+        assert_eq!(blink.flip_duration(false), Some(Duration::from_millis(500)));
+
+        // Without off time, a hidden cursor is shown at once and then stays on
+        let steady = CursorBlink {
+            on: Duration::from_millis(1000),
+            off: Duration::from_millis(0),
+        };
+        assert_eq!(steady.flip_duration(true), None);
+        assert_eq!(steady.flip_duration(false), Some(Duration::from_millis(0)));
+    }
+
+    #[test]
+    fn fixed_width_detection() {
+        assert!(is_fixed_width(&[10, 10, 10, 10]));
+        // Rounding may differ by a pixel
+        assert!(is_fixed_width(&[10, 11, 10]));
+        assert!(!is_fixed_width(&[4, 15, 13, 5]));
+        assert!(is_fixed_width(&[]));
+    }
+
+    #[test]
+    fn prompt_palette() {
+        assert_eq!(
+            colors::parse_prompt_colors("#ff0000, 00ff00 #0000FF"),
+            Some(vec![0xff0000, 0x00ff00, 0x0000ff])
+        );
+        assert_eq!(colors::parse_prompt_colors("#ff0000,#00ff0"), None);
+        assert_eq!(colors::parse_prompt_colors("red"), None);
+        assert_eq!(colors::parse_prompt_colors(" , "), None);
+
+        let palette = vec!["#123456"; 42].join(",");
+        assert_eq!(colors::prompt_colors(Some(&palette)).len(), 42);
+        assert_eq!(colors::prompt_colors(Some("#12345g")).len(), 20);
+        assert_eq!(
+            colors::prompt_colors(None),
+            colors::DEFAULT_PROMPT_COLORS.to_vec()
+        );
+    }
+}
@@ -0,0 +1,164 @@
+/*
+    BiTE - Bash-integrated Terminal Emulator
+    Copyright (C) 2020  Lars Krüger
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! Session transcripts as plain text or as HTML page in the colors of the window
+
+use std::io;
+use std::path::Path;
+
+use model::screen::{text_with_tabs, Attributes, Cell};
+use model::session::TranscriptFormat;
+
+use super::colors;
+
+/// Cells of a line up to the last drawn one
+fn drawn_part(line: &[Cell]) -> &[Cell] {
+    let used = line.iter().rposition(|c| c.drawn()).map_or(0, |p| p + 1);
+    &line[..used]
+}
+
+/// Plain text of the lines. Trailing blanks are removed.
+///
+/// Tabs in the output of the commands are kept, so tables can be pasted elsewhere.
+pub fn text(lines: &[Vec<Cell>]) -> String {
+    let mut text = String::new();
+    for line in lines {
+        text.push_str(text_with_tabs(drawn_part(line)).trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+/// Inline style of a cell
+fn style(cell: &Cell, colors: &[u32; 256]) -> String {
+    let (fg_color, bg_color) = colors::cell_colors(cell, colors);
+    let mut style = format!("color:#{:06x};background-color:#{:06x}", fg_color, bg_color);
+    if cell.attributes().contains(Attributes::BOLD) {
+        style.push_str(";font-weight:bold");
+    }
+    if cell.attributes().contains(Attributes::UNDERLINE) {
+        style.push_str(";text-decoration:underline");
+    }
+    style
+}
+
+/// HTML page of the lines. Cells of the same style are grouped in spans.
+pub fn html(lines: &[Vec<Cell>], colors: &[u32; 256]) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>BiTE session</title>\n\
+         </head>\n<body style=\"background-color:#{:06x}\">\n<pre>\n",
+        colors::DEFAULT_BACKGROUND
+    );
+    for line in lines {
+        let mut current: Option<String> = None;
+        for cell in drawn_part(line) {
+            let style = style(cell, colors);
+            if current.as_ref() != Some(&style) {
+                if current.is_some() {
+                    html.push_str("</span>");
+                }
+                html.push_str(&format!("<span style=\"{}\">", style));
+                current = Some(style);
+            }
+            if cell.drawn() {
+                for c in cell.chars() {
+                    match c {
+                        '&' => html.push_str("&amp;"),
+                        '<' => html.push_str("&lt;"),
+                        '>' => html.push_str("&gt;"),
+                        _ => html.push(c),
+                    }
+                }
+            } else {
+                html.push(' ');
+            }
+        }
+        if current.is_some() {
+            html.push_str("</span>");
+        }
+        html.push('\n');
+    }
+    html.push_str("</pre>\n</body>\n</html>\n");
+    html
+}
+
+/// Write the lines to a file in the given format
+pub fn write(
+    path: &Path,
+    format: TranscriptFormat,
+    lines: &[Vec<Cell>],
+    colors: &[u32; 256],
+) -> io::Result<()> {
+    let content = match format {
+        TranscriptFormat::Text => text(lines),
+        TranscriptFormat::Html => html(lines, colors),
+    };
+    std::fs::write(path, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::screen::Screen;
+    use model::session::test::new_test_session;
+    use model::session::{OutputVisibility, RunningStatus};
+    use presenter::transcript::session_lines;
+
+    #[test]
+    fn colored_session() {
+        let mut session = new_test_session(b"prompt");
+        let handle = session.add_interaction(Screen::one_line_matrix(b"ls <dir>"));
+        session.add_bytes(
+            OutputVisibility::Output,
+            handle,
+            b"\x1b[31mred\x1b[0m plain \x1b[1;44mbold\x1b[0m\n",
+        );
+        session.set_running_status(handle, RunningStatus::Exited(0));
+        let lines = session_lines(&session.0.lock().unwrap());
+
+        let text = text(&lines);
+        assert!(text.contains("ls <dir>\n"));
+        assert!(text.contains("   red plain bold\n"));
+        assert!(text.ends_with("prompt\n"));
+
+        let mut table = [0; 256];
+        colors::setupColors(&mut table);
+        let html = html(&lines, &table);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("ls &lt;dir&gt;"));
+        assert!(html.contains(
+            "<span style=\"color:#cd0000;background-color:#000000\">red</span>\
+             <span style=\"color:#ffd700;background-color:#000000\"> plain </span>\
+             <span style=\"color:#ffd700;background-color:#0000ee;font-weight:bold\">bold</span>\n"
+        ));
+        assert!(html.ends_with("</pre>\n</body>\n</html>\n"));
+    }
+
+    #[test]
+    fn tabs_in_text() {
+        let mut session = new_test_session(b"prompt");
+        let handle = session.add_interaction(Screen::one_line_matrix(b"ls -l"));
+        session.add_bytes(OutputVisibility::Output, handle, b"a\tb\nabc\tde\n");
+        session.set_running_status(handle, RunningStatus::Exited(0));
+        let lines = session_lines(&session.0.lock().unwrap());
+
+        let text = text(&lines);
+        assert!(text.contains("   a\tb\n"));
+        assert!(text.contains("   abc\tde\n"));
+    }
+}